@@ -1,7 +1,7 @@
 //! AsyncDht node.
 
 use std::{
-    net::SocketAddrV4,
+    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
     pin::Pin,
     task::{Context, Poll},
 };
@@ -10,12 +10,18 @@ use futures_lite::{Stream, StreamExt};
 
 use crate::{
     common::{
-        hash_immutable, AnnouncePeerRequestArguments, FindNodeRequestArguments,
-        GetPeersRequestArguments, GetValueRequestArguments, Id, MutableItem, Node,
-        PutImmutableRequestArguments, PutMutableRequestArguments, PutRequestSpecific,
+        chunk_immutable, chunks_manifest, hash_immutable, manifest_chunks,
+        AnnouncePeerRequestArguments, FindNodeRequestArguments, GetPeersRequestArguments,
+        GetValueRequestArguments, Id, MutableItem, Node, PutImmutableRequestArguments,
+        PutMutableRequestArguments, PutRequestSpecific,
+    },
+    dht::{
+        put_immutable_error, ActorMessage, Dht, PutChunkedError, PutMutableError, ResponseSender,
+    },
+    rpc::{
+        ConcurrencyError, GetRequestSpecific, Info, NodeDiagnostics, PutError, PutQueryError,
+        Response,
     },
-    dht::{ActorMessage, Dht, PutMutableError, ResponseSender},
-    rpc::{GetRequestSpecific, Info, PutError, PutQueryError},
 };
 
 impl Dht {
@@ -62,6 +68,20 @@ impl AsyncDht {
         !nodes.is_empty()
     }
 
+    /// Ping and send a find_node request to a specific node, and return a
+    /// [NodeDiagnostics] report about its reachability, round trip time,
+    /// claimed [Id], and whether it returns closer nodes.
+    ///
+    /// Useful to validate bootstrapping nodes before relying on them.
+    pub async fn diagnose_node(&self, address: SocketAddr) -> NodeDiagnostics {
+        let (tx, rx) = flume::bounded::<NodeDiagnostics>(1);
+        self.send(ActorMessage::DiagnoseNode(address, tx));
+
+        rx.recv_async()
+            .await
+            .expect("actor thread unexpectedly shutdown")
+    }
+
     // === Find nodes ===
 
     /// Returns the closest 20 [secure](Node::is_secure) nodes to a target [Id].
@@ -80,7 +100,10 @@ impl AsyncDht {
     pub async fn find_node(&self, target: Id) -> Box<[Node]> {
         let (tx, rx) = flume::bounded::<Box<[Node]>>(1);
         self.send(ActorMessage::Get(
-            GetRequestSpecific::FindNode(FindNodeRequestArguments { target }),
+            GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                target,
+                want: vec![],
+            }),
             ResponseSender::ClosestNodes(tx),
         ));
 
@@ -100,16 +123,61 @@ impl AsyncDht {
     /// for Bittorrent is that any peer will introduce you to more peers through "peer exchange"
     /// so if you are implementing something different from Bittorrent, you might want
     /// to implement your own logic for gossipping more peers after you discover the first ones.
+    ///
+    /// Only Ipv4 peers are returned, see [Self::get_peers6] for Ipv6 peers.
     pub fn get_peers(&self, info_hash: Id) -> GetStream<Vec<SocketAddrV4>> {
         let (tx, rx) = flume::unbounded::<Vec<SocketAddrV4>>();
         self.send(ActorMessage::Get(
-            GetRequestSpecific::GetPeers(GetPeersRequestArguments { info_hash }),
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
             ResponseSender::Peers(tx),
         ));
 
         GetStream(rx.into_stream())
     }
 
+    /// Get Ipv6 peers for a given infohash, same as [Self::get_peers] for Ipv4 peers.
+    ///
+    /// Only returns peers if [crate::DhtBuilder::ipv6] is enabled.
+    pub fn get_peers6(&self, info_hash: Id) -> GetStream<Vec<SocketAddrV6>> {
+        let (tx, rx) = flume::unbounded::<Vec<SocketAddrV6>>();
+        self.send(ActorMessage::Get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
+            ResponseSender::Peers6(tx),
+        ));
+
+        GetStream(rx.into_stream())
+    }
+
+    /// Estimate the number of seeders and peers for a given infohash, using
+    /// [BEP_0033](https://www.bittorrent.org/beps/bep_0033.html) scrape requests.
+    ///
+    /// Returns `(seeders, peers)` estimates after each response, each
+    /// more accurate than the last, so you probably want the last one.
+    pub fn scrape(&self, info_hash: Id) -> GetStream<(usize, usize)> {
+        let (tx, rx) = flume::unbounded::<(usize, usize)>();
+        self.send(ActorMessage::Get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: true,
+                noseed: false,
+                want: vec![],
+            }),
+            ResponseSender::Scrape(tx),
+        ));
+
+        GetStream(rx.into_stream())
+    }
+
     /// Announce a peer for a given infohash.
     ///
     /// The peer will be announced on this process IP.
@@ -130,6 +198,7 @@ impl AsyncDht {
                 info_hash,
                 port,
                 implied_port,
+                seed: false,
             }),
             None,
         )
@@ -139,6 +208,9 @@ impl AsyncDht {
             PutError::Concurrency(_) => {
                 unreachable!("should not receive a concurrency error from announce peer query")
             }
+            PutError::CasConflict { .. } => {
+                unreachable!("should not receive a CAS conflict from announce peer query")
+            }
         })
     }
 
@@ -171,12 +243,75 @@ impl AsyncDht {
             None,
         )
         .await
-        .map_err(|error| match error {
-            PutError::Query(error) => error,
-            PutError::Concurrency(_) => {
-                unreachable!("should not receive a concurrency error from put immutable query")
-            }
-        })
+        .map_err(put_immutable_error)
+    }
+
+    /// Put a value larger than a single immutable item, up to [crate::MAX_CHUNKED_IMMUTABLE_SIZE].
+    ///
+    /// See [Dht::put_immutable_chunked].
+    pub async fn put_immutable_chunked(&self, value: &[u8]) -> Result<Id, PutChunkedError> {
+        let chunks = chunk_immutable(value).ok_or(PutChunkedError::TooLarge(value.len()))?;
+        let manifest = chunks_manifest(&chunks);
+
+        let receivers = chunks
+            .iter()
+            .chain(std::iter::once(&manifest.as_ref()))
+            .map(|chunk| {
+                self.put_inner(
+                    PutRequestSpecific::PutImmutable(PutImmutableRequestArguments {
+                        target: hash_immutable(chunk).into(),
+                        v: (*chunk).into(),
+                    }),
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut root = None;
+
+        for rx in receivers {
+            root = Some(
+                rx.recv_async()
+                    .await
+                    .expect("Query was dropped before sending a response, please open an issue.")
+                    .map_err(put_immutable_error)?,
+            );
+        }
+
+        Ok(root.expect("manifest is always put"))
+    }
+
+    /// Get a value stored with [Self::put_immutable_chunked] by the hash of its manifest.
+    ///
+    /// Returns `None` if the manifest or any of the chunks is not found.
+    pub async fn get_immutable_chunked(&self, root: Id) -> Option<Box<[u8]>> {
+        let manifest = self.get_immutable(root).await?;
+        let chunks = manifest_chunks(&manifest)?;
+
+        let receivers = chunks
+            .into_iter()
+            .map(|target| {
+                let (tx, rx) = flume::unbounded::<Box<[u8]>>();
+                self.send(ActorMessage::Get(
+                    GetRequestSpecific::GetValue(GetValueRequestArguments {
+                        target,
+                        seq: None,
+                        salt: None,
+                    }),
+                    ResponseSender::Immutable(tx),
+                ));
+
+                rx
+            })
+            .collect::<Vec<_>>();
+
+        let mut value = Vec::new();
+
+        for rx in receivers {
+            value.extend_from_slice(&rx.recv_async().await.ok()?);
+        }
+
+        Some(value.into_boxed_slice())
     }
 
     // === Mutable data ===
@@ -215,6 +350,9 @@ impl AsyncDht {
     }
 
     /// Get the most recent [MutableItem] from the network.
+    ///
+    /// Waits for the query to finish, and returns the item with the highest `seq`,
+    /// see [MutableItem::is_more_recent_than].
     pub async fn get_mutable_most_recent(
         &self,
         public_key: &[u8; 32],
@@ -224,11 +362,10 @@ impl AsyncDht {
         let mut stream = self.get_mutable(public_key, salt, None);
 
         while let Some(item) = stream.next().await {
-            if let Some(mr) = &most_recent {
-                if item.seq() == mr.seq && item.value() > &mr.value {
-                    most_recent = Some(item)
-                }
-            } else {
+            if most_recent
+                .as_ref()
+                .map_or(true, |most_recent| item.is_more_recent_than(most_recent))
+            {
                 most_recent = Some(item);
             }
         }
@@ -298,6 +435,9 @@ impl AsyncDht {
         self.put(request, None).await.map_err(|error| match error {
             PutError::Query(err) => PutMutableError::Query(err),
             PutError::Concurrency(err) => PutMutableError::Concurrency(err),
+            PutError::CasConflict { .. } => {
+                PutMutableError::Concurrency(ConcurrencyError::CasFailed)
+            }
         })
     }
 
@@ -323,6 +463,16 @@ impl AsyncDht {
             .expect("Query was dropped before sending a response, please open an issue.")
     }
 
+    /// Send a raw GET request, and get a [Stream] of every [Response] to it.
+    ///
+    /// The stream ends when the query is done.
+    pub fn get_stream(&self, request: GetRequestSpecific) -> GetStream<Response> {
+        let (tx, rx) = flume::unbounded::<Response>();
+        self.send(ActorMessage::Get(request, ResponseSender::Response(tx)));
+
+        GetStream(rx.into_stream())
+    }
+
     /// Send a PUT request to the closest nodes, and optionally some extra nodes.
     ///
     /// This is useful to put data to regions of the DHT other than the closest nodes
@@ -443,6 +593,33 @@ mod test {
         futures::executor::block_on(test());
     }
 
+    #[test]
+    fn put_get_immutable_chunked() {
+        async fn test() {
+            let testnet = Testnet::new(10).unwrap();
+
+            let a = Dht::builder()
+                .bootstrap(&testnet.bootstrap)
+                .build()
+                .unwrap()
+                .as_async();
+            let b = Dht::builder()
+                .bootstrap(&testnet.bootstrap)
+                .build()
+                .unwrap()
+                .as_async();
+
+            let value = (0..5 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+
+            let root = a.put_immutable_chunked(&value).await.unwrap();
+
+            let response = b.get_immutable_chunked(root).await;
+            assert_eq!(response, Some(value.into_boxed_slice()));
+        }
+
+        futures::executor::block_on(test());
+    }
+
     #[test]
     fn put_get_mutable() {
         async fn test() {
@@ -483,6 +660,45 @@ mod test {
         futures::executor::block_on(test());
     }
 
+    #[test]
+    fn get_stream() {
+        async fn test() {
+            let testnet = Testnet::new(10).unwrap();
+
+            let a = Dht::builder()
+                .bootstrap(&testnet.bootstrap)
+                .build()
+                .unwrap()
+                .as_async();
+            let b = Dht::builder()
+                .bootstrap(&testnet.bootstrap)
+                .build()
+                .unwrap()
+                .as_async();
+
+            let signer = SigningKey::from_bytes(&[0; 32]);
+            let item = MutableItem::new(signer.clone(), b"Hello World!", 1, None);
+
+            a.put_mutable(item.clone(), None).await.unwrap();
+
+            let responses = b
+                .get_stream(GetRequestSpecific::GetValue(GetValueRequestArguments {
+                    target: *item.target(),
+                    seq: None,
+                    salt: None,
+                }))
+                .collect::<Vec<_>>()
+                .await;
+
+            assert!(!responses.is_empty());
+            for response in responses {
+                assert!(matches!(response, Response::Mutable(ref response) if response == &item));
+            }
+        }
+
+        futures::executor::block_on(test());
+    }
+
     #[test]
     fn put_get_mutable_no_more_recent_value() {
         async fn test() {
@@ -0,0 +1,138 @@
+//! Bloom filters of IP addresses as defined in [BEP_0033](https://www.bittorrent.org/beps/bep_0033.html).
+
+use std::net::IpAddr;
+
+/// Size of a [BloomFilter] in bytes.
+pub const BLOOM_FILTER_SIZE: usize = 256;
+
+/// Number of bits in a [BloomFilter].
+const M: usize = BLOOM_FILTER_SIZE * 8;
+/// Number of hash functions.
+const K: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A 2048 bits bloom filter of IP addresses, used in `get_peers` scrape responses
+/// to count seeders (`BFsd`) and peers (`BFpe`) of an info hash.
+pub struct BloomFilter(Box<[u8; BLOOM_FILTER_SIZE]>);
+
+impl BloomFilter {
+    /// Create an empty [BloomFilter].
+    pub fn new() -> Self {
+        Self(Box::new([0; BLOOM_FILTER_SIZE]))
+    }
+
+    /// Create a [BloomFilter] from its raw bytes.
+    pub fn from_bytes(bytes: [u8; BLOOM_FILTER_SIZE]) -> Self {
+        Self(Box::new(bytes))
+    }
+
+    /// Returns the raw bytes of this filter.
+    pub fn as_bytes(&self) -> &[u8; BLOOM_FILTER_SIZE] {
+        &self.0
+    }
+
+    /// Insert an IP address into this filter.
+    pub fn insert(&mut self, ip: &IpAddr) {
+        let hash = match ip {
+            IpAddr::V4(ip) => sha1_smol::Sha1::from(ip.octets()).digest().bytes(),
+            IpAddr::V6(ip) => sha1_smol::Sha1::from(ip.octets()).digest().bytes(),
+        };
+
+        for index in [
+            u16::from_le_bytes([hash[0], hash[1]]),
+            u16::from_le_bytes([hash[2], hash[3]]),
+        ] {
+            let index = index as usize % M;
+
+            self.0[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    /// Merge another filter into this one, as if all its addresses were inserted here.
+    pub fn union(&mut self, other: &BloomFilter) {
+        for (byte, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *byte |= other
+        }
+    }
+
+    /// Estimate the number of unique addresses inserted in this filter.
+    pub fn estimate(&self) -> f64 {
+        // A full filter can't estimate anything, so treat it as if one bit is unset.
+        let set_bits = self
+            .0
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum::<usize>()
+            .min(M - 1);
+
+        (1.0 - set_bits as f64 / M as f64).ln() / (K as f64 * (1.0 - 1.0 / M as f64).ln())
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    /// Filter of `192.0.2.0` to `192.0.2.255` and `2001:DB8::` to `2001:DB8::3E7`,
+    /// from the test vectors in [BEP_0033](https://www.bittorrent.org/beps/bep_0033.html).
+    const TEST_VECTOR: &str = "\
+        F6C3F5EAA07FFD91BDE89F777F26FB2BFF37BDB8FB2BBAA2FD3DDDE7BACFFF75\
+        EE7CCBAEFE5EEDB1FBFAFF67F6ABFF5E43DDBCA3FD9B9FFDF4FFD3E9DFF12D1B\
+        DF59DB53DBE9FA5B7FF3B8FDFCDE1AFB8BEDD7BE2F3EE71EBBBFE93BCDEEFE14\
+        8246C2BC5DBFF7E7EFDCF24FD8DC7ADFFD8FFFDFDDFFF7A4BBEEDF5CB95CE81F\
+        C7FCFF1FF4FFFFDFE5F7FDCBB7FD79B3FA1FC77BFE07FFF905B7B7FFC7FEFEFF\
+        E0B8370BB0CD3F5B7F2BD93FEB4386CFDD6F7FD5BFAF2E9EBFFFFEECD67ADBF7\
+        C67F17EFD5D75EBA6FFEBA7FFF47A91EB1BFBB53E8ABFB5762ABE8FF237279BF\
+        EFBFEEF5FFC5FEBFDFE5ADFFADFEE1FB737FFFFBFD9F6AEFFEEE76B6FD8F72EF";
+
+    fn test_vector() -> BloomFilter {
+        let mut bytes = [0; BLOOM_FILTER_SIZE];
+
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&TEST_VECTOR[i * 2..i * 2 + 2], 16).unwrap();
+        }
+
+        BloomFilter::from_bytes(bytes)
+    }
+
+    #[test]
+    fn insert() {
+        let mut filter = BloomFilter::new();
+
+        for i in 0..=255 {
+            filter.insert(&Ipv4Addr::new(192, 0, 2, i).into());
+        }
+        for i in 0..=0x3E7 {
+            filter.insert(&Ipv6Addr::new(0x2001, 0xDB8, 0, 0, 0, 0, 0, i).into());
+        }
+
+        assert_eq!(filter, test_vector());
+    }
+
+    #[test]
+    fn estimate() {
+        assert_eq!(BloomFilter::new().estimate(), 0.0);
+        assert!((test_vector().estimate() - 1224.9308).abs() < 0.0001);
+    }
+
+    #[test]
+    fn union() {
+        let mut filter = BloomFilter::new();
+        filter.insert(&Ipv4Addr::new(192, 0, 2, 1).into());
+
+        let mut other = BloomFilter::new();
+        other.insert(&Ipv4Addr::new(192, 0, 2, 2).into());
+
+        filter.union(&other);
+
+        assert_eq!(filter.estimate().round(), 2.0);
+    }
+}
@@ -2,18 +2,23 @@
 use crc::{Crc, CRC_32_ISCSI};
 use getrandom::getrandom;
 use serde::{Deserialize, Serialize};
+use sha1_smol::Sha1;
 use std::convert::TryInto;
 use std::{
+    cmp::Ordering,
     fmt::{self, Debug, Display, Formatter},
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
 };
 
+use super::validate_immutable;
+
 /// The size of node IDs in bits.
 pub const ID_SIZE: usize = 20;
 pub const MAX_DISTANCE: u8 = ID_SIZE as u8 * 8;
 
 const IPV4_MASK: u32 = 0x030f3fff;
+const IPV6_MASK: u64 = 0x0103070f1f3f7fff;
 const CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
 
 #[derive(Clone, Copy, PartialEq, Ord, PartialOrd, Eq, Hash, Serialize, Deserialize)]
@@ -29,6 +34,18 @@ impl Id {
         Id(bytes)
     }
 
+    /// Derive a deterministic Id from the SHA1 hash of a seed,
+    /// for example to run reproducible simulated networks in tests.
+    ///
+    /// The resulting Id is __not__ secure according to [BEP_0042](https://www.bittorrent.org/beps/bep_0042.html),
+    /// so nodes enforcing secure Ids won't store it in their routing tables.
+    pub fn from_seed(seed: &[u8]) -> Id {
+        let mut hasher = Sha1::new();
+        hasher.update(seed);
+
+        Id(hasher.digest().bytes())
+    }
+
     /// Create a new Id from some bytes. Returns Err if the input is not 20 bytes long.
     pub fn from_bytes<T: AsRef<[u8]>>(bytes: T) -> Result<Id, InvalidIdSize> {
         let bytes = bytes.as_ref();
@@ -42,6 +59,32 @@ impl Id {
         Ok(Id(tmp))
     }
 
+    /// Parse an Id from its 40 characters hex encoding, like a BitTorrent infohash.
+    ///
+    /// Both lowercase and uppercase hex characters are accepted.
+    pub fn from_hex(hex: &str) -> Result<Id, DecodeIdError> {
+        if hex.len() != ID_SIZE * 2 {
+            return Err(DecodeIdError::InvalidHexLength(hex.len()));
+        }
+
+        if let Some(invalid) = hex.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(DecodeIdError::InvalidHexCharacter(invalid.into()));
+        }
+
+        let mut bytes = [0; ID_SIZE];
+
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).expect("validated hex digits");
+        }
+
+        Ok(Id(bytes))
+    }
+
+    /// Returns the lowercase 40 characters hex encoding of this Id, same as its [Display].
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+
     /// Simplified XOR distance between this Id and a target Id.
     ///
     /// The distance is the number of trailing non zero bits in the XOR result.
@@ -53,6 +96,52 @@ impl Id {
         MAX_DISTANCE - self.xor(other).leading_zeros()
     }
 
+    /// Returns a random Id at the given [Self::distance] from this Id,
+    /// which is within the range of the routing table bucket at that distance.
+    ///
+    /// Returns this Id itself for a distance of zero.
+    pub fn random_at_distance(&self, distance: u8) -> Id {
+        let distance = distance.min(MAX_DISTANCE);
+
+        if distance == 0 {
+            return *self;
+        }
+
+        let mut mask = Id::random().0;
+        let prefix = (MAX_DISTANCE - distance) as usize;
+
+        for (i, byte) in mask.iter_mut().enumerate() {
+            let bit = i * 8;
+
+            if bit + 8 <= prefix {
+                *byte = 0;
+            } else if bit <= prefix {
+                // Clear the common prefix bits, and set the first differing bit.
+                let offset = prefix - bit;
+                *byte &= 0xff >> offset;
+                *byte |= 0x80 >> offset;
+            }
+        }
+
+        self.xor(&Id(mask))
+    }
+
+    /// Full XOR distance between this Id and another Id, as used to sort
+    /// the closest nodes to a target, see [Self::cmp_distance].
+    ///
+    /// Unlike [Self::distance], which only counts the bits after the common prefix,
+    /// this distinguishes any two Ids.
+    pub fn xor_distance(&self, other: &Id) -> Distance {
+        Distance(self.xor(other).0)
+    }
+
+    /// Compare which of `a` and `b` is closer to this Id as a target.
+    ///
+    /// Returns [Ordering::Less] if `a` is closer.
+    pub fn cmp_distance(&self, a: &Id, b: &Id) -> Ordering {
+        self.xor_distance(a).cmp(&self.xor_distance(b))
+    }
+
     /// Returns the number of leading zeros in the binary representation of `self`.
     pub fn leading_zeros(&self) -> u8 {
         for (i, byte) in self.0.iter().enumerate() {
@@ -88,11 +177,11 @@ impl Id {
         Id::from_ip(ip)
     }
 
-    /// Create a new Id from an Ipv4 address according to [BEP_0042](http://bittorrent.org/beps/bep_0042.html).
+    /// Create a new Id from an IP address according to [BEP_0042](http://bittorrent.org/beps/bep_0042.html).
     pub fn from_ip(ip: IpAddr) -> Id {
         match ip {
             IpAddr::V4(addr) => Id::from_ipv4(addr),
-            IpAddr::V6(_addr) => unimplemented!("Ipv6 is not supported"),
+            IpAddr::V6(addr) => Id::from_ipv6(addr),
         }
     }
 
@@ -104,6 +193,18 @@ impl Id {
         from_ipv4_and_r(bytes[1..].try_into().expect("infallible"), ipv4, bytes[0])
     }
 
+    /// Create a new Id from an Ipv6 address according to [BEP_0042](http://bittorrent.org/beps/bep_0042.html).
+    pub fn from_ipv6(ipv6: Ipv6Addr) -> Id {
+        let mut bytes = [0_u8; 21];
+        getrandom(&mut bytes).expect("getrandom");
+
+        from_prefix_and_r(
+            bytes[1..].try_into().expect("infallible"),
+            id_prefix_ipv6(ipv6, bytes[0]),
+            bytes[0],
+        )
+    }
+
     /// Validate that this Id is valid with respect to [BEP_0042](http://bittorrent.org/beps/bep_0042.html).
     pub fn is_valid_for_ip(&self, ipv4: Ipv4Addr) -> bool {
         if ipv4.is_private() || ipv4.is_link_local() || ipv4.is_loopback() {
@@ -115,6 +216,40 @@ impl Id {
         self.first_21_bits() == expected
     }
 
+    /// Validate that this Id is valid for an Ipv6 address with respect to [BEP_0042](http://bittorrent.org/beps/bep_0042.html).
+    pub fn is_valid_for_ipv6(&self, ipv6: Ipv6Addr) -> bool {
+        let first_segment = ipv6.segments()[0];
+
+        if ipv6.is_loopback()
+            // Unique local fc00::/7
+            || (first_segment & 0xfe00) == 0xfc00
+            // Link local fe80::/10
+            || (first_segment & 0xffc0) == 0xfe80
+        {
+            return true;
+        }
+
+        let expected = first_21_bits(&id_prefix_ipv6(ipv6, self.0[ID_SIZE - 1]));
+
+        self.first_21_bits() == expected
+    }
+
+    /// Validate that this Id is valid for an IP address of either family
+    /// with respect to [BEP_0042](http://bittorrent.org/beps/bep_0042.html).
+    pub fn is_valid_for_ip_addr(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ipv4) => self.is_valid_for_ip(ipv4),
+            IpAddr::V6(ipv6) => self.is_valid_for_ipv6(ipv6),
+        }
+    }
+
+    /// Validate that this Id is the target of an immutable `value`, as in
+    /// [BEP_0044](https://www.bittorrent.org/beps/bep_0044.html), for example to verify
+    /// content addressed values fetched through another channel, without a query.
+    pub fn is_valid_immutable(&self, value: &[u8]) -> bool {
+        validate_immutable(value, *self)
+    }
+
     pub(crate) fn first_21_bits(&self) -> [u8; 3] {
         first_21_bits(&self.0)
     }
@@ -125,8 +260,11 @@ fn first_21_bits(bytes: &[u8]) -> [u8; 3] {
 }
 
 fn from_ipv4_and_r(bytes: [u8; 20], ip: Ipv4Addr, r: u8) -> Id {
+    from_prefix_and_r(bytes, id_prefix_ipv4(ip, r), r)
+}
+
+fn from_prefix_and_r(bytes: [u8; 20], prefix: [u8; 3], r: u8) -> Id {
     let mut bytes = bytes;
-    let prefix = id_prefix_ipv4(ip, r);
 
     // Set first 21 bits to the prefix
     bytes[0] = prefix[0];
@@ -155,6 +293,21 @@ fn id_prefix_ipv4(ip: Ipv4Addr, r: u8) -> [u8; 3] {
         .expect("Failed to convert bytes 0-2 of the crc into a 3-byte array")
 }
 
+fn id_prefix_ipv6(ip: Ipv6Addr, r: u8) -> [u8; 3] {
+    let r64: u64 = r.into();
+    let ip_int: u64 = u64::from_be_bytes(ip.octets()[..8].try_into().expect("infallible"));
+    let masked_ip: u64 = (ip_int & IPV6_MASK) | (r64 << 61);
+
+    let mut digest = CASTAGNOLI.digest();
+    digest.update(&masked_ip.to_be_bytes());
+
+    let crc = digest.finalize();
+
+    crc.to_be_bytes()[..3]
+        .try_into()
+        .expect("Failed to convert bytes 0-2 of the crc into a 3-byte array")
+}
+
 impl Display for Id {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         #[allow(clippy::format_collect)]
@@ -211,6 +364,18 @@ impl Debug for Id {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+/// XOR distance between two [Id]s, ordered as a 160 bits big endian integer,
+/// see [Id::xor_distance].
+pub struct Distance([u8; ID_SIZE]);
+
+impl Distance {
+    /// Returns the bytes of this distance.
+    pub fn as_bytes(&self) -> &[u8; ID_SIZE] {
+        &self.0
+    }
+}
+
 #[derive(Debug)]
 pub struct InvalidIdSize(usize);
 
@@ -236,12 +401,47 @@ pub enum DecodeIdError {
     /// Invalid hex character
     #[error("Invalid Id encoding: {0}")]
     InvalidHexCharacter(String),
+
+    #[error("Hex encoded Id should be 40 characters, got {0}")]
+    /// Hex encoded Id should be 40 characters
+    InvalidHexLength(usize),
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn random_at_distance() {
+        let id = Id::random();
+
+        assert_eq!(id.random_at_distance(0), id);
+
+        for distance in 1..=MAX_DISTANCE {
+            assert_eq!(id.distance(&id.random_at_distance(distance)), distance);
+        }
+    }
+
+    #[test]
+    fn is_valid_immutable() {
+        let value = b"content addressed";
+        let target = Id::from(crate::common::hash_immutable(value));
+
+        assert!(target.is_valid_immutable(value));
+        assert!(!target.is_valid_immutable(b"content addressed!"));
+        assert!(!Id::random().is_valid_immutable(value));
+    }
+
+    #[test]
+    fn from_seed() {
+        assert_eq!(Id::from_seed(b"node-1"), Id::from_seed(b"node-1"));
+        assert_ne!(Id::from_seed(b"node-1"), Id::from_seed(b"node-2"));
+        assert_eq!(
+            Id::from_seed(b""),
+            Id::from_hex("da39a3ee5e6b4b0d3255bfef95601890afd80709").unwrap()
+        );
+    }
+
     #[test]
     fn distance_to_self() {
         let id = Id::random();
@@ -353,4 +553,71 @@ mod test {
             assert!(id.is_valid_for_ip(ip));
         }
     }
+
+    #[test]
+    fn is_valid_for_ipv6() {
+        let ip: Ipv6Addr = "2001:db8::1".parse().unwrap();
+
+        for _ in 0..10 {
+            let id = Id::from_ipv6(ip);
+
+            assert!(id.is_valid_for_ipv6(ip));
+            assert!(id.is_valid_for_ip_addr(ip.into()));
+        }
+
+        // Only the first 64 bits of the address are used.
+        assert!(Id::from_ipv6(ip).is_valid_for_ipv6("2001:db8::2".parse().unwrap()));
+
+        assert!(!Id::from_ipv6(ip).is_valid_for_ipv6("2001:db9::1".parse().unwrap()));
+        assert!(Id::random().is_valid_for_ipv6(Ipv6Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn hex() {
+        let hex = "0639a1e24fbb8ab277df033476ab0de10fab3bdc";
+        let id = Id::from_hex(hex).unwrap();
+
+        assert_eq!(id.to_hex(), hex);
+        assert_eq!(Id::from_hex(&hex.to_uppercase()).unwrap(), id);
+
+        assert!(matches!(
+            Id::from_hex(&hex[..38]),
+            Err(DecodeIdError::InvalidHexLength(38))
+        ));
+        assert!(matches!(
+            Id::from_hex("0639a1e24fbb8ab277df033476ab0de10fab3bdg"),
+            Err(DecodeIdError::InvalidHexCharacter(c)) if c == "g"
+        ));
+
+        // FromStr keeps its own error mapping.
+        assert_eq!(Id::from_str(hex).unwrap(), id);
+        assert!(matches!(
+            Id::from_str(&hex[..39]),
+            Err(DecodeIdError::OddNumberOfCharacters)
+        ));
+        assert!(matches!(
+            Id::from_str(&hex[..38]),
+            Err(DecodeIdError::InvalidIdSize(InvalidIdSize(19)))
+        ));
+    }
+
+    #[test]
+    fn xor_distance() {
+        let target = Id::from_hex("0000000000000000000000000000000000000000").unwrap();
+        let a = Id::from_hex("0000000000000000000000000000000000000003").unwrap();
+        let b = Id::from_hex("0000000000000000000000000000000000000100").unwrap();
+        let c = Id::from_hex("8000000000000000000000000000000000000000").unwrap();
+
+        // 3 < 256 < 2^159
+        assert!(target.xor_distance(&a) < target.xor_distance(&b));
+        assert!(target.xor_distance(&b) < target.xor_distance(&c));
+        assert_eq!(target.cmp_distance(&a, &b), Ordering::Less);
+        assert_eq!(target.cmp_distance(&c, &b), Ordering::Greater);
+        assert_eq!(target.cmp_distance(&a, &a), Ordering::Equal);
+
+        // 0x03 ^ 0x01 = 2 < 0x100 ^ 0x01 = 257
+        let target = Id::from_hex("0000000000000000000000000000000000000001").unwrap();
+        assert_eq!(target.xor_distance(&a).as_bytes()[19], 2);
+        assert_eq!(target.cmp_distance(&a, &b), Ordering::Less);
+    }
 }
@@ -5,10 +5,22 @@ use sha1_smol::Sha1;
 use super::ID_SIZE;
 use crate::Id;
 
+/// Maximum size of each chunk of a chunked immutable value,
+/// and of the manifest listing the chunks' hashes.
+pub const IMMUTABLE_CHUNK_SIZE: usize = 1000;
+
+/// Maximum size of a value stored with [crate::Dht::put_immutable_chunked],
+/// limited by the number of chunk hashes that fit in a single manifest.
+pub const MAX_CHUNKED_IMMUTABLE_SIZE: usize =
+    IMMUTABLE_CHUNK_SIZE * (IMMUTABLE_CHUNK_SIZE / ID_SIZE);
+
+/// Returns true if the `target` is the hash of the immutable value `v`,
+/// see [Id::is_valid_immutable].
 pub fn validate_immutable(v: &[u8], target: Id) -> bool {
     hash_immutable(v) == *target.as_bytes()
 }
 
+/// Returns the target of the immutable value `v`, the SHA1 hash of its bencoded form.
 pub fn hash_immutable(v: &[u8]) -> [u8; ID_SIZE] {
     let mut encoded = Vec::with_capacity(v.len() + 3);
     encoded.extend(format!("{}:", v.len()).bytes());
@@ -20,6 +32,37 @@ pub fn hash_immutable(v: &[u8]) -> [u8; ID_SIZE] {
     hasher.digest().bytes()
 }
 
+/// Split a value into chunks of at most [IMMUTABLE_CHUNK_SIZE].
+///
+/// Returns `None` if the value is larger than [MAX_CHUNKED_IMMUTABLE_SIZE].
+pub(crate) fn chunk_immutable(value: &[u8]) -> Option<Vec<&[u8]>> {
+    if value.len() > MAX_CHUNKED_IMMUTABLE_SIZE {
+        return None;
+    }
+
+    Some(value.chunks(IMMUTABLE_CHUNK_SIZE).collect())
+}
+
+/// Manifest of the concatenated hashes of chunks from [chunk_immutable].
+pub(crate) fn chunks_manifest(chunks: &[&[u8]]) -> Box<[u8]> {
+    chunks
+        .iter()
+        .flat_map(|chunk| hash_immutable(chunk))
+        .collect()
+}
+
+/// Parse a manifest created by [chunks_manifest] into the [Id]s of its chunks.
+pub(crate) fn manifest_chunks(manifest: &[u8]) -> Option<Vec<Id>> {
+    if manifest.len() % ID_SIZE != 0 {
+        return None;
+    }
+
+    manifest
+        .chunks(ID_SIZE)
+        .map(|bytes| Id::from_bytes(bytes).ok())
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -49,4 +92,23 @@ mod test {
 
         assert_eq!(hash_immutable(v), *target.as_bytes());
     }
+
+    #[test]
+    fn chunk_manifest() {
+        let value = vec![7; 2500];
+
+        let chunks = chunk_immutable(&value).unwrap();
+        let manifest = chunks_manifest(&chunks);
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(),
+            vec![1000, 1000, 500]
+        );
+
+        let ids = manifest_chunks(&manifest).unwrap();
+        assert_eq!(ids.len(), 3);
+        assert!(validate_immutable(chunks[2], ids[2]));
+
+        assert!(manifest_chunks(&manifest[1..]).is_none());
+        assert!(chunk_immutable(&vec![0; MAX_CHUNKED_IMMUTABLE_SIZE + 1]).is_none());
+    }
 }
@@ -7,9 +7,9 @@
 mod internal;
 
 use std::convert::TryInto;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 
-use crate::common::{Id, Node, ID_SIZE};
+use crate::common::{BloomFilter, Id, Node, BLOOM_FILTER_SIZE, ID_SIZE};
 
 use super::InvalidIdSize;
 
@@ -22,7 +22,7 @@ pub(crate) struct Message {
 
     /// The IP address and port ("SocketAddr") of the requester as seen from the responder's point of view.
     /// This should be set only on response, but is defined at this level with the other common fields to avoid defining yet another layer on the response objects.
-    pub requester_ip: Option<SocketAddrV4>,
+    pub requester_ip: Option<SocketAddr>,
 
     pub message_type: MessageType,
 
@@ -104,10 +104,57 @@ pub struct PingResponseArguments {
     pub responder_id: Id,
 }
 
+/// Address family of nodes requested in the `want` argument of `find_node`
+/// and `get_peers` requests, see [BEP_0032](https://www.bittorrent.org/beps/bep_0032.html).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Want {
+    /// Ipv4 nodes (`n4`) in the `nodes` field.
+    N4,
+    /// Ipv6 nodes (`n6`) in the `nodes6` field.
+    N6,
+}
+
+impl Want {
+    fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Want::N4 => b"n4",
+            Want::N6 => b"n6",
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            b"n4" => Some(Want::N4),
+            b"n6" => Some(Want::N6),
+            _ => None,
+        }
+    }
+}
+
+/// Encode a `want` list, omitting it if empty.
+fn want_to_bytes(want: &[Want]) -> Option<Vec<serde_bytes::ByteBuf>> {
+    (!want.is_empty()).then(|| {
+        want.iter()
+            .map(|want| serde_bytes::ByteBuf::from(want.as_bytes()))
+            .collect()
+    })
+}
+
+/// Decode a `want` list, ignoring unknown address families.
+fn bytes_to_want(want: Option<Vec<serde_bytes::ByteBuf>>) -> Vec<Want> {
+    want.unwrap_or_default()
+        .iter()
+        .filter_map(|want| Want::from_bytes(want))
+        .collect()
+}
+
 // === FIND_NODE ===
 #[derive(Debug, PartialEq, Clone)]
 pub struct FindNodeRequestArguments {
     pub target: Id,
+    /// Address families of nodes to return, or empty to let the responder
+    /// return nodes of the same address family as the requester.
+    pub want: Vec<Want>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -140,14 +187,25 @@ pub struct NoValuesResponseArguments {
 #[derive(Debug, PartialEq, Clone)]
 pub struct GetPeersRequestArguments {
     pub info_hash: Id,
+    /// Ask for seeders and peers bloom filters as in [BEP_0033](https://www.bittorrent.org/beps/bep_0033.html).
+    pub scrape: bool,
+    /// Ask to only return peers that didn't announce themselves as seeds,
+    /// see [AnnouncePeerRequestArguments::seed].
+    pub noseed: bool,
+    /// Address families of nodes to return, see [FindNodeRequestArguments::want].
+    pub want: Vec<Want>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct GetPeersResponseArguments {
     pub responder_id: Id,
     pub token: Box<[u8]>,
-    pub values: Vec<SocketAddrV4>,
+    pub values: Vec<SocketAddr>,
     pub nodes: Option<Box<[Node]>>,
+    /// Bloom filter of seeders (`BFsd`) in a scrape response.
+    pub seeders: Option<BloomFilter>,
+    /// Bloom filter of peers (`BFpe`) in a scrape response.
+    pub peers: Option<BloomFilter>,
 }
 
 // === Announce Peer ===
@@ -157,6 +215,8 @@ pub struct AnnouncePeerRequestArguments {
     pub info_hash: Id,
     pub port: u16,
     pub implied_port: Option<bool>,
+    /// Announce the peer as a seed, that already has the complete torrent.
+    pub seed: bool,
 }
 
 // === Get Immutable ===
@@ -218,7 +278,7 @@ impl Message {
             version: self.version,
             ip: self
                 .requester_ip
-                .map(|sockaddr| sockaddr_to_bytes(&sockaddr)),
+                .map(|sockaddr| sockaddr_to_bytes(&sockaddr).into()),
             read_only: if self.read_only { Some(1) } else { Some(0) },
             variant: match self.message_type {
                 MessageType::Request(RequestSpecific {
@@ -235,6 +295,7 @@ impl Message {
                             arguments: internal::DHTFindNodeRequestArguments {
                                 id: requester_id.into(),
                                 target: find_node_args.target.into(),
+                                want: want_to_bytes(&find_node_args.want),
                             },
                         }
                     }
@@ -243,6 +304,9 @@ impl Message {
                             arguments: internal::DHTGetPeersRequestArguments {
                                 id: requester_id.into(),
                                 info_hash: get_peers_args.info_hash.into(),
+                                scrape: get_peers_args.scrape.then_some(1),
+                                noseed: get_peers_args.noseed.then_some(1),
+                                want: want_to_bytes(&get_peers_args.want),
                             },
                         }
                     }
@@ -272,6 +336,7 @@ impl Message {
                                     } else {
                                         Some(0)
                                     },
+                                    seed: announce_peer_args.seed.then_some(1),
                                 },
                             }
                         }
@@ -321,6 +386,28 @@ impl Message {
                             arguments: internal::DHTFindNodeResponseArguments {
                                 id: find_node_args.responder_id.into(),
                                 nodes: nodes4_to_bytes(&find_node_args.nodes),
+                                nodes6: nodes6_to_bytes(&find_node_args.nodes),
+                            },
+                        }
+                    }
+                    ResponseSpecific::GetPeers(get_peers_args)
+                        if get_peers_args.seeders.is_some() || get_peers_args.peers.is_some() =>
+                    {
+                        internal::DHTResponseSpecific::GetPeersScrape {
+                            arguments: internal::DHTGetPeersScrapeResponseArguments {
+                                id: get_peers_args.responder_id.into(),
+                                token: get_peers_args.token,
+                                nodes: get_peers_args
+                                    .nodes
+                                    .as_ref()
+                                    .map(|nodes| nodes4_to_bytes(nodes)),
+                                nodes6: get_peers_args
+                                    .nodes
+                                    .as_ref()
+                                    .and_then(|nodes| nodes6_to_bytes(nodes)),
+                                values: peers_to_bytes(&get_peers_args.values),
+                                seeders: bloom_filter_to_bytes(get_peers_args.seeders),
+                                peers: bloom_filter_to_bytes(get_peers_args.peers),
                             },
                         }
                     }
@@ -333,6 +420,10 @@ impl Message {
                                     .nodes
                                     .as_ref()
                                     .map(|nodes| nodes4_to_bytes(nodes)),
+                                nodes6: get_peers_args
+                                    .nodes
+                                    .as_ref()
+                                    .and_then(|nodes| nodes6_to_bytes(nodes)),
                                 values: peers_to_bytes(&get_peers_args.values),
                             },
                         }
@@ -346,6 +437,10 @@ impl Message {
                                     .nodes
                                     .as_ref()
                                     .map(|nodes| nodes4_to_bytes(nodes)),
+                                nodes6: no_values_arguments
+                                    .nodes
+                                    .as_ref()
+                                    .and_then(|nodes| nodes6_to_bytes(nodes)),
                             },
                         }
                     }
@@ -358,6 +453,10 @@ impl Message {
                                     .nodes
                                     .as_ref()
                                     .map(|nodes| nodes4_to_bytes(nodes)),
+                                nodes6: get_immutable_args
+                                    .nodes
+                                    .as_ref()
+                                    .and_then(|nodes| nodes6_to_bytes(nodes)),
                                 v: get_immutable_args.v,
                             },
                         }
@@ -371,6 +470,10 @@ impl Message {
                                     .nodes
                                     .as_ref()
                                     .map(|nodes| nodes4_to_bytes(nodes)),
+                                nodes6: get_mutable_args
+                                    .nodes
+                                    .as_ref()
+                                    .and_then(|nodes| nodes6_to_bytes(nodes)),
                                 v: get_mutable_args.v,
                                 k: get_mutable_args.k,
                                 seq: get_mutable_args.seq,
@@ -384,6 +487,10 @@ impl Message {
                                 id: args.responder_id.into(),
                                 token: args.token,
                                 nodes: args.nodes.as_ref().map(|nodes| nodes4_to_bytes(nodes)),
+                                nodes6: args
+                                    .nodes
+                                    .as_ref()
+                                    .and_then(|nodes| nodes6_to_bytes(nodes)),
                                 seq: args.seq,
                             },
                         }
@@ -423,12 +530,16 @@ impl Message {
                             requester_id: Id::from_bytes(arguments.id)?,
                             request_type: RequestTypeSpecific::FindNode(FindNodeRequestArguments {
                                 target: Id::from_bytes(arguments.target)?,
+                                want: bytes_to_want(arguments.want),
                             }),
                         },
                         internal::DHTRequestSpecific::GetPeers { arguments } => RequestSpecific {
                             requester_id: Id::from_bytes(arguments.id)?,
                             request_type: RequestTypeSpecific::GetPeers(GetPeersRequestArguments {
                                 info_hash: Id::from_bytes(arguments.info_hash)?,
+                                scrape: arguments.scrape.is_some_and(|scrape| scrape > 0),
+                                noseed: arguments.noseed.is_some_and(|noseed| noseed > 0),
+                                want: bytes_to_want(arguments.want),
                             }),
                         },
                         internal::DHTRequestSpecific::GetValue { arguments } => RequestSpecific {
@@ -452,6 +563,7 @@ impl Message {
                                                 .map(|implied_port| implied_port != 0),
                                             info_hash: arguments.info_hash.into(),
                                             port: arguments.port,
+                                            seed: arguments.seed.is_some_and(|seed| seed > 0),
                                         },
                                     ),
                                 }),
@@ -510,38 +622,48 @@ impl Message {
                         internal::DHTResponseSpecific::FindNode { arguments } => {
                             ResponseSpecific::FindNode(FindNodeResponseArguments {
                                 responder_id: Id::from_bytes(arguments.id)?,
-                                nodes: bytes_to_nodes4(&arguments.nodes)?,
+                                nodes: bytes_to_nodes(Some(arguments.nodes), arguments.nodes6)?
+                                    .unwrap_or_default(),
+                            })
+                        }
+                        internal::DHTResponseSpecific::FindNode6 { arguments } => {
+                            ResponseSpecific::FindNode(FindNodeResponseArguments {
+                                responder_id: Id::from_bytes(arguments.id)?,
+                                nodes: bytes_to_nodes6(&arguments.nodes6)?,
                             })
                         }
                         internal::DHTResponseSpecific::GetPeers { arguments } => {
                             ResponseSpecific::GetPeers(GetPeersResponseArguments {
                                 responder_id: Id::from_bytes(arguments.id)?,
                                 token: arguments.token,
-                                nodes: match arguments.nodes {
-                                    Some(nodes) => Some(bytes_to_nodes4(nodes)?),
-                                    None => None,
-                                },
+                                nodes: bytes_to_nodes(arguments.nodes, arguments.nodes6)?,
                                 values: bytes_to_peers(arguments.values)?,
+                                seeders: None,
+                                peers: None,
+                            })
+                        }
+                        internal::DHTResponseSpecific::GetPeersScrape { arguments } => {
+                            ResponseSpecific::GetPeers(GetPeersResponseArguments {
+                                responder_id: Id::from_bytes(arguments.id)?,
+                                token: arguments.token,
+                                nodes: bytes_to_nodes(arguments.nodes, arguments.nodes6)?,
+                                values: bytes_to_peers(arguments.values)?,
+                                seeders: Some(bytes_to_bloom_filter(&arguments.seeders)?),
+                                peers: Some(bytes_to_bloom_filter(&arguments.peers)?),
                             })
                         }
                         internal::DHTResponseSpecific::NoValues { arguments } => {
                             ResponseSpecific::NoValues(NoValuesResponseArguments {
                                 responder_id: Id::from_bytes(arguments.id)?,
                                 token: arguments.token,
-                                nodes: match arguments.nodes {
-                                    Some(nodes) => Some(bytes_to_nodes4(nodes)?),
-                                    None => None,
-                                },
+                                nodes: bytes_to_nodes(arguments.nodes, arguments.nodes6)?,
                             })
                         }
                         internal::DHTResponseSpecific::GetImmutable { arguments } => {
                             ResponseSpecific::GetImmutable(GetImmutableResponseArguments {
                                 responder_id: Id::from_bytes(arguments.id)?,
                                 token: arguments.token,
-                                nodes: match arguments.nodes {
-                                    Some(nodes) => Some(bytes_to_nodes4(nodes)?),
-                                    None => None,
-                                },
+                                nodes: bytes_to_nodes(arguments.nodes, arguments.nodes6)?,
                                 v: arguments.v,
                             })
                         }
@@ -549,10 +671,7 @@ impl Message {
                             ResponseSpecific::GetMutable(GetMutableResponseArguments {
                                 responder_id: Id::from_bytes(arguments.id)?,
                                 token: arguments.token,
-                                nodes: match arguments.nodes {
-                                    Some(nodes) => Some(bytes_to_nodes4(nodes)?),
-                                    None => None,
-                                },
+                                nodes: bytes_to_nodes(arguments.nodes, arguments.nodes6)?,
                                 v: arguments.v,
                                 k: arguments.k,
                                 seq: arguments.seq,
@@ -564,10 +683,7 @@ impl Message {
                                 NoMoreRecentValueResponseArguments {
                                     responder_id: Id::from_bytes(arguments.id)?,
                                     token: arguments.token,
-                                    nodes: match arguments.nodes {
-                                        Some(nodes) => Some(bytes_to_nodes4(nodes)?),
-                                        None => None,
-                                    },
+                                    nodes: bytes_to_nodes(arguments.nodes, arguments.nodes6)?,
                                     seq: arguments.seq,
                                 },
                             )
@@ -587,14 +703,31 @@ impl Message {
         self.clone().into_serde_message().to_bytes()
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Message, DecodeMessageError> {
+    /// Decode a message, rejecting `get` responses with values larger than `max_value_size`.
+    pub fn from_bytes(bytes: &[u8], max_value_size: usize) -> Result<Message, DecodeMessageError> {
         if bytes.len() < 15 {
             return Err(DecodeMessageError::TooShort);
         } else if bytes[0] != 100 {
             return Err(DecodeMessageError::NotBencodeDictionary);
         }
 
-        Message::from_serde_message(internal::DHTMessage::from_bytes(bytes)?)
+        let msg = internal::DHTMessage::from_bytes(bytes)?;
+
+        if let internal::DHTMessageVariant::Response(
+            internal::DHTResponseSpecific::GetImmutable {
+                arguments: internal::DHTGetImmutableResponseArguments { v, .. },
+            }
+            | internal::DHTResponseSpecific::GetMutable {
+                arguments: internal::DHTGetMutableResponseArguments { v, .. },
+            },
+        ) = &msg.variant
+        {
+            if v.len() > max_value_size {
+                return Err(DecodeMessageError::ValueTooLarge(v.len()));
+            }
+        }
+
+        Message::from_serde_message(msg)
     }
 
     /// Return the Id of the sender of the Message
@@ -666,7 +799,7 @@ impl Message {
     }
 }
 
-fn bytes_to_sockaddr<T: AsRef<[u8]>>(bytes: T) -> Result<SocketAddrV4, DecodeMessageError> {
+fn bytes_to_sockaddr<T: AsRef<[u8]>>(bytes: T) -> Result<SocketAddr, DecodeMessageError> {
     let bytes = bytes.as_ref();
     match bytes.len() {
         6 => {
@@ -678,57 +811,135 @@ fn bytes_to_sockaddr<T: AsRef<[u8]>>(bytes: T) -> Result<SocketAddrV4, DecodeMes
 
             let port: u16 = u16::from_be_bytes(port_bytes_as_array);
 
-            Ok(SocketAddrV4::new(ip, port))
+            Ok(SocketAddrV4::new(ip, port).into())
+        }
+        18 => {
+            let ip_bytes_as_array: [u8; 16] = bytes[0..16]
+                .try_into()
+                .map_err(|_| DecodeMessageError::InvalidSocketAddrEncodingLength)?;
+            let ip = Ipv6Addr::from(ip_bytes_as_array);
+
+            let port_bytes_as_array: [u8; 2] = bytes[16..18]
+                .try_into()
+                .map_err(|_| DecodeMessageError::InvalidPortEncoding)?;
+
+            let port: u16 = u16::from_be_bytes(port_bytes_as_array);
+
+            Ok(SocketAddrV6::new(ip, port, 0, 0).into())
         }
-        18 => Err(DecodeMessageError::Ipv6Unsupported),
         _ => Err(DecodeMessageError::InvalidSocketAddrEncodingLength),
     }
 }
 
-pub fn sockaddr_to_bytes(sockaddr: &SocketAddrV4) -> [u8; 6] {
-    let mut bytes = [0u8; 6];
+/// Compact encoding of a socket address, 6 bytes for Ipv4 and 18 bytes for Ipv6.
+pub fn sockaddr_to_bytes(sockaddr: &SocketAddr) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(18);
 
-    bytes[0..4].copy_from_slice(&sockaddr.ip().octets());
+    match sockaddr {
+        SocketAddr::V4(sockaddr) => bytes.extend_from_slice(&sockaddr.ip().octets()),
+        SocketAddr::V6(sockaddr) => bytes.extend_from_slice(&sockaddr.ip().octets()),
+    };
 
-    bytes[4..6].copy_from_slice(&sockaddr.port().to_be_bytes());
+    bytes.extend_from_slice(&sockaddr.port().to_be_bytes());
 
     bytes
 }
 
 const NODE_BYTE_SIZE: usize = ID_SIZE + 6;
+const NODE6_BYTE_SIZE: usize = ID_SIZE + 18;
 
-fn nodes4_to_bytes(nodes: &[Node]) -> Box<[u8]> {
+/// Encode the Ipv4 nodes in compact format, ignoring any Ipv6 nodes.
+pub(crate) fn nodes4_to_bytes(nodes: &[Node]) -> Box<[u8]> {
     let mut bytes = Vec::with_capacity(NODE_BYTE_SIZE * nodes.len());
 
-    for node in nodes {
+    for node in nodes.iter().filter(|node| !node.is_ipv6()) {
         bytes.extend_from_slice(node.id().as_bytes());
-        bytes.extend_from_slice(&sockaddr_to_bytes(&node.address()));
+        bytes.extend_from_slice(&sockaddr_to_bytes(&node.socket_address()));
     }
 
     bytes.into_boxed_slice()
 }
 
-fn bytes_to_nodes4<T: AsRef<[u8]>>(bytes: T) -> Result<Box<[Node]>, DecodeMessageError> {
-    let bytes = bytes.as_ref();
+/// Encode the Ipv6 nodes in compact format, or return None if there are none.
+fn nodes6_to_bytes(nodes: &[Node]) -> Option<Box<[u8]>> {
+    let mut bytes = Vec::new();
+
+    for node in nodes.iter().filter(|node| node.is_ipv6()) {
+        bytes.extend_from_slice(node.id().as_bytes());
+        bytes.extend_from_slice(&sockaddr_to_bytes(&node.socket_address()));
+    }
+
+    if bytes.is_empty() {
+        return None;
+    }
 
-    if bytes.len() % NODE_BYTE_SIZE != 0 {
+    Some(bytes.into_boxed_slice())
+}
+
+fn bytes_to_compact_nodes(
+    bytes: &[u8],
+    node_byte_size: usize,
+) -> Result<Vec<Node>, DecodeMessageError> {
+    if bytes.len() % node_byte_size != 0 {
         return Err(DecodeMessageError::InvalidNodes4);
     }
 
-    let expected_num = bytes.len() / NODE_BYTE_SIZE;
-    let mut to_ret = Vec::with_capacity(expected_num);
-    for i in 0..bytes.len() / NODE_BYTE_SIZE {
-        let i = i * NODE_BYTE_SIZE;
+    let mut to_ret = Vec::with_capacity(bytes.len() / node_byte_size);
+    for i in 0..bytes.len() / node_byte_size {
+        let i = i * node_byte_size;
         let id = Id::from_bytes(&bytes[i..i + ID_SIZE])?;
-        let sockaddr = bytes_to_sockaddr(&bytes[i + ID_SIZE..i + NODE_BYTE_SIZE])?;
+        let sockaddr = bytes_to_sockaddr(&bytes[i + ID_SIZE..i + node_byte_size])?;
         let node = Node::new(id, sockaddr);
         to_ret.push(node);
     }
 
-    Ok(to_ret.into_boxed_slice())
+    Ok(to_ret)
+}
+
+pub(crate) fn bytes_to_nodes4<T: AsRef<[u8]>>(bytes: T) -> Result<Box<[Node]>, DecodeMessageError> {
+    Ok(bytes_to_compact_nodes(bytes.as_ref(), NODE_BYTE_SIZE)?.into_boxed_slice())
 }
 
-fn peers_to_bytes(peers: &[SocketAddrV4]) -> Vec<serde_bytes::ByteBuf> {
+fn bytes_to_nodes6<T: AsRef<[u8]>>(bytes: T) -> Result<Box<[Node]>, DecodeMessageError> {
+    bytes_to_compact_nodes(bytes.as_ref(), NODE6_BYTE_SIZE)
+        .map(|nodes| nodes.into_boxed_slice())
+        .map_err(|error| match error {
+            DecodeMessageError::InvalidNodes4 => DecodeMessageError::InvalidNodes6,
+            error => error,
+        })
+}
+
+/// Merge `nodes` and `nodes6` into one list of nodes, or None if both are missing.
+fn bytes_to_nodes(
+    nodes: Option<Box<[u8]>>,
+    nodes6: Option<Box<[u8]>>,
+) -> Result<Option<Box<[Node]>>, DecodeMessageError> {
+    match (nodes, nodes6) {
+        (None, None) => Ok(None),
+        (Some(nodes), None) => Ok(Some(bytes_to_nodes4(nodes)?)),
+        (None, Some(nodes6)) => Ok(Some(bytes_to_nodes6(nodes6)?)),
+        (Some(nodes), Some(nodes6)) => {
+            let mut merged = bytes_to_nodes4(nodes)?.into_vec();
+            merged.extend(bytes_to_nodes6(nodes6)?.into_vec());
+
+            Ok(Some(merged.into_boxed_slice()))
+        }
+    }
+}
+
+fn bloom_filter_to_bytes(filter: Option<BloomFilter>) -> Box<[u8]> {
+    filter.unwrap_or_default().as_bytes().as_slice().into()
+}
+
+fn bytes_to_bloom_filter(bytes: &[u8]) -> Result<BloomFilter, DecodeMessageError> {
+    let bytes: [u8; BLOOM_FILTER_SIZE] = bytes
+        .try_into()
+        .map_err(|_| DecodeMessageError::InvalidBloomFilter)?;
+
+    Ok(BloomFilter::from_bytes(bytes))
+}
+
+fn peers_to_bytes(peers: &[SocketAddr]) -> Vec<serde_bytes::ByteBuf> {
     peers
         .iter()
         .map(|p| serde_bytes::ByteBuf::from(sockaddr_to_bytes(p)))
@@ -737,7 +948,7 @@ fn peers_to_bytes(peers: &[SocketAddrV4]) -> Vec<serde_bytes::ByteBuf> {
 
 fn bytes_to_peers<T: AsRef<[serde_bytes::ByteBuf]>>(
     bytes: T,
-) -> Result<Vec<SocketAddrV4>, DecodeMessageError> {
+) -> Result<Vec<SocketAddr>, DecodeMessageError> {
     let bytes = bytes.as_ref();
     bytes.iter().map(bytes_to_sockaddr).collect()
 }
@@ -757,12 +968,18 @@ pub enum DecodeMessageError {
     #[error("wrong number of bytes for port")]
     InvalidPortEncoding,
 
-    #[error("IPv6 is not yet implemented")]
-    Ipv6Unsupported,
+    #[error("Wrong number of bytes for nodes6")]
+    InvalidNodes6,
 
     #[error("Wrong number of bytes for sockaddr")]
     InvalidSocketAddrEncodingLength,
 
+    #[error("Wrong number of bytes for bloom filter")]
+    InvalidBloomFilter,
+
+    #[error("Value is too large: {0} bytes")]
+    ValueTooLarge(usize),
+
     #[error("Failed to parse packet bytes: {0}")]
     BencodeError(#[from] serde_bencode::Error),
 
@@ -824,6 +1041,7 @@ mod tests {
                 requester_id: Id::random(),
                 request_type: RequestTypeSpecific::FindNode(FindNodeRequestArguments {
                     target: Id::random(),
+                    want: vec![],
                 }),
             }),
         };
@@ -835,6 +1053,33 @@ mod tests {
         assert_eq!(parsed_msg, original_msg);
     }
 
+    #[test]
+    fn test_find_node_request_want() {
+        let original_msg = Message {
+            transaction_id: 258,
+            version: None,
+            requester_ip: None,
+            read_only: false,
+            message_type: MessageType::Request(RequestSpecific {
+                requester_id: Id::random(),
+                request_type: RequestTypeSpecific::FindNode(FindNodeRequestArguments {
+                    target: Id::random(),
+                    want: vec![Want::N4, Want::N6],
+                }),
+            }),
+        };
+
+        let serde_msg = original_msg.clone().into_serde_message();
+        let bytes = serde_msg.to_bytes().unwrap();
+        assert!(bytes
+            .windows(b"4:wantl2:n42:n6e".len())
+            .any(|window| window == b"4:wantl2:n42:n6e"));
+
+        let parsed_serde_msg = internal::DHTMessage::from_bytes(&bytes).unwrap();
+        let parsed_msg = Message::from_serde_message(parsed_serde_msg).unwrap();
+        assert_eq!(parsed_msg, original_msg);
+    }
+
     #[test]
     fn test_find_node_request_read_only() {
         let original_msg = Message {
@@ -846,6 +1091,7 @@ mod tests {
                 requester_id: Id::random(),
                 request_type: RequestTypeSpecific::FindNode(FindNodeRequestArguments {
                     target: Id::random(),
+                    want: vec![],
                 }),
             }),
         };
@@ -867,8 +1113,11 @@ mod tests {
             message_type: MessageType::Response(ResponseSpecific::FindNode(
                 FindNodeResponseArguments {
                     responder_id: Id::random(),
-                    nodes: [Node::new(Id::random(), "49.50.52.52:5354".parse().unwrap()).into()]
-                        .into(),
+                    nodes: [Node::new(
+                        Id::random(),
+                        "49.50.52.52:5354".parse::<SocketAddr>().unwrap(),
+                    )]
+                    .into(),
                 },
             )),
         };
@@ -881,15 +1130,93 @@ mod tests {
         assert_eq!(
             parsed_msg.get_closer_nodes().map(|nodes| nodes
                 .iter()
-                .map(|n| (n.id(), n.address()))
+                .map(|n| (n.id(), n.socket_address()))
+                .collect::<Vec<_>>()),
+            original_msg.get_closer_nodes().map(|nodes| nodes
+                .iter()
+                .map(|n| (n.id(), n.socket_address()))
+                .collect::<Vec<_>>())
+        );
+    }
+
+    #[test]
+    fn test_find_node_response_nodes6() {
+        let original_msg = Message {
+            transaction_id: 258,
+            version: Some([1, 2, 3, 4]),
+            requester_ip: Some("[2001:db8::1]:5455".parse().unwrap()),
+            read_only: false,
+            message_type: MessageType::Response(ResponseSpecific::FindNode(
+                FindNodeResponseArguments {
+                    responder_id: Id::random(),
+                    nodes: [
+                        Node::new(
+                            Id::random(),
+                            "49.50.52.52:5354".parse::<SocketAddr>().unwrap(),
+                        ),
+                        Node::new(
+                            Id::random(),
+                            "[2001:db8::2]:5354".parse::<SocketAddr>().unwrap(),
+                        ),
+                    ]
+                    .into(),
+                },
+            )),
+        };
+
+        let serde_msg = original_msg.clone().into_serde_message();
+        let bytes = serde_msg.to_bytes().unwrap();
+        let parsed_serde_msg = internal::DHTMessage::from_bytes(&bytes).unwrap();
+        let parsed_msg = Message::from_serde_message(parsed_serde_msg).unwrap();
+        assert_eq!(parsed_msg.requester_ip, original_msg.requester_ip);
+        assert_eq!(
+            parsed_msg.get_closer_nodes().map(|nodes| nodes
+                .iter()
+                .map(|n| (n.id(), n.socket_address()))
                 .collect::<Vec<_>>()),
             original_msg.get_closer_nodes().map(|nodes| nodes
                 .iter()
-                .map(|n| (n.id(), n.address()))
+                .map(|n| (n.id(), n.socket_address()))
                 .collect::<Vec<_>>())
         );
     }
 
+    #[test]
+    fn test_find_node_response_only_nodes6() {
+        let responder_id = Id::random();
+        let node_id = Id::random();
+
+        let mut nodes6 = node_id.as_bytes().to_vec();
+        nodes6.extend_from_slice(&sockaddr_to_bytes(&"[2001:db8::2]:5354".parse().unwrap()));
+
+        let serde_message = internal::DHTMessage {
+            ip: None,
+            read_only: None,
+            transaction_id: [1, 2],
+            version: None,
+            variant: internal::DHTMessageVariant::Response(
+                internal::DHTResponseSpecific::FindNode6 {
+                    arguments: internal::DHTFindNode6ResponseArguments {
+                        id: responder_id.into(),
+                        nodes6: nodes6.into(),
+                    },
+                },
+            ),
+        };
+        let bytes = serde_message.to_bytes().unwrap();
+        let parsed_serde_message = internal::DHTMessage::from_bytes(&bytes).unwrap();
+        let parsed_message = Message::from_serde_message(parsed_serde_message).unwrap();
+
+        assert_eq!(parsed_message.get_author_id(), Some(responder_id));
+        assert_eq!(
+            parsed_message.get_closer_nodes().map(|nodes| nodes
+                .iter()
+                .map(|n| (*n.id(), n.socket_address()))
+                .collect::<Vec<_>>()),
+            Some(vec![(node_id, "[2001:db8::2]:5354".parse().unwrap())])
+        );
+    }
+
     #[test]
     fn test_get_peers_request() {
         let original_msg = Message {
@@ -901,6 +1228,9 @@ mod tests {
                 requester_id: Id::random(),
                 request_type: RequestTypeSpecific::GetPeers(GetPeersRequestArguments {
                     info_hash: Id::random(),
+                    scrape: false,
+                    noseed: false,
+                    want: vec![],
                 }),
             }),
         };
@@ -924,8 +1254,11 @@ mod tests {
                     responder_id: Id::random(),
                     token: [99, 100, 101, 102].into(),
                     nodes: Some(
-                        [Node::new(Id::random(), "49.50.52.52:5354".parse().unwrap()).into()]
-                            .into(),
+                        [Node::new(
+                            Id::random(),
+                            "49.50.52.52:5354".parse::<SocketAddr>().unwrap(),
+                        )]
+                        .into(),
                     ),
                 },
             )),
@@ -943,11 +1276,11 @@ mod tests {
         assert_eq!(
             parsed_msg.get_closer_nodes().map(|nodes| nodes
                 .iter()
-                .map(|n| (n.id(), n.address()))
+                .map(|n| (n.id(), n.socket_address()))
                 .collect::<Vec<_>>()),
             original_msg.get_closer_nodes().map(|nodes| nodes
                 .iter()
-                .map(|n| (n.id(), n.address()))
+                .map(|n| (n.id(), n.socket_address()))
                 .collect::<Vec<_>>())
         );
     }
@@ -965,6 +1298,127 @@ mod tests {
                     token: vec![99, 100, 101, 102].into(),
                     nodes: None,
                     values: ["123.123.123.123:123".parse().unwrap()].into(),
+                    seeders: None,
+                    peers: None,
+                },
+            )),
+        };
+
+        let serde_msg = original_msg.clone().into_serde_message();
+        let bytes = serde_msg.to_bytes().unwrap();
+        let parsed_serde_msg = internal::DHTMessage::from_bytes(&bytes).unwrap();
+        let parsed_msg = Message::from_serde_message(parsed_serde_msg).unwrap();
+        assert_eq!(parsed_msg, original_msg);
+    }
+
+    #[test]
+    fn test_get_peers_response_ipv6_peers() {
+        let original_msg = Message {
+            transaction_id: 3,
+            version: Some([1, 2, 3, 4]),
+            requester_ip: Some("[2001:db8::1]:5455".parse().unwrap()),
+            read_only: false,
+            message_type: MessageType::Response(ResponseSpecific::GetPeers(
+                GetPeersResponseArguments {
+                    responder_id: Id::random(),
+                    token: vec![99, 100, 101, 102].into(),
+                    nodes: None,
+                    values: [
+                        "123.123.123.123:123".parse().unwrap(),
+                        "[2001:db8::3]:123".parse().unwrap(),
+                    ]
+                    .into(),
+                    seeders: None,
+                    peers: None,
+                },
+            )),
+        };
+
+        let serde_msg = original_msg.clone().into_serde_message();
+        let bytes = serde_msg.to_bytes().unwrap();
+        let parsed_serde_msg = internal::DHTMessage::from_bytes(&bytes).unwrap();
+        let parsed_msg = Message::from_serde_message(parsed_serde_msg).unwrap();
+        assert_eq!(parsed_msg, original_msg);
+    }
+
+    #[test]
+    fn test_value_too_large() {
+        let message = |v: &[u8]| Message {
+            transaction_id: 3,
+            version: None,
+            requester_ip: None,
+            read_only: false,
+            message_type: MessageType::Response(ResponseSpecific::GetMutable(
+                GetMutableResponseArguments {
+                    responder_id: Id::random(),
+                    token: [99, 100, 101, 102].into(),
+                    nodes: None,
+                    v: v.into(),
+                    k: [0; 32],
+                    seq: 1,
+                    sig: [0; 64],
+                },
+            )),
+        };
+
+        let bytes = message(&[0; 2000]).to_bytes().unwrap();
+
+        assert!(matches!(
+            Message::from_bytes(&bytes, 1000),
+            Err(DecodeMessageError::ValueTooLarge(2000))
+        ));
+        assert!(Message::from_bytes(&bytes, 2000).is_ok());
+
+        let bytes = message(&[0; 1000]).to_bytes().unwrap();
+        assert!(Message::from_bytes(&bytes, 1000).is_ok());
+    }
+
+    #[test]
+    fn test_get_peers_scrape_request() {
+        let original_msg = Message {
+            transaction_id: 258,
+            version: None,
+            requester_ip: None,
+            read_only: false,
+            message_type: MessageType::Request(RequestSpecific {
+                requester_id: Id::random(),
+                request_type: RequestTypeSpecific::GetPeers(GetPeersRequestArguments {
+                    info_hash: Id::random(),
+                    scrape: true,
+                    noseed: false,
+                    want: vec![],
+                }),
+            }),
+        };
+
+        let serde_msg = original_msg.clone().into_serde_message();
+        let bytes = serde_msg.to_bytes().unwrap();
+        let parsed_serde_msg = internal::DHTMessage::from_bytes(&bytes).unwrap();
+        let parsed_msg = Message::from_serde_message(parsed_serde_msg).unwrap();
+        assert_eq!(parsed_msg, original_msg);
+    }
+
+    #[test]
+    fn test_get_peers_scrape_response() {
+        let mut seeders = BloomFilter::new();
+        seeders.insert(&"123.123.123.123".parse().unwrap());
+        let mut peers = seeders.clone();
+        peers.insert(&"2001:db8::3".parse().unwrap());
+
+        // Scrape responses don't have to include values.
+        let original_msg = Message {
+            transaction_id: 3,
+            version: None,
+            requester_ip: None,
+            read_only: false,
+            message_type: MessageType::Response(ResponseSpecific::GetPeers(
+                GetPeersResponseArguments {
+                    responder_id: Id::random(),
+                    token: vec![99, 100, 101, 102].into(),
+                    nodes: None,
+                    values: vec![],
+                    seeders: Some(seeders),
+                    peers: Some(peers),
                 },
             )),
         };
@@ -989,6 +1443,7 @@ mod tests {
                         id: Id::random().into(),
                         token: vec![0, 1].into(),
                         nodes: None,
+                        nodes6: None,
                     },
                 },
             ),
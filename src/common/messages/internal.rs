@@ -17,8 +17,8 @@ pub struct DHTMessage {
 
     #[serde(default)]
     #[serde(with = "serde_bytes")]
-    // Ipv6 is not supported anyways.
-    pub ip: Option<[u8; 6]>,
+    // 6 bytes for Ipv4 and 18 bytes for Ipv6.
+    pub ip: Option<Box<[u8]>>,
 
     #[serde(default)]
     #[serde(rename = "ro")]
@@ -107,6 +107,11 @@ pub enum DHTResponseSpecific {
         arguments: DHTGetImmutableResponseArguments,
     },
 
+    GetPeersScrape {
+        #[serde(rename = "r")]
+        arguments: DHTGetPeersScrapeResponseArguments,
+    },
+
     GetPeers {
         #[serde(rename = "r")]
         arguments: DHTGetPeersResponseArguments,
@@ -122,6 +127,11 @@ pub enum DHTResponseSpecific {
         arguments: DHTFindNodeResponseArguments,
     },
 
+    FindNode6 {
+        #[serde(rename = "r")]
+        arguments: DHTFindNode6ResponseArguments,
+    },
+
     Ping {
         #[serde(rename = "r")]
         arguments: DHTPingResponseArguments,
@@ -134,13 +144,6 @@ pub struct DHTErrorSpecific {
     pub error_info: (i32, String),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub enum DHTErrorValue {
-    #[serde(rename = "")]
-    ErrorCode(i32),
-    ErrorDescription(String),
-}
-
 // === PING ===
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -164,6 +167,10 @@ pub struct DHTFindNodeRequestArguments {
 
     #[serde(with = "serde_bytes")]
     pub target: [u8; 20],
+
+    // [BEP_0032](https://www.bittorrent.org/beps/bep_0032.html)
+    #[serde(default)]
+    pub want: Option<Vec<ByteBuf>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -173,6 +180,21 @@ pub struct DHTFindNodeResponseArguments {
 
     #[serde(with = "serde_bytes")]
     pub nodes: Box<[u8]>,
+
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub nodes6: Option<Box<[u8]>>,
+}
+
+/// [BEP_0032](https://www.bittorrent.org/beps/bep_0032.html) find_node response
+/// that only contains `nodes6`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DHTFindNode6ResponseArguments {
+    #[serde(with = "serde_bytes")]
+    pub id: [u8; 20],
+
+    #[serde(with = "serde_bytes")]
+    pub nodes6: Box<[u8]>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -186,6 +208,10 @@ pub struct DHTNoValuesResponseArguments {
     #[serde(with = "serde_bytes")]
     #[serde(default)]
     pub nodes: Option<Box<[u8]>>,
+
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub nodes6: Option<Box<[u8]>>,
 }
 
 // === Get Peers ===
@@ -197,6 +223,17 @@ pub struct DHTGetPeersRequestArguments {
 
     #[serde(with = "serde_bytes")]
     pub info_hash: [u8; 20],
+
+    // [BEP_0033](https://www.bittorrent.org/beps/bep_0033.html)
+    #[serde(default)]
+    pub scrape: Option<i32>,
+
+    #[serde(default)]
+    pub noseed: Option<i32>,
+
+    // [BEP_0032](https://www.bittorrent.org/beps/bep_0032.html)
+    #[serde(default)]
+    pub want: Option<Vec<ByteBuf>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -211,11 +248,43 @@ pub struct DHTGetPeersResponseArguments {
     #[serde(default)]
     pub nodes: Option<Box<[u8]>>,
 
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub nodes6: Option<Box<[u8]>>,
+
     // values are not optional, because if they are missing this missing
     // we can just treat this as DHTNoValuesResponseArguments
     pub values: Vec<ByteBuf>,
 }
 
+/// [BEP_0033](https://www.bittorrent.org/beps/bep_0033.html) get_peers response
+/// with seeders and peers bloom filters, and optional values.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DHTGetPeersScrapeResponseArguments {
+    #[serde(with = "serde_bytes")]
+    pub id: [u8; 20],
+
+    #[serde(with = "serde_bytes")]
+    pub token: Box<[u8]>,
+
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub nodes: Option<Box<[u8]>>,
+
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub nodes6: Option<Box<[u8]>>,
+
+    #[serde(default)]
+    pub values: Vec<ByteBuf>,
+
+    #[serde(rename = "BFsd", with = "serde_bytes")]
+    pub seeders: Box<[u8]>,
+
+    #[serde(rename = "BFpe", with = "serde_bytes")]
+    pub peers: Box<[u8]>,
+}
+
 // === Announce Peer ===
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -233,6 +302,9 @@ pub struct DHTAnnouncePeerRequestArguments {
 
     #[serde(default)]
     pub implied_port: Option<u8>,
+
+    #[serde(default)]
+    pub seed: Option<i32>,
 }
 
 // === Get Value ===
@@ -261,6 +333,10 @@ pub struct DHTGetImmutableResponseArguments {
     #[serde(default)]
     pub nodes: Option<Box<[u8]>>,
 
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub nodes6: Option<Box<[u8]>>,
+
     #[serde(with = "serde_bytes")]
     pub v: Box<[u8]>,
 }
@@ -277,6 +353,10 @@ pub struct DHTNoMoreRecentValueResponseArguments {
     #[serde(default)]
     pub nodes: Option<Box<[u8]>>,
 
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub nodes6: Option<Box<[u8]>>,
+
     pub seq: i64,
 }
 
@@ -292,6 +372,10 @@ pub struct DHTGetMutableResponseArguments {
     #[serde(default)]
     pub nodes: Option<Box<[u8]>>,
 
+    #[serde(with = "serde_bytes")]
+    #[serde(default)]
+    pub nodes6: Option<Box<[u8]>>,
+
     #[serde(with = "serde_bytes")]
     pub v: Box<[u8]>,
 
@@ -9,6 +9,12 @@ use crate::Id;
 
 use super::PutMutableRequestArguments;
 
+/// Maximum size of a [MutableItem]'s salt in bytes, as specified in [BEP_0044](https://www.bittorrent.org/beps/bep_0044.html).
+pub const MAX_SALT_SIZE: usize = 64;
+
+/// Size of the key, seq, signature and salt length in [MutableItem::to_bytes].
+const ENCODED_HEADER_SIZE: usize = 32 + 8 + 64 + 1;
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 /// [BEP_0044](https://www.bittorrent.org/beps/bep_0044.html)'s Mutable item.
 pub struct MutableItem {
@@ -28,18 +34,55 @@ pub struct MutableItem {
 }
 
 impl MutableItem {
+    /// Create a [MutableItemBuilder] to set the value, seq, salt and cas of an item fluently.
+    pub fn builder() -> MutableItemBuilder {
+        MutableItemBuilder::default()
+    }
+
     /// Create a new mutable item from a signing key, value, sequence number and optional salt.
     pub fn new(signer: SigningKey, value: &[u8], seq: i64, salt: Option<&[u8]>) -> Self {
+        Self::new_with(&signer, value, seq, salt)
+    }
+
+    /// Create a new mutable item from a value, sequence number and optional salt,
+    /// delegating the signing step to a custom [MutableSigner].
+    ///
+    /// # Interoperability
+    ///
+    /// [BEP_0044](https://www.bittorrent.org/beps/bep_0044.html) only allows plain Ed25519
+    /// signatures over the signable encoding of the item.
+    ///
+    /// Any other signing scheme (for example Ed25519ph over a prehashed digest) will
+    /// produce items that every other node rejects as invalid, so they should only
+    /// ever be used in private networks for experimentation, and never published
+    /// to the public mainline network.
+    pub fn new_with<S: MutableSigner + ?Sized>(
+        signer: &S,
+        value: &[u8],
+        seq: i64,
+        salt: Option<&[u8]>,
+    ) -> Self {
         let signable = encode_signable(seq, value, salt);
-        let signature = signer.sign(&signable);
+        let signature = signer.sign_signable(&signable);
 
-        Self::new_signed_unchecked(
-            signer.verifying_key().to_bytes(),
-            signature.into(),
-            value,
-            seq,
-            salt,
-        )
+        Self::new_signed_unchecked(signer.public_key(), signature, value, seq, salt)
+    }
+
+    /// Create a new mutable item from a public key, value, sequence number and optional salt,
+    /// passing the signable encoding of the item to `sign` and using the returned signature.
+    ///
+    /// Useful for keys that never leave a hardware device, without implementing [MutableSigner].
+    /// The same [interoperability](Self::new_with#interoperability) rules apply.
+    pub fn new_with_signer(
+        public_key: [u8; 32],
+        value: &[u8],
+        seq: i64,
+        salt: Option<&[u8]>,
+        sign: impl FnOnce(&[u8]) -> [u8; 64],
+    ) -> Self {
+        let signature = sign(&encode_signable(seq, value, salt));
+
+        Self::new_signed_unchecked(public_key, signature, value, seq, salt)
     }
 
     /// Return the target of a [MutableItem] by hashing its `public_key` and an optional `salt`
@@ -135,8 +178,180 @@ impl MutableItem {
     pub fn salt(&self) -> Option<&[u8]> {
         self.salt.as_deref()
     }
+
+    // === Public Methods ===
+
+    /// Returns `true` if this item should replace the `other` item for the same target.
+    ///
+    /// An item is more recent if it has a higher `seq`, and ties are broken by comparing
+    /// the values lexicographically, so that all readers agree on the same item
+    /// instead of oscillating between two values with the same `seq`.
+    pub fn is_more_recent_than(&self, other: &MutableItem) -> bool {
+        (self.seq, &self.value) > (other.seq, &other.value)
+    }
+
+    /// Encode this item in a compact binary layout, for persistence or passing
+    /// between services without depending on the stability of a serde format.
+    ///
+    /// The layout is `key (32) || seq (8, big endian) || signature (64) ||
+    /// salt length (1) || salt || value`, where a zero salt length means no salt.
+    ///
+    /// Decode with [Self::from_bytes].
+    ///
+    /// Returns [MutableError::SaltTooLong] if the salt exceeds [MAX_SALT_SIZE],
+    /// as its length wouldn't fit in the one byte length prefix.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MutableError> {
+        let salt = self.salt.as_deref().unwrap_or_default();
+
+        if salt.len() > MAX_SALT_SIZE {
+            return Err(MutableError::SaltTooLong(salt.len()));
+        }
+
+        let mut bytes = Vec::with_capacity(ENCODED_HEADER_SIZE + salt.len() + self.value.len());
+
+        bytes.extend(self.key);
+        bytes.extend(self.seq.to_be_bytes());
+        bytes.extend(self.signature);
+        bytes.push(salt.len() as u8);
+        bytes.extend(salt);
+        bytes.extend(&self.value);
+
+        Ok(bytes)
+    }
+
+    /// Decode an item encoded with [Self::to_bytes], verifying its signature.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MutableError> {
+        if bytes.len() < ENCODED_HEADER_SIZE {
+            return Err(MutableError::InvalidMutableEncoding);
+        }
+
+        let (key, rest) = bytes.split_at(32);
+        let (seq, rest) = rest.split_at(8);
+        let (signature, rest) = rest.split_at(64);
+        let (salt_len, rest) = (rest[0] as usize, &rest[1..]);
+
+        if salt_len > MAX_SALT_SIZE {
+            return Err(MutableError::SaltTooLong(salt_len));
+        }
+        if rest.len() < salt_len {
+            return Err(MutableError::InvalidMutableEncoding);
+        }
+
+        let (salt, value) = rest.split_at(salt_len);
+        let salt = (!salt.is_empty()).then_some(salt);
+
+        let key: [u8; 32] = key.try_into().expect("infallible");
+        let seq = i64::from_be_bytes(seq.try_into().expect("infallible"));
+
+        Self::from_dht_message(
+            MutableItem::target_from_key(&key, salt),
+            &key,
+            value.into(),
+            seq,
+            signature,
+            salt.map(|salt| salt.into()),
+        )
+    }
 }
 
+#[derive(Debug, Default, Clone)]
+/// Builder of a signed [MutableItem], see [MutableItem::builder].
+pub struct MutableItemBuilder {
+    value: Box<[u8]>,
+    seq: i64,
+    salt: Option<Box<[u8]>>,
+    cas: Option<i64>,
+}
+
+impl MutableItemBuilder {
+    /// Set the value of the item.
+    ///
+    /// Defaults to an empty value.
+    pub fn value(&mut self, value: &[u8]) -> &mut Self {
+        self.value = value.into();
+
+        self
+    }
+
+    /// Set the sequence number of the item.
+    ///
+    /// Defaults to 0.
+    pub fn seq(&mut self, seq: i64) -> &mut Self {
+        self.seq = seq;
+
+        self
+    }
+
+    /// Set the salt of the item, which changes its [target](MutableItem::target).
+    ///
+    /// Must be at most [MAX_SALT_SIZE] bytes.
+    pub fn salt(&mut self, salt: &[u8]) -> &mut Self {
+        self.salt = Some(salt.into());
+
+        self
+    }
+
+    /// Set the `seq` of the most recent known item, that the put request
+    /// should only overwrite, see [Self::sign_put_request].
+    pub fn cas(&mut self, cas: i64) -> &mut Self {
+        self.cas = Some(cas);
+
+        self
+    }
+
+    /// Sign and return the [MutableItem].
+    ///
+    /// Returns [MutableError::SaltTooLong] if the salt exceeds [MAX_SALT_SIZE].
+    pub fn sign<S: MutableSigner + ?Sized>(&self, signer: &S) -> Result<MutableItem, MutableError> {
+        if let Some(salt) = &self.salt {
+            if salt.len() > MAX_SALT_SIZE {
+                return Err(MutableError::SaltTooLong(salt.len()));
+            }
+        }
+
+        Ok(MutableItem::new_with(
+            signer,
+            &self.value,
+            self.seq,
+            self.salt.as_deref(),
+        ))
+    }
+
+    /// Sign the [MutableItem] and return it as [PutMutableRequestArguments]
+    /// with the [cas](Self::cas) condition if any.
+    pub fn sign_put_request<S: MutableSigner + ?Sized>(
+        &self,
+        signer: &S,
+    ) -> Result<PutMutableRequestArguments, MutableError> {
+        Ok(PutMutableRequestArguments::from(
+            self.sign(signer)?,
+            self.cas,
+        ))
+    }
+}
+
+/// The signing step of a [MutableItem], see [MutableItem::new_with].
+pub trait MutableSigner {
+    /// Returns the 32 bytes Ed25519 public key that signatures should be verified against.
+    fn public_key(&self) -> [u8; 32];
+
+    /// Sign the signable encoding of a [MutableItem],
+    /// and return the 64 bytes signature.
+    fn sign_signable(&self, signable: &[u8]) -> [u8; 64];
+}
+
+impl MutableSigner for SigningKey {
+    fn public_key(&self) -> [u8; 32] {
+        self.verifying_key().to_bytes()
+    }
+
+    fn sign_signable(&self, signable: &[u8]) -> [u8; 64] {
+        self.sign(signable).into()
+    }
+}
+
+/// Encode the `seq`, `value` and optional `salt` of a [MutableItem] as
+/// the bytes that get signed according to [BEP_0044](https://www.bittorrent.org/beps/bep_0044.html).
 pub fn encode_signable(seq: i64, value: &[u8], salt: Option<&[u8]>) -> Box<[u8]> {
     let mut signable = vec![];
 
@@ -151,7 +366,7 @@ pub fn encode_signable(seq: i64, value: &[u8], salt: Option<&[u8]>) -> Box<[u8]>
     signable.into()
 }
 
-#[derive(thiserror::Error, Debug)]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
 /// Mainline crate error enum.
 pub enum MutableError {
     #[error("Invalid mutable item signature")]
@@ -161,6 +376,14 @@ pub enum MutableError {
     #[error("Invalid mutable item public key")]
     /// Invalid mutable item public key
     InvalidMutablePublicKey,
+
+    #[error("Mutable item salt is {0} bytes, more than the maximum {MAX_SALT_SIZE} bytes")]
+    /// Mutable item salt is longer than [MAX_SALT_SIZE]
+    SaltTooLong(usize),
+
+    #[error("Invalid mutable item encoding")]
+    /// Bytes passed to [MutableItem::from_bytes] are too short to be a [MutableItem]
+    InvalidMutableEncoding,
 }
 
 impl PutMutableRequestArguments {
@@ -209,4 +432,180 @@ mod tests {
 
         assert_eq!(&*signable, b"4:salt6:foobar3:seqi4e1:v12:Hello world!");
     }
+
+    #[test]
+    fn most_recent() {
+        let signer = SigningKey::from_bytes(&[0; 32]);
+
+        let items = [
+            MutableItem::new(signer.clone(), b"b", 1, None),
+            MutableItem::new(signer.clone(), b"a", 5, None),
+            MutableItem::new(signer.clone(), b"c", 5, None),
+        ];
+
+        for order in [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ] {
+            let most_recent = order
+                .iter()
+                .map(|i| &items[*i])
+                .reduce(|most_recent, item| {
+                    if item.is_more_recent_than(most_recent) {
+                        item
+                    } else {
+                        most_recent
+                    }
+                })
+                .unwrap();
+
+            assert_eq!(most_recent, &items[2]);
+        }
+    }
+
+    #[test]
+    fn custom_signer() {
+        struct Wrapped(SigningKey);
+
+        impl MutableSigner for Wrapped {
+            fn public_key(&self) -> [u8; 32] {
+                self.0.verifying_key().to_bytes()
+            }
+
+            fn sign_signable(&self, signable: &[u8]) -> [u8; 64] {
+                self.0.sign(signable).to_bytes()
+            }
+        }
+
+        let signer = SigningKey::from_bytes(&[0; 32]);
+
+        let item = MutableItem::new_with(&Wrapped(signer.clone()), b"Hello world!", 4, None);
+
+        assert_eq!(item, MutableItem::new(signer, b"Hello world!", 4, None));
+        assert!(MutableItem::from_dht_message(
+            *item.target(),
+            item.key(),
+            item.value().into(),
+            item.seq(),
+            item.signature(),
+            None
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn closure_signer() {
+        let signer = SigningKey::from_bytes(&[0; 32]);
+        let salt = b"salt".as_slice();
+
+        let item = MutableItem::new_with_signer(
+            signer.verifying_key().to_bytes(),
+            b"Hello world!",
+            4,
+            Some(salt),
+            |signable| signer.sign(signable).to_bytes(),
+        );
+
+        assert_eq!(
+            item,
+            MutableItem::new(signer.clone(), b"Hello world!", 4, Some(salt))
+        );
+        assert!(MutableItem::from_dht_message(
+            *item.target(),
+            item.key(),
+            item.value().into(),
+            item.seq(),
+            item.signature(),
+            Some(salt.into())
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn builder_salt_size() {
+        let signer = SigningKey::from_bytes(&[0; 32]);
+
+        assert!(MutableItem::builder()
+            .salt(&[0; MAX_SALT_SIZE])
+            .sign(&signer)
+            .is_ok());
+        assert!(matches!(
+            MutableItem::builder()
+                .salt(&[0; MAX_SALT_SIZE + 1])
+                .sign(&signer),
+            Err(MutableError::SaltTooLong(65))
+        ));
+    }
+
+    #[test]
+    fn builder_target() {
+        let signer = SigningKey::from_bytes(&[0; 32]);
+        let public_key = signer.verifying_key().to_bytes();
+
+        let item = MutableItem::builder()
+            .value(b"Hello world!")
+            .seq(4)
+            .sign(&signer)
+            .unwrap();
+
+        assert_eq!(
+            item,
+            MutableItem::new(signer.clone(), b"Hello world!", 4, None)
+        );
+        assert_eq!(
+            item.target(),
+            &MutableItem::target_from_key(&public_key, None)
+        );
+
+        let request = MutableItem::builder()
+            .value(b"Hello world!")
+            .seq(4)
+            .salt(b"foobar")
+            .cas(3)
+            .sign_put_request(&signer)
+            .unwrap();
+
+        assert_eq!(
+            request.target,
+            MutableItem::target_from_key(&public_key, Some(b"foobar"))
+        );
+        assert_ne!(request.target, *item.target());
+        assert_eq!(request.cas, Some(3));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let signer = SigningKey::from_bytes(&[0; 32]);
+
+        for salt in [None, Some(b"foobar".as_slice())] {
+            let item = MutableItem::new(signer.clone(), b"Hello world!", 4, salt);
+
+            let bytes = item.to_bytes().unwrap();
+            assert_eq!(MutableItem::from_bytes(&bytes).unwrap(), item);
+
+            let mut tampered = bytes.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            assert!(matches!(
+                MutableItem::from_bytes(&tampered),
+                Err(MutableError::InvalidMutableSignature)
+            ));
+
+            assert!(matches!(
+                MutableItem::from_bytes(&bytes[..ENCODED_HEADER_SIZE - 1]),
+                Err(MutableError::InvalidMutableEncoding)
+            ));
+        }
+
+        let salt = [0; MAX_SALT_SIZE + 256];
+        let item = MutableItem::new(signer, b"Hello world!", 4, Some(&salt));
+
+        assert_eq!(
+            item.to_bytes(),
+            Err(MutableError::SaltTooLong(MAX_SALT_SIZE + 256))
+        );
+    }
 }
@@ -1,7 +1,7 @@
 //! Struct and implementation of the Node entry in the Kademlia routing table
 use std::{
     fmt::{self, Debug, Formatter},
-    net::SocketAddrV4,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -17,7 +17,7 @@ pub const TOKEN_ROTATE_INTERVAL: Duration = Duration::from_secs(60 * 5);
 #[derive(PartialEq)]
 pub(crate) struct NodeInner {
     pub(crate) id: Id,
-    pub(crate) address: SocketAddrV4,
+    pub(crate) address: SocketAddr,
     pub(crate) token: Option<Box<[u8]>>,
     pub(crate) last_seen: Instant,
 }
@@ -26,7 +26,7 @@ impl NodeInner {
     pub fn random() -> Self {
         Self {
             id: Id::random(),
-            address: SocketAddrV4::new(0.into(), 0),
+            address: SocketAddrV4::new(0.into(), 0).into(),
             token: None,
             last_seen: Instant::now(),
         }
@@ -49,21 +49,36 @@ impl Debug for Node {
 
 impl Node {
     /// Creates a new Node from an id and socket address.
-    pub fn new(id: Id, address: SocketAddrV4) -> Node {
+    pub fn new(id: Id, address: impl Into<SocketAddr>) -> Node {
         Node(Arc::new(NodeInner {
             id,
-            address,
+            address: address.into(),
             token: None,
             last_seen: Instant::now(),
         }))
     }
 
-    pub(crate) fn new_with_token(id: Id, address: SocketAddrV4, token: Box<[u8]>) -> Self {
+    /// Creates a new Node from an id and socket address, last seen at `last_seen`.
+    pub(crate) fn seen_at(id: Id, address: impl Into<SocketAddr>, last_seen: Instant) -> Node {
+        Node(Arc::new(NodeInner {
+            id,
+            address: address.into(),
+            token: None,
+            last_seen,
+        }))
+    }
+
+    pub(crate) fn new_with_token(
+        id: Id,
+        address: SocketAddr,
+        token: Box<[u8]>,
+        last_seen: Instant,
+    ) -> Self {
         Node(Arc::new(NodeInner {
             id,
             address,
             token: Some(token),
-            last_seen: Instant::now(),
+            last_seen,
         }))
     }
 
@@ -85,28 +100,75 @@ impl Node {
         &self.0.id
     }
 
-    /// Returns the address of this node
+    /// Returns the Ipv4 address of this node.
+    ///
+    /// Ipv6 nodes, only found if [crate::DhtBuilder::ipv6] is enabled, return the
+    /// unspecified address `0.0.0.0` with their port, see [Self::address6].
     pub fn address(&self) -> SocketAddrV4 {
+        match self.0.address {
+            SocketAddr::V4(address) => address,
+            SocketAddr::V6(address) => SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, address.port()),
+        }
+    }
+
+    /// Returns the Ipv6 address of this node, if it is an Ipv6 node.
+    pub fn address6(&self) -> Option<SocketAddrV6> {
+        match self.0.address {
+            SocketAddr::V4(_) => None,
+            SocketAddr::V6(address) => Some(address),
+        }
+    }
+
+    /// Returns the address of this node, whether Ipv4 or Ipv6.
+    pub fn socket_address(&self) -> SocketAddr {
         self.0.address
     }
 
+    /// Returns true if this node has an Ipv6 address.
+    pub fn is_ipv6(&self) -> bool {
+        self.0.address.is_ipv6()
+    }
+
     /// Returns the token we received from this node if any.
     pub fn token(&self) -> Option<Box<[u8]>> {
         self.0.token.clone()
     }
 
+    /// Returns the time this node was last seen.
+    pub fn last_seen(&self) -> Instant {
+        self.0.last_seen
+    }
+
+    /// Returns the time we received this node's [Self::token], if any.
+    ///
+    /// Tokens older than 5 minutes are likely rejected by the node,
+    /// see [Self::valid_token].
+    pub fn token_received_at(&self) -> Option<Instant> {
+        self.0.token.as_ref().map(|_| self.0.last_seen)
+    }
+
     /// Node is last seen more than a threshold ago.
     pub fn is_stale(&self) -> bool {
-        self.0.last_seen.elapsed() > STALE_TIME
+        self.is_stale_at(Instant::now())
+    }
+
+    /// Node is last seen more than a threshold before `now`.
+    pub(crate) fn is_stale_at(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.0.last_seen) > STALE_TIME
     }
 
     /// Node's token was received 5 minutes ago or less
     pub fn valid_token(&self) -> bool {
-        self.0.last_seen.elapsed() <= TOKEN_ROTATE_INTERVAL
+        self.valid_token_at(Instant::now())
+    }
+
+    /// Node's token was received 5 minutes or less before `now`
+    pub(crate) fn valid_token_at(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.0.last_seen) <= TOKEN_ROTATE_INTERVAL
     }
 
-    pub(crate) fn should_ping(&self) -> bool {
-        self.0.last_seen.elapsed() > MIN_PING_BACKOFF_INTERVAL
+    pub(crate) fn should_ping(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.0.last_seen) > MIN_PING_BACKOFF_INTERVAL
     }
 
     /// Returns true if both nodes have the same ip and port
@@ -123,13 +185,13 @@ impl Node {
     ///
     /// Check [BEP_0042](https://www.bittorrent.org/beps/bep_0042.html).
     pub fn is_secure(&self) -> bool {
-        self.0.id.is_valid_for_ip(*self.0.address.ip())
+        self.0.id.is_valid_for_ip_addr(self.0.address.ip())
     }
 
     /// Returns true if Any of the existing nodes:
     ///  - Have the same IP as this node, And:
-    ///     = The existing nodes is Not secure.
-    ///     = The existing nodes is secure And shares the same first 21 bits.
+    ///    = The existing nodes is Not secure.
+    ///    = The existing nodes is secure And shares the same first 21 bits.
     ///
     /// Effectively, allows only One non-secure node or Eight secure nodes from the same IP, in the routing table or ClosestNodes.
     pub(crate) fn already_exists(&self, nodes: &[Self]) -> bool {
@@ -140,3 +202,46 @@ impl Node {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn token_received_at() {
+        let node = Node::random();
+        assert!(node.token().is_none());
+        assert!(node.token_received_at().is_none());
+
+        let node = Node::new_with_token(
+            Id::random(),
+            SocketAddrV4::new(0.into(), 0).into(),
+            [1; 4].into(),
+            Instant::now(),
+        );
+
+        assert_eq!(node.token(), Some([1; 4].into()));
+        assert_eq!(node.token_received_at(), Some(node.last_seen()));
+        assert!(node.valid_token_at(node.last_seen()));
+        assert!(!node.valid_token_at(node.last_seen() + TOKEN_ROTATE_INTERVAL * 2));
+    }
+
+    #[test]
+    fn address_families() {
+        let address: SocketAddrV4 = "1.2.3.4:6881".parse().unwrap();
+        let node = Node::new(Id::random(), address);
+
+        assert_eq!(node.address(), address);
+        assert_eq!(node.address6(), None);
+
+        let address6: SocketAddrV6 = "[2001:db8::1]:6881".parse().unwrap();
+        let node = Node::new(Id::random(), address6);
+
+        assert_eq!(
+            node.address(),
+            SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 6881)
+        );
+        assert_eq!(node.address6(), Some(address6));
+        assert_eq!(node.socket_address(), SocketAddr::V6(address6));
+    }
+}
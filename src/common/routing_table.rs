@@ -2,8 +2,9 @@
 
 use std::collections::BTreeMap;
 use std::slice::Iter;
+use std::time::Instant;
 
-use crate::common::{Id, Node};
+use crate::common::{Id, Node, MAX_DISTANCE};
 use crate::rpc::ClosestNodes;
 
 /// K = the default maximum size of a k-bucket.
@@ -24,6 +25,42 @@ impl RoutingTable {
         RoutingTable { id, buckets }
     }
 
+    /// Create a [RoutingTable] pre-populated with nodes, placing each node directly
+    /// in the bucket at its distance from `id`, for example to build test fixtures.
+    ///
+    /// Unlike [Self::add], no node is ever replaced, so the result is deterministic:
+    /// nodes that [Self::add] would reject as duplicates, and nodes beyond
+    /// [MAX_BUCKET_SIZE_K] in a bucket, are dropped in favor of earlier nodes.
+    pub fn from_nodes(id: Id, nodes: Vec<Node>) -> Self {
+        let mut table = Self::new(id);
+
+        for node in nodes {
+            let distance = id.distance(node.id());
+
+            if distance == 0 {
+                continue;
+            }
+
+            if table
+                .buckets
+                .values()
+                .any(|bucket| node.already_exists(&bucket.nodes))
+            {
+                continue;
+            }
+
+            let bucket = table.buckets.entry(distance).or_default();
+
+            if bucket.nodes.len() < MAX_BUCKET_SIZE_K
+                && !bucket.iter().any(|existing| existing.id() == node.id())
+            {
+                bucket.nodes.push(node);
+            }
+        }
+
+        table
+    }
+
     /// Returns the [Id] of this node, where the distance is measured from.
     pub fn id(&self) -> &Id {
         &self.id
@@ -111,8 +148,40 @@ impl RoutingTable {
             .fold(0, |acc, bucket| acc + bucket.nodes.len())
     }
 
+    /// Return the number of nodes in each bucket, indexed by their [Id::distance]
+    /// from this table's [Id], from 0 (this node, always empty) to 160.
+    pub fn bucket_sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; MAX_DISTANCE as usize + 1];
+
+        for (distance, bucket) in &self.buckets {
+            sizes[*distance as usize] = bucket.nodes.len();
+        }
+
+        sizes
+    }
+
+    /// Returns the distances of the buckets that aren't full, from the furthest bucket
+    /// to the closest non-empty one, beyond which buckets are expected to be empty.
+    ///
+    /// Returns nothing if the table is empty.
+    pub fn sparse_buckets(&self) -> Vec<u8> {
+        let closest = match self.buckets.iter().find(|(_, bucket)| !bucket.is_empty()) {
+            Some((distance, _)) => *distance,
+            None => return vec![],
+        };
+
+        (closest..=MAX_DISTANCE)
+            .rev()
+            .filter(|distance| {
+                self.buckets
+                    .get(distance)
+                    .map_or(true, |bucket| bucket.nodes.len() < MAX_BUCKET_SIZE_K)
+            })
+            .collect()
+    }
+
     /// Returns an iterator over the nodes in this routing table.
-    pub fn nodes(&self) -> RoutingTableIterator {
+    pub fn nodes(&self) -> RoutingTableIterator<'_> {
         RoutingTableIterator {
             bucket_index: 1,
             node_index: 0,
@@ -120,16 +189,27 @@ impl RoutingTable {
         }
     }
 
+    /// Returns an iterator over references to the nodes in this routing table,
+    /// in the same order as [Self::nodes], but without cloning them.
+    pub fn iter(&self) -> impl Iterator<Item = &Node> {
+        self.buckets.values().flat_map(|bucket| bucket.nodes.iter())
+    }
+
     /// Export an owned vector of nodes from this routing table.
     pub fn to_owned_nodes(&self) -> Vec<Node> {
         self.nodes().collect()
     }
 
-    /// Turn this routing table to a list of bootstrapping nodes.   
+    /// Turn this routing table to a list of bootstrapping nodes.
     pub fn to_bootstrap(&self) -> Vec<String> {
+        self.to_bootstrap_at(Instant::now())
+    }
+
+    /// Turn this routing table to a list of bootstrapping nodes, that aren't stale at `now`.
+    pub(crate) fn to_bootstrap_at(&self, now: Instant) -> Vec<String> {
         self.nodes()
-            .filter(|n| !n.is_stale())
-            .map(|n| n.address().to_string())
+            .filter(|n| !n.is_stale_at(now))
+            .map(|n| n.socket_address().to_string())
             .collect()
     }
 
@@ -223,8 +303,9 @@ impl KBucket {
         } else if self.nodes.len() < MAX_BUCKET_SIZE_K {
             self.nodes.push(incoming);
             true
-        } else if self.nodes[0].is_stale() {
-            // Remove the least recently seen node and add the new one
+        } else if self.nodes[0].is_stale_at(incoming.last_seen()) {
+            // Remove the least recently seen node and add the new one,
+            // if it wasn't seen for a while before the incoming node was.
             self.nodes.remove(0);
             self.nodes.push(incoming);
 
@@ -260,7 +341,7 @@ impl Default for KBucket {
 
 #[cfg(test)]
 mod test {
-    use std::net::SocketAddrV4;
+    use std::net::{SocketAddr, SocketAddrV4};
     use std::str::FromStr;
     use std::sync::Arc;
     use std::time::Instant;
@@ -299,6 +380,40 @@ mod test {
         assert_eq!(sorted_table, sorted_expected);
     }
 
+    #[test]
+    fn bucket_sizes() {
+        let mut table = RoutingTable::new(Id::from_bytes([0; 20]).unwrap());
+
+        let mut furthest = [0; 20];
+        furthest[0] = 0b1000_0000;
+        let mut five_leading_zeros = [0; 20];
+        five_leading_zeros[0] = 0b0000_0100;
+        let mut closest = [0; 20];
+        closest[19] = 1;
+
+        let mut other_furthest = furthest;
+        other_furthest[19] = 1;
+
+        for (i, id) in [furthest, other_furthest, five_leading_zeros, closest]
+            .into_iter()
+            .enumerate()
+        {
+            table.add(Node::new(
+                Id::from_bytes(id).unwrap(),
+                SocketAddrV4::new([1, 1, 1, i as u8].into(), 6881),
+            ));
+        }
+
+        let sizes = table.bucket_sizes();
+
+        assert_eq!(sizes.len(), 161);
+        assert_eq!(sizes[160], 2);
+        assert_eq!(sizes[155], 1);
+        assert_eq!(sizes[1], 1);
+        assert_eq!(sizes.iter().sum::<usize>(), table.size());
+        assert_eq!(table.size(), 4);
+    }
+
     #[test]
     fn contains() {
         let mut table = RoutingTable::new(Id::random());
@@ -329,7 +444,7 @@ mod test {
         let mut table = RoutingTable::new(Id::random());
 
         let node1 = Node::random();
-        let node2 = Node::new(*node1.id(), node1.address());
+        let node2 = Node::new(*node1.id(), node1.socket_address());
 
         table.add(node1);
         table.add(node2);
@@ -369,7 +484,7 @@ mod test {
             let mut bucket = KBucket::new();
 
             let node1 = Node::random();
-            let node2 = Node::new(*node1.id(), node1.address());
+            let node2 = Node::new(*node1.id(), node1.socket_address());
 
             bucket.add(node1.clone());
             bucket.add(Node::random());
@@ -387,7 +502,7 @@ mod test {
             let mut bucket = KBucket::new();
 
             let node1 = Node::random();
-            let node2 = Node::new(*node1.id(), SocketAddrV4::new(*node1.address().ip(), 1));
+            let node2 = Node::new(*node1.id(), SocketAddr::new(node1.socket_address().ip(), 1));
 
             bucket.add(node1.clone());
             bucket.add(Node::random());
@@ -405,7 +520,7 @@ mod test {
 
             let secure = Node(Arc::new(NodeInner {
                 id: Id::from_str("5a3ce9c14e7a08645677bbd1cfe7d8f956d53256").unwrap(),
-                address: SocketAddrV4::new([21, 75, 31, 124].into(), 0),
+                address: SocketAddrV4::new([21, 75, 31, 124].into(), 0).into(),
                 token: None,
                 last_seen: Instant::now(),
             }));
@@ -416,14 +531,14 @@ mod test {
                 bucket.add(unsecure.clone());
                 bucket.add(secure.clone());
 
-                assert_eq!(bucket.nodes[0].address(), secure.address())
+                assert_eq!(bucket.nodes[0].socket_address(), secure.socket_address())
             }
 
             {
                 bucket.add(secure.clone());
                 bucket.add(unsecure.clone());
 
-                assert_eq!(bucket.nodes[0].address(), secure.address())
+                assert_eq!(bucket.nodes[0].socket_address(), secure.socket_address())
             }
         }
 
@@ -443,7 +558,7 @@ mod test {
 
             assert_eq!(bucket.nodes.len(), 2);
             assert_ne!(bucket.nodes[1].id(), node1.id());
-            assert_ne!(bucket.nodes[1].address(), node2.address());
+            assert_ne!(bucket.nodes[1].socket_address(), node2.socket_address());
         }
     }
 
@@ -559,7 +674,7 @@ mod test {
                 let id = Id::from_str(str).unwrap();
                 Node(Arc::new(NodeInner {
                     id,
-                    address: SocketAddrV4::new((i as u32).into(), i as u16),
+                    address: SocketAddrV4::new((i as u32).into(), i as u16).into(),
                     token: None,
                     last_seen: Instant::now(),
                 }))
@@ -646,4 +761,58 @@ mod test {
             assert_eq!(closest_ids, expected_closest_ids);
         }
     }
+
+    #[test]
+    fn from_nodes() {
+        let node = |prefix: u8, i: usize| {
+            let mut bytes = [0; 20];
+            bytes[0] = prefix;
+            bytes[19] = i as u8;
+
+            Node::new(
+                Id::from(bytes),
+                SocketAddrV4::new(((prefix as u32) << 8 | i as u32).into(), 6881),
+            )
+        };
+
+        // 50 nodes in each of the two farthest buckets.
+        let nodes = (0..50)
+            .map(|i| node(0x80, i))
+            .chain((0..50).map(|i| node(0x40, i)))
+            .collect::<Vec<_>>();
+
+        let table = RoutingTable::from_nodes(Id::from([0; 20]), nodes.clone());
+
+        assert_eq!(table.size(), 2 * MAX_BUCKET_SIZE_K);
+        assert_eq!(table.bucket_sizes()[160], MAX_BUCKET_SIZE_K);
+        assert_eq!(table.bucket_sizes()[159], MAX_BUCKET_SIZE_K);
+
+        let mut closest = table.closest_secure(*node(0x40, 0).id(), 0, 0);
+        closest.sort_by_key(|node| *node.id().as_bytes());
+
+        let expected = nodes[50..50 + MAX_BUCKET_SIZE_K]
+            .iter()
+            .chain(&nodes[..MAX_BUCKET_SIZE_K])
+            .cloned()
+            .collect::<Vec<_>>();
+
+        assert_eq!(closest.len(), expected.len());
+        assert!(closest.iter().zip(&expected).all(|(a, b)| a.id() == b.id()));
+    }
+
+    #[test]
+    fn iter() {
+        let mut table = RoutingTable::new(Id::random());
+
+        for i in 0..100 {
+            table.add(Node::unique(i));
+        }
+
+        assert_eq!(table.iter().count(), table.size());
+        assert!(table.iter().eq(table.nodes().collect::<Vec<_>>().iter()));
+
+        // Borrowed, not cloned.
+        assert!(table.iter().all(|node| Arc::strong_count(&node.0) == 1));
+        assert!(table.iter().all(|node| node.last_seen() <= Instant::now()));
+    }
 }
@@ -2,7 +2,9 @@
 
 use std::{
     collections::HashMap,
-    net::{Ipv4Addr, SocketAddrV4, ToSocketAddrs},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs},
+    num::NonZeroUsize,
+    sync::Arc,
     thread,
     time::Duration,
 };
@@ -13,15 +15,18 @@ use tracing::info;
 
 use crate::{
     common::{
-        hash_immutable, AnnouncePeerRequestArguments, FindNodeRequestArguments,
-        GetPeersRequestArguments, GetValueRequestArguments, Id, MutableItem,
-        PutImmutableRequestArguments, PutMutableRequestArguments, PutRequestSpecific,
+        chunk_immutable, chunks_manifest, hash_immutable, manifest_chunks,
+        AnnouncePeerRequestArguments, FindNodeRequestArguments, GetPeersRequestArguments,
+        GetValueRequestArguments, Id, MutableItem, PutImmutableRequestArguments,
+        PutMutableRequestArguments, PutRequestSpecific, RequestSpecific,
     },
     rpc::{
-        to_socket_address, ConcurrencyError, GetRequestSpecific, Info, PutError, PutQueryError,
-        Response, Rpc,
+        split_address_families, to_socket_address, AddressFamily, BootstrapHealth, Clock,
+        ConcurrencyError, DhtEvent, EventSink, EvictionPolicy, GetRequestSpecific, GetStatus, Info,
+        MutableValidator, NodeDiagnostics, PutError, PutQueryError, RequestObserver,
+        RequestTimeouts, Response, Rpc, Transport,
     },
-    Node, ServerSettings,
+    AnnounceFilter, Node, ServerSettings,
 };
 
 use crate::rpc::config::Config;
@@ -42,6 +47,50 @@ impl DhtBuilder {
         self
     }
 
+    /// Never run in server mode, even if this node is publicly reachable,
+    /// and mark all outgoing requests as read-only, overriding [Self::server_mode].
+    pub fn read_only(&mut self) -> &mut Self {
+        self.0.read_only = true;
+
+        self
+    }
+
+    /// Answer `find_node` requests even in client mode, without storing anything.
+    pub fn always_answer_find_node(&mut self) -> &mut Self {
+        self.0.always_answer_find_node = true;
+
+        self
+    }
+
+    /// Only add nodes to the routing table if their Id is valid for their IP address
+    /// according to [BEP_0042](https://www.bittorrent.org/beps/bep_0042.html).
+    pub fn enforce_secure_ids(&mut self) -> &mut Self {
+        self.0.enforce_secure_ids = true;
+
+        self
+    }
+
+    /// Only store peers announced for info_hashes that this filter allows, when in server mode.
+    ///
+    /// See [ServerSettings::announce_filter].
+    pub fn announce_filter(
+        &mut self,
+        filter: impl Fn(&Id) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.0.server_settings.announce_filter = Some(AnnounceFilter::new(filter));
+
+        self
+    }
+
+    /// Only answer `ping` and `find_node` requests when in server mode, never storing anything.
+    ///
+    /// See [ServerSettings::routing_only].
+    pub fn routing_only(&mut self) -> &mut Self {
+        self.0.server_settings.routing_only = true;
+
+        self
+    }
+
     /// Set a custom settings for the node to use at server mode.
     ///
     /// Defaults to [ServerSettings::default]
@@ -52,8 +101,13 @@ impl DhtBuilder {
     }
 
     /// Set bootstrapping nodes.
+    ///
+    /// Their Ipv6 addresses are set as [Config::bootstrap6].
     pub fn bootstrap<T: ToSocketAddrs>(&mut self, bootstrap: &[T]) -> &mut Self {
-        self.0.bootstrap = Some(to_socket_address(bootstrap));
+        let (bootstrap, bootstrap6) = split_address_families(to_socket_address(bootstrap));
+
+        self.0.bootstrap = Some(bootstrap);
+        self.0.bootstrap6 = bootstrap6;
 
         self
     }
@@ -63,11 +117,58 @@ impl DhtBuilder {
     /// Useful when you want to augment the default bootstrapping nodes with
     /// dynamic list of nodes you have seen in previous sessions.
     pub fn extra_bootstrap<T: ToSocketAddrs>(&mut self, extra_bootstrap: &[T]) -> &mut Self {
+        let (extra_bootstrap, extra_bootstrap6) =
+            split_address_families(to_socket_address(extra_bootstrap));
+
         let mut bootstrap = self.0.bootstrap.clone().unwrap_or_default();
-        for address in to_socket_address(extra_bootstrap) {
+        for address in extra_bootstrap {
             bootstrap.push(address);
         }
         self.0.bootstrap = Some(bootstrap);
+        self.0.bootstrap6.extend(extra_bootstrap6);
+
+        self
+    }
+
+    /// Add bootstrapping nodes by their known addresses, without any DNS resolution.
+    ///
+    /// Unless [Self::bootstrap] or [Self::extra_bootstrap] are also set, these replace
+    /// the default bootstrapping nodes, which otherwise need DNS resolution.
+    pub fn bootstrap_addrs(&mut self, bootstrap_addrs: &[SocketAddr]) -> &mut Self {
+        self.0.bootstrap_addrs = bootstrap_addrs.to_vec();
+
+        self
+    }
+
+    /// Set how often to rotate the secret of write tokens given to requesters in server mode.
+    ///
+    /// Defaults to 5 minutes.
+    pub fn token_rotation_interval(&mut self, interval: Duration) -> &mut Self {
+        self.0.token_rotation_interval = interval;
+
+        self
+    }
+
+    /// Use a custom [Clock] instead of the [crate::SystemClock], for example to
+    /// advance time instantly in tests.
+    pub fn clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.0.clock = clock;
+
+        self
+    }
+
+    /// Send and receive messages over a custom [Transport] instead of a UDP socket,
+    /// for example to connect nodes in memory, or to tunnel the Dht over a relay.
+    pub fn transport(&mut self, transport: Arc<dyn Transport>) -> &mut Self {
+        self.0.transport = Some(transport);
+
+        self
+    }
+
+    /// Start with the [BootstrapHealth] of a previous run, to query the historically
+    /// responsive bootstrapping nodes first, as read from [Info::bootstrap_health].
+    pub fn bootstrap_health(&mut self, bootstrap_health: BootstrapHealth) -> &mut Self {
+        self.0.bootstrap_health = Some(bootstrap_health);
 
         self
     }
@@ -75,6 +176,7 @@ impl DhtBuilder {
     /// Remove the existing bootstrapping nodes, usually to create the first node in a new network.
     pub fn no_bootstrap(&mut self) -> &mut Self {
         self.0.bootstrap = Some(vec![]);
+        self.0.bootstrap6 = vec![];
 
         self
     }
@@ -86,6 +188,13 @@ impl DhtBuilder {
         self
     }
 
+    /// Listen on a random port if the explicit [Self::port] is unavailable, instead of failing.
+    pub fn port_fallback(&mut self) -> &mut Self {
+        self.0.port_fallback = true;
+
+        self
+    }
+
     /// A known public IPv4 address for this node to generate
     /// a secure node Id from according to [BEP_0042](https://www.bittorrent.org/beps/bep_0042.html)
     ///
@@ -96,6 +205,17 @@ impl DhtBuilder {
         self
     }
 
+    /// Use a fixed node Id instead of a random one, or one generated from [Self::public_ip],
+    /// for example to keep the same Id across restarts.
+    ///
+    /// The Id is never replaced, even if it is not valid for the public address
+    /// according to [BEP_0042](https://www.bittorrent.org/beps/bep_0042.html).
+    pub fn node_id(&mut self, node_id: Id) -> &mut Self {
+        self.0.node_id = Some(node_id);
+
+        self
+    }
+
     /// UDP socket request timeout duration.
     ///
     /// The longer this duration is, the longer queries take until they are deemeed "done".
@@ -109,6 +229,261 @@ impl DhtBuilder {
         self
     }
 
+    /// Adapt the request timeout to the observed round trip times of responses,
+    /// between `min_request_timeout` and [Self::request_timeout].
+    ///
+    /// See [Config::adaptive_timeout].
+    pub fn adaptive_timeout(&mut self, min_request_timeout: Duration) -> &mut Self {
+        self.0.adaptive_timeout = true;
+        self.0.min_request_timeout = min_request_timeout;
+
+        self
+    }
+
+    /// Set the timeouts of specific request types, for example a shorter
+    /// timeout for pings than for get requests.
+    ///
+    /// Request types without a timeout use [Self::request_timeout].
+    pub fn timeouts(&mut self, timeouts: RequestTimeouts) -> &mut Self {
+        self.0.timeouts = timeouts;
+
+        self
+    }
+
+    /// Report PUT queries as done successfully as soon as this many nodes
+    /// acknowledged storing the value, instead of waiting for all of them.
+    ///
+    /// Defaults to None, where PUT queries wait for all nodes to respond or time out.
+    pub fn put_ack_threshold(&mut self, threshold: NonZeroUsize) -> &mut Self {
+        self.0.put_ack_threshold = Some(threshold);
+
+        self
+    }
+
+    /// Fail PUT queries that were acknowledged by fewer than this many nodes,
+    /// with [crate::errors::PutQueryError::InsufficientStorageNodes].
+    ///
+    /// Defaults to 1.
+    pub fn min_storage_nodes(&mut self, min_storage_nodes: usize) -> &mut Self {
+        self.0.min_storage_nodes = min_storage_nodes;
+
+        self
+    }
+
+    /// Bind a second UDP socket for Ipv6 and maintain a parallel Ipv6 routing table.
+    ///
+    /// Defaults to false, where only Ipv4 is used.
+    pub fn ipv6(&mut self) -> &mut Self {
+        self.0.ipv6 = true;
+
+        self
+    }
+
+    /// Set which address families of the bootstrapping nodes to use, and in which order,
+    /// enabling Ipv6 unless it is [AddressFamily::V4Only].
+    ///
+    /// This only affects bootstrapping, see [Config::bootstrap_address_family].
+    ///
+    /// Defaults to [AddressFamily::V4Only]
+    pub fn bootstrap_address_family(&mut self, preference: AddressFamily) -> &mut Self {
+        self.0.bootstrap_address_family = preference;
+
+        self
+    }
+
+    /// Retry PUT queries that found no nodes to store at up to `max_retries` times,
+    /// waiting `backoff` before the first retry, and doubling it after every retry.
+    ///
+    /// Defaults to no retries.
+    pub fn put_retries(&mut self, max_retries: usize, backoff: Duration) -> &mut Self {
+        self.0.put_max_retries = max_retries;
+        self.0.put_retry_backoff = backoff;
+
+        self
+    }
+
+    /// Set the maximum number of nodes a query waits for responses from at once
+    /// (the Kademlia "alpha" parameter).
+    ///
+    /// Defaults to [crate::DEFAULT_QUERY_CONCURRENCY]
+    pub fn query_concurrency(&mut self, query_concurrency: usize) -> &mut Self {
+        self.0.query_concurrency = query_concurrency;
+
+        self
+    }
+
+    /// Set the maximum duration of a query, after which it is done
+    /// with whatever responses it has so far.
+    ///
+    /// Defaults to [crate::DEFAULT_QUERY_TIMEOUT]
+    pub fn query_timeout(&mut self, query_timeout: Duration) -> &mut Self {
+        self.0.query_timeout = query_timeout;
+
+        self
+    }
+
+    /// Set how often to refresh the routing table.
+    ///
+    /// Defaults to [crate::DEFAULT_REFRESH_INTERVAL]
+    pub fn refresh_interval(&mut self, refresh_interval: Duration) -> &mut Self {
+        self.0.refresh_interval = refresh_interval;
+
+        self
+    }
+
+    /// Set how often to ping nodes in the routing table that weren't seen recently.
+    ///
+    /// Defaults to [crate::DEFAULT_PING_INTERVAL]
+    pub fn ping_interval(&mut self, ping_interval: Duration) -> &mut Self {
+        self.0.ping_interval = ping_interval;
+
+        self
+    }
+
+    /// Ping a few routing table nodes at every `interval`, to keep the NAT mapping
+    /// of this node alive, so incoming requests keep reaching it.
+    pub fn nat_keepalive_interval(&mut self, interval: Duration) -> &mut Self {
+        self.0.nat_keepalive_interval = Some(interval);
+
+        self
+    }
+
+    /// Use a custom [EvictionPolicy] to decide which routing table nodes
+    /// are removed or pinged, instead of the [crate::DefaultEvictionPolicy].
+    pub fn eviction_policy(&mut self, policy: Arc<dyn EvictionPolicy>) -> &mut Self {
+        self.0.eviction_policy = policy;
+
+        self
+    }
+
+    /// Return the responses of GET queries done within `ttl` immediately,
+    /// while refreshing them in the background.
+    pub fn value_cache_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.0.value_cache_ttl = Some(ttl);
+
+        self
+    }
+
+    /// Stop collecting peers in a `get_peers` query after this many peers.
+    ///
+    /// See [Config::max_peers_per_query].
+    pub fn max_peers_per_query(&mut self, max_peers_per_query: usize) -> &mut Self {
+        self.0.max_peers_per_query = Some(max_peers_per_query);
+
+        self
+    }
+
+    /// Set the maximum number of requests awaiting a response at once,
+    /// queueing further requests until earlier ones are done.
+    ///
+    /// Defaults to [crate::DEFAULT_MAX_INFLIGHT_REQUESTS]
+    pub fn max_inflight_requests(&mut self, max_inflight_requests: usize) -> &mut Self {
+        self.0.max_inflight_requests = max_inflight_requests;
+
+        self
+    }
+
+    /// Use cryptographically random transaction ids for outgoing requests,
+    /// instead of sequential ones, so remote nodes can't predict them.
+    pub fn random_transaction_ids(&mut self) -> &mut Self {
+        self.0.random_transaction_ids = true;
+
+        self
+    }
+
+    /// Receive structured [DhtEvent]s, like invalid values or public address
+    /// changes, in addition to the tracing logs.
+    ///
+    /// The callback is called on the Dht's actor thread, so it should return quickly.
+    pub fn event_sink(&mut self, sink: impl Fn(DhtEvent) + Send + Sync + 'static) -> &mut Self {
+        self.0.event_sink = Some(EventSink::new(sink));
+
+        self
+    }
+
+    /// Keep the raw values of the most recent invalid responses,
+    /// readable from the [info](Dht::info) of the running node.
+    pub fn capture_rejected(&mut self) -> &mut Self {
+        self.0.capture_rejected = true;
+
+        self
+    }
+
+    /// Observe every incoming request, including the ones received in client mode,
+    /// for example to collect statistics about the most requested targets.
+    ///
+    /// The callback is called on the Dht's actor thread, so it should return quickly.
+    pub fn request_observer(
+        &mut self,
+        observer: impl Fn(&SocketAddr, &RequestSpecific) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.0.request_observer = Some(RequestObserver::new(observer));
+
+        self
+    }
+
+    /// Validate mutable items in responses beyond their signatures, dropping the items
+    /// the callback returns false for, for example values that aren't valid for the application.
+    ///
+    /// The callback is called on the Dht's actor thread, so it should return quickly.
+    pub fn mutable_validator(
+        &mut self,
+        validator: impl Fn(&MutableItem) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.0.mutable_validator = Some(MutableValidator::new(validator));
+
+        self
+    }
+
+    /// Set the maximum number of incoming requests per second to respond to from any single IP,
+    /// dropping the rest, to avoid being used for reflection or amplification.
+    pub fn max_requests_per_ip_per_sec(&mut self, max_requests_per_ip_per_sec: u32) -> &mut Self {
+        self.0.max_requests_per_ip_per_sec = Some(max_requests_per_ip_per_sec);
+
+        self
+    }
+
+    /// Bound the memory used by the closest nodes cached from done queries
+    /// to an estimated number of bytes, in addition to the [Self::query_cache_size].
+    pub fn max_query_cache_bytes(&mut self, max_query_cache_bytes: usize) -> &mut Self {
+        self.0.max_query_cache_bytes = Some(max_query_cache_bytes);
+
+        self
+    }
+
+    /// Set the maximum number of done queries to cache the closest nodes of,
+    /// or disable the cache if None, for memory constrained nodes.
+    ///
+    /// Defaults to [crate::DEFAULT_QUERY_CACHE_SIZE]
+    pub fn query_cache_size(&mut self, query_cache_size: Option<NonZeroUsize>) -> &mut Self {
+        self.0.query_cache_size = query_cache_size;
+
+        self
+    }
+
+    /// Set the client identifier and version in the `v` field of every outgoing message,
+    /// or omit it if None.
+    ///
+    /// Defaults to [crate::DEFAULT_CLIENT_VERSION]
+    pub fn client_version(&mut self, client_version: Option<[u8; 4]>) -> &mut Self {
+        self.0.client_version = client_version;
+
+        self
+    }
+
+    /// Set the maximum size in bytes of values in `get` responses,
+    /// dropping larger responses before validating them.
+    ///
+    /// Putting larger values fails with [crate::errors::PutQueryError::ValueTooLarge]
+    /// without sending anything.
+    ///
+    /// Defaults to [crate::DEFAULT_MAX_VALUE_SIZE]
+    pub fn max_value_size(&mut self, max_value_size: usize) -> &mut Self {
+        self.0.max_value_size = max_value_size;
+
+        self
+    }
+
     /// Create a Dht node.
     pub fn build(&self) -> Result<Dht, std::io::Error> {
         Dht::new(self.0.clone())
@@ -191,6 +566,18 @@ impl Dht {
         !nodes.is_empty()
     }
 
+    /// Ping and send a find_node request to a specific node, and return a
+    /// [NodeDiagnostics] report about its reachability, round trip time,
+    /// claimed [Id], and whether it returns closer nodes.
+    ///
+    /// Useful to validate bootstrapping nodes before relying on them.
+    pub fn diagnose_node(&self, address: SocketAddr) -> NodeDiagnostics {
+        let (tx, rx) = flume::bounded::<NodeDiagnostics>(1);
+        self.send(ActorMessage::DiagnoseNode(address, tx));
+
+        rx.recv().expect("actor thread unexpectedly shutdown")
+    }
+
     // === Find nodes ===
 
     /// Returns the closest 20 [secure](Node::is_secure) nodes to a target [Id].
@@ -209,7 +596,10 @@ impl Dht {
     pub fn find_node(&self, target: Id) -> Box<[Node]> {
         let (tx, rx) = flume::bounded::<Box<[Node]>>(1);
         self.send(ActorMessage::Get(
-            GetRequestSpecific::FindNode(FindNodeRequestArguments { target }),
+            GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                target,
+                want: vec![],
+            }),
             ResponseSender::ClosestNodes(tx),
         ));
 
@@ -228,16 +618,61 @@ impl Dht {
     /// for Bittorrent is that any peer will introduce you to more peers through "peer exchange"
     /// so if you are implementing something different from Bittorrent, you might want
     /// to implement your own logic for gossipping more peers after you discover the first ones.
+    ///
+    /// Only Ipv4 peers are returned, see [Self::get_peers6] for Ipv6 peers.
     pub fn get_peers(&self, info_hash: Id) -> GetIterator<Vec<SocketAddrV4>> {
         let (tx, rx) = flume::unbounded::<Vec<SocketAddrV4>>();
         self.send(ActorMessage::Get(
-            GetRequestSpecific::GetPeers(GetPeersRequestArguments { info_hash }),
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
             ResponseSender::Peers(tx),
         ));
 
         GetIterator(rx.into_iter())
     }
 
+    /// Get Ipv6 peers for a given infohash, same as [Self::get_peers] for Ipv4 peers.
+    ///
+    /// Only returns peers if [DhtBuilder::ipv6] is enabled.
+    pub fn get_peers6(&self, info_hash: Id) -> GetIterator<Vec<SocketAddrV6>> {
+        let (tx, rx) = flume::unbounded::<Vec<SocketAddrV6>>();
+        self.send(ActorMessage::Get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
+            ResponseSender::Peers6(tx),
+        ));
+
+        GetIterator(rx.into_iter())
+    }
+
+    /// Estimate the number of seeders and peers for a given infohash, using
+    /// [BEP_0033](https://www.bittorrent.org/beps/bep_0033.html) scrape requests.
+    ///
+    /// Returns `(seeders, peers)` estimates after each response, each
+    /// more accurate than the last, so you probably want the last one.
+    pub fn scrape(&self, info_hash: Id) -> GetIterator<(usize, usize)> {
+        let (tx, rx) = flume::unbounded::<(usize, usize)>();
+        self.send(ActorMessage::Get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: true,
+                noseed: false,
+                want: vec![],
+            }),
+            ResponseSender::Scrape(tx),
+        ));
+
+        GetIterator(rx.into_iter())
+    }
+
     /// Announce a peer for a given infohash.
     ///
     /// The peer will be announced on this process IP.
@@ -254,6 +689,7 @@ impl Dht {
                 info_hash,
                 port,
                 implied_port,
+                seed: false,
             }),
             None,
         )
@@ -262,6 +698,9 @@ impl Dht {
             PutError::Concurrency(_) => {
                 unreachable!("should not receive a concurrency error from announce peer query")
             }
+            PutError::CasConflict { .. } => {
+                unreachable!("should not receive a CAS conflict from announce peer query")
+            }
         })
     }
 
@@ -293,12 +732,76 @@ impl Dht {
             }),
             None,
         )
-        .map_err(|error| match error {
-            PutError::Query(error) => error,
-            PutError::Concurrency(_) => {
-                unreachable!("should not receive a concurrency error from put immutable query")
-            }
-        })
+        .map_err(put_immutable_error)
+    }
+
+    /// Put a value larger than a single immutable item, up to [crate::MAX_CHUNKED_IMMUTABLE_SIZE].
+    ///
+    /// The value is split into chunks of at most [crate::IMMUTABLE_CHUNK_SIZE] stored as
+    /// immutable items, and a manifest of the chunks' hashes is stored as another
+    /// immutable item, whose hash is returned to be used with [Self::get_immutable_chunked].
+    pub fn put_immutable_chunked(&self, value: &[u8]) -> Result<Id, PutChunkedError> {
+        let chunks = chunk_immutable(value).ok_or(PutChunkedError::TooLarge(value.len()))?;
+        let manifest = chunks_manifest(&chunks);
+
+        let receivers = chunks
+            .iter()
+            .chain(std::iter::once(&manifest.as_ref()))
+            .map(|chunk| {
+                self.put_inner(
+                    PutRequestSpecific::PutImmutable(PutImmutableRequestArguments {
+                        target: hash_immutable(chunk).into(),
+                        v: (*chunk).into(),
+                    }),
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut root = None;
+
+        for rx in receivers {
+            root = Some(
+                rx.recv()
+                    .expect("Query was dropped before sending a response, please open an issue.")
+                    .map_err(put_immutable_error)?,
+            );
+        }
+
+        Ok(root.expect("manifest is always put"))
+    }
+
+    /// Get a value stored with [Self::put_immutable_chunked] by the hash of its manifest.
+    ///
+    /// Returns `None` if the manifest or any of the chunks is not found.
+    pub fn get_immutable_chunked(&self, root: Id) -> Option<Box<[u8]>> {
+        let manifest = self.get_immutable(root)?;
+        let chunks = manifest_chunks(&manifest)?;
+
+        let receivers = chunks
+            .into_iter()
+            .map(|target| {
+                let (tx, rx) = flume::unbounded::<Box<[u8]>>();
+                self.send(ActorMessage::Get(
+                    GetRequestSpecific::GetValue(GetValueRequestArguments {
+                        target,
+                        seq: None,
+                        salt: None,
+                    }),
+                    ResponseSender::Immutable(tx),
+                ));
+
+                rx
+            })
+            .collect::<Vec<_>>();
+
+        let mut value = Vec::new();
+
+        for rx in receivers {
+            value.extend_from_slice(&rx.recv().ok()?);
+        }
+
+        Some(value.into_boxed_slice())
     }
 
     // === Mutable data ===
@@ -337,6 +840,9 @@ impl Dht {
     }
 
     /// Get the most recent [MutableItem] from the network.
+    ///
+    /// Waits for the query to finish, and returns the item with the highest `seq`,
+    /// see [MutableItem::is_more_recent_than].
     pub fn get_mutable_most_recent(
         &self,
         public_key: &[u8; 32],
@@ -345,11 +851,10 @@ impl Dht {
         let mut most_recent: Option<MutableItem> = None;
         let iter = self.get_mutable(public_key, salt, None);
         for item in iter {
-            if let Some(mr) = &most_recent {
-                if item.seq() == mr.seq && item.value() > &mr.value {
-                    most_recent = Some(item)
-                }
-            } else {
+            if most_recent
+                .as_ref()
+                .map_or(true, |most_recent| item.is_more_recent_than(most_recent))
+            {
                 most_recent = Some(item);
             }
         }
@@ -413,6 +918,9 @@ impl Dht {
         self.put(request, None).map_err(|error| match error {
             PutError::Query(err) => PutMutableError::Query(err),
             PutError::Concurrency(err) => PutMutableError::Concurrency(err),
+            PutError::CasConflict { .. } => {
+                PutMutableError::Concurrency(ConcurrencyError::CasFailed)
+            }
         })
     }
 
@@ -494,6 +1002,7 @@ fn run(config: Config, receiver: Receiver<ActorMessage>) {
 
             let mut put_senders = HashMap::new();
             let mut get_senders = HashMap::new();
+            let mut diagnose_senders: Vec<(SocketAddr, Sender<NodeDiagnostics>)> = vec![];
 
             loop {
                 match receiver.try_recv() {
@@ -521,18 +1030,43 @@ fn run(config: Config, receiver: Receiver<ActorMessage>) {
                         ActorMessage::Get(request, sender) => {
                             let target = *request.target();
 
-                            if let Some(responses) = rpc.get(request, None) {
-                                for response in responses {
-                                    send(&sender, response);
+                            match rpc.get(request, None) {
+                                GetStatus::Done(responses) | GetStatus::Cached(responses) => {
+                                    for response in responses {
+                                        send(&sender, response);
+                                    }
+
+                                    // return closest_nodes of the just done query
+                                    if let ResponseSender::ClosestNodes(sender) = sender {
+                                        let _ = sender.send(
+                                            rpc.cached_closest_nodes(&target)
+                                                .unwrap_or_default()
+                                                .into(),
+                                        );
+                                    }
                                 }
-                            };
+                                // Dropping the sender ends the iterator right away.
+                                GetStatus::ShuttingDown => {}
+                                status => {
+                                    if let GetStatus::InProgress(responses) = status {
+                                        for response in responses {
+                                            send(&sender, response);
+                                        }
+                                    }
 
-                            let senders = get_senders.entry(target).or_insert(vec![]);
+                                    let senders = get_senders.entry(target).or_insert(vec![]);
 
-                            senders.push(sender);
+                                    senders.push(sender);
+                                }
+                            };
                         }
                         ActorMessage::ToBootstrap(sender) => {
-                            let _ = sender.send(rpc.routing_table().to_bootstrap());
+                            let _ = sender.send(rpc.to_bootstrap());
+                        }
+                        ActorMessage::DiagnoseNode(address, sender) => {
+                            rpc.diagnose_node(address);
+
+                            diagnose_senders.push((address, sender));
                         }
                     },
                     Err(TryRecvError::Disconnected) => {
@@ -568,14 +1102,20 @@ fn run(config: Config, receiver: Receiver<ActorMessage>) {
                     }
                 }
 
+                for diagnostics in report.done_node_diagnostics {
+                    if let Some(index) = diagnose_senders
+                        .iter()
+                        .position(|(address, _)| *address == diagnostics.address)
+                    {
+                        let (_, sender) = diagnose_senders.remove(index);
+                        let _ = sender.send(diagnostics);
+                    }
+                }
+
                 // Cleanup done PUT query and send a resulting error if any.
-                for (id, error) in report.done_put_queries {
+                for (id, result) in report.done_put_queries {
                     if let Some(senders) = put_senders.remove(&id) {
-                        let result = if let Some(error) = error {
-                            Err(error)
-                        } else {
-                            Ok(id)
-                        };
+                        let result = result.map(|_| id);
 
                         for sender in senders {
                             let _ = sender.send(result.clone());
@@ -586,7 +1126,7 @@ fn run(config: Config, receiver: Receiver<ActorMessage>) {
         }
         Err(err) => {
             if let Ok(ActorMessage::Check(sender)) = receiver.try_recv() {
-                let _ = sender.send(Err(err));
+                let _ = sender.send(Err(err.into()));
             }
         }
     };
@@ -597,12 +1137,27 @@ fn send(sender: &ResponseSender, response: Response) {
         (ResponseSender::Peers(s), Response::Peers(r)) => {
             let _ = s.send(r);
         }
+        (ResponseSender::Peers6(s), Response::Peers6(r)) => {
+            let _ = s.send(r);
+        }
+        (
+            ResponseSender::Scrape(s),
+            Response::Scrape {
+                seeders_estimate,
+                peers_estimate,
+            },
+        ) => {
+            let _ = s.send((seeders_estimate, peers_estimate));
+        }
         (ResponseSender::Mutable(s), Response::Mutable(r)) => {
             let _ = s.send(r);
         }
         (ResponseSender::Immutable(s), Response::Immutable(r)) => {
             let _ = s.send(r);
         }
+        (ResponseSender::Response(s), r) => {
+            let _ = s.send(r);
+        }
         _ => {}
     }
 }
@@ -618,14 +1173,18 @@ pub(crate) enum ActorMessage {
     Get(GetRequestSpecific, ResponseSender),
     Check(Sender<Result<(), std::io::Error>>),
     ToBootstrap(Sender<Vec<String>>),
+    DiagnoseNode(SocketAddr, Sender<NodeDiagnostics>),
 }
 
 #[derive(Debug, Clone)]
 pub enum ResponseSender {
     ClosestNodes(Sender<Box<[Node]>>),
     Peers(Sender<Vec<SocketAddrV4>>),
+    Peers6(Sender<Vec<SocketAddrV6>>),
+    Scrape(Sender<(usize, usize)>),
     Mutable(Sender<MutableItem>),
     Immutable(Sender<Box<[u8]>>),
+    Response(Sender<Response>),
 }
 
 /// Create a testnet of Dht nodes to run tests against instead of the real mainline network.
@@ -693,6 +1252,31 @@ pub enum PutMutableError {
     Concurrency(#[from] ConcurrencyError),
 }
 
+#[derive(thiserror::Error, Debug)]
+/// Put chunked immutable value errors.
+pub enum PutChunkedError {
+    #[error("Value of {0} bytes is larger than MAX_CHUNKED_IMMUTABLE_SIZE")]
+    /// The value is larger than [crate::MAX_CHUNKED_IMMUTABLE_SIZE].
+    TooLarge(usize),
+
+    #[error(transparent)]
+    /// Common PutQuery errors
+    Query(#[from] PutQueryError),
+}
+
+/// Unwrap the [PutQueryError] of an immutable put, which can't fail otherwise.
+pub(crate) fn put_immutable_error(error: PutError) -> PutQueryError {
+    match error {
+        PutError::Query(error) => error,
+        PutError::Concurrency(_) => {
+            unreachable!("should not receive a concurrency error from put immutable query")
+        }
+        PutError::CasConflict { .. } => {
+            unreachable!("should not receive a CAS conflict from put immutable query")
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
@@ -714,6 +1298,37 @@ mod test {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn diagnose_node() {
+        let testnet = Testnet::new(3).unwrap();
+
+        let dht = Dht::builder().no_bootstrap().build().unwrap();
+
+        let target = testnet.nodes[0].info();
+        let address = SocketAddr::from(([127, 0, 0, 1], target.local_addr().port()));
+
+        let diagnostics = dht.diagnose_node(address);
+
+        assert_eq!(diagnostics.address, address);
+        assert!(diagnostics.reachable);
+        assert!(diagnostics.rtt.is_some());
+        assert_eq!(diagnostics.id, Some(*target.id()));
+        assert_eq!(diagnostics.secure, Some(true));
+        assert!(diagnostics.closer_nodes.is_some());
+        assert!(diagnostics.error.is_none());
+
+        // Bound but never responds.
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = silent.local_addr().unwrap();
+
+        let diagnostics = dht.diagnose_node(address);
+
+        assert!(!diagnostics.reachable);
+        assert!(diagnostics.rtt.is_none());
+        assert!(diagnostics.id.is_none());
+        assert!(diagnostics.closer_nodes.is_none());
+    }
+
     #[test]
     fn announce_get_peer() {
         let testnet = Testnet::new(10).unwrap();
@@ -737,6 +1352,30 @@ mod test {
         assert_eq!(peers.first().unwrap().port(), 45555);
     }
 
+    #[test]
+    fn scrape() {
+        let testnet = Testnet::new(10).unwrap();
+
+        let a = Dht::builder()
+            .bootstrap(&testnet.bootstrap)
+            .build()
+            .unwrap();
+        let b = Dht::builder()
+            .bootstrap(&testnet.bootstrap)
+            .build()
+            .unwrap();
+
+        let info_hash = Id::random();
+
+        a.announce_peer(info_hash, Some(45555))
+            .expect("failed to announce");
+
+        let (seeders, peers) = b.scrape(info_hash).last().expect("No scrape responses");
+
+        assert_eq!(seeders, 0);
+        assert_eq!(peers, 1);
+    }
+
     #[test]
     fn put_get_immutable() {
         let testnet = Testnet::new(10).unwrap();
@@ -761,6 +1400,32 @@ mod test {
         assert_eq!(response, value.to_vec().into_boxed_slice());
     }
 
+    #[test]
+    fn put_get_immutable_chunked() {
+        let testnet = Testnet::new(10).unwrap();
+
+        let a = Dht::builder()
+            .bootstrap(&testnet.bootstrap)
+            .build()
+            .unwrap();
+        let b = Dht::builder()
+            .bootstrap(&testnet.bootstrap)
+            .build()
+            .unwrap();
+
+        let value = (0..5 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+
+        let root = a.put_immutable_chunked(&value).unwrap();
+
+        let response = b.get_immutable_chunked(root).unwrap();
+        assert_eq!(response, value.into_boxed_slice());
+
+        assert!(matches!(
+            a.put_immutable_chunked(&vec![0; crate::MAX_CHUNKED_IMMUTABLE_SIZE + 1]),
+            Err(PutChunkedError::TooLarge(_))
+        ));
+    }
+
     #[test]
     fn find_node_no_values() {
         let client = Dht::builder().no_bootstrap().build().unwrap();
@@ -808,6 +1473,36 @@ mod test {
         assert_eq!(&response, &item);
     }
 
+    #[test]
+    fn mutable_validator() {
+        let testnet = Testnet::new(10).unwrap();
+
+        let a = Dht::builder()
+            .bootstrap(&testnet.bootstrap)
+            .build()
+            .unwrap();
+        let b = Dht::builder()
+            .bootstrap(&testnet.bootstrap)
+            .mutable_validator(|item| item.value() != b"junk")
+            .build()
+            .unwrap();
+
+        let valid = MutableItem::new(SigningKey::from_bytes(&[1; 32]), b"valid", 1, None);
+        let junk = MutableItem::new(SigningKey::from_bytes(&[2; 32]), b"junk", 1, None);
+
+        a.put_mutable(valid.clone(), None).unwrap();
+        a.put_mutable(junk.clone(), None).unwrap();
+
+        assert_eq!(
+            b.get_mutable_most_recent(valid.key(), None),
+            Some(valid.clone())
+        );
+        assert_eq!(b.get_mutable_most_recent(junk.key(), None), None);
+
+        // Only dropped by the node with the validator.
+        assert_eq!(a.get_mutable_most_recent(junk.key(), None), Some(junk));
+    }
+
     #[test]
     fn put_get_mutable_no_more_recent_value() {
         let testnet = Testnet::new(10).unwrap();
@@ -16,15 +16,30 @@ mod rpc;
 #[cfg(feature = "async")]
 pub mod async_dht;
 
-pub use common::{Id, MutableItem, Node, RoutingTable};
+pub use common::{
+    hash_immutable, validate_immutable, Distance, Id, MutableItem, MutableItemBuilder,
+    MutableSigner, Node, RoutingTable, IMMUTABLE_CHUNK_SIZE, MAX_CHUNKED_IMMUTABLE_SIZE,
+    MAX_SALT_SIZE,
+};
 
 #[cfg(feature = "node")]
 pub use dht::{Dht, DhtBuilder, Testnet};
 #[cfg(feature = "node")]
 pub use rpc::{
     messages::{MessageType, PutRequestSpecific, RequestSpecific},
-    server::{RequestFilter, ServerSettings, MAX_INFO_HASHES, MAX_PEERS, MAX_VALUES},
-    ClosestNodes, DEFAULT_REQUEST_TIMEOUT,
+    server::{
+        AnnounceFilter, RequestFilter, ServerSettings, MAX_INFO_HASHES, MAX_PEERS, MAX_VALUES,
+    },
+    AddressConfidence, AddressFamily, BandwidthStats, BootstrapHealth, BootstrapStatus, Clock,
+    ClosestNodes, DefaultEvictionPolicy, DhtEvent, EventSink, EvictionPolicy, GetRequestSpecific,
+    GetStatus, HopTiming, JoinSwarmReport, MessageBytes, Metrics, MutableValidator, NatType,
+    NodeDiagnostics, NodeHealth, QueryKind, QuerySummary, RejectedSample, RejectionReason,
+    RequestCounts, RequestObserver, RequestTimeouts, Response, SystemClock, Transport,
+    BANDWIDTH_RATE_WINDOW, DEFAULT_CLIENT_VERSION, DEFAULT_MAX_INFLIGHT_REQUESTS,
+    DEFAULT_MAX_VALUE_SIZE, DEFAULT_MIN_REQUEST_TIMEOUT, DEFAULT_PING_INTERVAL,
+    DEFAULT_PUT_RETRY_BACKOFF, DEFAULT_QUERY_CACHE_SIZE, DEFAULT_QUERY_CONCURRENCY,
+    DEFAULT_QUERY_TIMEOUT, DEFAULT_REFRESH_INTERVAL, DEFAULT_REQUEST_TIMEOUT, MAX_QUEUED_REQUESTS,
+    MAX_REJECTED_SAMPLES, STATE_VERSION,
 };
 
 pub use ed25519_dalek::SigningKey;
@@ -32,11 +47,13 @@ pub use ed25519_dalek::SigningKey;
 pub mod errors {
     //! Exported errors
     #[cfg(feature = "node")]
-    pub use super::common::ErrorSpecific;
+    pub use super::common::{DecodeMessageError, ErrorSpecific};
     #[cfg(feature = "node")]
-    pub use super::dht::PutMutableError;
+    pub use super::dht::{PutChunkedError, PutMutableError};
     #[cfg(feature = "node")]
-    pub use super::rpc::{ConcurrencyError, PutError, PutQueryError};
+    pub use super::rpc::{
+        ConcurrencyError, ImportStateError, PutBatchError, PutError, PutQueryError, RpcError,
+    };
 
     pub use super::common::DecodeIdError;
     pub use super::common::MutableError;
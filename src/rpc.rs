@@ -1,43 +1,85 @@
 //! K-RPC implementation.
 
+mod bandwidth;
+mod bootstrap_health;
+mod clock;
 mod closest_nodes;
 pub(crate) mod config;
+mod diagnostics;
+mod events;
+mod eviction;
 mod info;
 mod iterative_query;
+mod metrics;
+mod nat;
 mod put_query;
+mod query_summary;
+mod rate_limiter;
+mod rejected;
+mod rtt;
 pub(crate) mod server;
 mod socket;
+mod state;
+mod transport;
 
-use std::collections::HashMap;
-use std::net::{SocketAddr, SocketAddrV4, ToSocketAddrs};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
 use std::num::NonZeroUsize;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use ed25519_dalek::SigningKey;
 use lru::LruCache;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
+use diagnostics::DiagnosticsQuery;
 use iterative_query::IterativeQuery;
-use put_query::PutQuery;
+use put_query::{validate_put_request, PutQuery};
+use rate_limiter::RateLimiter;
+use rejected::RejectedSamples;
+use state::RpcState;
 
 use crate::common::{
     validate_immutable, ErrorSpecific, FindNodeRequestArguments, GetImmutableResponseArguments,
     GetMutableResponseArguments, GetPeersResponseArguments, GetValueRequestArguments, Id, Message,
     MessageType, MutableItem, NoMoreRecentValueResponseArguments, NoValuesResponseArguments, Node,
     PutRequestSpecific, RequestSpecific, RequestTypeSpecific, ResponseSpecific, RoutingTable,
-    MAX_BUCKET_SIZE_K,
+    MAX_BUCKET_SIZE_K, MAX_SALT_SIZE,
 };
 use server::Server;
 
-use self::messages::{GetPeersRequestArguments, PutMutableRequestArguments};
+use self::messages::{
+    AnnouncePeerRequestArguments, GetPeersRequestArguments, PutMutableRequestArguments,
+};
 use server::ServerSettings;
 use socket::KrpcSocket;
 
 pub use crate::common::messages;
+pub use bandwidth::{BandwidthStats, MessageBytes, BANDWIDTH_RATE_WINDOW};
+pub use bootstrap_health::{BootstrapHealth, NodeHealth};
+pub use clock::{Clock, SystemClock};
 pub use closest_nodes::ClosestNodes;
-pub use info::Info;
-pub use iterative_query::GetRequestSpecific;
-pub use put_query::{ConcurrencyError, PutError, PutQueryError};
-pub use socket::DEFAULT_REQUEST_TIMEOUT;
+pub use config::{AddressFamily, RequestTimeouts};
+pub use diagnostics::NodeDiagnostics;
+pub use events::{DhtEvent, EventSink, MutableValidator, RequestObserver};
+pub use eviction::{DefaultEvictionPolicy, EvictionPolicy};
+pub use info::{BootstrapStatus, Info};
+pub use iterative_query::{
+    GetRequestSpecific, HopTiming, DEFAULT_QUERY_CONCURRENCY, DEFAULT_QUERY_TIMEOUT,
+};
+pub use metrics::{Metrics, RequestCounts};
+pub use nat::{AddressConfidence, NatType};
+pub use put_query::{
+    ConcurrencyError, PutBatchError, PutError, PutQueryError, DEFAULT_PUT_RETRY_BACKOFF,
+};
+pub use query_summary::{QueryKind, QuerySummary};
+pub use rejected::{RejectedSample, RejectionReason, MAX_REJECTED_SAMPLES};
+pub use socket::{
+    DEFAULT_CLIENT_VERSION, DEFAULT_MAX_INFLIGHT_REQUESTS, DEFAULT_MAX_VALUE_SIZE,
+    DEFAULT_MIN_REQUEST_TIMEOUT, DEFAULT_REQUEST_TIMEOUT, MAX_QUEUED_REQUESTS,
+};
+pub use state::{ImportStateError, STATE_VERSION};
+pub use transport::Transport;
 
 pub const DEFAULT_BOOTSTRAP_NODES: [&str; 4] = [
     "router.bittorrent.com:6881",
@@ -46,26 +88,68 @@ pub const DEFAULT_BOOTSTRAP_NODES: [&str; 4] = [
     "relay.pkarr.org:6881",
 ];
 
-const REFRESH_TABLE_INTERVAL: Duration = Duration::from_secs(15 * 60);
-const PING_TABLE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Maximum number of resolved addresses of each family to keep per bootstrapping hostname,
+/// so a single multi-homed operator doesn't dominate the bootstrapping nodes.
+const MAX_RESOLVED_ADDRESSES_PER_HOST: usize = 2;
+
+/// Maximum random deviation of the refresh and ping intervals, as a fraction of the interval,
+/// so nodes started at the same time don't do their maintenance at the same time.
+const MAINTENANCE_JITTER: f64 = 0.1;
+
+/// Default interval of refreshing the routing table, see [config::Config::refresh_interval].
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Default interval of pinging the routing table nodes, see [config::Config::ping_interval].
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Minimum number of leading bits targets batched in [Rpc::put_batch] share with their lookup,
+/// even if the Dht size estimate is small.
+const MIN_NEIGHBORHOOD_PREFIX_BITS: u8 = 8;
+
+/// Number of routing table nodes pinged at every [config::Config::nat_keepalive_interval].
+const NAT_KEEPALIVE_NODES: usize = 3;
 
-const MAX_CACHED_ITERATIVE_QUERIES: usize = 1000;
+/// Duration after which the responses of a done [Rpc::subscribe] query are dropped,
+/// even if some of its subscribers didn't take them with [Rpc::take_completed].
+const COMPLETED_SUBSCRIPTION_TTL: Duration = Duration::from_secs(60);
+
+/// Default number of done queries to cache the closest nodes of, see [config::Config::query_cache_size].
+pub const DEFAULT_QUERY_CACHE_SIZE: usize = 1000;
 
 #[derive(Debug)]
 /// Internal Rpc called in the Dht thread loop, useful to create your own actor setup.
 pub struct Rpc {
     // Options
-    bootstrap: Box<[SocketAddrV4]>,
+    /// Bootstrapping nodes of both address families,
+    /// in the order of [config::Config::bootstrap_address_family].
+    bootstrap: Box<[SocketAddr]>,
+    address_family: AddressFamily,
+    /// Responses and timeouts of the bootstrapping nodes, to query the healthy ones first.
+    bootstrap_health: BootstrapHealth,
 
     socket: KrpcSocket,
 
     // Routing
     /// Closest nodes to this node
     routing_table: RoutingTable,
+    /// Closest Ipv6 nodes to this node, if [config::Config::ipv6] is enabled.
+    routing_table6: Option<RoutingTable>,
     /// Last time we refreshed the routing table with a find_node query.
     last_table_refresh: Instant,
     /// Last time we pinged nodes in the routing table.
     last_table_ping: Instant,
+    refresh_interval: Duration,
+    ping_interval: Duration,
+    /// [Self::refresh_interval] with a random jitter, recomputed every refresh.
+    refresh_delay: Duration,
+    /// [Self::ping_interval] with a random jitter, recomputed every ping.
+    ping_delay: Duration,
+    /// See [config::Config::nat_keepalive_interval].
+    nat_keepalive_interval: Option<Duration>,
+    /// See [config::Config::eviction_policy].
+    eviction_policy: Arc<dyn EvictionPolicy>,
+    last_nat_keepalive: Instant,
+    /// Index of the next routing table node to ping for the NAT keepalive.
+    nat_keepalive_cursor: usize,
     /// Closest responding nodes to specific target
     ///
     /// as well as the:
@@ -73,12 +157,71 @@ pub struct Rpc {
     /// 2. dht size estimate based on closest responding nodes.
     /// 3. number of subnets with unique 6 bits prefix in ipv4
     cached_iterative_queries: LruCache<Id, CachedIterativeQuery>,
+    /// See [config::Config::query_cache_size].
+    query_cache_size: Option<NonZeroUsize>,
+    /// Estimated size of [Self::cached_iterative_queries], see [config::Config::max_query_cache_bytes].
+    query_cache_bytes: usize,
+    max_query_cache_bytes: Option<usize>,
 
     // Active IterativeQueries
     iterative_queries: HashMap<Id, IterativeQuery>,
+    /// Responses of the queries done in the last tick, see [GetStatus::Done].
+    done_queries: HashMap<Id, Vec<Response>>,
+    /// Responses of done queries, with the time they were done, and the number of
+    /// subscribers that didn't take them yet, see [Rpc::subscribe].
+    completed_subscriptions: HashMap<Id, (Instant, usize, Vec<Response>)>,
+    /// Responses of done queries with their time, see [config::Config::value_cache_ttl].
+    value_cache: LruCache<Id, (Instant, Vec<Response>)>,
+    value_cache_ttl: Option<Duration>,
+    /// See [config::Config::max_peers_per_query].
+    max_peers_per_query: Option<usize>,
     /// Put queries are special, since they have to wait for a corresponding
     /// get query to finish, update the closest_nodes, then `query_all` these.
     put_queries: HashMap<Id, PutQuery>,
+    /// Put queries from [Rpc::put_batch] waiting for a nearby lookup, by the lookup's target.
+    put_batches: HashMap<Id, Vec<Id>>,
+    /// Put queries that failed outside of [Rpc::tick], to report in the next [RpcTickReport::done_put_queries].
+    failed_put_queries: Vec<(Id, PutError)>,
+    /// Put queries that were reported done after reaching the [config::Config::put_ack_threshold],
+    /// but still have inflight requests.
+    background_put_queries: Vec<PutQuery>,
+    /// Put requests to re-run periodically, see [Rpc::track_for_republish].
+    republished: HashMap<Id, Republish>,
+    /// Info hashes to announce periodically, see [Rpc::maintain_announce].
+    announced: HashMap<Id, Reannounce>,
+    put_ack_threshold: Option<usize>,
+    /// See [config::Config::min_storage_nodes].
+    min_storage_nodes: usize,
+    /// Put queries waiting to retry their lookup after finding no nodes to store at.
+    put_retries: HashMap<Id, PutRetry>,
+    put_max_retries: usize,
+    put_retry_backoff: Duration,
+    query_concurrency: usize,
+    query_timeout: Duration,
+
+    /// Active [Rpc::diagnose_node] requests.
+    node_diagnostics: Vec<DiagnosticsQuery>,
+    /// Inflight [Rpc::get_from] requests by their transaction id.
+    direct_requests: HashMap<u16, RequestTypeSpecific>,
+    /// Inflight [Rpc::ping] requests by their transaction id, with the time they were sent.
+    pings: HashMap<u16, Instant>,
+    /// Responses to [Rpc::ping] received in this tick, see [RpcTickReport::ping_responses].
+    ping_responses: Vec<(SocketAddr, Duration)>,
+    /// Targets of the [Rpc::get_first] queries.
+    get_first_queries: HashSet<Id>,
+    /// Targets of the [Rpc::find_live_nodes] queries.
+    live_nodes_queries: HashSet<Id>,
+    /// Info hashes of the [Rpc::join_swarm] queries, with the peers found once the lookup is done.
+    join_swarm_queries: HashMap<Id, Vec<SocketAddr>>,
+    /// Targets of the [Rpc::put_mutable_next] queries, with the items to sign once the lookup is done.
+    put_mutable_next_queries: HashMap<Id, PendingMutable>,
+    /// Responses of queries waiting to be reported in [RpcTickReport::new_query_response],
+    /// when a single message carried more than one, like both peers and a scrape.
+    queued_query_responses: VecDeque<(Id, Response)>,
+    /// Newer mutable item received in this tick, see [RpcTickReport::newer_mutable].
+    newer_mutable: Option<(Id, MutableItem)>,
+    /// New Id after our public IP invalidated the previous one, see [RpcTickReport::node_id_changed].
+    node_id_changed: Option<Id>,
 
     /// Sum of Dht size estimates from closest nodes from get queries.
     dht_size_estimates_sum: f64,
@@ -89,42 +232,162 @@ pub struct Rpc {
 
     /// Sum of the number of subnets with 6 bits prefix in the closest nodes ipv4
     subnets_sum: usize,
+    /// Number of cached queries that finished early, which are left out of the estimates.
+    partial_cached_queries: usize,
+
+    /// Query and cache counters, see [Rpc::metrics].
+    metrics: Metrics,
 
     server: Server,
+    /// Per source IP rate limiting of incoming requests, if [config::Config::max_requests_per_ip_per_sec] is set.
+    rate_limiter: Option<RateLimiter>,
+
+    /// Whether the first self-id find_node query populated the routing table,
+    /// see [RpcTickReport::bootstrapped].
+    bootstrapped: bool,
+    /// Result of the last self-id find_node query, see [Rpc::bootstrap_status].
+    bootstrap_status: BootstrapStatus,
 
     public_address: Option<SocketAddrV4>,
     firewalled: bool,
+    /// Whether the address votes of the last query showed a symmetric NAT, see [Rpc::nat_type].
+    symmetric_nat: Option<bool>,
+    /// Votes backing [Self::public_address] in the last query, see [Rpc::public_address_confidence].
+    public_address_confidence: Option<AddressConfidence>,
+    /// Never switch to server mode, see [config::Config::read_only].
+    read_only: bool,
+    /// Answer find_node requests in client mode, see [config::Config::always_answer_find_node].
+    always_answer_find_node: bool,
+    /// Only store nodes with secure Ids, see [config::Config::enforce_secure_ids].
+    enforce_secure_ids: bool,
+    /// Never replace the Id, see [config::Config::node_id].
+    fixed_id: bool,
+
+    event_sink: Option<EventSink>,
+    /// Set if [config::Config::capture_rejected] is true.
+    rejected_samples: Option<RejectedSamples>,
+    request_observer: Option<RequestObserver>,
+    mutable_validator: Option<MutableValidator>,
+
+    /// Maximum size of values to put, see [config::Config::max_value_size].
+    max_value_size: usize,
+
+    /// Set by [Rpc::shutdown] to stop accepting new queries.
+    shutting_down: bool,
 }
 
 impl Rpc {
     /// Create a new Rpc
-    pub fn new(config: config::Config) -> Result<Self, std::io::Error> {
-        let id = if let Some(ip) = config.public_ip {
+    pub fn new(mut config: config::Config) -> Result<Self, RpcError> {
+        if config.request_timeout.is_zero() {
+            return Err(RpcError::InvalidConfig(
+                "request_timeout must be greater than zero".to_string(),
+            ));
+        }
+
+        // Enabling Ipv6 without a preference uses both families.
+        let address_family = match config.bootstrap_address_family {
+            AddressFamily::V4Only if config.ipv6 => AddressFamily::Both,
+            preference => preference,
+        };
+        config.ipv6 = address_family.ipv6();
+
+        let id = if let Some(id) = config.node_id {
+            id
+        } else if let Some(ip) = config.public_ip {
             Id::from_ip(ip.into())
         } else {
             Id::random()
         };
 
-        let socket = KrpcSocket::new(&config)?;
+        let socket = KrpcSocket::new(&config).map_err(RpcError::BindFailed)?;
+        let now = socket.now();
+
+        let bootstrap: Vec<SocketAddr> = match config.bootstrap {
+            Some(bootstrap) => bootstrap.into_iter().map(SocketAddr::V4).collect(),
+            None if !config.bootstrap_addrs.is_empty() => Vec::new(),
+            None => {
+                let bootstrap = to_socket_address(&DEFAULT_BOOTSTRAP_NODES);
+
+                // Keep running offline, the routing table can still be populated by incoming requests.
+                if bootstrap.is_empty() {
+                    warn!(
+                        "Failed to resolve the default bootstrapping nodes, starting without them"
+                    );
+                }
+
+                bootstrap
+            }
+        };
+
+        let bootstrap = address_family.apply(
+            bootstrap
+                .into_iter()
+                .chain(config.bootstrap6.into_iter().map(SocketAddr::V6))
+                .chain(config.bootstrap_addrs)
+                .collect(),
+        );
 
         Ok(Rpc {
-            bootstrap: config
-                .bootstrap
-                .unwrap_or(to_socket_address(&DEFAULT_BOOTSTRAP_NODES))
-                .into(),
+            bootstrap: bootstrap.into(),
+            address_family,
+            bootstrap_health: config.bootstrap_health.unwrap_or_default(),
             socket,
 
             routing_table: RoutingTable::new(id),
+            routing_table6: config.ipv6.then(|| RoutingTable::new(id)),
             iterative_queries: HashMap::new(),
-            put_queries: HashMap::new(),
-
-            cached_iterative_queries: LruCache::new(
-                NonZeroUsize::new(MAX_CACHED_ITERATIVE_QUERIES)
-                    .expect("MAX_CACHED_BUCKETS is NonZeroUsize"),
+            done_queries: HashMap::new(),
+            completed_subscriptions: HashMap::new(),
+            value_cache: LruCache::new(
+                NonZeroUsize::new(DEFAULT_QUERY_CACHE_SIZE)
+                    .expect("DEFAULT_QUERY_CACHE_SIZE is NonZeroUsize"),
             ),
-
-            last_table_refresh: Instant::now(),
-            last_table_ping: Instant::now(),
+            value_cache_ttl: config.value_cache_ttl,
+            max_peers_per_query: config.max_peers_per_query,
+            put_queries: HashMap::new(),
+            put_batches: HashMap::new(),
+            failed_put_queries: Vec::new(),
+            background_put_queries: Vec::new(),
+            republished: HashMap::new(),
+            announced: HashMap::new(),
+            put_ack_threshold: config.put_ack_threshold.map(|threshold| threshold.get()),
+            min_storage_nodes: config.min_storage_nodes.max(1),
+            put_retries: HashMap::new(),
+            put_max_retries: config.put_max_retries,
+            put_retry_backoff: config.put_retry_backoff,
+            query_concurrency: config.query_concurrency,
+            query_timeout: config.query_timeout,
+            node_diagnostics: Vec::new(),
+            direct_requests: HashMap::new(),
+            pings: HashMap::new(),
+            ping_responses: Vec::new(),
+            queued_query_responses: VecDeque::new(),
+            newer_mutable: None,
+            node_id_changed: None,
+            get_first_queries: HashSet::new(),
+            live_nodes_queries: HashSet::new(),
+            join_swarm_queries: HashMap::new(),
+            put_mutable_next_queries: HashMap::new(),
+
+            cached_iterative_queries: match config.query_cache_size {
+                Some(size) => LruCache::new(size),
+                None => LruCache::unbounded(),
+            },
+            query_cache_size: config.query_cache_size,
+            query_cache_bytes: 0,
+            max_query_cache_bytes: config.max_query_cache_bytes,
+
+            last_table_refresh: now,
+            last_table_ping: now,
+            refresh_interval: config.refresh_interval,
+            ping_interval: config.ping_interval,
+            refresh_delay: jitter(config.refresh_interval),
+            ping_delay: jitter(config.ping_interval),
+            nat_keepalive_interval: config.nat_keepalive_interval,
+            eviction_policy: config.eviction_policy.clone(),
+            last_nat_keepalive: now,
+            nat_keepalive_cursor: 0,
 
             dht_size_estimates_sum: 0.0,
             responders_based_dht_size_estimates_count: 0,
@@ -132,11 +395,37 @@ impl Rpc {
             // Don't store to too many nodes just because you are in a cold start.
             responders_based_dht_size_estimates_sum: 1_000_000.0,
             subnets_sum: 20,
+            partial_cached_queries: 0,
+
+            metrics: Metrics::default(),
+
+            server: {
+                let mut server = Server::new(config.server_settings);
+                server.set_token_rotation_interval(config.token_rotation_interval);
+
+                server
+            },
+            rate_limiter: config.max_requests_per_ip_per_sec.map(RateLimiter::new),
 
-            server: Server::new(config.server_settings),
+            bootstrapped: false,
+            bootstrap_status: BootstrapStatus::InProgress,
 
             public_address: None,
             firewalled: true,
+            symmetric_nat: None,
+            public_address_confidence: None,
+            read_only: config.read_only,
+            always_answer_find_node: config.always_answer_find_node,
+            enforce_secure_ids: config.enforce_secure_ids,
+            fixed_id: config.node_id.is_some(),
+            max_value_size: config.max_value_size,
+
+            event_sink: config.event_sink,
+            rejected_samples: config.capture_rejected.then(RejectedSamples::default),
+            request_observer: config.request_observer,
+            mutable_validator: config.mutable_validator,
+
+            shutting_down: false,
         })
     }
 
@@ -153,6 +442,12 @@ impl Rpc {
         self.socket.local_addr()
     }
 
+    /// Returns the address the Ipv6 socket is listening to, if [config::Config::ipv6] is enabled.
+    #[inline]
+    pub fn local_addr6(&self) -> Option<SocketAddrV6> {
+        self.socket.local_addr6()
+    }
+
     /// Returns the best guess for this node's Public address.
     ///
     /// If [crate::DhtBuilder::public_ip] was set, this is what will be returned
@@ -162,6 +457,23 @@ impl Rpc {
         self.public_address
     }
 
+    /// Returns the address voted for by most responders of the last query,
+    /// with the number of responders that voted for it and against it.
+    ///
+    /// Useful to decide whether to trust [Self::public_address] enough to advertise it.
+    pub fn public_address_confidence(&self) -> Option<AddressConfidence> {
+        self.public_address_confidence
+    }
+
+    /// Returns the port that responding nodes see this node's requests coming from,
+    /// according to the votes of the last query.
+    ///
+    /// Behind a NAT, this may differ from the port of [Self::local_addr].
+    pub fn observed_external_port(&self) -> Option<u16> {
+        self.public_address_confidence
+            .map(|confidence| confidence.address.port())
+    }
+
     /// Returns `true` if we can't confirm that [Self::public_address] is publicly addressable.
     ///
     /// If this node is firewalled, it won't switch to server mode if it is in adaptive mode,
@@ -171,36 +483,194 @@ impl Rpc {
         self.firewalled
     }
 
+    /// Returns the type of NAT this node is behind, according to the
+    /// public address votes of the responders of the last query.
+    pub fn nat_type(&self) -> NatType {
+        match self.symmetric_nat {
+            None => NatType::Unknown,
+            Some(true) => NatType::SymmetricNat,
+            Some(false) if self.firewalled => NatType::ConeNat,
+            Some(false) => NatType::Open,
+        }
+    }
+
     /// Returns whether or not this node is running in server mode.
     pub fn server_mode(&self) -> bool {
         self.socket.server_mode
     }
 
+    /// Returns whether the first find_node query for this node's Id is still running,
+    /// or the last one populated the routing table or failed to.
+    pub fn bootstrap_status(&self) -> BootstrapStatus {
+        self.bootstrap_status
+    }
+
+    /// Returns the current Ipv4 bootstrapping nodes.
+    pub fn bootstrap(&self) -> Vec<SocketAddrV4> {
+        split_address_families(self.bootstrap.iter().copied()).0
+    }
+
+    /// Returns the current Ipv6 bootstrapping nodes, if [config::Config::ipv6] is enabled.
+    pub fn bootstrap6(&self) -> Vec<SocketAddrV6> {
+        split_address_families(self.bootstrap.iter().copied()).1
+    }
+
+    /// Returns the responses and timeouts of the bootstrapping nodes so far.
+    pub fn bootstrap_health(&self) -> &BootstrapHealth {
+        &self.bootstrap_health
+    }
+
     pub fn routing_table(&self) -> &RoutingTable {
         &self.routing_table
     }
 
+    /// Returns the Ipv6 routing table, if [config::Config::ipv6] is enabled.
+    pub fn routing_table6(&self) -> Option<&RoutingTable> {
+        self.routing_table6.as_ref()
+    }
+
+    /// Turn the routing tables to a list of bootstrapping nodes,
+    /// excluding nodes that are stale at the time of the [Clock].
+    pub fn to_bootstrap(&self) -> Vec<String> {
+        let now = self.socket.now();
+        let mut bootstrap = self.routing_table.to_bootstrap_at(now);
+
+        if let Some(routing_table6) = &self.routing_table6 {
+            bootstrap.extend(routing_table6.to_bootstrap_at(now));
+        }
+
+        bootstrap
+    }
+
+    /// Returns the closest responding nodes cached from the last done
+    /// query for the given target, if any.
+    ///
+    /// These are the nodes that [Rpc::put] uses directly without a new lookup,
+    /// as long as any of them has a [valid token](Node::valid_token).
+    pub fn cached_closest_nodes(&self, target: &Id) -> Option<&[Node]> {
+        self.cached_iterative_queries
+            .peek(target)
+            .map(|cached| cached.closest_responding_nodes.as_ref())
+    }
+
+    /// Returns the closest responding nodes cached from the last done query
+    /// for the given target, with the tokens they responded with.
+    ///
+    /// Useful to hold on to the tokens and announce or store later, for example
+    /// after a user confirmation. Tokens expire (usually within 10 minutes), so
+    /// check [Node::token_received_at] or [Node::valid_token] before using them.
+    pub fn responders_with_tokens(&self, target: &Id) -> Vec<(Node, Box<[u8]>)> {
+        self.cached_closest_nodes(target)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|node| node.token().map(|token| (node.clone(), token)))
+            .collect()
+    }
+
+    /// Returns the timing of every node visited by the active or last done
+    /// query for the given target, if any.
+    ///
+    /// Useful to debug slow lookups, by finding slow or unresponsive hops.
+    pub fn last_query_trace(&self, target: &Id) -> Option<Vec<HopTiming>> {
+        if let Some(query) = self.iterative_queries.get(target) {
+            return Some(query.trace());
+        }
+
+        self.cached_iterative_queries
+            .peek(target)
+            .map(|cached| cached.trace.to_vec())
+    }
+
     /// Returns:
     ///  1. Normal Dht size estimate based on all closer `nodes` in query responses.
     ///  2. Standard deviaiton as a function of the number of samples used in this estimate.
     ///
     /// [Read more](https://github.com/pubky/mainline/blob/main/docs/dht_size_estimate.md)
     pub fn dht_size_estimate(&self) -> (usize, f64) {
-        let normal =
-            self.dht_size_estimates_sum as usize / self.cached_iterative_queries.len().max(1);
+        let normal = self.dht_size_estimates_sum as usize / self.complete_cached_queries().max(1);
 
         // See https://github.com/pubky/mainline/blob/main/docs/standard-deviation-vs-lookups.png
-        let std_dev = 0.281 * (self.cached_iterative_queries.len() as f64).powf(-0.529);
+        let std_dev = 0.281 * (self.complete_cached_queries() as f64).powf(-0.529);
 
         (normal, std_dev)
     }
 
+    /// Returns a rough estimate of how long a lookup for the given target would take,
+    /// as one round trip per hop, with `log2` of the [Self::dht_size_estimate] hops,
+    /// or a single hop if the target's closest nodes are cached from a previous query.
+    ///
+    /// Uses the smoothed round trip time of responses so far, or the request timeout
+    /// before any responses.
+    pub fn estimated_lookup_time(&self, target: Id) -> Duration {
+        let rtt = self
+            .socket
+            .average_rtt()
+            .unwrap_or(self.socket.request_timeout());
+
+        let hops = if self.cached_iterative_queries.contains(&target) {
+            1
+        } else {
+            (self.dht_size_estimate().0.max(2) as f64).log2().ceil() as u32
+        };
+
+        rtt * hops
+    }
+
     /// Returns a thread safe and lightweight summary of this node's
     /// information and statistics.
     pub fn info(&self) -> Info {
         Info::from(self)
     }
 
+    /// Returns the most recent invalid values received from other nodes,
+    /// oldest first, if [config::Config::capture_rejected] is enabled.
+    pub fn rejected_samples(&self) -> Vec<RejectedSample> {
+        self.rejected_samples
+            .as_ref()
+            .map(RejectedSamples::to_vec)
+            .unwrap_or_default()
+    }
+
+    /// Returns a summary of every active get and put query, for introspection.
+    pub fn active_queries(&self) -> Vec<QuerySummary> {
+        self.iterative_queries
+            .values()
+            .map(|query| query.summary(&self.socket))
+            .chain(
+                self.put_queries
+                    .values()
+                    .map(|query| query.summary(&self.socket)),
+            )
+            .collect()
+    }
+
+    /// Returns the bytes this node sent and received, in total, by message type,
+    /// and per second over the last [BANDWIDTH_RATE_WINDOW].
+    pub fn bandwidth(&self) -> BandwidthStats {
+        self.socket.bandwidth()
+    }
+
+    /// Returns a snapshot of counters of this node's activity, for monitoring.
+    pub fn metrics(&self) -> Metrics {
+        let socket = self.socket.metrics();
+
+        Metrics {
+            requests_sent: socket.requests_sent,
+            requests_received: socket.requests_received,
+            responses_received: socket.responses_received,
+            timeouts: socket.timeouts,
+            dropped_sends: socket.dropped_sends,
+            routing_table_size: self.routing_table.size()
+                + self
+                    .routing_table6
+                    .as_ref()
+                    .map(|routing_table6| routing_table6.size())
+                    .unwrap_or_default(),
+            dht_size_estimate: self.dht_size_estimate().0,
+            ..self.metrics.clone()
+        }
+    }
+
     // === Public Methods ===
 
     /// Advance the inflight queries, receive incoming requests,
@@ -208,18 +678,61 @@ impl Rpc {
     /// to happen at every tick.
     pub fn tick(&mut self) -> RpcTickReport {
         let mut done_get_queries = Vec::with_capacity(self.iterative_queries.len());
+        let mut done_live_nodes = Vec::new();
+        let mut done_join_swarms = Vec::new();
         let mut done_put_queries = Vec::with_capacity(self.put_queries.len());
+        let mut done_background_put_queries = Vec::new();
+        let mut bootstrapped = false;
+        let mut bootstrap_failed = false;
+
+        self.done_queries.clear();
+
+        let now = self.socket.now();
+        self.completed_subscriptions
+            .retain(|_, (completed_at, _, _)| {
+                now.saturating_duration_since(*completed_at) < COMPLETED_SUBSCRIPTION_TTL
+            });
 
         // === Tick Queries ===
 
+        let (done_diagnostics, node_diagnostics): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.node_diagnostics)
+                .into_iter()
+                .partition(|query| query.is_done(&self.socket));
+        self.node_diagnostics = node_diagnostics;
+        self.direct_requests
+            .retain(|tid, _| self.socket.inflight(tid));
+        self.pings.retain(|tid, _| self.socket.inflight(tid));
+
+        let done_node_diagnostics = done_diagnostics
+            .into_iter()
+            .map(|query| query.report())
+            .collect();
+
+        self.background_put_queries.retain(|query| {
+            if query.is_done(&self.socket) {
+                done_background_put_queries.push((query.target, query.stored_at() as usize));
+
+                return false;
+            }
+
+            true
+        });
+
+        done_put_queries.extend(
+            self.failed_put_queries
+                .drain(..)
+                .map(|(id, error)| (id, Err(error))),
+        );
+
         for (id, query) in self.put_queries.iter_mut() {
             match query.tick(&self.socket) {
                 Ok(done) => {
                     if done {
-                        done_put_queries.push((*id, None));
+                        done_put_queries.push((*id, Ok(query.stored_at() as usize)));
                     }
                 }
-                Err(error) => done_put_queries.push((*id, Some(error))),
+                Err(error) => done_put_queries.push((*id, Err(error))),
             };
         }
 
@@ -238,8 +751,16 @@ impl Rpc {
                         if *id == self_id {
                             if table_size == 0 {
                                 error!("Could not bootstrap the routing table");
+                                self.bootstrap_status = BootstrapStatus::Failed;
+                                bootstrap_failed = true;
                             } else {
                                 debug!(?self_id, table_size, "Populated the routing table");
+                                self.bootstrap_status = BootstrapStatus::Done { table_size };
+
+                                if !self.bootstrapped {
+                                    self.bootstrapped = true;
+                                    bootstrapped = true;
+                                }
                             }
                         };
 
@@ -259,23 +780,105 @@ impl Rpc {
                     };
 
                 done_get_queries.push((*id, closest_nodes));
+
+                if self.live_nodes_queries.remove(id) {
+                    done_live_nodes.push((*id, query.live_nodes()));
+                }
             };
         }
 
+        if bootstrap_failed {
+            self.emit(DhtEvent::BootstrapFailed);
+        }
+
         // === Cleanup done queries ===
 
+        self.metrics.queries_completed += done_get_queries.len() as u64;
+
         // Has to happen _before_ `self.socket.recv_from()`.
         for (id, closest_nodes) in &done_get_queries {
-            if let Some(query) = self.iterative_queries.remove(id) {
+            if let Some(mut query) = self.iterative_queries.remove(id) {
+                self.get_first_queries.remove(id);
+                if let Some(peers) = self.join_swarm_queries.get_mut(id) {
+                    *peers = query.take_peers();
+                }
+                let responses = query.take_responses();
+                if query.subscribers() > 0 {
+                    let now = self.socket.now();
+                    let (completed_at, subscribers, subscription_responses) = self
+                        .completed_subscriptions
+                        .entry(*id)
+                        .or_insert_with(|| (now, 0, Vec::new()));
+
+                    *completed_at = now;
+                    *subscribers += query.subscribers();
+                    *subscription_responses = responses.clone();
+                }
+                if self.value_cache_ttl.is_some() && !responses.is_empty() {
+                    self.value_cache
+                        .put(*id, (self.socket.now(), responses.clone()));
+                }
+                self.update_bootstrap_health(&query);
                 self.update_address_votes_from_iterative_query(&query);
                 self.cache_iterative_query(&query, closest_nodes);
 
+                if let Some(pending) = self.put_mutable_next_queries.remove(id) {
+                    if let Err(error) = self.put_mutable_next_query(pending, &responses) {
+                        done_put_queries.push((*id, Err(error)));
+                    }
+                }
+
+                // Kept until the next tick, for [Rpc::get] to return them as [GetStatus::Done].
+                self.done_queries.insert(*id, responses);
+
+                // A [Rpc::get_first] query that finished early only has some of the closest nodes,
+                // so puts waiting for it need a full lookup.
+                if let Some(put_query) = self
+                    .put_queries
+                    .get(id)
+                    .filter(|put_query| query.is_finished() && !put_query.started())
+                {
+                    debug!(?id, "Restarting the lookup of a put after a partial query");
+
+                    let salt = match &put_query.request {
+                        PutRequestSpecific::PutMutable(args) => args.salt.clone(),
+                        _ => None,
+                    };
+
+                    self.start_query(
+                        GetRequestSpecific::GetValue(GetValueRequestArguments {
+                            target: *id,
+                            seq: None,
+                            salt,
+                        }),
+                        None,
+                    );
+
+                    continue;
+                }
+
                 // Only for get queries, not find node.
                 if !matches!(query.request.request_type, RequestTypeSpecific::FindNode(_)) {
-                    if let Some(put_query) = self.put_queries.get_mut(id) {
-                        if !put_query.started() {
-                            if let Err(error) = put_query.start(&mut self.socket, closest_nodes) {
-                                done_put_queries.push((*id, Some(error)))
+                    let batched = self.put_batches.remove(id).unwrap_or_default();
+
+                    for target in std::iter::once(*id).chain(batched) {
+                        let result = match self.put_queries.get_mut(&target) {
+                            Some(put_query) if !put_query.started() => put_query
+                                .start(&mut self.socket, closest_nodes)
+                                .and_then(|_| {
+                                    // None of the closest nodes gave us a token.
+                                    if put_query.started() {
+                                        Ok(())
+                                    } else {
+                                        Err(PutQueryError::NoClosestNodes)?
+                                    }
+                                }),
+                            _ => Ok(()),
+                        };
+
+                        if let Err(error) = result {
+                            if !self.schedule_put_retry(target) {
+                                done_put_queries.push((target, Err(error)))
                             }
                         }
                     }
@@ -283,12 +886,29 @@ impl Rpc {
             };
         }
 
-        for (id, _) in &done_put_queries {
-            self.put_queries.remove(id);
+        for (id, result) in &done_put_queries {
+            if let Some(peers) = self.join_swarm_queries.remove(id) {
+                done_join_swarms.push((
+                    *id,
+                    JoinSwarmReport {
+                        peers,
+                        announced: result.clone(),
+                    },
+                ));
+            }
+
+            if let Some(query) = self.put_queries.remove(id) {
+                if result.is_ok() && !query.is_done(&self.socket) {
+                    self.background_put_queries.push(query);
+                }
+            }
         }
 
         // === Periodic node maintaenance ===
         self.periodic_node_maintaenance();
+        self.republish();
+        self.reannounce();
+        self.retry_puts();
 
         // Handle new incoming message
         let new_query_response = self
@@ -303,22 +923,41 @@ impl Rpc {
                 _ => self.handle_response(from, message),
             });
 
+        // Report queued responses first, one per tick, to keep their order.
+        let new_query_response = match new_query_response {
+            Some(response) if !self.queued_query_responses.is_empty() => {
+                self.queued_query_responses.push_back(response);
+                self.queued_query_responses.pop_front()
+            }
+            Some(response) => Some(response),
+            None => self.queued_query_responses.pop_front(),
+        };
+
         RpcTickReport {
             done_get_queries,
             done_put_queries,
+            done_background_put_queries,
+            done_node_diagnostics,
             new_query_response,
+            bootstrapped,
+            ping_responses: std::mem::take(&mut self.ping_responses),
+            newer_mutable: self.newer_mutable.take(),
+            node_id_changed: self.node_id_changed.take(),
+            send_pressure: self.socket.take_send_pressure(),
+            done_live_nodes,
+            done_join_swarms,
         }
     }
 
     /// Send a request to the given address and return the transaction_id
-    pub fn request(&mut self, address: SocketAddrV4, request: RequestSpecific) -> u16 {
+    pub fn request(&mut self, address: SocketAddr, request: RequestSpecific) -> u16 {
         self.socket.request(address, request)
     }
 
     /// Send a response to the given address.
     pub fn response(
         &mut self,
-        address: SocketAddrV4,
+        address: SocketAddr,
         transaction_id: u16,
         response: ResponseSpecific,
     ) {
@@ -326,7 +965,7 @@ impl Rpc {
     }
 
     /// Send an error to the given address.
-    pub fn error(&mut self, address: SocketAddrV4, transaction_id: u16, error: ErrorSpecific) {
+    pub fn error(&mut self, address: SocketAddr, transaction_id: u16, error: ErrorSpecific) {
         self.socket.error(address, transaction_id, error)
     }
 
@@ -339,47 +978,36 @@ impl Rpc {
         request: PutRequestSpecific,
         extra_nodes: Option<Box<[Node]>>,
     ) -> Result<(), PutError> {
-        let target = *request.target();
+        if self.shutting_down {
+            return Err(PutQueryError::ShuttingDown)?;
+        }
 
-        if let PutRequestSpecific::PutMutable(PutMutableRequestArguments {
-            sig, cas, seq, ..
-        }) = &request
-        {
-            if let Some(PutRequestSpecific::PutMutable(inflight_request)) = self
-                .put_queries
-                .get(&target)
-                .map(|existing| &existing.request)
-            {
-                debug!(?inflight_request, ?request, "Possible conflict risk");
+        validate_put_request(&request, self.max_value_size)?;
 
-                if *sig == inflight_request.sig {
-                    // Noop, the inflight query is sufficient.
-                    return Ok(());
-                } else if *seq < inflight_request.seq {
-                    return Err(ConcurrencyError::NotMostRecent)?;
-                } else if let Some(cas) = cas {
-                    if *cas == inflight_request.seq {
-                        // The user is aware of the inflight query and whiches to overrides it.
-                        //
-                        // Remove the inflight request, and create a new one.
-                        self.put_queries.remove(&target);
-                    } else {
-                        return Err(ConcurrencyError::CasFailed)?;
-                    }
-                } else {
-                    return Err(ConcurrencyError::ConflictRisk)?;
-                };
-            };
+        let target = *request.target();
+
+        if !self.check_put_concurrency(&request)? {
+            // Noop, the inflight query is sufficient.
+            return Ok(());
         }
 
-        let mut query = PutQuery::new(target, request.clone(), extra_nodes);
+        let now = self.socket.now();
+        let mut query = PutQuery::new(
+            target,
+            request.clone(),
+            extra_nodes,
+            self.put_ack_threshold,
+            self.min_storage_nodes,
+            now,
+        );
 
         if let Some(closest_nodes) = self
             .cached_iterative_queries
             .get(&target)
+            .filter(|cached| !cached.partial)
             .map(|cached| cached.closest_responding_nodes.clone())
             .filter(|closest_nodes| {
-                !closest_nodes.is_empty() && closest_nodes.iter().any(|n| n.valid_token())
+                !closest_nodes.is_empty() && closest_nodes.iter().any(|n| n.valid_token_at(now))
             })
         {
             query.start(&mut self.socket, &closest_nodes)?
@@ -389,7 +1017,7 @@ impl Rpc {
                 _ => None,
             };
 
-            self.get(
+            self.start_query(
                 GetRequestSpecific::GetValue(GetValueRequestArguments {
                     target,
                     seq: None,
@@ -397,6 +1025,9 @@ impl Rpc {
                 }),
                 None,
             );
+
+            // Joining a [Rpc::get_first] query, which needs to run to the end now.
+            self.get_first_queries.remove(&target);
         };
 
         self.put_queries.insert(target, query);
@@ -404,539 +1035,6193 @@ impl Rpc {
         Ok(())
     }
 
-    /// Send a message to closer and closer nodes until we can't find any more nodes.
-    ///
-    /// Queries take few seconds to fully traverse the network, once it is done, it will be removed from
-    /// self.iterative_queries. But until then, calling [Rpc::get] multiple times, will just return the list
-    /// of responses seen so far.
+    /// Find the peers of an info hash, and announce this node as a peer to the closest
+    /// nodes that gave us a token, as a torrent client joining a swarm would.
     ///
-    /// Subsequent responses can be obtained from the [RpcTickReport::new_query_response] you get after calling [Rpc::tick].
+    /// Unlike announcing with [Rpc::put], the announcement always waits for a fresh
+    /// get_peers lookup, and is reported in [RpcTickReport::done_join_swarms] with
+    /// all the peers found, in addition to [RpcTickReport::done_put_queries].
     ///
-    /// Effectively, we are caching responses and backing off the network for the duration it takes
-    /// to traverse it.
+    /// If `port` is None, the port is implied by remote nodes to be the one
+    /// they received the request from.
     ///
-    /// - `request` [RequestTypeSpecific], except [RequestTypeSpecific::Ping] and
-    ///     [RequestTypeSpecific::Put] which will be ignored.
-    /// - `extra_nodes` option allows the query to visit specific nodes, that won't necessesarily be visited
-    ///     through the query otherwise.
-    pub fn get(
-        &mut self,
-        request: GetRequestSpecific,
-        extra_nodes: Option<&[SocketAddrV4]>,
-    ) -> Option<Vec<Response>> {
-        let target = match request {
-            GetRequestSpecific::FindNode(FindNodeRequestArguments { target }) => target,
-            GetRequestSpecific::GetPeers(GetPeersRequestArguments { info_hash, .. }) => info_hash,
-            GetRequestSpecific::GetValue(GetValueRequestArguments { target, .. }) => target,
-        };
-
-        let response_from_inflight_put_mutable_request =
-            self.put_queries.get(&target).and_then(|existing| {
-                if let PutRequestSpecific::PutMutable(request) = &existing.request {
-                    Some(Response::Mutable(request.clone().into()))
-                } else {
-                    None
-                }
-            });
-
-        // If query is still active, no need to create a new one.
-        if let Some(query) = self.iterative_queries.get(&target) {
-            let mut responses = query.responses().to_vec();
-
-            if let Some(response) = response_from_inflight_put_mutable_request {
-                responses.push(response);
-            }
-
-            return Some(responses);
+    /// Joining a swarm that is already being joined is a noop, but any other
+    /// inflight [Rpc::put] for the same info hash returns [ConcurrencyError::Inflight].
+    pub fn join_swarm(&mut self, info_hash: Id, port: Option<u16>) -> Result<(), PutError> {
+        if self.shutting_down {
+            return Err(PutQueryError::ShuttingDown)?;
         }
 
-        let node_id = self.routing_table.id();
+        if self.join_swarm_queries.contains_key(&info_hash) {
+            // Noop, the inflight query is sufficient.
+            return Ok(());
+        }
 
-        if target == *node_id {
-            debug!(?node_id, "Bootstrapping the routing table");
+        if self.put_queries.contains_key(&info_hash) {
+            return Err(ConcurrencyError::Inflight)?;
         }
 
-        let mut query = IterativeQuery::new(*self.id(), target, request);
+        let (port, implied_port) = match port {
+            Some(port) => (port, None),
+            None => (0, Some(true)),
+        };
 
-        // Seed the query either with the closest nodes from the routing table, or the
-        // bootstrapping nodes if the closest nodes are not enough.
+        let request = PutRequestSpecific::AnnouncePeer(AnnouncePeerRequestArguments {
+            info_hash,
+            port,
+            implied_port,
+            seed: false,
+        });
+
+        self.start_query(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
+            None,
+        );
 
-        let routing_table_closest = self.routing_table.closest_secure(
-            target,
-            self.responders_based_dht_size_estimate(),
-            self.average_subnets(),
+        self.put_queries.insert(
+            info_hash,
+            PutQuery::new(
+                info_hash,
+                request,
+                None,
+                self.put_ack_threshold,
+                self.min_storage_nodes,
+                self.socket.now(),
+            ),
         );
+        self.join_swarm_queries.insert(info_hash, Vec::new());
 
-        // If we don't have enough or any closest nodes, call the bootstrapping nodes.
-        if routing_table_closest.is_empty() || routing_table_closest.len() < self.bootstrap.len() {
-            for bootstrapping_node in self.bootstrap.clone() {
-                query.visit(&mut self.socket, bootstrapping_node);
-            }
-        }
+        Ok(())
+    }
 
-        if let Some(extra_nodes) = extra_nodes {
-            for extra_node in extra_nodes {
-                query.visit(&mut self.socket, *extra_node)
-            }
+    /// Publish a mutable item with the next `seq` after the most recent one found
+    /// in the network, without having to track it locally.
+    ///
+    /// The item is signed once a fresh lookup for the target is done, with `seq` set to
+    /// the highest `seq` found plus one, and `cas` set to that highest `seq`, so the put
+    /// fails instead of overwriting a concurrent update. If no item is found, the `seq`
+    /// is the current timestamp in microseconds.
+    ///
+    /// The put is reported in [RpcTickReport::done_put_queries] like [Rpc::put],
+    /// and until then, another call for the same target returns [ConcurrencyError::Inflight].
+    pub fn put_mutable_next(
+        &mut self,
+        signer: SigningKey,
+        value: &[u8],
+        salt: Option<&[u8]>,
+    ) -> Result<(), PutError> {
+        if self.shutting_down {
+            return Err(PutQueryError::ShuttingDown)?;
         }
 
-        // Seed this query with the closest nodes we know about.
-        for node in routing_table_closest {
-            query.add_candidate(node)
+        if value.len() > self.max_value_size {
+            return Err(PutQueryError::ValueTooLarge(value.len()))?;
+        }
+        if let Some(salt) = salt.filter(|salt| salt.len() > MAX_SALT_SIZE) {
+            return Err(PutQueryError::SaltTooLarge(salt.len()))?;
         }
 
-        if let Some(CachedIterativeQuery {
-            closest_responding_nodes,
-            ..
-        }) = self.cached_iterative_queries.get(&target)
-        {
-            for node in closest_responding_nodes {
-                query.add_candidate(node.clone())
-            }
+        let target = MutableItem::target_from_key(signer.verifying_key().as_bytes(), salt);
+
+        if self.put_mutable_next_queries.contains_key(&target) {
+            return Err(ConcurrencyError::Inflight)?;
         }
 
-        // After adding the nodes, we need to start the query.
-        query.start(&mut self.socket);
+        self.start_query(
+            GetRequestSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: salt.map(|salt| salt.into()),
+            }),
+            None,
+        );
 
-        self.iterative_queries.insert(target, query);
+        self.put_mutable_next_queries.insert(
+            target,
+            PendingMutable {
+                signer,
+                value: value.into(),
+                salt: salt.map(|salt| salt.into()),
+            },
+        );
 
-        // If there is an inflight PutQuery for mutable item return its value
-        if let Some(response) = response_from_inflight_put_mutable_request {
-            return Some(vec![response]);
+        Ok(())
+    }
+
+    /// Find the closest nodes to a target that are known to be alive,
+    /// because they responded to the find_node query itself.
+    ///
+    /// The nodes are reported in [RpcTickReport::done_live_nodes] once the query is
+    /// done, while [RpcTickReport::done_get_queries] also includes nodes that were
+    /// only seen in responses of other nodes, and may never respond to this node.
+    pub fn find_live_nodes(&mut self, target: Id) {
+        if self.shutting_down {
+            return;
         }
 
-        None
+        self.start_query(
+            GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                target,
+                want: vec![],
+            }),
+            None,
+        );
+
+        self.live_nodes_queries.insert(target);
     }
 
-    // === Private Methods ===
+    /// Refresh the routing table now with a find_node query for this node's Id,
+    /// and restart the timer of the periodic refresh, see [config::Config::refresh_interval].
+    ///
+    /// Useful after a connectivity change, like waking up from sleep or changing networks.
+    /// Does nothing if a refresh query is already running.
+    pub fn refresh(&mut self) {
+        self.last_table_refresh = self.socket.now();
+        self.refresh_delay = jitter(self.refresh_interval);
 
-    fn handle_request(
-        &mut self,
-        from: SocketAddrV4,
-        transaction_id: u16,
-        request_specific: RequestSpecific,
-    ) {
-        let is_ping = matches!(request_specific.request_type, RequestTypeSpecific::Ping);
+        self.populate();
+    }
 
-        if self.server_mode() {
-            let server = &mut self.server;
+    /// Fill the sparse buckets of the routing table faster than [Rpc::refresh],
+    /// with a find_node query for a random Id in the range of every bucket that
+    /// isn't full, see [RoutingTable::sparse_buckets].
+    pub fn refresh_buckets(&mut self) {
+        for distance in self.routing_table.sparse_buckets() {
+            let target = self.id().random_at_distance(distance);
 
-            match server.handle_request(&self.routing_table, from, request_specific) {
-                Some(MessageType::Error(error)) => {
-                    self.error(from, transaction_id, error);
-                }
-                Some(MessageType::Response(response)) => {
-                    self.response(from, transaction_id, response);
-                }
-                _ => {}
+            self.start_query(
+                GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                    target,
+                    want: vec![],
+                }),
+                None,
+            );
+        }
+    }
+
+    /// Populate the cache of closest nodes to a target, for example from
+    /// nodes persisted from a previous session, so the next queries to that
+    /// target can skip the full traversal.
+    ///
+    /// Nodes with a [valid token](Node::valid_token) are used directly by the
+    /// next [Rpc::put], and all the nodes are visited first by the next query to
+    /// this target, which replaces them with the nodes that actually respond.
+    ///
+    /// This is a noop if the cache is disabled, see [config::Config::query_cache_size].
+    pub fn seed_query_cache(&mut self, target: Id, nodes: Vec<Node>) {
+        if self.query_cache_size.is_none() {
+            return;
+        }
+        let mut query = IterativeQuery::new(
+            *self.id(),
+            target,
+            GetRequestSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: None,
+            }),
+            self.query_concurrency,
+            self.query_timeout,
+            self.socket.now(),
+        );
+
+        for node in &nodes {
+            query.add_candidate(node.clone());
+            query.add_responding_node(node.clone());
+        }
+
+        self.cache_iterative_query(&query, &nodes);
+    }
+
+    /// Export the node Id, the nodes of the routing tables, and the closest nodes
+    /// of the cached queries, in a compact versioned binary format.
+    ///
+    /// Pass the result to [Rpc::import_state] of a new [Rpc], for example after a restart,
+    /// to skip bootstrapping and warm up the cache of previous queries.
+    pub fn export_state(&self) -> Vec<u8> {
+        let mut nodes = self.routing_table.to_owned_nodes();
+        if let Some(table) = &self.routing_table6 {
+            nodes.extend(table.to_owned_nodes());
+        }
+
+        let mut cached_queries = self
+            .cached_iterative_queries
+            .iter()
+            .map(|(target, cached)| (*target, cached.closest_responding_nodes.to_vec()))
+            .collect::<Vec<_>>();
+        // Least recently used first, so importing them in order keeps the same order.
+        cached_queries.reverse();
+
+        RpcState {
+            id: *self.id(),
+            nodes,
+            cached_queries,
+        }
+        .encode()
+    }
+
+    /// Restore the state exported by [Rpc::export_state], replacing the node Id,
+    /// the routing tables, and the cached queries.
+    ///
+    /// Ipv6 nodes are ignored unless [config::Config::ipv6] is enabled, and cached
+    /// queries are ignored if the cache is disabled, see [config::Config::query_cache_size].
+    pub fn import_state(&mut self, bytes: &[u8]) -> Result<(), ImportStateError> {
+        let state = RpcState::decode(bytes)?;
+
+        let (nodes6, nodes): (Vec<_>, Vec<_>) =
+            state.nodes.into_iter().partition(|node| node.is_ipv6());
+
+        self.routing_table = RoutingTable::from_nodes(state.id, nodes);
+        if self.routing_table6.is_some() {
+            self.routing_table6 = Some(RoutingTable::from_nodes(state.id, nodes6));
+        }
+
+        while let Some((_, cached)) = self.cached_iterative_queries.pop_lru() {
+            self.decrement_cached_iterative_query_stats(Some(cached));
+        }
+        for (target, nodes) in state.cached_queries {
+            self.seed_query_cache(target, nodes);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the nodes a [Rpc::put] of this request would store the value at,
+    /// without sending any store requests.
+    ///
+    /// If there are no cached closest nodes for the target, a GET query is started
+    /// and an empty list is returned, the nodes are then reported in
+    /// [RpcTickReport::done_get_queries] once the query is done.
+    pub fn put_dry_run(&mut self, request: PutRequestSpecific) -> Vec<Node> {
+        let target = *request.target();
+        let now = self.socket.now();
+
+        if let Some(closest_nodes) = self
+            .cached_iterative_queries
+            .get(&target)
+            .filter(|cached| !cached.partial)
+            .map(|cached| cached.closest_responding_nodes.clone())
+            .filter(|closest_nodes| closest_nodes.iter().any(|n| n.valid_token_at(now)))
+        {
+            return closest_nodes
+                .iter()
+                .filter(|node| node.token().is_some())
+                .cloned()
+                .collect();
+        }
+
+        if !self.shutting_down {
+            let salt = match request {
+                PutRequestSpecific::PutMutable(args) => args.salt,
+                _ => None,
             };
+
+            self.start_query(
+                GetRequestSpecific::GetValue(GetValueRequestArguments {
+                    target,
+                    seq: None,
+                    salt,
+                }),
+                None,
+            );
         }
 
-        if let Some(our_address) = self.public_address {
-            if from == our_address && is_ping {
-                self.firewalled = false;
+        vec![]
+    }
 
-                let ipv4 = our_address.ip();
+    /// Store multiple values, sharing a single lookup between targets that are
+    /// close enough to each other to likely have the same closest nodes.
+    ///
+    /// Requests with fresh cached closest nodes, or that aren't near any other
+    /// request in the batch, are handled exactly like [Rpc::put].
+    ///
+    /// Each target is still reported separately in [RpcTickReport::done_put_queries].
+    /// Requests that fail immediately don't affect the rest of the batch, and are
+    /// aggregated in a [PutBatchError].
+    ///
+    /// Batching is skipped until there is a Dht size estimate from previous queries.
+    pub fn put_batch(&mut self, requests: Vec<PutRequestSpecific>) -> Result<(), PutBatchError> {
+        if self.shutting_down {
+            return Err(PutBatchError(
+                requests
+                    .iter()
+                    .map(|request| (*request.target(), PutQueryError::ShuttingDown.into()))
+                    .collect(),
+            ));
+        }
 
-                // Restarting our routing table with new secure Id if necessary.
-                if !self.id().is_valid_for_ip(*ipv4) {
-                    let new_id = Id::from_ipv4(*ipv4);
+        let neighborhood_prefix_bits = self.neighborhood_prefix_bits();
+        let now = self.socket.now();
 
-                    info!(
-                        "Our current id {} is not valid for adrsess {}. Using new id {}",
-                        self.id(),
-                        our_address,
-                        new_id
-                    );
+        // Targets of the lookups started by this batch.
+        let mut lookups: Vec<Id> = Vec::new();
+        let mut errors = Vec::new();
 
-                    self.get(
-                        GetRequestSpecific::FindNode(FindNodeRequestArguments { target: new_id }),
-                        None,
-                    );
+        for request in requests {
+            let target = *request.target();
 
-                    self.routing_table = RoutingTable::new(new_id);
+            if let Err(error) = validate_put_request(&request, self.max_value_size) {
+                errors.push((target, error));
+                continue;
+            }
+
+            let has_cached_closest_nodes =
+                self.cached_iterative_queries
+                    .peek(&target)
+                    .is_some_and(|cached| {
+                        !cached.partial
+                            && cached
+                                .closest_responding_nodes
+                                .iter()
+                                .any(|n| n.valid_token_at(now))
+                    });
+
+            let nearby_lookup = neighborhood_prefix_bits.and_then(|bits| {
+                lookups
+                    .iter()
+                    .find(|lookup| lookup.xor(&target).leading_zeros() >= bits)
+                    .copied()
+            });
+
+            match nearby_lookup {
+                Some(lookup) if !has_cached_closest_nodes => {
+                    match self.check_put_concurrency(&request) {
+                        Ok(true) => {
+                            debug!(?target, ?lookup, "Batching put query with a nearby lookup");
+
+                            self.put_queries.insert(
+                                target,
+                                PutQuery::new(
+                                    target,
+                                    request,
+                                    None,
+                                    self.put_ack_threshold,
+                                    self.min_storage_nodes,
+                                    self.socket.now(),
+                                ),
+                            );
+                            self.put_batches.entry(lookup).or_default().push(target);
+                        }
+                        Ok(false) => {}
+                        Err(error) => errors.push((target, error)),
+                    }
                 }
+                _ => match self.put(request, None) {
+                    Ok(()) => {
+                        if self
+                            .put_queries
+                            .get(&target)
+                            .is_some_and(|query| !query.started())
+                        {
+                            lookups.push(target);
+                        }
+                    }
+                    Err(error) => errors.push((target, error)),
+                },
             }
         }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(PutBatchError(errors))
+        }
     }
 
-    fn handle_response(&mut self, from: SocketAddrV4, message: Message) -> Option<(Id, Response)> {
-        // If someone claims to be readonly, then let's not store anything even if they respond.
-        if message.read_only {
-            return None;
+    /// Stop accepting new [Rpc::get] and [Rpc::put] queries, and keep ticking until
+    /// all inflight put queries are done, or the `timeout` elapses.
+    ///
+    /// Put queries still running after the `timeout` are abandoned, and reported in
+    /// [ShutdownReport::not_stored] without an error.
+    ///
+    /// The `timeout` is measured by the [config::Config::clock].
+    pub fn shutdown(&mut self, timeout: Duration) -> ShutdownReport {
+        self.shutting_down = true;
+
+        let deadline = self.socket.now() + timeout;
+
+        let mut report = ShutdownReport {
+            stored: Vec::new(),
+            not_stored: Vec::new(),
         };
 
-        // If the response looks like a Ping response, check StoreQueries for the transaction_id.
-        if let Some(query) = self
-            .put_queries
-            .values_mut()
-            .find(|query| query.inflight(message.transaction_id))
-        {
-            match message.message_type {
-                MessageType::Response(ResponseSpecific::Ping(_)) => {
-                    // Mark storage at that node as a success.
-                    query.success();
+        while !self.put_queries.is_empty() && self.socket.now() < deadline {
+            for (target, result) in self.tick().done_put_queries {
+                match result {
+                    Ok(_) => report.stored.push(target),
+                    Err(error) => report.not_stored.push((target, Some(error))),
                 }
-                MessageType::Error(error) => query.error(error),
-                _ => {}
-            };
+            }
+        }
 
-            return None;
+        for (target, query) in self.put_queries.drain() {
+            debug!(?target, "Abandoning put query on shutdown");
+
+            self.socket.cancel(query.inflight_requests());
+            report.not_stored.push((target, None));
         }
+        self.put_batches.clear();
 
-        let mut should_add_node = false;
-        let author_id = message.get_author_id();
-        let from_version = message.version.to_owned();
+        report
+    }
 
-        // Get corresponding query for message.transaction_id
-        if let Some(query) = self
-            .iterative_queries
-            .values_mut()
-            .find(|query| query.inflight(message.transaction_id))
-        {
-            // KrpcSocket would not give us a response from the wrong address for the transaction_id
-            should_add_node = true;
+    /// Send a message to closer and closer nodes until we can't find any more nodes.
+    ///
+    /// Queries take few seconds to fully traverse the network, once it is done, it will be removed from
+    /// self.iterative_queries. But until then, calling [Rpc::get] multiple times, will just return the list
+    /// of responses seen so far as [GetStatus::InProgress].
+    ///
+    /// Subsequent responses can be obtained from the [RpcTickReport::new_query_response] you get after calling [Rpc::tick].
+    ///
+    /// Effectively, we are caching responses and backing off the network for the duration it takes
+    /// to traverse it.
+    ///
+    /// Calling [Rpc::get] right after the [Rpc::tick] that finished the query returns all its
+    /// responses as [GetStatus::Done], even if there were none, and after that, a new query is started.
+    ///
+    /// If [config::Config::value_cache_ttl] is set, responses of queries done within that duration
+    /// are returned as [GetStatus::Cached], while a new query is started to refresh them.
+    ///
+    /// - `request` [RequestTypeSpecific], except [RequestTypeSpecific::Ping] and
+    ///   [RequestTypeSpecific::Put] which will be ignored.
+    /// - `extra_nodes` option allows the query to visit specific nodes, that won't necessesarily be visited
+    ///   through the query otherwise.
+    pub fn get(
+        &mut self,
+        request: GetRequestSpecific,
+        extra_nodes: Option<&[SocketAddr]>,
+    ) -> GetStatus {
+        let target = *request.target();
 
-            if let Some(nodes) = message.get_closer_nodes() {
-                for node in nodes {
-                    query.add_candidate(node.clone());
-                }
-            }
+        if self.shutting_down {
+            debug!(?target, "Ignoring get query while shutting down");
 
-            if let Some((responder_id, token)) = message.get_token() {
-                query.add_responding_node(Node::new_with_token(responder_id, from, token.into()));
+            return GetStatus::ShuttingDown;
+        }
+
+        if !self.iterative_queries.contains_key(&target) {
+            if let Some(responses) = self.done_queries.get(&target) {
+                return GetStatus::Done(responses.clone());
             }
+        }
 
-            if let Some(proposed_ip) = message.requester_ip {
-                query.add_address_vote(proposed_ip);
+        if let Some(ttl) = self.value_cache_ttl {
+            let now = self.socket.now();
+
+            if let Some((_, responses)) = self
+                .value_cache
+                .get(&target)
+                .filter(|(cached_at, _)| now.saturating_duration_since(*cached_at) <= ttl)
+                .cloned()
+            {
+                // Refresh the cached values in the background.
+                self.start_query(request, extra_nodes);
+
+                return GetStatus::Cached(responses);
             }
+        }
 
-            let target = query.target();
+        self.start_query(request, extra_nodes)
+    }
 
-            match message.message_type {
-                MessageType::Response(ResponseSpecific::GetPeers(GetPeersResponseArguments {
-                    values,
-                    ..
-                })) => {
-                    let response = Response::Peers(values);
-                    query.response(from, response.clone());
+    /// Same as [Rpc::get], but if a query is active, whether it was just started or
+    /// already in progress, it counts the caller as a subscriber to its completion.
+    ///
+    /// Once the query is done, its responses are kept until every subscriber
+    /// took them with [Rpc::take_completed], so multiple independent callers can
+    /// each get the responses of a single query, or for up to a minute at most.
+    pub fn subscribe(
+        &mut self,
+        request: GetRequestSpecific,
+        extra_nodes: Option<&[SocketAddr]>,
+    ) -> GetStatus {
+        let target = *request.target();
+        let status = self.get(request, extra_nodes);
 
-                    return Some((target, response));
-                }
-                MessageType::Response(ResponseSpecific::GetImmutable(
-                    GetImmutableResponseArguments {
-                        v, responder_id, ..
-                    },
-                )) => {
-                    if validate_immutable(&v, query.target()) {
-                        let response = Response::Immutable(v);
-                        query.response(from, response.clone());
+        if let Some(query) = self.iterative_queries.get_mut(&target) {
+            query.subscribe();
+        }
 
-                        return Some((target, response));
-                    }
+        status
+    }
 
-                    let target = query.target();
-                    debug!(
-                        ?v,
-                        ?target,
-                        ?responder_id,
-                        ?from,
-                        ?from_version,
-                        "Invalid immutable value"
-                    );
+    /// Take the responses of a done query for one of its subscribers, see [Rpc::subscribe].
+    ///
+    /// Returns `None` if the query is still active, or if all its subscribers
+    /// already took its responses.
+    pub fn take_completed(&mut self, target: &Id) -> Option<Vec<Response>> {
+        let (_, subscribers, responses) = self.completed_subscriptions.get_mut(target)?;
+
+        *subscribers -= 1;
+
+        if *subscribers == 0 {
+            return self
+                .completed_subscriptions
+                .remove(target)
+                .map(|(_, _, responses)| responses);
+        }
+
+        Some(responses.clone())
+    }
+
+    /// Same as [Rpc::get], but for immutable and mutable values, the query is done
+    /// as soon as the first valid value is received, instead of traversing the
+    /// network to the closest nodes, for lower latency of single value lookups.
+    ///
+    /// The closest nodes of an early done query are still cached to seed the next query,
+    /// but [Rpc::put] does a full lookup instead of storing at these nodes.
+    ///
+    /// Other requests behave exactly like [Rpc::get].
+    pub fn get_first(
+        &mut self,
+        request: GetRequestSpecific,
+        extra_nodes: Option<&[SocketAddr]>,
+    ) -> GetStatus {
+        let target = *request.target();
+        let is_get_value = matches!(request, GetRequestSpecific::GetValue(_));
+
+        let status = self.get(request, extra_nodes);
+
+        // A put needs the full lookup to store at the closest nodes.
+        if is_get_value
+            && self.iterative_queries.contains_key(&target)
+            && !self.put_queries.contains_key(&target)
+        {
+            self.get_first_queries.insert(target);
+        }
+
+        status
+    }
+
+    /// Start a new query, or join the active query for the same target,
+    /// ignoring queries done in the last tick, for queries started internally.
+    fn start_query(
+        &mut self,
+        request: GetRequestSpecific,
+        extra_nodes: Option<&[SocketAddr]>,
+    ) -> GetStatus {
+        let target = *request.target();
+
+        let response_from_inflight_put_mutable_request =
+            self.put_queries.get(&target).and_then(|existing| {
+                if let PutRequestSpecific::PutMutable(request) = &existing.request {
+                    Some(Response::Mutable(request.clone().into()))
+                } else {
+                    None
                 }
-                MessageType::Response(ResponseSpecific::GetMutable(
-                    GetMutableResponseArguments {
-                        v,
-                        seq,
-                        sig,
-                        k,
-                        responder_id,
-                        ..
-                    },
-                )) => {
-                    let salt = match query.request.request_type.clone() {
-                        RequestTypeSpecific::GetValue(args) => args.salt,
-                        _ => None,
-                    };
-                    let target = query.target();
+            });
 
-                    match MutableItem::from_dht_message(query.target(), &k, v, seq, &sig, salt) {
-                        Ok(item) => {
-                            let response = Response::Mutable(item);
-                            query.response(from, response.clone());
+        // If query is still active, no need to create a new one.
+        if let Some(query) = self.iterative_queries.get(&target) {
+            let mut responses = query.responses().to_vec();
 
-                            return Some((target, response));
-                        }
-                        Err(error) => {
-                            debug!(
-                                ?error,
+            if let Some(response) = response_from_inflight_put_mutable_request {
+                responses.push(response);
+            }
+
+            return GetStatus::InProgress(responses);
+        }
+
+        let node_id = self.routing_table.id();
+
+        if target == *node_id {
+            debug!(?node_id, "Bootstrapping the routing table");
+        }
+
+        let mut query = IterativeQuery::new(
+            *self.id(),
+            target,
+            request,
+            self.query_concurrency,
+            self.query_timeout,
+            self.socket.now(),
+        );
+
+        // Seed the query either with the closest nodes from the routing table, or the
+        // bootstrapping nodes if the closest nodes are not enough.
+
+        let mut routing_table_closest = self.routing_table.closest_secure(
+            target,
+            self.responders_based_dht_size_estimate(),
+            self.average_subnets(),
+        );
+
+        if let Some(routing_table6) = &self.routing_table6 {
+            routing_table_closest.extend(routing_table6.closest(target).into_vec());
+        }
+
+        // If we don't have enough or any closest nodes, call the bootstrapping nodes.
+        if routing_table_closest.is_empty() || routing_table_closest.len() < self.bootstrap.len() {
+            let mut bootstrap = self.bootstrap.clone();
+            self.bootstrap_health.sort(&mut bootstrap);
+
+            for bootstrapping_node in bootstrap {
+                query.visit(&mut self.socket, bootstrapping_node);
+            }
+        }
+
+        if let Some(extra_nodes) = extra_nodes {
+            for extra_node in extra_nodes {
+                query.visit(&mut self.socket, *extra_node)
+            }
+        }
+
+        // Seed this query with the closest nodes we know about.
+        for node in routing_table_closest {
+            query.add_candidate(node)
+        }
+
+        if let Some(CachedIterativeQuery {
+            closest_responding_nodes,
+            ..
+        }) = self.cached_iterative_queries.get(&target)
+        {
+            self.metrics.cache_hits += 1;
+
+            for node in closest_responding_nodes {
+                query.add_candidate(node.clone())
+            }
+        } else {
+            self.metrics.cache_misses += 1;
+        }
+
+        // After adding the nodes, we need to start the query.
+        query.start(&mut self.socket);
+
+        self.iterative_queries.insert(target, query);
+        self.metrics.queries_started += 1;
+
+        // If there is an inflight PutQuery for mutable item return its value
+        if let Some(response) = response_from_inflight_put_mutable_request {
+            return GetStatus::InProgress(vec![response]);
+        }
+
+        GetStatus::Started
+    }
+
+    /// Replace the bootstrapping nodes with a fresh list, and seed a new
+    /// self lookup (find_node for this node's [Id]) from them, without
+    /// discarding the current routing table.
+    ///
+    /// Nodes discovered through this lookup are merged into the routing table,
+    /// and its completion is reported in [RpcTickReport::done_get_queries]
+    /// under this node's [Id].
+    ///
+    /// If a self lookup is already running (for example the periodic refresh),
+    /// the new nodes are visited as part of that query instead of starting
+    /// a competing one.
+    ///
+    /// IPv6 addresses are ignored unless [config::Config::ipv6] is enabled.
+    pub fn rebootstrap(&mut self, nodes: Vec<SocketAddr>) {
+        let bootstrap = self.address_family.apply(
+            nodes
+                .into_iter()
+                .filter(|address| self.socket.supports(address))
+                .collect(),
+        );
+
+        if bootstrap.is_empty() {
+            debug!("Ignoring rebootstrap without any supported nodes");
+            return;
+        }
+
+        self.bootstrap = bootstrap.into();
+
+        let self_id = *self.id();
+
+        if let Some(query) = self.iterative_queries.get_mut(&self_id) {
+            debug!(
+                ?self_id,
+                "Adding fresh bootstrapping nodes to the running self lookup"
+            );
+
+            for address in self.bootstrap.iter() {
+                query.visit(&mut self.socket, *address);
+            }
+
+            return;
+        }
+
+        debug!(?self_id, "Rebootstrapping the routing table");
+
+        let bootstrap = self.bootstrap.clone();
+
+        self.start_query(
+            GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                target: self_id,
+                want: vec![],
+            }),
+            Some(&bootstrap),
+        );
+    }
+
+    /// Abort an active [Rpc::get] query for this target, and stop waiting
+    /// for responses to its inflight requests.
+    ///
+    /// The query won't be reported in [RpcTickReport::done_get_queries], and its
+    /// results so far are not cached.
+    ///
+    /// A [Rpc::put] waiting for this query to find the closest nodes will never
+    /// start, so you should cancel it with [Rpc::cancel_put] as well.
+    ///
+    /// Puts from [Rpc::put_batch] sharing this query are reported in the next
+    /// [RpcTickReport::done_put_queries] with [PutQueryError::LookupCancelled].
+    ///
+    /// Returns `true` if an active query was found and cancelled.
+    pub fn cancel_get(&mut self, target: Id) -> bool {
+        match self.iterative_queries.remove(&target) {
+            Some(query) => {
+                debug!(?target, "Cancelled get query");
+
+                self.socket.cancel(query.inflight_requests());
+
+                for batched in self.put_batches.remove(&target).unwrap_or_default() {
+                    if let Some(put_query) = self.put_queries.remove(&batched) {
+                        self.socket.cancel(put_query.inflight_requests());
+                    }
+
+                    self.failed_put_queries
+                        .push((batched, PutQueryError::LookupCancelled.into()));
+                }
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Abort an active [Rpc::put] query for this target, including one that
+    /// is still awaiting acknowledgements in the background after reaching
+    /// the [config::Config::put_ack_threshold], and stop waiting for responses
+    /// to its inflight requests.
+    ///
+    /// The query won't be reported in [RpcTickReport::done_put_queries]
+    /// nor [RpcTickReport::done_background_put_queries].
+    ///
+    /// Returns `true` if an active query was found and cancelled.
+    pub fn cancel_put(&mut self, target: Id) -> bool {
+        let mut cancelled = Vec::new();
+
+        if let Some(query) = self.put_queries.remove(&target) {
+            cancelled.push(query);
+        }
+
+        let (background, remaining): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.background_put_queries)
+                .into_iter()
+                .partition(|query| query.target == target);
+        self.background_put_queries = remaining;
+        cancelled.extend(background);
+
+        for query in &cancelled {
+            self.socket.cancel(query.inflight_requests());
+        }
+
+        if !cancelled.is_empty() {
+            debug!(?target, "Cancelled put query");
+        }
+
+        !cancelled.is_empty()
+    }
+
+    /// Re-run a put `request` every `interval`, to keep the value stored in the
+    /// network after the nodes storing it expire it (usually after 2 hours).
+    ///
+    /// This doesn't put the value right away, so you still need to call [Rpc::put] first.
+    ///
+    /// Republished puts are reported in [RpcTickReport::done_put_queries] like any other put.
+    ///
+    /// Tracking a request for the same target replaces the previous one.
+    pub fn track_for_republish(&mut self, request: PutRequestSpecific, interval: Duration) {
+        let target = *request.target();
+
+        self.republished.insert(
+            target,
+            Republish {
+                request,
+                interval,
+                last_published: self.socket.now(),
+            },
+        );
+    }
+
+    /// Stop republishing the put request for this target, see [Rpc::track_for_republish].
+    ///
+    /// Returns `true` if the target was tracked.
+    pub fn untrack_republish(&mut self, target: &Id) -> bool {
+        self.republished.remove(target).is_some()
+    }
+
+    /// Join the swarm of an info hash with [Rpc::join_swarm] right away, and again
+    /// every `interval` (usually 15 minutes), to stay in the swarm after the nodes
+    /// storing this peer expire it.
+    ///
+    /// Every announcement starts with a fresh get_peers lookup, so it uses fresh tokens,
+    /// and is reported in [RpcTickReport::done_join_swarms].
+    ///
+    /// Maintaining an announcement for the same info hash replaces the previous one.
+    pub fn maintain_announce(
+        &mut self,
+        info_hash: Id,
+        port: Option<u16>,
+        interval: Duration,
+    ) -> Result<(), PutError> {
+        self.join_swarm(info_hash, port)?;
+
+        self.announced.insert(
+            info_hash,
+            Reannounce {
+                port,
+                interval,
+                last_announced: self.socket.now(),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Stop announcing this peer to the swarm of an info hash, see [Rpc::maintain_announce],
+    /// cancelling the announcement in progress if any.
+    ///
+    /// Returns `true` if the info hash was announced.
+    pub fn stop_announce(&mut self, info_hash: &Id) -> bool {
+        if self.join_swarm_queries.remove(info_hash).is_some() {
+            self.cancel_put(*info_hash);
+        }
+
+        self.announced.remove(info_hash).is_some()
+    }
+
+    /// Send a `ping` and a `find_node` request to a specific node, to check its
+    /// reachability, round trip time, claimed [Id], and whether it returns closer nodes.
+    ///
+    /// The resulting [NodeDiagnostics] is reported in [RpcTickReport::done_node_diagnostics]
+    /// once both requests got responses or timed out.
+    ///
+    /// Responses to these requests don't affect the routing table.
+    pub fn diagnose_node(&mut self, address: SocketAddr) {
+        let requester_id = *self.id();
+        let query = DiagnosticsQuery::start(&mut self.socket, requester_id, address);
+
+        self.node_diagnostics.push(query);
+    }
+
+    /// Send a single request directly to a specific node, without an iterative query,
+    /// and return its transaction id.
+    ///
+    /// Peers, immutable and mutable values in the response to a `get_peers` or
+    /// `get_value` request are reported in [RpcTickReport::new_query_response]
+    /// with the request's target, other responses are ignored.
+    pub fn get_from(&mut self, address: SocketAddr, request: RequestTypeSpecific) -> u16 {
+        let tid = self.socket.request(
+            address,
+            RequestSpecific {
+                requester_id: *self.id(),
+                request_type: request.clone(),
+            },
+        );
+
+        self.direct_requests.insert(tid, request);
+
+        tid
+    }
+
+    /// Send a ping request to a specific node and return its transaction id.
+    ///
+    /// If the node responds, its address and the round trip time are reported
+    /// in [RpcTickReport::ping_responses], otherwise the ping is not reported at all.
+    pub fn ping(&mut self, address: SocketAddr) -> u16 {
+        let tid = self.send_ping(address);
+
+        self.pings.insert(tid, self.socket.now());
+
+        tid
+    }
+
+    // === Private Methods ===
+
+    /// Sign the item of a [Rpc::put_mutable_next] with the next `seq` after the most recent
+    /// in the lookup responses or in an inflight put, and add its put query, which is
+    /// then started with the closest nodes of the lookup.
+    fn put_mutable_next_query(
+        &mut self,
+        pending: PendingMutable,
+        responses: &[Response],
+    ) -> Result<(), PutError> {
+        let target = MutableItem::target_from_key(
+            pending.signer.verifying_key().as_bytes(),
+            pending.salt.as_deref(),
+        );
+
+        let inflight_seq = match self.put_queries.get(&target).map(|query| &query.request) {
+            Some(PutRequestSpecific::PutMutable(request)) => Some(request.seq),
+            _ => None,
+        };
+
+        let most_recent_seq = responses
+            .iter()
+            .filter_map(|response| match response {
+                Response::Mutable(item) => Some(item.seq()),
+                _ => None,
+            })
+            .chain(inflight_seq)
+            .max();
+
+        let seq = match most_recent_seq {
+            Some(seq) => seq + 1,
+            None => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|duration| duration.as_micros() as i64)
+                .unwrap_or_default(),
+        };
+
+        let item = MutableItem::new(pending.signer, &pending.value, seq, pending.salt.as_deref());
+        let request =
+            PutRequestSpecific::PutMutable(PutMutableRequestArguments::from(item, most_recent_seq));
+
+        if !self.check_put_concurrency(&request)? {
+            return Ok(());
+        }
+
+        self.put_queries.insert(
+            target,
+            PutQuery::new(
+                target,
+                request,
+                None,
+                self.put_ack_threshold,
+                self.min_storage_nodes,
+                self.socket.now(),
+            ),
+        );
+
+        Ok(())
+    }
+
+    /// Check for conflicts with an inflight [PutRequestSpecific::PutMutable] to the same target.
+    ///
+    /// Returns `Ok(false)` if the same request is already inflight.
+    fn check_put_concurrency(&mut self, request: &PutRequestSpecific) -> Result<bool, PutError> {
+        let target = *request.target();
+
+        if let PutRequestSpecific::PutMutable(PutMutableRequestArguments {
+            sig, cas, seq, ..
+        }) = request
+        {
+            if let Some(PutRequestSpecific::PutMutable(inflight_request)) = self
+                .put_queries
+                .get(&target)
+                .map(|existing| &existing.request)
+            {
+                debug!(?inflight_request, ?request, "Possible conflict risk");
+
+                if *sig == inflight_request.sig {
+                    return Ok(false);
+                } else if *seq < inflight_request.seq {
+                    return Err(ConcurrencyError::NotMostRecent)?;
+                } else if let Some(cas) = cas {
+                    if *cas == inflight_request.seq {
+                        // The user is aware of the inflight query and whiches to overrides it.
+                        //
+                        // Remove the inflight request, and create a new one.
+                        self.put_queries.remove(&target);
+                    } else {
+                        return Err(ConcurrencyError::CasFailed)?;
+                    }
+                } else {
+                    return Err(ConcurrencyError::ConflictRisk)?;
+                };
+            };
+        }
+
+        Ok(true)
+    }
+
+    /// Number of leading bits two targets need to share, to likely have
+    /// the same closest nodes, given the current Dht size estimate,
+    /// or None if no query contributed to the estimate yet.
+    fn neighborhood_prefix_bits(&self) -> Option<u8> {
+        if self.responders_based_dht_size_estimates_count == 0 {
+            return None;
+        }
+
+        let neighborhoods = self.responders_based_dht_size_estimate() / MAX_BUCKET_SIZE_K;
+
+        Some((neighborhoods.max(1).ilog2() as u8).max(MIN_NEIGHBORHOOD_PREFIX_BITS))
+    }
+
+    fn handle_request(
+        &mut self,
+        from: SocketAddr,
+        transaction_id: u16,
+        request_specific: RequestSpecific,
+    ) {
+        if let Some(request_observer) = &self.request_observer {
+            request_observer.observe(&from, &request_specific);
+        }
+
+        let is_ping = matches!(request_specific.request_type, RequestTypeSpecific::Ping);
+
+        let now = self.socket.now();
+        let within_rate_limit = self
+            .rate_limiter
+            .as_mut()
+            .map_or(true, |rate_limiter| rate_limiter.allow(from.ip(), now));
+
+        if !within_rate_limit {
+            debug!(?from, "Dropping request exceeding the rate limit");
+        }
+
+        let should_answer = self.server_mode()
+            || (self.always_answer_find_node
+                && matches!(
+                    request_specific.request_type,
+                    RequestTypeSpecific::FindNode(_)
+                ));
+
+        if should_answer && within_rate_limit {
+            let server = &mut self.server;
+
+            match server.handle_request(
+                &self.routing_table,
+                self.routing_table6.as_ref(),
+                from,
+                request_specific,
+                now,
+            ) {
+                Some(MessageType::Error(error)) => {
+                    self.error(from, transaction_id, error);
+                }
+                Some(MessageType::Response(response)) => {
+                    self.response(from, transaction_id, response);
+                }
+                _ => {}
+            };
+        }
+
+        if let Some(our_address) = self.public_address {
+            if from == SocketAddr::V4(our_address) && is_ping {
+                self.firewalled = false;
+
+                let ipv4 = our_address.ip();
+
+                // Restarting our routing table with new secure Id if necessary.
+                if !self.fixed_id && !self.id().is_valid_for_ip(*ipv4) {
+                    let new_id = Id::from_ipv4(*ipv4);
+
+                    info!(
+                        "Our current id {} is not valid for adrsess {}. Using new id {}",
+                        self.id(),
+                        our_address,
+                        new_id
+                    );
+
+                    self.start_query(
+                        GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                            target: new_id,
+                            want: vec![],
+                        }),
+                        None,
+                    );
+
+                    self.routing_table = RoutingTable::new(new_id);
+
+                    if self.routing_table6.is_some() {
+                        self.routing_table6 = Some(RoutingTable::new(new_id));
+                    }
+
+                    self.node_id_changed = Some(new_id);
+                }
+            }
+        }
+    }
+
+    fn handle_response(&mut self, from: SocketAddr, message: Message) -> Option<(Id, Response)> {
+        if let Some(query) = self
+            .node_diagnostics
+            .iter_mut()
+            .find(|query| query.inflight(message.transaction_id))
+        {
+            query.response(message, self.socket.now());
+
+            return None;
+        }
+
+        if let Some(request) = self.direct_requests.remove(&message.transaction_id) {
+            let (target, responses) = direct_response(request, from, message.message_type)?;
+
+            let mut responses = responses.into_iter().filter(|response| {
+                match (response, &self.mutable_validator) {
+                    (Response::Mutable(item), Some(validator)) => validator.validate(item),
+                    _ => true,
+                }
+            });
+
+            // Responses after the first are reported in the next ticks.
+            let response = responses.next()?;
+            self.queued_query_responses
+                .extend(responses.map(|response| (target, response)));
+
+            return Some((target, response));
+        }
+
+        if let Some(sent_at) = self.pings.remove(&message.transaction_id) {
+            if let MessageType::Response(ResponseSpecific::Ping(_)) = message.message_type {
+                let rtt = self.socket.now().saturating_duration_since(sent_at);
+
+                self.ping_responses.push((from, rtt));
+            }
+
+            return None;
+        }
+
+        // If someone claims to be readonly, then let's not store anything even if they respond.
+        if message.read_only {
+            return None;
+        };
+
+        // If the response looks like a Ping response, check StoreQueries for the transaction_id.
+        if let Some(query) = self
+            .put_queries
+            .values_mut()
+            .chain(self.background_put_queries.iter_mut())
+            .find(|query| query.inflight(message.transaction_id))
+        {
+            match message.message_type {
+                MessageType::Response(ResponseSpecific::Ping(_)) => {
+                    // Mark storage at that node as a success.
+                    query.success();
+                }
+                MessageType::Error(error) => query.error(error),
+                _ => {}
+            };
+
+            return None;
+        }
+
+        let mut should_add_node = false;
+        let author_id = message.get_author_id();
+        let ipv6 = self.routing_table6.is_some();
+        let from_version = message.version.to_owned();
+
+        // Get corresponding query for message.transaction_id
+        if let Some(query) = self
+            .iterative_queries
+            .values_mut()
+            .find(|query| query.inflight(message.transaction_id))
+        {
+            // KrpcSocket drops responses from any address other than the one the request was sent to,
+            // see `ignore_spoofed_response`.
+            should_add_node = true;
+
+            query.hop_response(
+                self.socket.now(),
+                message.transaction_id,
+                author_id,
+                message
+                    .get_closer_nodes()
+                    .is_some_and(|nodes| !nodes.is_empty()),
+            );
+
+            if let Some(nodes) = message.get_closer_nodes() {
+                // Ignore Ipv6 nodes if we can't reach them anyway.
+                for node in nodes.iter().filter(|node| ipv6 || !node.is_ipv6()) {
+                    query.add_candidate(node.clone());
+                }
+            }
+
+            if let Some((responder_id, token)) = message.get_token() {
+                query.add_responding_node(Node::new_with_token(
+                    responder_id,
+                    from,
+                    token.into(),
+                    self.socket.now(),
+                ));
+            }
+
+            // Our public address is only tracked for Ipv4.
+            if let Some(SocketAddr::V4(proposed_ip)) = message.requester_ip {
+                query.add_address_vote(proposed_ip);
+            }
+
+            let target = query.target();
+
+            match message.message_type {
+                MessageType::Response(ResponseSpecific::GetPeers(GetPeersResponseArguments {
+                    values,
+                    seeders,
+                    peers,
+                    ..
+                })) => {
+                    let scrape = match (seeders, peers) {
+                        (Some(seeders), Some(peers)) => {
+                            let (seeders_estimate, peers_estimate) =
+                                query.add_scrape(&seeders, &peers);
+
+                            let response = Response::Scrape {
+                                seeders_estimate,
+                                peers_estimate,
+                            };
+                            query.response(from, response.clone());
+
+                            Some(response)
+                        }
+                        _ => None,
+                    };
+
+                    let mut responses =
+                        peers_responses(query.new_peers(values, self.max_peers_per_query));
+
+                    for response in &responses {
+                        query.response(from, response.clone());
+                    }
+
+                    responses.extend(scrape);
+
+                    // Responses after the first are reported in the next ticks.
+                    let mut responses = responses.into_iter();
+
+                    if let Some(response) = responses.next() {
+                        self.queued_query_responses
+                            .extend(responses.map(|response| (target, response)));
+
+                        return Some((target, response));
+                    }
+                }
+                MessageType::Response(ResponseSpecific::GetImmutable(
+                    GetImmutableResponseArguments {
+                        v, responder_id, ..
+                    },
+                )) => {
+                    if validate_immutable(&v, query.target()) {
+                        let response = Response::Immutable(v);
+                        query.response(from, response.clone());
+
+                        if self.get_first_queries.contains(&target) {
+                            query.finish();
+                        }
+
+                        return Some((target, response));
+                    }
+
+                    let target = query.target();
+                    debug!(
+                        ?v,
+                        ?target,
+                        ?responder_id,
+                        ?from,
+                        ?from_version,
+                        "Invalid immutable value"
+                    );
+
+                    self.emit(DhtEvent::InvalidImmutable { target, from });
+
+                    if let Some(rejected_samples) = &mut self.rejected_samples {
+                        rejected_samples.push(RejectedSample {
+                            target,
+                            from,
+                            v,
+                            reason: RejectionReason::InvalidImmutable,
+                        });
+                    }
+                }
+                MessageType::Response(ResponseSpecific::GetMutable(
+                    GetMutableResponseArguments {
+                        v,
+                        seq,
+                        sig,
+                        k,
+                        responder_id,
+                        ..
+                    },
+                )) => {
+                    let salt = match query.request.request_type.clone() {
+                        RequestTypeSpecific::GetValue(args) => args.salt,
+                        _ => None,
+                    };
+                    let target = query.target();
+
+                    let raw_v = self.rejected_samples.is_some().then(|| v.clone());
+
+                    match MutableItem::from_dht_message(query.target(), &k, v, seq, &sig, salt) {
+                        Ok(item)
+                            if self
+                                .mutable_validator
+                                .as_ref()
+                                .is_some_and(|validator| !validator.validate(&item)) =>
+                        {
+                            debug!(
+                                ?from,
+                                ?responder_id,
+                                ?from_version,
+                                "Rejected mutable record"
+                            );
+
+                            self.reject_mutable(
+                                target,
+                                from,
+                                raw_v,
+                                RejectionReason::RejectedMutable,
+                            );
+                        }
+                        Ok(item) => {
+                            if query.newer_mutable(&item) {
+                                self.newer_mutable = Some((target, item.clone()));
+                            }
+
+                            let response = Response::Mutable(item);
+                            query.response(from, response.clone());
+
+                            if self.get_first_queries.contains(&target) {
+                                query.finish();
+                            }
+
+                            return Some((target, response));
+                        }
+                        Err(error) => {
+                            debug!(
+                                ?error,
                                 ?from,
                                 ?responder_id,
                                 ?from_version,
                                 "Invalid mutable record"
                             );
+
+                            self.reject_mutable(
+                                target,
+                                from,
+                                raw_v,
+                                RejectionReason::InvalidMutable(error),
+                            );
                         }
                     }
                 }
-                MessageType::Response(ResponseSpecific::NoMoreRecentValue(
-                    NoMoreRecentValueResponseArguments {
-                        seq, responder_id, ..
+                MessageType::Response(ResponseSpecific::NoMoreRecentValue(
+                    NoMoreRecentValueResponseArguments {
+                        seq, responder_id, ..
+                    },
+                )) => {
+                    debug!(
+                        target= ?query.target(),
+                        salt= ?match query.request.request_type.clone() {
+                            RequestTypeSpecific::GetValue(args) => args.salt,
+                            _ => None,
+                        },
+                        ?seq,
+                        ?from,
+                        ?responder_id,
+                        ?from_version,
+                        "No more recent value"
+                    );
+                }
+                MessageType::Response(ResponseSpecific::NoValues(NoValuesResponseArguments {
+                    responder_id,
+                    ..
+                })) => {
+                    debug!(
+                        target= ?query.target(),
+                        salt= ?match query.request.request_type.clone() {
+                            RequestTypeSpecific::GetValue(args) => args.salt,
+                            _ => None,
+                        },
+                        ?from,
+                        ?responder_id,
+                        ?from_version ,
+                        "No values"
+                    );
+                }
+                MessageType::Error(error) => {
+                    debug!(?error, ?from_version, "Get query got error response");
+                }
+                // Ping response is already handled in add_node()
+                // FindNode response is already handled in query.add_candidate()
+                // Requests are handled elsewhere
+                MessageType::Response(ResponseSpecific::Ping(_))
+                | MessageType::Response(ResponseSpecific::FindNode(_))
+                | MessageType::Request(_) => {}
+            };
+        };
+
+        if should_add_node {
+            // Add a node to our routing table on any expected incoming response.
+
+            if let Some(id) = author_id {
+                self.add_node(Node::seen_at(id, from, self.socket.now()));
+            }
+        }
+
+        None
+    }
+
+    fn periodic_node_maintaenance(&mut self) {
+        // Bootstrap if necessary
+        if self.routing_table.is_empty()
+            && self
+                .routing_table6
+                .as_ref()
+                .map_or(true, |routing_table6| routing_table6.is_empty())
+        {
+            self.populate();
+        }
+
+        // Every 15 minutes (by default) refresh the routing table.
+        let now = self.socket.now();
+
+        if now.saturating_duration_since(self.last_table_refresh) > self.refresh_delay {
+            self.last_table_refresh = now;
+            self.refresh_delay = jitter(self.refresh_interval);
+
+            if !self.server_mode()
+                && !self.firewalled()
+                && !self.read_only
+                && self.nat_type() != NatType::SymmetricNat
+            {
+                info!("Adaptive mode: have been running long enough (not firewalled), switching to server mode");
+
+                self.socket.server_mode = true;
+
+                self.emit(DhtEvent::SwitchedToServerMode);
+            }
+
+            self.populate();
+        }
+
+        if now.saturating_duration_since(self.last_table_ping) > self.ping_delay {
+            self.last_table_ping = now;
+            self.ping_delay = jitter(self.ping_interval);
+
+            let mut to_remove = Vec::with_capacity(self.routing_table.size());
+            let mut to_ping = Vec::with_capacity(self.routing_table.size());
+
+            for node in self
+                .routing_table
+                .nodes()
+                .chain(self.routing_table6.iter().flat_map(|table| table.nodes()))
+            {
+                if self.eviction_policy.should_evict(&node, now) {
+                    to_remove.push((*node.id(), node.is_ipv6()))
+                } else if self.eviction_policy.should_ping(&node, now) {
+                    to_ping.push(node.socket_address())
+                }
+            }
+
+            for (id, is_ipv6) in to_remove {
+                match (is_ipv6, &mut self.routing_table6) {
+                    (true, Some(routing_table6)) => routing_table6.remove(&id),
+                    _ => self.routing_table.remove(&id),
+                };
+            }
+
+            for address in to_ping {
+                self.send_ping(address);
+            }
+        }
+
+        if let Some(interval) = self.nat_keepalive_interval {
+            if now.saturating_duration_since(self.last_nat_keepalive) >= interval {
+                self.last_nat_keepalive = now;
+
+                self.nat_keepalive();
+            }
+        }
+    }
+
+    /// Ping the next few nodes of the routing tables, to spread the keepalive load.
+    fn nat_keepalive(&mut self) {
+        let nodes = self
+            .routing_table
+            .nodes()
+            .chain(self.routing_table6.iter().flat_map(|table| table.nodes()))
+            .map(|node| node.socket_address())
+            .collect::<Vec<_>>();
+
+        if nodes.is_empty() {
+            return;
+        }
+
+        let count = NAT_KEEPALIVE_NODES.min(nodes.len());
+
+        for i in 0..count {
+            self.send_ping(nodes[(self.nat_keepalive_cursor + i) % nodes.len()]);
+        }
+
+        self.nat_keepalive_cursor = (self.nat_keepalive_cursor + count) % nodes.len();
+    }
+
+    /// Re-run tracked put requests that are due, see [Rpc::track_for_republish].
+    fn republish(&mut self) {
+        let now = self.socket.now();
+
+        let due = self
+            .republished
+            .values_mut()
+            .filter(|republish| {
+                now.saturating_duration_since(republish.last_published) >= republish.interval
+            })
+            .map(|republish| {
+                republish.last_published = now;
+
+                republish.request.clone()
+            })
+            .collect::<Vec<_>>();
+
+        for request in due {
+            let target = *request.target();
+            debug!(?target, "Republishing");
+
+            if let Err(error) = self.put(request, None) {
+                debug!(?target, ?error, "Failed to republish");
+            }
+        }
+    }
+
+    /// Re-join the swarms that are due, see [Rpc::maintain_announce].
+    fn reannounce(&mut self) {
+        let now = self.socket.now();
+
+        let due = self
+            .announced
+            .iter_mut()
+            .filter(|(info_hash, announce)| {
+                // Wait for the previous announcement to be done.
+                !self.join_swarm_queries.contains_key(info_hash)
+                    && now.saturating_duration_since(announce.last_announced) >= announce.interval
+            })
+            .map(|(info_hash, announce)| {
+                announce.last_announced = now;
+
+                (*info_hash, announce.port)
+            })
+            .collect::<Vec<_>>();
+
+        for (info_hash, port) in due {
+            debug!(?info_hash, "Re-announcing");
+
+            if let Err(error) = self.join_swarm(info_hash, port) {
+                debug!(?info_hash, ?error, "Failed to re-announce");
+            }
+        }
+    }
+
+    /// Schedule a new lookup for a put query that found no nodes to store at,
+    /// with an exponential backoff.
+    ///
+    /// Returns false if the query ran out of retries, see [config::Config::put_max_retries].
+    fn schedule_put_retry(&mut self, target: Id) -> bool {
+        let now = self.socket.now();
+
+        let retry = self.put_retries.entry(target).or_insert(PutRetry {
+            attempts: 0,
+            retry_at: now,
+        });
+
+        if retry.attempts >= self.put_max_retries {
+            self.put_retries.remove(&target);
+
+            return false;
+        }
+
+        let backoff = self
+            .put_retry_backoff
+            .saturating_mul(2_u32.saturating_pow(retry.attempts as u32));
+
+        retry.attempts += 1;
+        retry.retry_at = now + backoff;
+
+        debug!(
+            ?target,
+            attempts = retry.attempts,
+            ?backoff,
+            "Retrying put query"
+        );
+
+        true
+    }
+
+    /// Restart the lookups of put queries whose retry is due.
+    fn retry_puts(&mut self) {
+        let now = self.socket.now();
+
+        // Forget retries of put queries that were cancelled or are done.
+        self.put_retries
+            .retain(|target, _| self.put_queries.contains_key(target));
+
+        let due = self
+            .put_retries
+            .iter()
+            .filter(|(_, retry)| retry.retry_at <= now)
+            .filter_map(|(target, _)| self.put_queries.get(target))
+            .filter(|query| !query.started())
+            .map(|query| (query.target, query.request.clone()))
+            .collect::<Vec<_>>();
+
+        for (target, request) in due {
+            let salt = match request {
+                PutRequestSpecific::PutMutable(args) => args.salt,
+                _ => None,
+            };
+
+            self.start_query(
+                GetRequestSpecific::GetValue(GetValueRequestArguments {
+                    target,
+                    seq: None,
+                    salt,
+                }),
+                None,
+            );
+        }
+    }
+
+    /// Ping bootstrap nodes, add them to the routing table with closest query.
+    fn populate(&mut self) {
+        if self.bootstrap.is_empty() {
+            return;
+        }
+
+        self.start_query(
+            GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                target: *self.id(),
+                want: vec![],
+            }),
+            None,
+        );
+    }
+
+    /// Add a node to the routing table matching its address family.
+    fn add_node(&mut self, node: Node) {
+        if self.enforce_secure_ids && !node.is_secure() {
+            debug!(?node, "Ignoring node with insecure Id");
+
+            return;
+        }
+
+        match (node.is_ipv6(), &mut self.routing_table6) {
+            (true, Some(routing_table6)) => routing_table6.add(node),
+            (true, None) => false,
+            (false, _) => self.routing_table.add(node),
+        };
+    }
+
+    fn emit(&self, event: DhtEvent) {
+        if let Some(event_sink) = &self.event_sink {
+            event_sink.emit(event);
+        }
+    }
+
+    /// Emit [DhtEvent::InvalidMutable] for a mutable item from a `get` response,
+    /// and sample its raw value if [config::Config::capture_rejected] is enabled.
+    fn reject_mutable(
+        &mut self,
+        target: Id,
+        from: SocketAddr,
+        raw_v: Option<Box<[u8]>>,
+        reason: RejectionReason,
+    ) {
+        self.emit(DhtEvent::InvalidMutable { target, from });
+
+        if let (Some(rejected_samples), Some(v)) = (&mut self.rejected_samples, raw_v) {
+            rejected_samples.push(RejectedSample {
+                target,
+                from,
+                v,
+                reason,
+            });
+        }
+    }
+
+    fn send_ping(&mut self, address: SocketAddr) -> u16 {
+        self.socket.request(
+            address,
+            RequestSpecific {
+                requester_id: *self.id(),
+                request_type: RequestTypeSpecific::Ping,
+            },
+        )
+    }
+
+    fn update_bootstrap_health(&mut self, query: &IterativeQuery) {
+        for hop in query.trace() {
+            if self.bootstrap.contains(&hop.address) {
+                if hop.rtt.is_some() {
+                    self.bootstrap_health.success(hop.address);
+                } else {
+                    self.bootstrap_health.failure(hop.address);
+                }
+            }
+        }
+    }
+
+    fn update_address_votes_from_iterative_query(&mut self, query: &IterativeQuery) {
+        if let Some(symmetric) = nat::is_symmetric(query.address_votes()) {
+            if symmetric && self.symmetric_nat != Some(true) {
+                debug!(
+                    "Responders see different ports for this node, it is behind a symmetric NAT"
+                );
+            }
+
+            self.symmetric_nat = Some(symmetric);
+        }
+
+        if let Some(new_address) = query.best_address() {
+            self.public_address_confidence =
+                Some(AddressConfidence::new(new_address, query.address_votes()));
+
+            if self.public_address.is_none()
+                || new_address
+                    != self
+                        .public_address
+                        .expect("self.public_address is not None")
+            {
+                debug!(
+                    ?new_address,
+                    "Query responses suggest a different public_address, trying to confirm.."
+                );
+
+                self.firewalled = true;
+                self.send_ping(new_address.into());
+
+                self.emit(DhtEvent::PublicAddressChanged(new_address));
+            }
+
+            self.public_address = Some(new_address)
+        }
+    }
+
+    fn cache_iterative_query(&mut self, query: &IterativeQuery, closest_responding_nodes: &[Node]) {
+        let closest = query.closest();
+        let responders = query.responders();
+
+        if closest.nodes().is_empty() {
+            // We are clearly offline.
+            return;
+        }
+
+        // Queries that finished early didn't reach the closest nodes.
+        let partial = query.is_finished();
+
+        let Some(query_cache_size) = self.query_cache_size else {
+            if partial {
+                return;
+            }
+
+            // Nothing is cached, so the estimates only rely on the latest query.
+            self.dht_size_estimates_sum = closest.dht_size_estimate();
+            self.responders_based_dht_size_estimates_sum = responders.dht_size_estimate();
+            self.responders_based_dht_size_estimates_count = 1;
+            self.subnets_sum = closest.subnets_count() as usize;
+
+            return;
+        };
+
+        let size = CachedIterativeQuery::estimated_size(closest_responding_nodes);
+
+        if self
+            .max_query_cache_bytes
+            .is_some_and(|max_bytes| size > max_bytes)
+        {
+            // Caching it would evict everything else and still exceed the limit.
+            let target = query.target();
+            debug!(
+                ?target,
+                size, "Skipping caching a query larger than max_query_cache_bytes"
+            );
+
+            return;
+        }
+
+        let previous = self.cached_iterative_queries.pop(&query.target());
+        self.decrement_cached_iterative_query_stats(previous);
+
+        if self.cached_iterative_queries.len() >= query_cache_size.get() {
+            let q = self.cached_iterative_queries.pop_lru();
+            self.decrement_cached_iterative_query_stats(q.map(|q| q.1));
+        }
+
+        if let Some(max_bytes) = self.max_query_cache_bytes {
+            while self.query_cache_bytes + size > max_bytes {
+                match self.cached_iterative_queries.pop_lru() {
+                    Some((_, q)) => self.decrement_cached_iterative_query_stats(Some(q)),
+                    None => break,
+                }
+            }
+        }
+
+        let dht_size_estimate = closest.dht_size_estimate();
+        let responders_dht_size_estimate = responders.dht_size_estimate();
+        let subnets_count = closest.subnets_count();
+
+        let previous = self.cached_iterative_queries.put(
+            query.target(),
+            CachedIterativeQuery {
+                closest_responding_nodes: closest_responding_nodes.into(),
+                trace: query.trace().into(),
+                dht_size_estimate,
+                responders_dht_size_estimate,
+                subnets: subnets_count,
+                partial,
+
+                is_find_node: matches!(
+                    query.request.request_type,
+                    RequestTypeSpecific::FindNode(_)
+                ),
+            },
+        );
+
+        self.decrement_cached_iterative_query_stats(previous);
+
+        self.query_cache_bytes += size;
+
+        if partial {
+            self.partial_cached_queries += 1;
+
+            return;
+        }
+
+        self.dht_size_estimates_sum += dht_size_estimate;
+        self.responders_based_dht_size_estimates_sum += responders_dht_size_estimate;
+        self.subnets_sum += subnets_count as usize;
+        self.responders_based_dht_size_estimates_count += 1;
+    }
+
+    /// Number of cached queries contributing to the estimates.
+    fn complete_cached_queries(&self) -> usize {
+        self.cached_iterative_queries.len() - self.partial_cached_queries
+    }
+
+    fn responders_based_dht_size_estimate(&self) -> usize {
+        self.responders_based_dht_size_estimates_sum as usize
+            / self.responders_based_dht_size_estimates_count.max(1)
+    }
+
+    fn average_subnets(&self) -> usize {
+        self.subnets_sum / self.complete_cached_queries().max(1)
+    }
+
+    fn decrement_cached_iterative_query_stats(&mut self, query: Option<CachedIterativeQuery>) {
+        if let Some(CachedIterativeQuery {
+            closest_responding_nodes,
+            dht_size_estimate,
+            responders_dht_size_estimate,
+            subnets,
+            is_find_node,
+            partial,
+            ..
+        }) = query
+        {
+            self.query_cache_bytes -=
+                CachedIterativeQuery::estimated_size(&closest_responding_nodes);
+
+            if partial {
+                self.partial_cached_queries -= 1;
+
+                return;
+            }
+
+            self.dht_size_estimates_sum -= dht_size_estimate;
+            self.responders_based_dht_size_estimates_sum -= responders_dht_size_estimate;
+            self.subnets_sum -= subnets as usize;
+
+            if !is_find_node {
+                self.responders_based_dht_size_estimates_count -= 1;
+            }
+        };
+    }
+}
+
+/// Split peers into a [Response::Peers] and a [Response::Peers6], skipping empty ones.
+fn peers_responses(peers: Vec<SocketAddr>) -> Vec<Response> {
+    let (peers, peers6) = split_address_families(peers);
+
+    let mut responses = vec![];
+
+    if !peers.is_empty() {
+        responses.push(Response::Peers(peers));
+    }
+    if !peers6.is_empty() {
+        responses.push(Response::Peers6(peers6));
+    }
+
+    responses
+}
+
+/// Split addresses into Ipv4 and Ipv6 addresses, keeping their order.
+pub(crate) fn split_address_families(
+    addresses: impl IntoIterator<Item = SocketAddr>,
+) -> (Vec<SocketAddrV4>, Vec<SocketAddrV6>) {
+    let mut ipv4 = vec![];
+    let mut ipv6 = vec![];
+
+    for address in addresses {
+        match address {
+            SocketAddr::V4(address) => ipv4.push(address),
+            SocketAddr::V6(address) => ipv6.push(address),
+        }
+    }
+
+    (ipv4, ipv6)
+}
+
+/// Convert the response to a [Rpc::get_from] request to [Response]s, if it has any values.
+///
+/// The Ipv4 [Response::Peers] is returned even if it is empty, followed by
+/// a [Response::Peers6] if there are any Ipv6 peers.
+fn direct_response(
+    request: RequestTypeSpecific,
+    from: SocketAddr,
+    message_type: MessageType,
+) -> Option<(Id, Vec<Response>)> {
+    match (request, message_type) {
+        (
+            RequestTypeSpecific::GetPeers(GetPeersRequestArguments { info_hash, .. }),
+            MessageType::Response(ResponseSpecific::GetPeers(GetPeersResponseArguments {
+                values,
+                ..
+            })),
+        ) => {
+            let (peers, peers6) = split_address_families(values);
+
+            let mut responses = vec![Response::Peers(peers)];
+            if !peers6.is_empty() {
+                responses.push(Response::Peers6(peers6));
+            }
+
+            Some((info_hash, responses))
+        }
+        (
+            RequestTypeSpecific::GetValue(GetValueRequestArguments { target, .. }),
+            MessageType::Response(ResponseSpecific::GetImmutable(GetImmutableResponseArguments {
+                v,
+                ..
+            })),
+        ) => {
+            if validate_immutable(&v, target) {
+                return Some((target, vec![Response::Immutable(v)]));
+            }
+
+            debug!(?target, ?from, "Invalid immutable value");
+
+            None
+        }
+        (
+            RequestTypeSpecific::GetValue(GetValueRequestArguments { target, salt, .. }),
+            MessageType::Response(ResponseSpecific::GetMutable(GetMutableResponseArguments {
+                v,
+                seq,
+                sig,
+                k,
+                ..
+            })),
+        ) => match MutableItem::from_dht_message(target, &k, v, seq, &sig, salt) {
+            Ok(item) => Some((target, vec![Response::Mutable(item)])),
+            Err(error) => {
+                debug!(?target, ?from, ?error, "Invalid mutable record");
+
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+/// An item to sign and put once the lookup of a [Rpc::put_mutable_next] is done.
+struct PendingMutable {
+    signer: SigningKey,
+    value: Box<[u8]>,
+    salt: Option<Box<[u8]>>,
+}
+
+#[derive(Debug)]
+struct PutRetry {
+    /// Number of retries so far.
+    attempts: usize,
+    retry_at: Instant,
+}
+
+#[derive(Debug)]
+struct Republish {
+    request: PutRequestSpecific,
+    interval: Duration,
+    last_published: Instant,
+}
+
+#[derive(Debug)]
+struct Reannounce {
+    port: Option<u16>,
+    interval: Duration,
+    last_announced: Instant,
+}
+
+struct CachedIterativeQuery {
+    closest_responding_nodes: Box<[Node]>,
+    trace: Box<[HopTiming]>,
+    dht_size_estimate: f64,
+    responders_dht_size_estimate: f64,
+    subnets: u8,
+
+    /// Keeping track of find_node queries, because they shouldn't
+    /// be counted in `responders_based_dht_size_estimates_count`
+    is_find_node: bool,
+    /// Finished early by [Rpc::get_first], so [Rpc::put] shouldn't reuse its closest nodes.
+    partial: bool,
+}
+
+impl CachedIterativeQuery {
+    /// Estimated size in bytes of the cached closest nodes, see [config::Config::max_query_cache_bytes].
+    fn estimated_size(closest_responding_nodes: &[Node]) -> usize {
+        std::mem::size_of_val(closest_responding_nodes)
+    }
+}
+
+/// State change after a call to [Rpc::tick], including
+/// done PUT, GET, and FIND_NODE queries, as well as any
+/// incoming value response for any GET query.
+#[derive(Debug, Clone)]
+pub struct RpcTickReport {
+    /// All the [Id]s of the done [Rpc::get] queries, with their closest nodes.
+    ///
+    /// For find_node queries these are the closest nodes to the target, and for
+    /// other queries the closest nodes that responded, which are the nodes a
+    /// following [Rpc::put] to the same target would store the value at.
+    pub done_get_queries: Vec<(Id, Box<[Node]>)>,
+    /// All the [Id]s of the done [Rpc::put] queries, with either the number
+    /// of nodes that stored the value so far, or the [PutError] if the query failed.
+    ///
+    /// If [config::Config::put_ack_threshold] is set, more nodes may still store the value
+    /// later, see [Self::done_background_put_queries].
+    pub done_put_queries: Vec<(Id, Result<usize, PutError>)>,
+    /// [Rpc::put] queries that were already reported in [Self::done_put_queries]
+    /// after reaching the [config::Config::put_ack_threshold], and are now
+    /// fully done, with the final number of nodes that stored the value.
+    pub done_background_put_queries: Vec<(Id, usize)>,
+    /// Reports of done [Rpc::diagnose_node] requests.
+    pub done_node_diagnostics: Vec<NodeDiagnostics>,
+    /// Received GET query response.
+    pub new_query_response: Option<(Id, Response)>,
+    /// True only in the first tick after the initial self-id find_node query
+    /// completed with a non-empty routing table, meaning the node is ready to
+    /// be used for queries.
+    pub bootstrapped: bool,
+    /// Addresses and round trip times of nodes that responded to [Rpc::ping].
+    pub ping_responses: Vec<(SocketAddr, Duration)>,
+    /// A mutable item received in the last tick, only if its seq is strictly
+    /// higher than every other item seen by the same [Rpc::get] query so far.
+    ///
+    /// The same item is also reported in [Self::new_query_response].
+    pub newer_mutable: Option<(Id, MutableItem)>,
+    /// The new Id of this node, if it was replaced in the last tick by a secure Id
+    /// for the public IP confirmed by responding nodes, which also restarts the
+    /// routing table, see [BEP_0042](https://www.bittorrent.org/beps/bep_0042.html).
+    pub node_id_changed: Option<Id>,
+    /// True if any request was dropped since the last tick because the OS send buffer
+    /// was full, meaning the caller should slow down issuing new queries.
+    pub send_pressure: bool,
+    /// All the [Id]s of the done [Rpc::find_live_nodes] queries, with the closest
+    /// nodes that responded to the query.
+    pub done_live_nodes: Vec<(Id, Box<[Node]>)>,
+    /// All the info hashes of the done [Rpc::join_swarm] queries, with their reports.
+    pub done_join_swarms: Vec<(Id, JoinSwarmReport)>,
+}
+
+/// Result of a [Rpc::join_swarm] query.
+#[derive(Debug, Clone)]
+pub struct JoinSwarmReport {
+    /// Peers found by the get_peers lookup.
+    pub peers: Vec<SocketAddr>,
+    /// Number of nodes that accepted the announcement, or the [PutError] if it failed.
+    pub announced: Result<usize, PutError>,
+}
+
+/// Final state of the put queries that were inflight when [Rpc::shutdown] was called.
+#[derive(Debug, Clone)]
+pub struct ShutdownReport {
+    /// Targets of the put queries that were stored successfully.
+    pub stored: Vec<Id>,
+    /// Targets of the put queries that didn't reach enough nodes, with
+    /// the [PutError] if they failed, or `None` if they were abandoned.
+    pub not_stored: Vec<(Id, Option<PutError>)>,
+}
+
+#[derive(Debug, Clone)]
+/// Status of a GET query returned from [Rpc::get].
+pub enum GetStatus {
+    /// A new query was started, its responses are reported in
+    /// [RpcTickReport::new_query_response].
+    Started,
+    /// The query is still running, with the responses seen so far.
+    InProgress(Vec<Response>),
+    /// The query finished in the last [Rpc::tick], with all its responses,
+    /// which is empty if nothing was found.
+    Done(Vec<Response>),
+    /// The responses of a previous query to the same target, done within the
+    /// [config::Config::value_cache_ttl], while a new query refreshes them.
+    Cached(Vec<Response>),
+    /// No query was started, because the [Rpc] is shutting down, see [Rpc::shutdown].
+    ShuttingDown,
+}
+
+#[derive(Debug, Clone)]
+/// A response to a GET query.
+pub enum Response {
+    /// Ipv4 peers announced on an info hash, not seen in previous responses to the same query.
+    Peers(Vec<SocketAddrV4>),
+    /// Ipv6 peers announced on an info hash, not seen in previous responses to the same query,
+    /// only found if [config::Config::ipv6] is enabled.
+    Peers6(Vec<SocketAddrV6>),
+    /// An immutable value.
+    Immutable(Box<[u8]>),
+    /// A mutable item.
+    Mutable(MutableItem),
+    /// Estimated swarm size from the union of all the
+    /// [BEP_0033](https://www.bittorrent.org/beps/bep_0033.html) scrape responses so far.
+    Scrape {
+        /// Estimated number of seeders.
+        seeders_estimate: usize,
+        /// Estimated number of peers.
+        peers_estimate: usize,
+    },
+}
+
+#[derive(thiserror::Error, Debug)]
+/// Errors creating a new [Rpc].
+pub enum RpcError {
+    #[error("Failed to bind the udp socket: {0}")]
+    /// Failed to bind the udp socket, for example if the port is already in use.
+    BindFailed(#[source] std::io::Error),
+
+    #[error("Invalid config: {0}")]
+    /// The [config::Config] has an invalid value.
+    InvalidConfig(String),
+}
+
+impl From<RpcError> for std::io::Error {
+    fn from(error: RpcError) -> Self {
+        match error {
+            RpcError::BindFailed(error) => error,
+            error => std::io::Error::other(error),
+        }
+    }
+}
+
+/// Randomly shorten or lengthen an interval by up to [MAINTENANCE_JITTER].
+fn jitter(interval: Duration) -> Duration {
+    let mut bytes = [0; 4];
+    getrandom::getrandom(&mut bytes).expect("getrandom");
+
+    // Uniform in [-1.0, 1.0]
+    let random = u32::from_le_bytes(bytes) as f64 / u32::MAX as f64 * 2.0 - 1.0;
+
+    interval.mul_f64(1.0 + random * MAINTENANCE_JITTER)
+}
+
+/// Resolve bootstrapping nodes, skipping duplicates, and keeping up to
+/// [MAX_RESOLVED_ADDRESSES_PER_HOST] IPv4 and IPv6 addresses of each host.
+///
+/// Addresses of both families are capped separately, so IPv6 addresses never
+/// crowd out IPv4 ones, and are only filtered out later if IPv6 is disabled.
+pub(crate) fn to_socket_address<T: ToSocketAddrs>(bootstrap: &[T]) -> Vec<SocketAddr> {
+    let mut addresses: Vec<SocketAddr> = Vec::new();
+
+    for host in bootstrap {
+        let Ok(resolved) = host.to_socket_addrs() else {
+            continue;
+        };
+
+        let mut host_addresses: Vec<SocketAddr> = Vec::new();
+
+        for address in resolved {
+            if host_addresses.contains(&address) {
+                continue;
+            }
+
+            let same_family = host_addresses
+                .iter()
+                .filter(|a| a.is_ipv4() == address.is_ipv4())
+                .count();
+
+            if same_family < MAX_RESOLVED_ADDRESSES_PER_HOST {
+                host_addresses.push(address);
+            }
+        }
+
+        for address in host_addresses {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+
+    addresses
+}
+
+#[cfg(all(test, feature = "node"))]
+mod test {
+    use crate::common::{
+        AnnouncePeerRequestArguments, FindNodeResponseArguments, PingResponseArguments,
+        PutImmutableRequestArguments, Want, MAX_SALT_SIZE,
+    };
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::Testnet;
+
+    use super::*;
+
+    fn tick_until_done(rpc: &mut Rpc, target: Id) -> Box<[Node]> {
+        loop {
+            let report = rpc.tick();
+
+            if let Some((_, closest_nodes)) = report
+                .done_get_queries
+                .into_iter()
+                .find(|(id, _)| *id == target)
+            {
+                return closest_nodes;
+            }
+        }
+    }
+
+    /// Addresses of all the nodes in a testnet, since nodes in a [Testnet]
+    /// only know about the first node.
+    fn testnet_nodes(testnet: &Testnet) -> Vec<SocketAddrV4> {
+        testnet
+            .nodes
+            .iter()
+            .map(|node| SocketAddrV4::new([127, 0, 0, 1].into(), node.info().local_addr().port()))
+            .collect()
+    }
+
+    fn addresses(bootstrap: &[String]) -> Vec<SocketAddr> {
+        bootstrap
+            .iter()
+            .map(|address| address.parse().unwrap())
+            .collect()
+    }
+
+    /// Sockets answering requests from a test script, with their localhost addresses.
+    fn scripted_nodes(n: usize) -> (Vec<KrpcSocket>, Vec<SocketAddr>) {
+        let nodes = (0..n)
+            .map(|_| KrpcSocket::server().unwrap())
+            .collect::<Vec<_>>();
+        let addresses = nodes
+            .iter()
+            .map(|node| SocketAddr::from(([127, 0, 0, 1], node.local_addr().port())))
+            .collect();
+
+        (nodes, addresses)
+    }
+
+    /// Wait up to `timeout` for the next request to a scripted node,
+    /// returning it with its transaction id and the requester's address.
+    fn recv_request(
+        node: &mut KrpcSocket,
+        timeout: Duration,
+    ) -> Option<(RequestSpecific, u16, SocketAddr)> {
+        let deadline = Instant::now() + timeout;
+
+        while Instant::now() < deadline {
+            if let Some((message, from)) = node.recv_from() {
+                if let MessageType::Request(request) = message.message_type {
+                    return Some((request, message.transaction_id, from));
+                }
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn rebootstrap() {
+        let old = Testnet::new(3).unwrap();
+        let new = Testnet::new(3).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: to_socket_address(&old.bootstrap),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let old_nodes = rpc.routing_table().to_owned_nodes();
+        assert!(!old_nodes.is_empty());
+
+        rpc.rebootstrap(addresses(&new.bootstrap));
+        assert_eq!(rpc.bootstrap.to_vec(), to_socket_address(&new.bootstrap));
+
+        tick_until_done(&mut rpc, self_id);
+
+        let new_ports = new
+            .nodes
+            .iter()
+            .map(|node| node.info().local_addr().port())
+            .collect::<Vec<_>>();
+
+        for node in old_nodes {
+            assert!(rpc.routing_table().nodes().any(|n| n.id() == node.id()));
+        }
+
+        assert!(rpc
+            .routing_table()
+            .nodes()
+            .any(|node| new_ports.contains(&node.socket_address().port())));
+    }
+
+    #[test]
+    fn rebootstrap_joins_running_self_lookup() {
+        let old = Testnet::new(3).unwrap();
+        let new = Testnet::new(3).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: to_socket_address(&old.bootstrap),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+
+        // Start the bootstrapping self lookup.
+        rpc.tick();
+        assert!(rpc.iterative_queries.contains_key(&self_id));
+
+        rpc.rebootstrap(addresses(&new.bootstrap));
+        assert_eq!(rpc.iterative_queries.len(), 1);
+
+        tick_until_done(&mut rpc, self_id);
+
+        let new_ports = new
+            .nodes
+            .iter()
+            .map(|node| node.info().local_addr().port())
+            .collect::<Vec<_>>();
+
+        assert!(rpc
+            .routing_table()
+            .nodes()
+            .any(|node| new_ports.contains(&node.socket_address().port())));
+    }
+
+    #[test]
+    fn cached_closest_nodes_tokens() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: to_socket_address(&testnet.bootstrap),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let target = Id::random();
+
+        assert!(rpc.cached_closest_nodes(&target).is_none());
+
+        rpc.get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash: target,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
+            None,
+        );
+        let closest_nodes = tick_until_done(&mut rpc, target);
+
+        let cached = rpc.cached_closest_nodes(&target).unwrap();
+        assert_eq!(cached, &*closest_nodes);
+        assert!(!cached.is_empty());
+
+        for node in cached {
+            assert!(node.token().is_some());
+            assert!(node.token_received_at().is_some());
+        }
+    }
+
+    #[test]
+    fn put_ack_threshold() {
+        let testnet = Testnet::new(10).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            put_ack_threshold: NonZeroUsize::new(1),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let value: Box<[u8]> = b"Hello World!".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        rpc.put(
+            PutRequestSpecific::PutImmutable(crate::common::PutImmutableRequestArguments {
+                target,
+                v: value,
+            }),
+            None,
+        )
+        .unwrap();
+
+        loop {
+            let report = rpc.tick();
+
+            if let Some((_, result)) = report.done_put_queries.iter().find(|(id, _)| *id == target)
+            {
+                assert!(result.is_ok(), "{result:?}");
+                break;
+            }
+        }
+
+        assert!(rpc.put_queries.is_empty());
+        assert_eq!(rpc.background_put_queries.len(), 1);
+
+        let stored_at = loop {
+            let report = rpc.tick();
+
+            if let Some((_, stored_at)) = report
+                .done_background_put_queries
+                .iter()
+                .find(|(id, _)| *id == target)
+            {
+                break *stored_at;
+            }
+        };
+
+        assert!(stored_at > 1);
+        assert!(rpc.background_put_queries.is_empty());
+    }
+
+    #[test]
+    fn ipv6_find_node() {
+        let mut server = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            server_mode: true,
+            ipv6: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let server_address = SocketAddrV6::new(
+            Ipv6Addr::LOCALHOST,
+            server.local_addr6().unwrap().port(),
+            0,
+            0,
+        );
+
+        let mut client = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            bootstrap6: vec![server_address],
+            ipv6: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(client.bootstrap().is_empty());
+        assert_eq!(client.bootstrap6(), [server_address]);
+
+        let client_id = *client.id();
+
+        client.tick();
+
+        loop {
+            server.tick();
+
+            if client
+                .tick()
+                .done_get_queries
+                .iter()
+                .any(|(id, _)| *id == client_id)
+            {
+                break;
+            }
+        }
+
+        let routing_table6 = client.routing_table6().unwrap();
+
+        assert!(client.routing_table().is_empty());
+        assert!(routing_table6
+            .nodes()
+            .any(|node| node.id() == server.id() && node.address6() == Some(server_address)));
+    }
+
+    #[test]
+    fn ipv6_disabled_ignores_ipv6_bootstrap() {
+        let rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec!["127.0.0.1:6881".parse().unwrap()]),
+            bootstrap6: vec!["[::1]:6881".parse().unwrap()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(rpc.bootstrap(), ["127.0.0.1:6881".parse().unwrap()]);
+        assert!(rpc.bootstrap6().is_empty());
+        assert!(rpc.local_addr6().is_none());
+        assert!(rpc.routing_table6().is_none());
+    }
+
+    #[test]
+    fn cancel_get() {
+        // Bound but never responds.
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![silent.local_addr().unwrap()],
+            request_timeout: Duration::from_millis(50),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let target = Id::random();
+
+        rpc.get(
+            GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                target,
+                want: vec![],
+            }),
+            None,
+        );
+
+        let inflight_requests = rpc.iterative_queries[&target].inflight_requests().to_vec();
+        assert!(!inflight_requests.is_empty());
+
+        assert!(rpc.cancel_get(target));
+        assert!(!rpc.cancel_get(target));
+
+        assert!(!rpc.iterative_queries.contains_key(&target));
+        assert!(inflight_requests
+            .iter()
+            .all(|tid| !rpc.socket.inflight(tid)));
+
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(200) {
+            let report = rpc.tick();
+
+            assert!(!report.done_get_queries.iter().any(|(id, _)| *id == target));
+        }
+    }
+
+    #[test]
+    fn cancel_put() {
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![silent.local_addr().unwrap()],
+            request_timeout: Duration::from_millis(50),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let value: Box<[u8]> = b"Hello World!".as_slice().into();
+        let target = crate::common::hash_immutable(&value).into();
+
+        rpc.put(
+            PutRequestSpecific::PutImmutable(crate::common::PutImmutableRequestArguments {
+                target,
+                v: value,
+            }),
+            None,
+        )
+        .unwrap();
+
+        assert!(rpc.cancel_put(target));
+        assert!(!rpc.cancel_put(target));
+        assert!(rpc.cancel_get(target));
+
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(200) {
+            let report = rpc.tick();
+
+            assert!(!report.done_put_queries.iter().any(|(id, _)| *id == target));
+            assert!(!report.done_get_queries.iter().any(|(id, _)| *id == target));
+        }
+    }
+
+    #[test]
+    fn last_query_trace() {
+        let testnet = Testnet::new(3).unwrap();
+
+        // Bound but never responds.
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_address = silent.local_addr().unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            bootstrap_addrs: vec![silent_address],
+            request_timeout: Duration::from_millis(200),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let target = *rpc.id();
+
+        assert!(rpc.last_query_trace(&target).is_none());
+
+        rpc.tick();
+        tick_until_done(&mut rpc, target);
+
+        let trace = rpc.last_query_trace(&target).unwrap();
+
+        let silent_hop = trace
+            .iter()
+            .find(|hop| hop.address == silent_address)
+            .unwrap();
+        assert!(silent_hop.rtt.is_none());
+        assert!(silent_hop.id.is_none());
+        assert!(!silent_hop.closer_nodes);
+
+        for node in &testnet.nodes {
+            let info = node.info();
+
+            let hop = trace
+                .iter()
+                .find(|hop| hop.address.port() == info.local_addr().port())
+                .unwrap();
+
+            assert_eq!(hop.id, Some(*info.id()));
+            assert!(hop.rtt.unwrap() < Duration::from_millis(200));
+        }
+
+        assert!(trace.iter().any(|hop| hop.closer_nodes));
+    }
+
+    #[test]
+    fn put_batch() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let base = Id::random();
+
+        let mut near = *base.as_bytes();
+        near[19] ^= 1;
+        let near = Id::from_bytes(near).unwrap();
+
+        let mut far = *base.as_bytes();
+        far[0] ^= 0x80;
+        let far = Id::from_bytes(far).unwrap();
+
+        let announce = |info_hash| {
+            PutRequestSpecific::AnnouncePeer(crate::common::AnnouncePeerRequestArguments {
+                info_hash,
+                port: 6881,
+                implied_port: None,
+                seed: false,
+            })
+        };
+
+        rpc.put_batch(vec![announce(base), announce(near), announce(far)])
+            .unwrap();
+
+        // One lookup for `base` and `near`, and one for `far`.
+        assert_eq!(rpc.iterative_queries.len(), 2);
+        assert!(rpc.iterative_queries.contains_key(&base));
+        assert!(rpc.iterative_queries.contains_key(&far));
+
+        let mut done = HashMap::new();
+        while done.len() < 3 {
+            for (id, result) in rpc.tick().done_put_queries {
+                done.insert(id, result);
+            }
+        }
+
+        for target in [base, near, far] {
+            assert!(done[&target].is_ok());
+        }
+    }
+
+    #[test]
+    fn event_sink() {
+        let testnet = Testnet::new(3).unwrap();
+
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink_events = events.clone();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            event_sink: Some(EventSink::new(move |event| {
+                sink_events.lock().unwrap().push(event)
+            })),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let public_address = rpc.public_address().unwrap();
+        assert_eq!(public_address.port(), rpc.local_addr().port());
+
+        assert_eq!(
+            events.lock().unwrap().as_slice(),
+            &[DhtEvent::PublicAddressChanged(public_address)]
+        );
+    }
+
+    #[test]
+    fn event_sink_bootstrap_failed() {
+        // Bound but never responds.
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink_events = events.clone();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![silent.local_addr().unwrap()],
+            request_timeout: Duration::from_millis(50),
+            event_sink: Some(EventSink::new(move |event| {
+                sink_events.lock().unwrap().push(event)
+            })),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        assert_eq!(
+            events.lock().unwrap().as_slice(),
+            &[DhtEvent::BootstrapFailed]
+        );
+    }
+
+    #[test]
+    fn put_mutable_cas_conflict() {
+        // A scripted node that rejects every put with a CAS mismatch.
+        let mut node = KrpcSocket::server().unwrap();
+        let node_id = Id::random();
+        let node_address = SocketAddr::from(([127, 0, 0, 1], node.local_addr().port()));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![node_address],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let item = MutableItem::new(
+            ed25519_dalek::SigningKey::from_bytes(&[0; 32]),
+            b"cas",
+            2,
+            None,
+        );
+        let target = *item.target();
+
+        rpc.put(
+            PutRequestSpecific::PutMutable(PutMutableRequestArguments::from(item, Some(1))),
+            None,
+        )
+        .unwrap();
+
+        let error = loop {
+            if let Some((message, from)) = node.recv_from() {
+                if let MessageType::Request(request) = message.message_type {
+                    let tid = message.transaction_id;
+
+                    match request.request_type {
+                        RequestTypeSpecific::Ping => node.response(
+                            from,
+                            tid,
+                            ResponseSpecific::Ping(PingResponseArguments {
+                                responder_id: node_id,
+                            }),
+                        ),
+                        RequestTypeSpecific::FindNode(_) => node.response(
+                            from,
+                            tid,
+                            ResponseSpecific::FindNode(FindNodeResponseArguments {
+                                responder_id: node_id,
+                                nodes: [].into(),
+                            }),
+                        ),
+                        RequestTypeSpecific::GetValue(_) => node.response(
+                            from,
+                            tid,
+                            ResponseSpecific::NoValues(NoValuesResponseArguments {
+                                responder_id: node_id,
+                                token: [0; 4].into(),
+                                nodes: None,
+                            }),
+                        ),
+                        RequestTypeSpecific::Put(_) => node.error(
+                            from,
+                            tid,
+                            ErrorSpecific {
+                                code: 301,
+                                description: "CAS mismatch".to_string(),
+                            },
+                        ),
+                        RequestTypeSpecific::GetPeers(_) => {}
+                    }
+                }
+            }
+
+            if let Some((_, result)) = rpc
+                .tick()
+                .done_put_queries
+                .into_iter()
+                .find(|(id, _)| *id == target)
+            {
+                break result;
+            }
+        };
+
+        assert!(matches!(
+            error,
+            Err(PutError::CasConflict { target: t, expected: 1 }) if t == target
+        ));
+    }
+
+    #[test]
+    fn rate_limit_requests_per_ip() {
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            server_mode: true,
+            max_requests_per_ip_per_sec: Some(5),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let address = SocketAddr::from(([127, 0, 0, 1], rpc.local_addr().port()));
+
+        let ping = |transaction_id| {
+            Message {
+                transaction_id,
+                version: None,
+                requester_ip: None,
+                read_only: false,
+                message_type: MessageType::Request(RequestSpecific {
+                    requester_id: Id::random(),
+                    request_type: RequestTypeSpecific::Ping,
+                }),
+            }
+            .to_bytes()
+            .unwrap()
+        };
+
+        let noisy = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let other = std::net::UdpSocket::bind("127.0.0.2:0").unwrap();
+
+        for tid in 0..10 {
+            noisy.send_to(&ping(tid), address).unwrap();
+        }
+        for tid in 0..3 {
+            other.send_to(&ping(tid), address).unwrap();
+        }
+
+        for _ in 0..100 {
+            rpc.tick();
+        }
+
+        let count_responses = |socket: &std::net::UdpSocket| {
+            socket
+                .set_read_timeout(Some(Duration::from_millis(100)))
+                .unwrap();
+
+            let mut buf = [0; 1024];
+            let mut count = 0;
+            while socket.recv_from(&mut buf).is_ok() {
+                count += 1;
+            }
+
+            count
+        };
+
+        assert_eq!(count_responses(&noisy), 5);
+        assert_eq!(count_responses(&other), 3);
+    }
+
+    #[test]
+    fn shutdown_drains_put_queries() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let value: Box<[u8]> = b"shutdown".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        rpc.put(
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments { target, v: value }),
+            None,
+        )
+        .unwrap();
+
+        let report = rpc.shutdown(Duration::from_secs(10));
+
+        assert_eq!(report.stored, vec![target]);
+        assert!(report.not_stored.is_empty());
+
+        assert!(matches!(
+            rpc.get(
+                GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                    target,
+                    want: vec![],
+                }),
+                None
+            ),
+            GetStatus::ShuttingDown
+        ));
+        assert!(matches!(
+            rpc.put(
+                PutRequestSpecific::AnnouncePeer(AnnouncePeerRequestArguments {
+                    info_hash: target,
+                    port: 1,
+                    implied_port: None,
+                    seed: false,
+                }),
+                None
+            ),
+            Err(PutError::Query(PutQueryError::ShuttingDown))
+        ));
+    }
+
+    #[test]
+    fn shutdown_abandons_put_queries_after_timeout() {
+        // Bound but never responds.
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![silent.local_addr().unwrap()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let target = Id::random();
+
+        rpc.put(
+            PutRequestSpecific::AnnouncePeer(AnnouncePeerRequestArguments {
+                info_hash: target,
+                port: 1,
+                implied_port: None,
+                seed: false,
+            }),
+            None,
+        )
+        .unwrap();
+
+        let report = rpc.shutdown(Duration::from_millis(100));
+
+        assert!(report.stored.is_empty());
+        assert!(matches!(report.not_stored.as_slice(), [(id, None)] if *id == target));
+    }
+
+    #[test]
+    fn bootstrap_addrs_without_dns() {
+        let address = SocketAddr::from(([127, 0, 0, 1], 6881));
+
+        let rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![address],
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Default bootstrap nodes would have been resolved through DNS.
+        assert_eq!(rpc.bootstrap.to_vec(), &[address]);
+
+        let rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![SocketAddrV4::new([127, 0, 0, 1].into(), 6882)]),
+            bootstrap_addrs: vec![address],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            rpc.bootstrap.to_vec(),
+            &[SocketAddr::from(([127, 0, 0, 1], 6882)), address]
+        );
+    }
+
+    #[test]
+    fn read_only_never_switches_to_server_mode() {
+        for read_only in [false, true] {
+            let mut rpc = Rpc::new(config::Config {
+                bootstrap: Some(vec![]),
+                read_only,
+                ..Default::default()
+            })
+            .unwrap();
+
+            rpc.firewalled = false;
+            rpc.last_table_refresh = Instant::now() - rpc.refresh_delay - Duration::from_secs(1);
+
+            rpc.tick();
+
+            assert_eq!(rpc.server_mode(), !read_only);
+        }
+    }
+
+    #[test]
+    fn deduplicate_peers() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        // Stored at every node, so every responder returns the same peer.
+        let info_hash = Id::random();
+        rpc.put(
+            PutRequestSpecific::AnnouncePeer(AnnouncePeerRequestArguments {
+                info_hash,
+                port: 1234,
+                implied_port: None,
+                seed: false,
+            }),
+            None,
+        )
+        .unwrap();
+
+        while !rpc
+            .tick()
+            .done_put_queries
+            .iter()
+            .any(|(id, _)| *id == info_hash)
+        {}
+
+        rpc.get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
+            None,
+        );
+
+        let mut peers = vec![];
+
+        loop {
+            let report = rpc.tick();
+
+            if let Some((_, Response::Peers(new_peers))) = report.new_query_response {
+                peers.extend(new_peers);
+            }
+
+            if report
+                .done_get_queries
+                .iter()
+                .any(|(id, _)| *id == info_hash)
+            {
+                break;
+            }
+        }
+
+        assert_eq!(peers, vec![SocketAddrV4::new([127, 0, 0, 1].into(), 1234)]);
+    }
+
+    #[test]
+    fn bootstrap_health_queries_failing_nodes_last() {
+        let testnet = Testnet::new(3).unwrap();
+
+        // Bound but never responds.
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_address = silent.local_addr().unwrap();
+
+        let mut bootstrap = vec![silent_address];
+        bootstrap.extend(testnet_nodes(&testnet).into_iter().map(SocketAddr::V4));
+
+        let config = config::Config {
+            bootstrap_addrs: bootstrap.clone(),
+            request_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+
+        let mut rpc = Rpc::new(config.clone()).unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let health = rpc.bootstrap_health();
+        assert_eq!(health.get(&silent_address).unwrap().consecutive_failures, 1);
+        for address in &bootstrap[1..] {
+            assert_eq!(health.get(address).unwrap().successes, 1);
+        }
+
+        let mut health = health.clone();
+        health.failure(silent_address);
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_health: Some(health),
+            ..config
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+
+        let visited = rpc
+            .last_query_trace(&self_id)
+            .unwrap()
+            .iter()
+            .map(|hop| hop.address)
+            .take(bootstrap.len())
+            .collect::<Vec<_>>();
+
+        assert_eq!(visited.last(), Some(&silent_address));
+        assert_eq!(visited[..bootstrap.len() - 1], bootstrap[1..]);
+    }
+
+    #[derive(Debug)]
+    struct MockClock(std::sync::Mutex<Instant>);
+
+    impl MockClock {
+        fn advance(&self, duration: Duration) {
+            *self.0.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn mock_clock_triggers_table_refresh() {
+        let testnet = Testnet::new(3).unwrap();
+
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            clock: clock.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        rpc.tick();
+        assert!(!rpc.iterative_queries.contains_key(&self_id));
+
+        clock.advance(rpc.refresh_delay + Duration::from_secs(1));
+
+        rpc.tick();
+        assert!(rpc.iterative_queries.contains_key(&self_id));
+        assert_eq!(rpc.last_table_refresh, clock.now());
+    }
+
+    #[test]
+    fn mock_clock_ages_nodes() {
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            clock: clock.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let address = SocketAddr::from(([127, 0, 0, 1], 6881));
+        rpc.add_node(Node::seen_at(Id::random(), address, clock.now()));
+        assert_eq!(rpc.to_bootstrap(), vec![address.to_string()]);
+
+        clock.advance(crate::common::STALE_TIME + Duration::from_secs(1));
+        assert!(rpc.to_bootstrap().is_empty());
+    }
+
+    #[test]
+    fn put_reports_stored_at() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let value: Box<[u8]> = b"stored at".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        rpc.put(
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments { target, v: value }),
+            None,
+        )
+        .unwrap();
+
+        let result = loop {
+            if let Some((_, result)) = rpc
+                .tick()
+                .done_put_queries
+                .into_iter()
+                .find(|(id, _)| *id == target)
+            {
+                break result;
+            }
+        };
+
+        // Every node in the testnet acknowledged storing the value.
+        assert_eq!(result.unwrap(), testnet.nodes.len());
+    }
+
+    #[test]
+    fn republish_tracked_put() {
+        let testnet = Testnet::new(3).unwrap();
+
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            clock: clock.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let value: Box<[u8]> = b"republish".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+        let interval = Duration::from_secs(60 * 60);
+
+        rpc.track_for_republish(
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments { target, v: value }),
+            interval,
+        );
+
+        rpc.tick();
+        assert!(!rpc.put_queries.contains_key(&target));
+
+        clock.advance(interval);
+
+        rpc.tick();
+        assert!(rpc.put_queries.contains_key(&target));
+
+        while !rpc
+            .tick()
+            .done_put_queries
+            .iter()
+            .any(|(id, result)| *id == target && result.is_ok())
+        {}
+
+        assert!(rpc.untrack_republish(&target));
+
+        clock.advance(interval);
+
+        rpc.tick();
+        assert!(!rpc.put_queries.contains_key(&target));
+    }
+
+    #[test]
+    fn get_from_single_node() {
+        // A scripted node that responds to any request with an immutable value.
+        let mut node = KrpcSocket::server().unwrap();
+        let node_address = SocketAddr::from(([127, 0, 0, 1], node.local_addr().port()));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let value: Box<[u8]> = b"direct".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        let tid = rpc.get_from(
+            node_address,
+            RequestTypeSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: None,
+            }),
+        );
+
+        assert!(rpc.iterative_queries.is_empty());
+
+        let (message, from) = loop {
+            if let Some(received) = node.recv_from() {
+                break received;
+            }
+        };
+        assert_eq!(message.transaction_id, tid);
+
+        node.response(
+            from,
+            tid,
+            ResponseSpecific::GetImmutable(GetImmutableResponseArguments {
+                responder_id: Id::random(),
+                token: [0; 4].into(),
+                nodes: None,
+                v: value.clone(),
+            }),
+        );
+
+        let (id, response) = loop {
+            if let Some(response) = rpc.tick().new_query_response {
+                break response;
+            }
+        };
+
+        assert_eq!(id, target);
+        assert!(matches!(response, Response::Immutable(v) if v == value));
+        assert!(rpc.direct_requests.is_empty());
+    }
+
+    #[test]
+    fn want_n4_omits_nodes6() {
+        let mut server = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            server_mode: true,
+            ipv6: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        server.routing_table.add(Node::new(
+            Id::random(),
+            SocketAddr::from(([127, 0, 0, 2], 6881)),
+        ));
+        server.routing_table6.as_mut().unwrap().add(Node::new(
+            Id::random(),
+            SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 2], 6881)),
+        ));
+
+        let server_address = SocketAddr::from((
+            [0, 0, 0, 0, 0, 0, 0, 1],
+            server.local_addr6().unwrap().port(),
+        ));
+
+        let mut client = KrpcSocket::new(&config::Config {
+            ipv6: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut find_node = |want: Vec<Want>| {
+            let tid = client.request(
+                server_address,
+                RequestSpecific {
+                    requester_id: Id::random(),
+                    request_type: RequestTypeSpecific::FindNode(FindNodeRequestArguments {
+                        target: Id::random(),
+                        want,
+                    }),
+                },
+            );
+
+            loop {
+                server.tick();
+
+                if let Some((message, _)) = client.recv_from() {
+                    assert_eq!(message.transaction_id, tid);
+
+                    break message.get_closer_nodes().unwrap().to_vec();
+                }
+            }
+        };
+
+        let nodes = find_node(vec![Want::N4]);
+        assert_eq!(nodes.len(), 1);
+        assert!(!nodes[0].is_ipv6());
+
+        // Ipv6 enabled requesters ask for both by default.
+        let nodes = find_node(vec![]);
+        assert_eq!(nodes.len(), 2);
+    }
+
+    #[test]
+    fn bootstrapped_reported_once() {
+        let testnet = Testnet::new(3).unwrap();
+
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            clock: clock.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        let mut bootstrapped = 0;
+
+        loop {
+            let report = rpc.tick();
+
+            if report.bootstrapped {
+                bootstrapped += 1;
+            }
+
+            if report.done_get_queries.iter().any(|(id, _)| *id == self_id) {
+                break;
+            }
+        }
+
+        assert_eq!(bootstrapped, 1);
+        assert!(rpc.routing_table().size() > 0);
+
+        // Refreshing the table runs the self-id query again.
+        clock.advance(rpc.refresh_delay + Duration::from_secs(1));
+        rpc.tick();
+        assert!(rpc.iterative_queries.contains_key(&self_id));
+
+        while rpc.iterative_queries.contains_key(&self_id) {
+            if rpc.tick().bootstrapped {
+                bootstrapped += 1;
+            }
+        }
+
+        assert_eq!(bootstrapped, 1);
+    }
+
+    #[test]
+    fn query_timeout() {
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![silent.local_addr().unwrap()],
+            request_timeout: Duration::from_secs(60 * 60),
+            query_timeout: Duration::from_secs(10),
+            clock: clock.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let target = Id::random();
+        rpc.get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash: target,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
+            None,
+        );
+
+        let report = rpc.tick();
+        assert!(!report.done_get_queries.iter().any(|(id, _)| *id == target));
+        assert!(!rpc.iterative_queries[&target]
+            .inflight_requests()
+            .is_empty());
+
+        clock.advance(Duration::from_secs(11));
+
+        let report = rpc.tick();
+        assert!(report.done_get_queries.iter().any(|(id, _)| *id == target));
+        assert!(!rpc.iterative_queries.contains_key(&target));
+    }
+
+    #[test]
+    fn get_reports_closest_responding_nodes() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let target = Id::random();
+        rpc.get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash: target,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
+            None,
+        );
+
+        let closest_nodes = tick_until_done(&mut rpc, target);
+
+        assert!(!closest_nodes.is_empty());
+        assert_eq!(
+            rpc.cached_iterative_queries
+                .get(&target)
+                .unwrap()
+                .closest_responding_nodes,
+            closest_nodes
+        );
+    }
+
+    #[test]
+    fn get_status() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let value: Box<[u8]> = b"get status".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        let request = || {
+            GetRequestSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: None,
+            })
+        };
+
+        // Not found
+        assert!(matches!(rpc.get(request(), None), GetStatus::Started));
+        assert!(matches!(
+            rpc.get(request(), None),
+            GetStatus::InProgress(responses) if responses.is_empty()
+        ));
+
+        tick_until_done(&mut rpc, target);
+        assert!(matches!(
+            rpc.get(request(), None),
+            GetStatus::Done(responses) if responses.is_empty()
+        ));
+
+        // Found
+        rpc.put(
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments {
+                target,
+                v: value.clone(),
+            }),
+            None,
+        )
+        .unwrap();
+
+        loop {
+            if rpc
+                .tick()
+                .done_put_queries
+                .iter()
+                .any(|(id, _)| *id == target)
+            {
+                break;
+            }
+        }
+
+        assert!(matches!(rpc.get(request(), None), GetStatus::Started));
+        tick_until_done(&mut rpc, target);
+
+        match rpc.get(request(), None) {
+            GetStatus::Done(responses) => {
+                assert!(!responses.is_empty());
+                assert!(responses
+                    .iter()
+                    .all(|response| matches!(response, Response::Immutable(v) if *v == value)));
+            }
+            status => panic!("expected done, got {status:?}"),
+        }
+
+        // A new query is started after the next tick.
+        rpc.tick();
+        assert!(matches!(rpc.get(request(), None), GetStatus::Started));
+    }
+
+    #[test]
+    fn enforce_secure_ids() {
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            enforce_secure_ids: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let secure_ip = Ipv4Addr::new(21, 75, 31, 124);
+        let secure = Node::new(
+            Id::from_ipv4(secure_ip),
+            SocketAddr::from((secure_ip, 6881)),
+        );
+        let insecure = Node::new(
+            Id::from_ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+            SocketAddr::from((Ipv4Addr::new(8, 8, 8, 8), 6881)),
+        );
+        assert!(secure.is_secure());
+        assert!(!insecure.is_secure());
+
+        rpc.add_node(secure.clone());
+        rpc.add_node(insecure);
+
+        assert_eq!(rpc.routing_table().to_owned_nodes(), vec![secure]);
+    }
+
+    #[test]
+    fn metrics() {
+        let testnet = Testnet::new(3).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let metrics = rpc.metrics();
+        assert!(metrics.requests_sent.find_node >= 3);
+        assert!(metrics.responses_received >= 3);
+        assert_eq!(metrics.queries_started, 1);
+        assert_eq!(metrics.queries_completed, 1);
+        assert_eq!(metrics.cache_misses, 1);
+        assert_eq!(metrics.routing_table_size, rpc.routing_table().size());
+
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        rpc.ping(silent.local_addr().unwrap());
+
+        rpc.tick();
+        assert_eq!(
+            rpc.metrics().requests_sent.ping,
+            metrics.requests_sent.ping + 1
+        );
+
+        let target = Id::random();
+        for _ in 0..2 {
+            rpc.get(
+                GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                    target,
+                    want: vec![],
+                }),
+                None,
+            );
+            tick_until_done(&mut rpc, target);
+            rpc.tick();
+        }
+
+        let metrics = rpc.metrics();
+        assert_eq!(metrics.queries_started, 3);
+        assert_eq!(metrics.queries_completed, 3);
+        assert_eq!(metrics.cache_hits, 1);
+        assert_eq!(metrics.cache_misses, 2);
+        assert!(metrics.cache_hit_rate() > 0.3);
+    }
+
+    #[test]
+    fn put_retries_with_backoff() {
+        let testnet = Testnet::new(3).unwrap();
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![silent.local_addr().unwrap()],
+            request_timeout: Duration::from_millis(50),
+            put_max_retries: 3,
+            put_retry_backoff: Duration::from_millis(200),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let value: Box<[u8]> = b"retry".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        rpc.put(
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments { target, v: value }),
+            None,
+        )
+        .unwrap();
+
+        // The first lookup finds no nodes, so the put waits for a retry.
+        tick_until_done(&mut rpc, target);
+        assert_eq!(rpc.put_retries[&target].attempts, 1);
+        assert!(rpc.put_queries.contains_key(&target));
+
+        rpc.rebootstrap(
+            testnet_nodes(&testnet)
+                .into_iter()
+                .map(SocketAddr::V4)
+                .collect(),
+        );
+
+        loop {
+            let report = rpc.tick();
+
+            if let Some((_, result)) = report
+                .done_put_queries
+                .into_iter()
+                .find(|(id, _)| *id == target)
+            {
+                assert!(matches!(result, Ok(stored_at) if stored_at > 0));
+                break;
+            }
+        }
+
+        rpc.tick();
+        assert!(rpc.put_retries.is_empty());
+    }
+
+    #[test]
+    fn ping_reports_rtt() {
+        let testnet = Testnet::new(1).unwrap();
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            request_timeout: Duration::from_millis(100),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let responder = addresses(&testnet.bootstrap)[0];
+
+        rpc.ping(silent.local_addr().unwrap());
+        rpc.ping(responder);
+
+        let mut ping_responses = Vec::new();
+
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(300) {
+            ping_responses.extend(rpc.tick().ping_responses);
+        }
+
+        assert_eq!(ping_responses.len(), 1);
+
+        let (address, rtt) = ping_responses[0];
+        assert_eq!(address, responder);
+        assert!(rtt < Duration::from_millis(100));
+        assert!(rpc.pings.is_empty());
+    }
+
+    #[test]
+    fn symmetric_nat_never_switches_to_server_mode() {
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(rpc.nat_type(), NatType::Unknown);
+
+        let target = Id::random();
+        let mut query = IterativeQuery::new(
+            *rpc.id(),
+            target,
+            GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                target,
+                want: vec![],
+            }),
+            DEFAULT_QUERY_CONCURRENCY,
+            DEFAULT_QUERY_TIMEOUT,
+            rpc.socket.now(),
+        );
+
+        for port in 40000..40004 {
+            query.add_address_vote(SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), port));
+        }
+
+        rpc.update_address_votes_from_iterative_query(&query);
+        assert_eq!(rpc.nat_type(), NatType::SymmetricNat);
+        assert_eq!(rpc.info().nat_type(), NatType::SymmetricNat);
+
+        rpc.firewalled = false;
+        rpc.last_table_refresh = Instant::now() - rpc.refresh_delay - Duration::from_secs(1);
+
+        rpc.tick();
+
+        assert!(!rpc.server_mode());
+    }
+
+    #[test]
+    fn newer_mutable() {
+        // Scripted nodes that respond to get_value with a mutable item of a given seq.
+        let mut nodes = (0..3)
+            .map(|_| KrpcSocket::server().unwrap())
+            .collect::<Vec<_>>();
+        let addresses = nodes
+            .iter()
+            .map(|node| SocketAddr::from(([127, 0, 0, 1], node.local_addr().port())))
+            .collect::<Vec<_>>();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: addresses,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[0; 32]);
+        let target = *MutableItem::new(signer.clone(), b"", 0, None).target();
+
+        rpc.get(
+            GetRequestSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: None,
+            }),
+            None,
+        );
+
+        let mut reported = vec![];
+
+        for (node, seq) in nodes.iter_mut().zip([3, 1, 7]) {
+            let (from, tid) = loop {
+                if let Some((message, from)) = node.recv_from() {
+                    if let MessageType::Request(RequestSpecific {
+                        request_type: RequestTypeSpecific::GetValue(_),
+                        ..
+                    }) = message.message_type
+                    {
+                        break (from, message.transaction_id);
+                    }
+                }
+            };
+
+            let item = MutableItem::new(signer.clone(), b"newer", seq, None);
+
+            node.response(
+                from,
+                tid,
+                ResponseSpecific::GetMutable(GetMutableResponseArguments {
+                    responder_id: Id::random(),
+                    token: [0; 4].into(),
+                    nodes: None,
+                    v: item.value().into(),
+                    k: *item.key(),
+                    seq,
+                    sig: *item.signature(),
+                }),
+            );
+
+            loop {
+                let report = rpc.tick();
+
+                if let Some((id, Response::Mutable(_))) = report.new_query_response {
+                    assert_eq!(id, target);
+
+                    if let Some((id, item)) = report.newer_mutable {
+                        assert_eq!(id, target);
+                        reported.push(item.seq());
+                    }
+
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(reported, vec![3, 7]);
+    }
+
+    #[test]
+    fn put_dry_run() {
+        let testnet = Testnet::new(10).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let value: Box<[u8]> = b"dry run".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+        let request =
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments { target, v: value });
+
+        assert!(rpc.put_dry_run(request.clone()).is_empty());
+        assert!(rpc.put_queries.is_empty());
+
+        let closest_nodes = tick_until_done(&mut rpc, target);
+
+        let nodes = rpc.put_dry_run(request);
+
+        assert!(!nodes.is_empty());
+        assert_eq!(
+            nodes.iter().map(|n| *n.id()).collect::<Vec<_>>(),
+            closest_nodes.iter().map(|n| *n.id()).collect::<Vec<_>>()
+        );
+        assert!(rpc.put_queries.is_empty());
+    }
+
+    #[test]
+    fn max_query_cache_bytes() {
+        let nodes = (0..20)
+            .map(|port| Node::new(Id::random(), SocketAddr::from(([127, 0, 0, 1], port))))
+            .collect::<Vec<_>>();
+        let entry_size = std::mem::size_of_val(nodes.as_slice());
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            max_query_cache_bytes: Some(3 * entry_size),
+            ..Default::default()
+        })
+        .unwrap();
+
+        for _ in 0..5 {
+            let target = Id::random();
+            let mut query = IterativeQuery::new(
+                *rpc.id(),
+                target,
+                GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                    target,
+                    want: vec![],
+                }),
+                1,
+                DEFAULT_QUERY_TIMEOUT,
+                rpc.socket.now(),
+            );
+            query.add_candidate(nodes[0].clone());
+
+            rpc.cache_iterative_query(&query, &nodes);
+        }
+
+        // Evicted far below the count threshold.
+        assert_eq!(rpc.cached_iterative_queries.len(), 3);
+        assert_eq!(rpc.query_cache_bytes, 3 * entry_size);
+
+        // Both limits are enforced.
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            max_query_cache_bytes: Some(3 * entry_size),
+            query_cache_size: NonZeroUsize::new(2),
+            ..Default::default()
+        })
+        .unwrap();
+
+        for _ in 0..5 {
+            rpc.seed_query_cache(Id::random(), nodes.clone());
+        }
+
+        assert_eq!(rpc.cached_iterative_queries.len(), 2);
+        assert_eq!(rpc.query_cache_bytes, 2 * entry_size);
+    }
+
+    #[test]
+    fn skip_caching_oversized_query() {
+        let nodes = (0..20)
+            .map(|port| Node::new(Id::random(), SocketAddr::from(([127, 0, 0, 1], port))))
+            .collect::<Vec<_>>();
+        let entry_size = std::mem::size_of_val(&nodes[..10]);
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            max_query_cache_bytes: Some(2 * entry_size),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let first = Id::random();
+        let second = Id::random();
+        rpc.seed_query_cache(first, nodes[..10].to_vec());
+        rpc.seed_query_cache(second, nodes[10..].to_vec());
+
+        let dht_size_estimates_sum = rpc.dht_size_estimates_sum;
+        let subnets_sum = rpc.subnets_sum;
+
+        // Larger than the whole cache.
+        rpc.seed_query_cache(Id::random(), nodes.iter().chain(&nodes).cloned().collect());
+
+        assert_eq!(rpc.cached_iterative_queries.len(), 2);
+        assert!(rpc.cached_iterative_queries.contains(&first));
+        assert!(rpc.cached_iterative_queries.contains(&second));
+        assert_eq!(rpc.query_cache_bytes, 2 * entry_size);
+        assert_eq!(rpc.dht_size_estimates_sum, dht_size_estimates_sum);
+        assert_eq!(rpc.subnets_sum, subnets_sum);
+    }
+
+    #[test]
+    fn find_live_nodes() {
+        let testnet = Testnet::new(5).unwrap();
+
+        // Bound but never respond.
+        let silent = (0..3)
+            .map(|_| std::net::UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect::<Vec<_>>();
+        let silent_addresses = silent
+            .iter()
+            .map(|socket| socket.local_addr().unwrap())
+            .collect::<Vec<_>>();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            bootstrap_addrs: silent_addresses.clone(),
+            request_timeout: Duration::from_millis(100),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let target = Id::random();
+        rpc.find_live_nodes(target);
+
+        let live_nodes = loop {
+            if let Some((_, nodes)) = rpc
+                .tick()
+                .done_live_nodes
+                .into_iter()
+                .find(|(id, _)| *id == target)
+            {
+                break nodes;
+            }
+        };
+
+        let trace = rpc.last_query_trace(&target).unwrap();
+        assert!(silent_addresses
+            .iter()
+            .all(|address| trace.iter().any(|hop| hop.address == *address)));
+
+        assert!(!live_nodes.is_empty());
+        assert!(live_nodes
+            .iter()
+            .all(|node| !silent_addresses.contains(&node.socket_address())));
+    }
+
+    #[test]
+    fn seed_query_cache() {
+        let testnet = Testnet::new(10).unwrap();
+
+        let value: Box<[u8]> = b"seeded".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        // Closest nodes (with tokens) found by a previous session.
+        let nodes = {
+            let mut rpc = Rpc::new(config::Config {
+                bootstrap: Some(testnet_nodes(&testnet)),
+                ..Default::default()
+            })
+            .unwrap();
+
+            let self_id = *rpc.id();
+            rpc.tick();
+            tick_until_done(&mut rpc, self_id);
+
+            rpc.get(
+                GetRequestSpecific::GetValue(GetValueRequestArguments {
+                    target,
+                    seq: None,
+                    salt: None,
+                }),
+                None,
+            );
+
+            tick_until_done(&mut rpc, target)
+        };
+        assert!(!nodes.is_empty());
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        rpc.seed_query_cache(target, nodes.to_vec());
+
+        rpc.put(
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments { target, v: value }),
+            None,
+        )
+        .unwrap();
+
+        assert!(!rpc.iterative_queries.contains_key(&target));
+        assert!(rpc.put_queries[&target].started());
+
+        let result = loop {
+            if let Some((_, result)) = rpc
+                .tick()
+                .done_put_queries
+                .into_iter()
+                .find(|(id, _)| *id == target)
+            {
+                break result;
+            }
+        };
+
+        assert_eq!(result.unwrap(), nodes.len());
+    }
+
+    #[test]
+    fn max_inflight_requests() {
+        let testnet = Testnet::new(10).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            max_inflight_requests: 4,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let targets = (0..20).map(|_| Id::random()).collect::<Vec<_>>();
+
+        for target in &targets {
+            rpc.get(
+                GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                    target: *target,
+                    want: vec![],
+                }),
+                None,
+            );
+        }
+
+        let mut done = std::collections::HashSet::new();
+
+        while done.len() < targets.len() {
+            assert!(rpc.socket.inflight_count() <= 4);
+
+            for (id, _) in rpc.tick().done_get_queries {
+                done.insert(id);
+            }
+        }
+
+        assert!(rpc.socket.inflight_count() <= 4);
+        assert!(targets.iter().all(|target| done.contains(target)));
+    }
+
+    #[test]
+    fn value_cache_ttl() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            value_cache_ttl: Some(Duration::from_secs(60)),
+            clock: clock.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let value: Box<[u8]> = b"value cache".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        rpc.put(
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments {
+                target,
+                v: value.clone(),
+            }),
+            None,
+        )
+        .unwrap();
+
+        while !rpc
+            .tick()
+            .done_put_queries
+            .iter()
+            .any(|(id, _)| *id == target)
+        {}
+
+        let request = || {
+            GetRequestSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: None,
+            })
+        };
+
+        assert!(matches!(rpc.get(request(), None), GetStatus::Started));
+        tick_until_done(&mut rpc, target);
+        rpc.tick();
+
+        // Returned immediately, while refreshing in the background.
+        match rpc.get(request(), None) {
+            GetStatus::Cached(responses) => {
+                assert!(!responses.is_empty());
+                assert!(responses
+                    .iter()
+                    .all(|response| matches!(response, Response::Immutable(v) if *v == value)));
+            }
+            status => panic!("expected cached, got {status:?}"),
+        }
+        assert!(rpc.iterative_queries.contains_key(&target));
+
+        tick_until_done(&mut rpc, target);
+        rpc.tick();
+
+        // Stale
+        clock.advance(Duration::from_secs(61));
+        assert!(matches!(rpc.get(request(), None), GetStatus::Started));
+    }
+
+    #[test]
+    fn get_first() {
+        let testnet = Testnet::new(10).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let value: Box<[u8]> = b"get first".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        rpc.put(
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments {
+                target,
+                v: value.clone(),
+            }),
+            None,
+        )
+        .unwrap();
+
+        let stored_at = loop {
+            if let Some((_, result)) = rpc
+                .tick()
+                .done_put_queries
+                .into_iter()
+                .find(|(id, _)| *id == target)
+            {
+                break result.unwrap();
+            }
+        };
+        assert!(stored_at > 1);
+
+        let request = || {
+            GetRequestSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: None,
+            })
+        };
+
+        assert!(matches!(rpc.get_first(request(), None), GetStatus::Started));
+
+        // Done in the tick right after the first value.
+        loop {
+            let report = rpc.tick();
+
+            if let Some((_, response)) = report.new_query_response {
+                assert!(matches!(response, Response::Immutable(v) if v == value));
+                break;
+            }
+        }
+        assert!(rpc
+            .tick()
+            .done_get_queries
+            .iter()
+            .any(|(id, _)| *id == target));
+
+        match rpc.get(request(), None) {
+            GetStatus::Done(responses) => assert_eq!(responses.len(), 1),
+            status => panic!("expected done, got {status:?}"),
+        }
+        assert!(rpc.cached_iterative_queries.peek(&target).unwrap().partial);
+    }
+
+    #[test]
+    fn custom_refresh_interval() {
+        let testnet = Testnet::new(3).unwrap();
+
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            clock: clock.clone(),
+            refresh_interval: Duration::from_secs(60),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        rpc.tick();
+        assert!(!rpc.iterative_queries.contains_key(&self_id));
+
+        clock.advance(Duration::from_secs(30));
+        rpc.tick();
+        assert!(!rpc.iterative_queries.contains_key(&self_id));
+
+        // Long before the default interval, even with the maximum jitter.
+        clock.advance(Duration::from_secs(37));
+        rpc.tick();
+        assert!(rpc.iterative_queries.contains_key(&self_id));
+        assert_eq!(rpc.last_table_refresh, clock.now());
+    }
+
+    #[test]
+    fn request_observer() {
+        let observed = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let observer_observed = observed.clone();
+
+        // Client mode, so the request is observed but not responded to.
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            request_observer: Some(RequestObserver::new(move |from, request| {
+                observer_observed
+                    .lock()
+                    .unwrap()
+                    .push((*from, request.clone()))
+            })),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut client = KrpcSocket::client().unwrap();
+        let client_address = SocketAddr::from(([127, 0, 0, 1], client.local_addr().port()));
+
+        let request = RequestSpecific {
+            requester_id: Id::random(),
+            request_type: RequestTypeSpecific::FindNode(FindNodeRequestArguments {
+                target: Id::random(),
+                want: vec![Want::N4],
+            }),
+        };
+
+        client.request(
+            SocketAddr::from(([127, 0, 0, 1], rpc.local_addr().port())),
+            request.clone(),
+        );
+
+        while observed.lock().unwrap().is_empty() {
+            rpc.tick();
+        }
+
+        assert_eq!(
+            observed.lock().unwrap().as_slice(),
+            &[(client_address, request)]
+        );
+        assert!(!rpc.server_mode());
+    }
+
+    #[test]
+    fn bootstrap_resolved_addresses_per_host() {
+        /// A hostname resolving to many addresses.
+        struct MultiHomed;
+
+        impl ToSocketAddrs for MultiHomed {
+            type Iter = std::vec::IntoIter<SocketAddr>;
+
+            fn to_socket_addrs(&self) -> std::io::Result<Self::Iter> {
+                Ok(vec![
+                    SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 6881)),
+                    SocketAddr::from(([127, 0, 0, 1], 6881)),
+                    SocketAddr::from(([127, 0, 0, 1], 6881)),
+                    SocketAddr::from(([127, 0, 0, 2], 6881)),
+                    SocketAddr::from(([127, 0, 0, 3], 6881)),
+                ]
+                .into_iter())
+            }
+        }
+
+        let rpc = Rpc::new(config::Config {
+            bootstrap_addrs: to_socket_address(&[MultiHomed]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            rpc.bootstrap.to_vec(),
+            [
+                SocketAddr::from(([127, 0, 0, 1], 6881)),
+                SocketAddr::from(([127, 0, 0, 2], 6881)),
+            ]
+        );
+
+        let rpc = Rpc::new(config::Config {
+            bootstrap_addrs: to_socket_address(&[MultiHomed, MultiHomed]),
+            ipv6: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            rpc.bootstrap.to_vec(),
+            [
+                SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 6881)),
+                SocketAddr::from(([127, 0, 0, 1], 6881)),
+                SocketAddr::from(([127, 0, 0, 2], 6881)),
+            ]
+        );
+    }
+
+    #[test]
+    fn bind_failed() {
+        let rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let result = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            port: Some(rpc.local_addr().port()),
+            ..Default::default()
+        });
+
+        let Err(error) = result else {
+            panic!("expected binding to a used port to fail");
+        };
+
+        assert!(matches!(error, RpcError::BindFailed(_)));
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    #[test]
+    fn fixed_node_id() {
+        let testnet = Testnet::new(3).unwrap();
+
+        let node_id = Id::random();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            node_id: Some(node_id),
+            public_ip: Some(Ipv4Addr::LOCALHOST),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(*rpc.id(), node_id);
+
+        rpc.tick();
+        tick_until_done(&mut rpc, node_id);
+
+        // Not replaced by a secure Id for the public address.
+        assert!(rpc.public_address().is_some());
+        rpc.tick();
+        assert_eq!(*rpc.id(), node_id);
+    }
+
+    #[test]
+    fn capture_rejected() {
+        // A scripted node that responds to get_value with an invalid immutable value.
+        let mut node = KrpcSocket::server().unwrap();
+        let node_address = SocketAddr::from(([127, 0, 0, 1], node.local_addr().port()));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![node_address],
+            capture_rejected: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let target = Id::from(crate::common::hash_immutable(b"expected"));
+
+        rpc.get(
+            GetRequestSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: None,
+            }),
+            None,
+        );
+
+        let (from, tid) = loop {
+            if let Some((message, from)) = node.recv_from() {
+                if let MessageType::Request(RequestSpecific {
+                    request_type: RequestTypeSpecific::GetValue(_),
+                    ..
+                }) = message.message_type
+                {
+                    break (from, message.transaction_id);
+                }
+            }
+        };
+
+        let invalid: Box<[u8]> = b"invalid".as_slice().into();
+
+        node.response(
+            from,
+            tid,
+            ResponseSpecific::GetImmutable(GetImmutableResponseArguments {
+                responder_id: Id::random(),
+                token: [0; 4].into(),
+                nodes: None,
+                v: invalid.clone(),
+            }),
+        );
+
+        while rpc.rejected_samples().is_empty() {
+            rpc.tick();
+        }
+
+        assert_eq!(
+            rpc.rejected_samples(),
+            vec![RejectedSample {
+                target,
+                from: node_address,
+                v: invalid,
+                reason: RejectionReason::InvalidImmutable,
+            }]
+        );
+    }
+
+    #[test]
+    fn subscribe() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let target = crate::Dht::builder()
+            .bootstrap(&testnet.bootstrap)
+            .build()
+            .unwrap()
+            .put_immutable(b"shared")
+            .unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let request = || {
+            GetRequestSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: None,
+            })
+        };
+
+        assert!(matches!(rpc.subscribe(request(), None), GetStatus::Started));
+        assert!(matches!(
+            rpc.subscribe(request(), None),
+            GetStatus::InProgress(_)
+        ));
+        assert_eq!(rpc.iterative_queries[&target].subscribers(), 2);
+        assert!(rpc.take_completed(&target).is_none());
+
+        tick_until_done(&mut rpc, target);
+
+        // Still kept after the tick the query was done in.
+        rpc.tick();
+
+        let first = rpc.take_completed(&target).unwrap();
+        let second = rpc.take_completed(&target).unwrap();
+
+        assert_eq!(first.len(), 1);
+        assert_eq!(second.len(), 1);
+        assert!(matches!(&first[0], Response::Immutable(v) if v.as_ref() == b"shared"));
+        assert!(matches!(&second[0], Response::Immutable(v) if v.as_ref() == b"shared"));
+        assert!(rpc.take_completed(&target).is_none());
+    }
+
+    #[test]
+    fn completed_subscriptions_expire() {
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            clock: clock.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Done with a subscriber that never takes its responses.
+        let target = Id::random();
+        rpc.completed_subscriptions
+            .insert(target, (rpc.socket.now(), 1, vec![]));
+
+        clock.advance(COMPLETED_SUBSCRIPTION_TTL - Duration::from_secs(1));
+        rpc.tick();
+        assert!(rpc.completed_subscriptions.contains_key(&target));
+
+        clock.advance(Duration::from_secs(1));
+        rpc.tick();
+        assert!(rpc.completed_subscriptions.is_empty());
+        assert!(rpc.take_completed(&target).is_none());
+    }
+
+    #[test]
+    fn put_value_and_salt_too_large() {
+        // A scripted node that should never receive anything.
+        let node = KrpcSocket::server().unwrap();
+        let node_address = SocketAddr::from(([127, 0, 0, 1], node.local_addr().port()));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![node_address],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mutable = |v: usize, salt: usize| {
+            PutRequestSpecific::PutMutable(PutMutableRequestArguments {
+                target: Id::random(),
+                v: vec![0; v].into(),
+                k: [0; 32],
+                seq: 0,
+                sig: [0; 64],
+                salt: Some(vec![0; salt].into()),
+                cas: None,
+            })
+        };
+        let immutable = |v: usize| {
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments {
+                target: Id::random(),
+                v: vec![0; v].into(),
+            })
+        };
+
+        assert!(matches!(
+            rpc.put(immutable(DEFAULT_MAX_VALUE_SIZE + 1), None),
+            Err(PutError::Query(PutQueryError::ValueTooLarge(1001)))
+        ));
+        assert!(matches!(
+            rpc.put(mutable(DEFAULT_MAX_VALUE_SIZE + 1, 0), None),
+            Err(PutError::Query(PutQueryError::ValueTooLarge(1001)))
+        ));
+        assert!(matches!(
+            rpc.put(mutable(0, MAX_SALT_SIZE + 1), None),
+            Err(PutError::Query(PutQueryError::SaltTooLarge(65)))
+        ));
+
+        assert!(rpc.put_queries.is_empty());
+        assert!(rpc.iterative_queries.is_empty());
+        assert_eq!(rpc.metrics().requests_sent.total(), 0);
+
+        rpc.put(immutable(DEFAULT_MAX_VALUE_SIZE), None).unwrap();
+        rpc.put(mutable(DEFAULT_MAX_VALUE_SIZE, MAX_SALT_SIZE), None)
+            .unwrap();
+
+        assert_eq!(rpc.put_queries.len(), 2);
+    }
+
+    #[test]
+    fn active_queries() {
+        let testnet = Testnet::new(3).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let peers_target = Id::random();
+        let put_target = Id::random();
+
+        rpc.get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash: peers_target,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
+            None,
+        );
+        rpc.put(
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments {
+                target: put_target,
+                v: b"summary".as_slice().into(),
+            }),
+            None,
+        )
+        .unwrap();
+
+        let summaries = rpc.active_queries();
+        let kind = |target: Id| {
+            summaries
+                .iter()
+                .filter(|summary| summary.target == target)
+                .map(|summary| summary.kind)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(kind(peers_target), vec![QueryKind::GetPeers]);
+        // The put query, and the lookup of the closest nodes to store at.
+        let mut put_kinds = kind(put_target);
+        put_kinds.sort_by_key(|kind| *kind == QueryKind::Put);
+        assert_eq!(put_kinds, vec![QueryKind::GetValue, QueryKind::Put]);
+
+        let peers = summaries
+            .iter()
+            .find(|summary| summary.target == peers_target)
+            .unwrap();
+        assert!(peers.inflight > 0);
+        assert_eq!(peers.responders, 0);
+    }
+
+    #[test]
+    fn ignore_spoofed_response() {
+        // A scripted node that never responds itself.
+        let mut node = KrpcSocket::server().unwrap();
+        let node_address = SocketAddr::from(([127, 0, 0, 1], node.local_addr().port()));
+
+        // Another node replaying the transaction id of requests sent to the first one.
+        let mut spoofer = KrpcSocket::server().unwrap();
+        let bogus = SocketAddr::from(([127, 0, 0, 3], 6881));
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![node_address],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+
+        let tid = loop {
+            if let Some((message, _)) = node.recv_from() {
+                break message.transaction_id;
+            }
+        };
+
+        spoofer.response(
+            SocketAddr::from(([127, 0, 0, 1], rpc.local_addr().port())),
+            tid,
+            ResponseSpecific::FindNode(FindNodeResponseArguments {
+                responder_id: Id::random(),
+                nodes: [Node::new(Id::random(), bogus)].into(),
+            }),
+        );
+
+        for _ in 0..5 {
+            rpc.tick();
+        }
+
+        let query = &rpc.iterative_queries[&self_id];
+        assert!(query.responders().is_empty());
+        assert!(!query
+            .closest()
+            .nodes()
+            .iter()
+            .any(|node| node.socket_address() == bogus));
+        assert!(rpc.socket.inflight(&tid));
+    }
+
+    #[test]
+    fn maintenance_jitter() {
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+
+        let new_rpc = || {
+            Rpc::new(config::Config {
+                bootstrap: Some(vec![]),
+                clock: clock.clone(),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        let a = new_rpc();
+        let b = new_rpc();
+
+        assert_eq!(a.last_table_refresh, b.last_table_refresh);
+        assert_ne!(
+            a.last_table_refresh + a.refresh_delay,
+            b.last_table_refresh + b.refresh_delay
+        );
+        assert_ne!(a.ping_delay, b.ping_delay);
+
+        for rpc in [a, b] {
+            assert!(rpc.refresh_delay >= DEFAULT_REFRESH_INTERVAL.mul_f64(0.9));
+            assert!(rpc.refresh_delay <= DEFAULT_REFRESH_INTERVAL.mul_f64(1.1));
+            assert!(rpc.ping_delay >= DEFAULT_PING_INTERVAL.mul_f64(0.9));
+            assert!(rpc.ping_delay <= DEFAULT_PING_INTERVAL.mul_f64(1.1));
+        }
+    }
+
+    #[test]
+    fn join_swarm() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let info_hash = Id::random();
+
+        // An existing peer in the swarm.
+        let peer = crate::Dht::builder()
+            .bootstrap(&testnet.bootstrap)
+            .build()
+            .unwrap();
+        peer.announce_peer(info_hash, Some(4000)).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        rpc.join_swarm(info_hash, Some(5000)).unwrap();
+
+        // Joining again while the first join is inflight is a noop.
+        rpc.join_swarm(info_hash, Some(6000)).unwrap();
+        assert!(matches!(
+            &rpc.put_queries[&info_hash].request,
+            PutRequestSpecific::AnnouncePeer(args) if args.port == 5000
+        ));
+
+        let report = loop {
+            if let Some((id, report)) = rpc.tick().done_join_swarms.pop() {
+                assert_eq!(id, info_hash);
+
+                break report;
+            }
+        };
+
+        assert_eq!(report.peers, vec![SocketAddr::from(([127, 0, 0, 1], 4000))]);
+        assert!(report.announced.unwrap() > 0);
+        assert!(rpc.join_swarm_queries.is_empty());
+
+        // Announced at the nodes that gave us a token.
+        let mut peers = peer
+            .get_peers(info_hash)
+            .flatten()
+            .map(|peer| peer.port())
+            .collect::<Vec<_>>();
+        peers.sort();
+        peers.dedup();
+
+        assert_eq!(peers, vec![4000, 5000]);
+
+        // Joining can't replace another inflight announcement.
+        rpc.put(
+            PutRequestSpecific::AnnouncePeer(AnnouncePeerRequestArguments {
+                info_hash,
+                port: 6000,
+                implied_port: None,
+                seed: false,
+            }),
+            None,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            rpc.join_swarm(info_hash, Some(6000)),
+            Err(PutError::Concurrency(ConcurrencyError::Inflight))
+        ));
+    }
+
+    #[test]
+    fn min_storage_nodes() {
+        // Scripted nodes, of which only 2 store the value.
+        let (mut nodes, addresses) = scripted_nodes(8);
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            min_storage_nodes: 5,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let value: Box<[u8]> = b"durable".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        rpc.seed_query_cache(
+            target,
+            addresses
+                .iter()
+                .map(|address| {
+                    Node::new_with_token(Id::random(), *address, [0; 4].into(), Instant::now())
+                })
+                .collect(),
+        );
+
+        rpc.put(
+            PutRequestSpecific::PutImmutable(PutImmutableRequestArguments { target, v: value }),
+            None,
+        )
+        .unwrap();
+
+        for (i, node) in nodes.iter_mut().enumerate() {
+            let (_, tid, from) = recv_request(node, Duration::from_secs(1)).unwrap();
+
+            if i < 2 {
+                node.response(
+                    from,
+                    tid,
+                    ResponseSpecific::Ping(PingResponseArguments {
+                        responder_id: Id::random(),
+                    }),
+                );
+            } else {
+                node.error(
+                    from,
+                    tid,
+                    ErrorSpecific {
+                        code: 201,
+                        description: "Generic Error".to_string(),
                     },
-                )) => {
-                    debug!(
-                        target= ?query.target(),
-                        salt= ?match query.request.request_type.clone() {
-                            RequestTypeSpecific::GetValue(args) => args.salt,
-                            _ => None,
-                        },
-                        ?seq,
-                        ?from,
-                        ?responder_id,
-                        ?from_version,
-                        "No more recent value"
-                    );
+                );
+            }
+        }
+
+        let result = loop {
+            if let Some((_, result)) = rpc.tick().done_put_queries.pop() {
+                break result;
+            }
+        };
+
+        assert!(matches!(
+            result,
+            Err(PutError::Query(PutQueryError::InsufficientStorageNodes {
+                stored: 2,
+                required: 5
+            }))
+        ));
+    }
+
+    #[test]
+    fn export_import_state() {
+        let testnet = Testnet::new(10).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let target = Id::random();
+        rpc.get(
+            GetRequestSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: None,
+            }),
+            None,
+        );
+        tick_until_done(&mut rpc, target);
+
+        let bytes = rpc.export_state();
+        assert_eq!(bytes[0], STATE_VERSION);
+
+        let mut imported = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+        imported.import_state(&bytes).unwrap();
+
+        let summary = |nodes: &[Node]| {
+            nodes
+                .iter()
+                .map(|node| (*node.id(), node.socket_address()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(imported.id(), rpc.id());
+        assert!(!rpc.routing_table().is_empty());
+        assert_eq!(
+            summary(&imported.routing_table().to_owned_nodes()),
+            summary(&rpc.routing_table().to_owned_nodes())
+        );
+
+        for target in [self_id, target] {
+            assert_eq!(
+                imported.cached_closest_nodes(&target).map(summary),
+                rpc.cached_closest_nodes(&target).map(summary),
+            );
+        }
+        assert_eq!(imported.export_state(), bytes);
+    }
+
+    #[test]
+    fn always_answer_find_node() {
+        for always_answer_find_node in [false, true] {
+            let mut rpc = Rpc::new(config::Config {
+                bootstrap: Some(vec![]),
+                always_answer_find_node,
+                ..Default::default()
+            })
+            .unwrap();
+            let rpc_address = SocketAddr::from(([127, 0, 0, 1], rpc.local_addr().port()));
+
+            let mut client = KrpcSocket::client().unwrap();
+
+            let find_node_tid = client.request(
+                rpc_address,
+                RequestSpecific {
+                    requester_id: Id::random(),
+                    request_type: RequestTypeSpecific::FindNode(FindNodeRequestArguments {
+                        target: Id::random(),
+                        want: vec![Want::N4],
+                    }),
+                },
+            );
+            client.request(
+                rpc_address,
+                RequestSpecific {
+                    requester_id: Id::random(),
+                    request_type: RequestTypeSpecific::GetPeers(GetPeersRequestArguments {
+                        info_hash: Id::random(),
+                        scrape: false,
+                        noseed: false,
+                        want: vec![],
+                    }),
+                },
+            );
+
+            let mut responses = vec![];
+            for _ in 0..20 {
+                rpc.tick();
+
+                if let Some((message, _)) = client.recv_from() {
+                    responses.push(message);
                 }
-                MessageType::Response(ResponseSpecific::NoValues(NoValuesResponseArguments {
-                    responder_id,
-                    ..
-                })) => {
-                    debug!(
-                        target= ?query.target(),
-                        salt= ?match query.request.request_type.clone() {
-                            RequestTypeSpecific::GetValue(args) => args.salt,
-                            _ => None,
-                        },
-                        ?from,
-                        ?responder_id,
-                        ?from_version ,
-                        "No values"
-                    );
+            }
+
+            assert!(!rpc.server_mode());
+            if always_answer_find_node {
+                assert_eq!(responses.len(), 1);
+                assert_eq!(responses[0].transaction_id, find_node_tid);
+                assert!(matches!(
+                    responses[0].message_type,
+                    MessageType::Response(ResponseSpecific::FindNode(_))
+                ));
+            } else {
+                assert!(responses.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn public_address_confidence() {
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(rpc.public_address_confidence(), None);
+
+        let target = Id::random();
+        let mut query = IterativeQuery::new(
+            *rpc.id(),
+            target,
+            GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                target,
+                want: vec![],
+            }),
+            DEFAULT_QUERY_CONCURRENCY,
+            DEFAULT_QUERY_TIMEOUT,
+            rpc.socket.now(),
+        );
+
+        let address = SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), 6881);
+        for _ in 0..7 {
+            query.add_address_vote(address);
+        }
+        query.add_address_vote(SocketAddrV4::new(Ipv4Addr::new(5, 6, 7, 8), 6881));
+
+        rpc.update_address_votes_from_iterative_query(&query);
+
+        assert_eq!(rpc.public_address(), Some(address));
+        assert_eq!(
+            rpc.public_address_confidence(),
+            Some(AddressConfidence {
+                address,
+                votes: 7,
+                dissenting: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn observed_external_port() {
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(rpc.observed_external_port(), None);
+
+        let target = Id::random();
+        let mut query = IterativeQuery::new(
+            *rpc.id(),
+            target,
+            GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                target,
+                want: vec![],
+            }),
+            DEFAULT_QUERY_CONCURRENCY,
+            DEFAULT_QUERY_TIMEOUT,
+            rpc.socket.now(),
+        );
+
+        let external_port = rpc.local_addr().port().wrapping_add(1000).max(1);
+        for _ in 0..3 {
+            query.add_address_vote(SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), external_port));
+        }
+
+        rpc.update_address_votes_from_iterative_query(&query);
+
+        assert_eq!(rpc.observed_external_port(), Some(external_port));
+        assert_ne!(rpc.observed_external_port(), Some(rpc.local_addr().port()));
+    }
+
+    #[test]
+    fn refresh() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+        assert!(!rpc.iterative_queries.contains_key(&self_id));
+
+        rpc.last_table_refresh = Instant::now() - Duration::from_secs(60);
+
+        rpc.refresh();
+
+        let query = rpc.iterative_queries.get(&self_id).unwrap();
+        assert!(matches!(
+            query.request.request_type,
+            RequestTypeSpecific::FindNode(_)
+        ));
+        assert!(rpc.last_table_refresh.elapsed() < Duration::from_secs(1));
+
+        // Idempotent while the refresh query is running.
+        rpc.refresh();
+        assert_eq!(rpc.iterative_queries.len(), 1);
+    }
+
+    #[test]
+    fn max_peers_per_query() {
+        // Scripted nodes, each responding with 100 different peers.
+        let (mut nodes, addresses) = scripted_nodes(5);
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: addresses,
+            request_timeout: Duration::from_millis(100),
+            max_peers_per_query: Some(100),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let info_hash = Id::random();
+
+        rpc.get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
+            None,
+        );
+
+        for (i, node) in nodes.iter_mut().enumerate() {
+            let (from, tid) = loop {
+                let (request, tid, from) = recv_request(node, Duration::from_secs(1)).unwrap();
+
+                if let RequestTypeSpecific::GetPeers(_) = request.request_type {
+                    break (from, tid);
                 }
-                MessageType::Error(error) => {
-                    debug!(?error, ?from_version, "Get query got error response");
+            };
+
+            node.response(
+                from,
+                tid,
+                ResponseSpecific::GetPeers(GetPeersResponseArguments {
+                    responder_id: Id::random(),
+                    token: [0; 4].into(),
+                    values: (0..100)
+                        .map(|j| SocketAddr::from(([10, 0, i as u8, j as u8], 6881)))
+                        .collect(),
+                    nodes: None,
+                    seeders: None,
+                    peers: None,
+                }),
+            );
+        }
+
+        let mut collected = 0;
+        loop {
+            let report = rpc.tick();
+
+            if let Some((id, Response::Peers(peers))) = report.new_query_response {
+                assert_eq!(id, info_hash);
+                collected += peers.len();
+            }
+
+            if report
+                .done_get_queries
+                .iter()
+                .any(|(id, _)| *id == info_hash)
+            {
+                break;
+            }
+        }
+
+        assert_eq!(collected, 100);
+    }
+
+    #[test]
+    fn bootstrap_status() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(rpc.info().bootstrap_status(), BootstrapStatus::InProgress);
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        assert_eq!(rpc.bootstrap_status(), BootstrapStatus::InProgress);
+
+        tick_until_done(&mut rpc, self_id);
+
+        assert!(matches!(
+            rpc.info().bootstrap_status(),
+            BootstrapStatus::Done { table_size } if table_size > 0
+        ));
+    }
+
+    #[test]
+    fn memory_transport() {
+        let network = transport::memory::MemoryNetwork::default();
+
+        let server_address = SocketAddr::from(([10, 0, 0, 2], 6881));
+        let mut server = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            server_mode: true,
+            transport: Some(std::sync::Arc::new(network.transport(server_address))),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut client = Rpc::new(config::Config {
+            bootstrap_addrs: vec![server_address],
+            transport: Some(std::sync::Arc::new(
+                network.transport(SocketAddr::from(([10, 0, 0, 1], 6881))),
+            )),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            server.local_addr(),
+            SocketAddrV4::new([10, 0, 0, 2].into(), 6881)
+        );
+
+        let self_id = *client.id();
+        loop {
+            server.tick();
+
+            if client
+                .tick()
+                .done_get_queries
+                .iter()
+                .any(|(id, _)| *id == self_id)
+            {
+                break;
+            }
+        }
+
+        let nodes = client.routing_table().to_owned_nodes();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].id(), server.id());
+        assert_eq!(nodes[0].socket_address(), server_address);
+    }
+
+    #[test]
+    fn responders_with_tokens() {
+        // Scripted nodes, each responding with its own token.
+        let (mut nodes, addresses) = scripted_nodes(3);
+        let ids = addresses.iter().map(|_| Id::random()).collect::<Vec<_>>();
+        let closer_nodes = ids
+            .iter()
+            .zip(&addresses)
+            .map(|(id, address)| Node::new(*id, *address))
+            .collect::<Box<[_]>>();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: addresses.clone(),
+            request_timeout: Duration::from_millis(100),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let info_hash = Id::random();
+
+        rpc.get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: false,
+                noseed: false,
+                want: vec![],
+            }),
+            None,
+        );
+
+        for (i, node) in nodes.iter_mut().enumerate() {
+            let (from, tid) = loop {
+                let (request, tid, from) = recv_request(node, Duration::from_secs(1)).unwrap();
+
+                if let RequestTypeSpecific::GetPeers(_) = request.request_type {
+                    break (from, tid);
+                }
+            };
+
+            node.response(
+                from,
+                tid,
+                ResponseSpecific::GetPeers(GetPeersResponseArguments {
+                    responder_id: ids[i],
+                    token: [i as u8; 4].into(),
+                    values: vec![],
+                    nodes: Some(closer_nodes.clone()),
+                    seeders: None,
+                    peers: None,
+                }),
+            );
+        }
+
+        while !rpc
+            .tick()
+            .done_get_queries
+            .iter()
+            .any(|(id, _)| *id == info_hash)
+        {}
+
+        let mut tokens = rpc
+            .responders_with_tokens(&info_hash)
+            .into_iter()
+            .map(|(node, token)| {
+                assert!(node.valid_token_at(rpc.socket.now()));
+                (node.socket_address(), token)
+            })
+            .collect::<Vec<_>>();
+        tokens.sort();
+
+        let mut expected = addresses
+            .iter()
+            .enumerate()
+            .map(|(i, address)| (*address, [i as u8; 4].into()))
+            .collect::<Vec<(SocketAddr, Box<[u8]>)>>();
+        expected.sort();
+
+        assert_eq!(tokens, expected);
+        assert!(rpc.responders_with_tokens(&Id::random()).is_empty());
+    }
+
+    #[test]
+    fn node_id_changed() {
+        let network = transport::memory::MemoryNetwork::default();
+
+        let rpc_address = SocketAddr::from(([5, 6, 7, 8], 6881));
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            transport: Some(std::sync::Arc::new(network.transport(rpc_address))),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Our public address, confirmed when it pings us.
+        let public_address = SocketAddrV4::new([1, 2, 3, 4].into(), 6881);
+        let public = network.transport(public_address.into());
+        rpc.public_address = Some(public_address);
+
+        let old_id = *rpc.id();
+        let mut changes = vec![];
+
+        for transaction_id in 0..2 {
+            let ping = Message {
+                transaction_id,
+                message_type: MessageType::Request(RequestSpecific {
+                    requester_id: Id::random(),
+                    request_type: RequestTypeSpecific::Ping,
+                }),
+                version: None,
+                read_only: false,
+                requester_ip: None,
+            };
+            public
+                .send_to(&ping.to_bytes().unwrap(), rpc_address)
+                .unwrap();
+
+            for _ in 0..10 {
+                if let Some(id) = rpc.tick().node_id_changed {
+                    changes.push(id);
+                }
+            }
+        }
+
+        assert_ne!(*rpc.id(), old_id);
+        assert!(rpc.id().is_valid_for_ip(*public_address.ip()));
+        assert_eq!(changes, vec![*rpc.id()]);
+    }
+
+    #[test]
+    fn refresh_buckets() {
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let id = *rpc.id();
+        let mut i = 0;
+        let mut nodes = |distance: u8, count: usize| {
+            (0..count)
+                .map(|_| {
+                    i += 1;
+                    Node::new(
+                        id.random_at_distance(distance),
+                        SocketAddrV4::new(Ipv4Addr::from(0x0a000000 + i), 6881),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Full furthest bucket, and sparse buckets down to distance 157.
+        let mut table = nodes(160, MAX_BUCKET_SIZE_K);
+        table.extend(nodes(159, 3));
+        table.extend(nodes(157, 1));
+        rpc.routing_table = RoutingTable::from_nodes(id, table);
+
+        assert_eq!(rpc.routing_table.sparse_buckets(), vec![159, 158, 157]);
+
+        rpc.refresh_buckets();
+
+        let mut distances = rpc
+            .iterative_queries
+            .values()
+            .map(|query| {
+                assert!(matches!(
+                    query.request.request_type,
+                    RequestTypeSpecific::FindNode(_)
+                ));
+
+                id.distance(&query.target())
+            })
+            .collect::<Vec<_>>();
+        distances.sort();
+
+        assert_eq!(distances, vec![157, 158, 159]);
+    }
+
+    #[test]
+    fn port_fallback() {
+        let rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+        let port = rpc.local_addr().port();
+
+        let fallback = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            port: Some(port),
+            port_fallback: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_ne!(fallback.local_addr().port(), port);
+
+        let error = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            port: Some(port),
+            ..Default::default()
+        })
+        .unwrap_err();
+
+        assert!(error.to_string().contains(&format!("port {port}")));
+    }
+
+    #[test]
+    fn estimated_lookup_time() {
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let target = Id::random();
+
+        // Before any responses, the request timeout is the pessimistic round trip time.
+        rpc.dht_size_estimates_sum = 1024.0;
+        assert_eq!(
+            rpc.estimated_lookup_time(target),
+            DEFAULT_REQUEST_TIMEOUT * 10
+        );
+
+        rpc.socket.sample_rtt(Duration::from_millis(100));
+        assert_eq!(
+            rpc.estimated_lookup_time(target),
+            Duration::from_millis(100) * 10
+        );
+
+        rpc.seed_query_cache(target, vec![Node::random()]);
+        assert_eq!(
+            rpc.estimated_lookup_time(target),
+            Duration::from_millis(100)
+        );
+    }
+
+    #[test]
+    fn query_cache_disabled() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            query_cache_size: None,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        assert!(rpc.cached_iterative_queries.is_empty());
+        assert!(rpc.dht_size_estimate().0 > 0);
+
+        let value: Box<[u8]> = b"no cache".as_slice().into();
+        let target = Id::from(crate::common::hash_immutable(&value));
+
+        // Both puts start with a fresh get query.
+        for _ in 0..2 {
+            rpc.put(
+                PutRequestSpecific::PutImmutable(PutImmutableRequestArguments {
+                    target,
+                    v: value.clone(),
+                }),
+                None,
+            )
+            .unwrap();
+            assert!(rpc.iterative_queries.contains_key(&target));
+
+            let result = loop {
+                if let Some((_, result)) = rpc
+                    .tick()
+                    .done_put_queries
+                    .into_iter()
+                    .find(|(id, _)| *id == target)
+                {
+                    break result;
                 }
-                // Ping response is already handled in add_node()
-                // FindNode response is already handled in query.add_candidate()
-                // Requests are handled elsewhere
-                MessageType::Response(ResponseSpecific::Ping(_))
-                | MessageType::Response(ResponseSpecific::FindNode(_))
-                | MessageType::Request(_) => {}
             };
-        };
 
-        if should_add_node {
-            // Add a node to our routing table on any expected incoming response.
+            assert!(result.is_ok());
+        }
 
-            if let Some(id) = author_id {
-                self.routing_table.add(Node::new(id, from));
-            }
+        assert!(rpc.cached_iterative_queries.is_empty());
+
+        // Seeding and importing cached queries are noops.
+        let estimate = rpc.dht_size_estimate();
+        let nodes = rpc.routing_table().to_owned_nodes();
+
+        rpc.seed_query_cache(Id::random(), nodes.clone());
+        assert!(rpc.cached_iterative_queries.is_empty());
+        assert_eq!(rpc.dht_size_estimate(), estimate);
+
+        let state = RpcState {
+            id: *rpc.id(),
+            nodes: nodes.clone(),
+            cached_queries: vec![(Id::random(), nodes)],
         }
+        .encode();
 
-        None
+        rpc.import_state(&state).unwrap();
+        assert!(rpc.cached_iterative_queries.is_empty());
+        assert!(!rpc.routing_table().is_empty());
     }
 
-    fn periodic_node_maintaenance(&mut self) {
-        // Bootstrap if necessary
-        if self.routing_table.is_empty() {
-            self.populate();
+    #[test]
+    fn bootstrap_address_family() {
+        /// A hostname resolving to both address families.
+        struct DualStack;
+
+        impl ToSocketAddrs for DualStack {
+            type Iter = std::vec::IntoIter<SocketAddr>;
+
+            fn to_socket_addrs(&self) -> std::io::Result<Self::Iter> {
+                Ok(vec![
+                    SocketAddr::from(([127, 0, 0, 1], 6881)),
+                    SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 6881)),
+                    SocketAddr::from(([127, 0, 0, 2], 6881)),
+                ]
+                .into_iter())
+            }
         }
 
-        // Every 15 minutes refresh the routing table.
-        if self.last_table_refresh.elapsed() > REFRESH_TABLE_INTERVAL {
-            self.last_table_refresh = Instant::now();
+        let ipv4_1 = SocketAddr::from(([127, 0, 0, 1], 6881));
+        let ipv4_2 = SocketAddr::from(([127, 0, 0, 2], 6881));
+        let ipv6 = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 6881));
+
+        for (preference, expected) in [
+            (AddressFamily::V4Only, vec![ipv4_1, ipv4_2]),
+            (AddressFamily::V6Only, vec![ipv6]),
+            (AddressFamily::PreferV4, vec![ipv4_1, ipv4_2, ipv6]),
+            (AddressFamily::PreferV6, vec![ipv6, ipv4_1, ipv4_2]),
+            (AddressFamily::Both, vec![ipv4_1, ipv6, ipv4_2]),
+        ] {
+            let rpc = Rpc::new(config::Config {
+                bootstrap_addrs: to_socket_address(&[DualStack]),
+                bootstrap_address_family: preference,
+                ..Default::default()
+            })
+            .unwrap();
 
-            if !self.server_mode() && !self.firewalled() {
-                info!("Adaptive mode: have been running long enough (not firewalled), switching to server mode");
+            assert_eq!(rpc.bootstrap.to_vec(), expected, "{preference:?}");
+            assert_eq!(rpc.local_addr6().is_some(), preference.ipv6());
+        }
+    }
 
-                self.socket.server_mode = true;
+    #[test]
+    fn send_pressure() {
+        /// A transport whose send buffer is always full.
+        #[derive(Debug)]
+        struct Saturated(transport::memory::MemoryTransport);
+
+        impl Transport for Saturated {
+            fn send_to(&self, _buf: &[u8], _address: SocketAddr) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::WouldBlock.into())
             }
 
-            self.populate();
+            fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+                self.0.recv_from(buf)
+            }
+
+            fn local_addr(&self) -> std::io::Result<SocketAddr> {
+                self.0.local_addr()
+            }
         }
 
-        if self.last_table_ping.elapsed() > PING_TABLE_INTERVAL {
-            self.last_table_ping = Instant::now();
+        let network = transport::memory::MemoryNetwork::default();
 
-            let mut to_remove = Vec::with_capacity(self.routing_table.size());
-            let mut to_ping = Vec::with_capacity(self.routing_table.size());
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![SocketAddrV4::new([10, 0, 0, 2].into(), 6881)]),
+            transport: Some(std::sync::Arc::new(Saturated(
+                network.transport(SocketAddr::from(([10, 0, 0, 1], 6881))),
+            ))),
+            ..Default::default()
+        })
+        .unwrap();
 
-            for node in self.routing_table.nodes() {
-                if node.is_stale() {
-                    to_remove.push(*node.id())
-                } else if node.should_ping() {
-                    to_ping.push(node.address())
-                }
-            }
+        let report = rpc.tick();
+
+        assert!(report.send_pressure);
+        assert!(rpc.metrics().dropped_sends > 0);
 
-            for id in to_remove {
-                self.routing_table.remove(&id);
+        // Reset once reported.
+        assert!(!rpc.tick().send_pressure);
+    }
+
+    #[test]
+    fn put_mutable_next() {
+        let testnet = Testnet::new(5).unwrap();
+
+        let signer = SigningKey::from_bytes(&[4; 32]);
+        let existing = MutableItem::new(signer.clone(), b"existing", 4, None);
+        let target = *existing.target();
+
+        crate::Dht::builder()
+            .bootstrap(&testnet.bootstrap)
+            .build()
+            .unwrap()
+            .put_mutable(existing, None)
+            .unwrap();
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        rpc.put_mutable_next(signer.clone(), b"next", None).unwrap();
+        assert!(!rpc.put_queries.contains_key(&target));
+
+        assert!(matches!(
+            rpc.put_mutable_next(signer.clone(), b"other", None),
+            Err(PutError::Concurrency(ConcurrencyError::Inflight))
+        ));
+        assert!(matches!(
+            rpc.put_mutable_next(signer.clone(), &[0; 1001], Some(b"salt")),
+            Err(PutError::Query(PutQueryError::ValueTooLarge(1001)))
+        ));
+        assert!(matches!(
+            rpc.put_mutable_next(signer.clone(), b"next", Some(&[0; 65])),
+            Err(PutError::Query(PutQueryError::SaltTooLarge(65)))
+        ));
+
+        let mut request = None;
+        let result = loop {
+            let report = rpc.tick();
+
+            if let Some(PutRequestSpecific::PutMutable(args)) =
+                rpc.put_queries.get(&target).map(|query| &query.request)
+            {
+                request = Some(args.clone());
             }
 
-            for address in to_ping {
-                self.ping(address);
+            if let Some((_, result)) = report
+                .done_put_queries
+                .into_iter()
+                .find(|(id, _)| *id == target)
+            {
+                break result;
             }
-        }
+        };
+
+        assert!(result.is_ok());
+
+        let request = request.unwrap();
+        assert_eq!(request.seq, 5);
+        assert_eq!(request.cas, Some(4));
+        assert_eq!(request.v.as_ref(), b"next");
     }
 
-    /// Ping bootstrap nodes, add them to the routing table with closest query.
-    fn populate(&mut self) {
-        if self.bootstrap.is_empty() {
-            return;
+    #[test]
+    fn nat_keepalive() {
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+        let interval = Duration::from_secs(10);
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(vec![]),
+            nat_keepalive_interval: Some(interval),
+            clock: clock.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut nodes = (0..6)
+            .map(|_| KrpcSocket::server().unwrap())
+            .collect::<Vec<_>>();
+        for node in &nodes {
+            rpc.routing_table.add(Node::new(
+                Id::random(),
+                SocketAddr::from(([127, 0, 0, 1], node.local_addr().port())),
+            ));
         }
+        assert_eq!(rpc.routing_table.size(), nodes.len());
+
+        let mut pinged = |rpc: &mut Rpc| {
+            rpc.tick();
+
+            nodes
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, node)| {
+                    matches!(
+                        node.recv_from(),
+                        Some((
+                            Message {
+                                message_type: MessageType::Request(RequestSpecific {
+                                    request_type: RequestTypeSpecific::Ping,
+                                    ..
+                                }),
+                                ..
+                            },
+                            _
+                        ))
+                    )
+                    .then_some(i)
+                })
+                .collect::<HashSet<_>>()
+        };
 
-        self.get(
-            GetRequestSpecific::FindNode(FindNodeRequestArguments { target: *self.id() }),
-            None,
-        );
-    }
+        // Not due yet.
+        assert!(pinged(&mut rpc).is_empty());
 
-    fn ping(&mut self, address: SocketAddrV4) {
-        self.socket.request(
-            address,
-            RequestSpecific {
-                requester_id: *self.id(),
-                request_type: RequestTypeSpecific::Ping,
-            },
-        );
+        clock.advance(interval);
+        let first = pinged(&mut rpc);
+
+        clock.advance(interval);
+        let second = pinged(&mut rpc);
+
+        assert_eq!(first.len(), NAT_KEEPALIVE_NODES);
+        assert_eq!(second.len(), NAT_KEEPALIVE_NODES);
+        assert!(first.is_disjoint(&second));
     }
 
-    fn update_address_votes_from_iterative_query(&mut self, query: &IterativeQuery) {
-        if let Some(new_address) = query.best_address() {
-            if self.public_address.is_none()
-                || new_address
-                    != self
-                        .public_address
-                        .expect("self.public_address is not None")
-            {
-                debug!(
-                    ?new_address,
-                    "Query responses suggest a different public_address, trying to confirm.."
-                );
+    #[test]
+    fn maintain_announce() {
+        let mut node = KrpcSocket::server().unwrap();
+        let node_address = SocketAddr::from(([127, 0, 0, 1], node.local_addr().port()));
+        let node_id = Id::random();
 
-                self.firewalled = true;
-                self.ping(new_address);
+        let clock = std::sync::Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
+        let interval = Duration::from_secs(15 * 60);
+
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap_addrs: vec![node_address],
+            clock: clock.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let info_hash = Id::random();
+
+        // Respond to the lookups with `token`, until the announcement is done,
+        // and return the token of the announcement.
+        let mut announce = |rpc: &mut Rpc, token: &[u8]| {
+            let mut announced = None;
+
+            for _ in 0..1000 {
+                // Let unanswered requests time out.
+                clock.advance(Duration::from_millis(100));
+                let report = rpc.tick();
+
+                if let Some((_, report)) = report
+                    .done_join_swarms
+                    .into_iter()
+                    .find(|(id, _)| *id == info_hash)
+                {
+                    assert_eq!(report.announced.unwrap(), 1);
+
+                    return announced.unwrap();
+                }
+
+                let Some((request, tid, from)) = recv_request(&mut node, Duration::from_millis(10))
+                else {
+                    continue;
+                };
+
+                match request.request_type {
+                    RequestTypeSpecific::FindNode(_) => node.response(
+                        from,
+                        tid,
+                        ResponseSpecific::FindNode(FindNodeResponseArguments {
+                            responder_id: node_id,
+                            nodes: [].into(),
+                        }),
+                    ),
+                    RequestTypeSpecific::GetPeers(_) => node.response(
+                        from,
+                        tid,
+                        ResponseSpecific::GetPeers(GetPeersResponseArguments {
+                            responder_id: node_id,
+                            token: token.into(),
+                            values: vec![],
+                            nodes: None,
+                            seeders: None,
+                            peers: None,
+                        }),
+                    ),
+                    RequestTypeSpecific::Put(crate::common::PutRequest { token, .. }) => {
+                        announced = Some(token);
+
+                        node.response(
+                            from,
+                            tid,
+                            ResponseSpecific::Ping(PingResponseArguments {
+                                responder_id: node_id,
+                            }),
+                        );
+                    }
+                    _ => {}
+                }
             }
 
-            self.public_address = Some(new_address)
-        }
+            panic!("announcement wasn't done");
+        };
+
+        rpc.maintain_announce(info_hash, Some(6881), interval)
+            .unwrap();
+        assert_eq!(announce(&mut rpc, &[1; 4]).as_ref(), [1; 4]);
+
+        clock.advance(interval);
+        assert_eq!(announce(&mut rpc, &[2; 4]).as_ref(), [2; 4]);
+
+        assert!(rpc.stop_announce(&info_hash));
+        assert!(!rpc.stop_announce(&info_hash));
+
+        clock.advance(interval);
+        rpc.tick();
+        assert!(!rpc.put_queries.contains_key(&info_hash));
+
+        // Re-announcing waits for the announcement in progress.
+        let interval = Duration::from_millis(1);
+        rpc.maintain_announce(info_hash, Some(6881), interval)
+            .unwrap();
+        let last_announced = rpc.announced[&info_hash].last_announced;
+
+        clock.advance(interval * 10);
+        rpc.tick();
+        assert_eq!(rpc.announced[&info_hash].last_announced, last_announced);
+
+        // Stopping cancels the announcement in progress.
+        assert!(rpc.stop_announce(&info_hash));
+        assert!(!rpc.join_swarm_queries.contains_key(&info_hash));
+        assert!(!rpc.put_queries.contains_key(&info_hash));
     }
 
-    fn cache_iterative_query(&mut self, query: &IterativeQuery, closest_responding_nodes: &[Node]) {
-        if self.cached_iterative_queries.len() >= MAX_CACHED_ITERATIVE_QUERIES {
-            let q = self.cached_iterative_queries.pop_lru();
-            self.decrement_cached_iterative_query_stats(q.map(|q| q.1));
-        }
+    #[test]
+    fn eviction_policy() {
+        #[derive(Debug)]
+        struct KeepAll;
 
-        let closest = query.closest();
-        let responders = query.responders();
+        impl EvictionPolicy for KeepAll {
+            fn should_evict(&self, _node: &Node, _now: Instant) -> bool {
+                false
+            }
 
-        if closest.nodes().is_empty() {
-            // We are clearly offline.
-            return;
+            fn should_ping(&self, _node: &Node, _now: Instant) -> bool {
+                false
+            }
         }
 
-        let dht_size_estimate = closest.dht_size_estimate();
-        let responders_dht_size_estimate = responders.dht_size_estimate();
-        let subnets_count = closest.subnets_count();
+        let maintained = |eviction_policy: Arc<dyn EvictionPolicy>| {
+            let clock = Arc::new(MockClock(std::sync::Mutex::new(Instant::now())));
 
-        let previous = self.cached_iterative_queries.put(
-            query.target(),
-            CachedIterativeQuery {
-                closest_responding_nodes: closest_responding_nodes.into(),
-                dht_size_estimate,
-                responders_dht_size_estimate,
-                subnets: subnets_count,
+            let mut rpc = Rpc::new(config::Config {
+                bootstrap: Some(vec![]),
+                eviction_policy,
+                clock: clock.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+
+            for i in 1..=5 {
+                rpc.routing_table.add(Node::seen_at(
+                    Id::random(),
+                    SocketAddr::from(([127, 0, 0, i], 6881)),
+                    clock.now(),
+                ));
+            }
+            assert_eq!(rpc.routing_table.size(), 5);
 
-                is_find_node: matches!(
-                    query.request.request_type,
-                    RequestTypeSpecific::FindNode(_)
-                ),
-            },
-        );
+            // Past both the ping interval and the stale time.
+            clock.advance(crate::common::STALE_TIME + Duration::from_secs(1));
+            rpc.periodic_node_maintaenance();
 
-        self.decrement_cached_iterative_query_stats(previous);
+            rpc.routing_table.size()
+        };
 
-        self.dht_size_estimates_sum += dht_size_estimate;
-        self.responders_based_dht_size_estimates_sum += responders_dht_size_estimate;
-        self.subnets_sum += subnets_count as usize;
-        self.responders_based_dht_size_estimates_count += 1;
+        assert_eq!(maintained(Arc::new(KeepAll)), 5);
+        assert_eq!(maintained(Arc::new(DefaultEvictionPolicy)), 0);
     }
 
-    fn responders_based_dht_size_estimate(&self) -> usize {
-        self.responders_based_dht_size_estimates_sum as usize
-            / self.responders_based_dht_size_estimates_count.max(1)
-    }
+    #[test]
+    fn put_batch_without_estimate_or_cancelled() {
+        let testnet = Testnet::new(5).unwrap();
 
-    fn average_subnets(&self) -> usize {
-        self.subnets_sum / self.cached_iterative_queries.len().max(1)
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let base = Id::random();
+        let mut near = *base.as_bytes();
+        near[19] ^= 1;
+        let near = Id::from_bytes(near).unwrap();
+
+        let announce = |info_hash| {
+            PutRequestSpecific::AnnouncePeer(crate::common::AnnouncePeerRequestArguments {
+                info_hash,
+                port: 6881,
+                implied_port: None,
+                seed: false,
+            })
+        };
+
+        // Without a Dht size estimate, every target gets its own lookup.
+        rpc.put_batch(vec![announce(base), announce(near)]).unwrap();
+        assert!(rpc.iterative_queries.contains_key(&base));
+        assert!(rpc.iterative_queries.contains_key(&near));
+        assert!(rpc.put_batches.is_empty());
+
+        rpc.cancel_get(base);
+        rpc.cancel_get(near);
+        rpc.cancel_put(base);
+        rpc.cancel_put(near);
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+        assert!(rpc.neighborhood_prefix_bits().is_some());
+
+        rpc.put_batch(vec![announce(base), announce(near)]).unwrap();
+        assert_eq!(rpc.put_batches[&base], vec![near]);
+
+        // Cancelling the shared lookup reports the batched put as failed.
+        assert!(rpc.cancel_get(base));
+        assert!(rpc.put_batches.is_empty());
+
+        let report = rpc.tick();
+        assert!(matches!(
+            report.done_put_queries.iter().find(|(id, _)| *id == near),
+            Some((_, Err(PutError::Query(PutQueryError::LookupCancelled))))
+        ));
+        assert!(!rpc.put_queries.contains_key(&near));
     }
 
-    fn decrement_cached_iterative_query_stats(&mut self, query: Option<CachedIterativeQuery>) {
-        if let Some(CachedIterativeQuery {
-            dht_size_estimate,
-            responders_dht_size_estimate,
-            subnets,
-            is_find_node,
-            ..
-        }) = query
-        {
-            self.dht_size_estimates_sum -= dht_size_estimate;
-            self.responders_based_dht_size_estimates_sum -= responders_dht_size_estimate;
-            self.subnets_sum -= subnets as usize;
+    #[test]
+    fn scrape_with_peers() {
+        let testnet = Testnet::new(5).unwrap();
 
-            if !is_find_node {
-                self.responders_based_dht_size_estimates_count -= 1;
+        let mut rpc = Rpc::new(config::Config {
+            bootstrap: Some(testnet_nodes(&testnet)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let self_id = *rpc.id();
+        rpc.tick();
+        tick_until_done(&mut rpc, self_id);
+
+        let info_hash = Id::random();
+        rpc.put(
+            PutRequestSpecific::AnnouncePeer(AnnouncePeerRequestArguments {
+                info_hash,
+                port: 1234,
+                implied_port: None,
+                seed: false,
+            }),
+            None,
+        )
+        .unwrap();
+
+        while !rpc
+            .tick()
+            .done_put_queries
+            .iter()
+            .any(|(id, _)| *id == info_hash)
+        {}
+
+        rpc.get(
+            GetRequestSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape: true,
+                noseed: false,
+                want: vec![],
+            }),
+            None,
+        );
+
+        let mut peers = vec![];
+        let mut scrapes = vec![];
+        let mut done = false;
+
+        // Keep ticking after the query is done, until the queued scrapes are reported.
+        while !done || !rpc.queued_query_responses.is_empty() {
+            let report = rpc.tick();
+
+            match report.new_query_response {
+                Some((_, Response::Peers(new_peers))) => peers.extend(new_peers),
+                Some((_, Response::Scrape { peers_estimate, .. })) => scrapes.push(peers_estimate),
+                _ => {}
             }
-        };
+
+            done |= report
+                .done_get_queries
+                .iter()
+                .any(|(id, _)| *id == info_hash);
+        }
+
+        // Responses carry both the peer and the bloom filters.
+        assert_eq!(peers, vec![SocketAddrV4::new([127, 0, 0, 1].into(), 1234)]);
+        assert_eq!(scrapes.last(), Some(&1));
     }
-}
 
-struct CachedIterativeQuery {
-    closest_responding_nodes: Box<[Node]>,
-    dht_size_estimate: f64,
-    responders_dht_size_estimate: f64,
-    subnets: u8,
+    #[test]
+    fn get_first_then_put() {
+        let testnet = Testnet::new(10).unwrap();
 
-    /// Keeping track of find_node queries, because they shouldn't
-    /// be counted in `responders_based_dht_size_estimates_count`
-    is_find_node: bool,
-}
+        let bootstrapped = || {
+            let mut rpc = Rpc::new(config::Config {
+                bootstrap: Some(testnet_nodes(&testnet)),
+                ..Default::default()
+            })
+            .unwrap();
 
-/// State change after a call to [Rpc::tick], including
-/// done PUT, GET, and FIND_NODE queries, as well as any
-/// incoming value response for any GET query.
-#[derive(Debug, Clone)]
-pub struct RpcTickReport {
-    /// All the [Id]s of the done [Rpc::get] queries.
-    pub done_get_queries: Vec<(Id, Box<[Node]>)>,
-    /// All the [Id]s of the done [Rpc::put] queries,
-    /// and optional [PutError] if the query failed.
-    pub done_put_queries: Vec<(Id, Option<PutError>)>,
-    /// Received GET query response.
-    pub new_query_response: Option<(Id, Response)>,
-}
+            let self_id = *rpc.id();
+            rpc.tick();
+            tick_until_done(&mut rpc, self_id);
 
-#[derive(Debug, Clone)]
-pub enum Response {
-    Peers(Vec<SocketAddrV4>),
-    Immutable(Box<[u8]>),
-    Mutable(MutableItem),
-}
+            rpc
+        };
 
-pub(crate) fn to_socket_address<T: ToSocketAddrs>(bootstrap: &[T]) -> Vec<SocketAddrV4> {
-    bootstrap
-        .iter()
-        .flat_map(|s| {
-            s.to_socket_addrs().map(|addrs| {
-                addrs
-                    .filter_map(|addr| match addr {
-                        SocketAddr::V4(addr_v4) => Some(addr_v4),
-                        _ => None,
-                    })
-                    .collect::<Box<[_]>>()
+        let put = |rpc: &mut Rpc, value: &[u8]| {
+            let target = Id::from(crate::common::hash_immutable(value));
+
+            rpc.put(
+                PutRequestSpecific::PutImmutable(PutImmutableRequestArguments {
+                    target,
+                    v: value.into(),
+                }),
+                None,
+            )
+            .unwrap();
+
+            target
+        };
+
+        let stored = |rpc: &mut Rpc, target: Id| loop {
+            if let Some((_, result)) = rpc
+                .tick()
+                .done_put_queries
+                .into_iter()
+                .find(|(id, _)| *id == target)
+            {
+                break result.unwrap();
+            }
+        };
+
+        let request = |target| {
+            GetRequestSpecific::GetValue(GetValueRequestArguments {
+                target,
+                seq: None,
+                salt: None,
             })
-        })
-        .flatten()
-        .collect()
+        };
+
+        let mut a = bootstrapped();
+        let partial_target = put(&mut a, b"partial");
+        stored(&mut a, partial_target);
+        let target = put(&mut a, b"put while getting first");
+        let full_stored_at = stored(&mut a, target);
+
+        let mut b = bootstrapped();
+        let estimates = (b.dht_size_estimates_sum, b.subnets_sum);
+
+        // Queries that finish early are cached, but left out of the estimates.
+        b.get_first(request(partial_target), None);
+        tick_until_done(&mut b, partial_target);
+        assert!(
+            b.cached_iterative_queries
+                .peek(&partial_target)
+                .unwrap()
+                .partial
+        );
+        assert_eq!(b.partial_cached_queries, 1);
+        assert_eq!((b.dht_size_estimates_sum, b.subnets_sum), estimates);
+
+        // A put joining a get_first query makes it run to the end.
+        b.get_first(request(target), None);
+        b.tick();
+        put(&mut b, b"put while getting first");
+
+        assert_eq!(stored(&mut b, target), full_stored_at);
+        assert!(!b.cached_iterative_queries.peek(&target).unwrap().partial);
+    }
 }
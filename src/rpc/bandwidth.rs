@@ -0,0 +1,137 @@
+//! Bytes sent and received by the [super::Rpc], for capacity planning, see [super::Rpc::bandwidth].
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::common::{MessageType, RequestTypeSpecific};
+
+/// Duration over which [BandwidthStats::send_rate] and [BandwidthStats::receive_rate] are averaged.
+pub const BANDWIDTH_RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Duration of each bucket of the rolling rate.
+const BUCKET: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Number of bytes of each message type.
+pub struct MessageBytes {
+    /// Bytes of `ping` requests.
+    pub ping: u64,
+    /// Bytes of `find_node` requests.
+    pub find_node: u64,
+    /// Bytes of `get_peers` requests.
+    pub get_peers: u64,
+    /// Bytes of `get` requests for immutable or mutable values.
+    pub get_value: u64,
+    /// Bytes of `announce_peer` and `put` requests.
+    pub put: u64,
+    /// Bytes of responses of all request types.
+    pub responses: u64,
+    /// Bytes of error responses.
+    pub errors: u64,
+}
+
+impl MessageBytes {
+    fn add(&mut self, message_type: &MessageType, bytes: u64) {
+        let count = match message_type {
+            MessageType::Request(request) => match request.request_type {
+                RequestTypeSpecific::Ping => &mut self.ping,
+                RequestTypeSpecific::FindNode(_) => &mut self.find_node,
+                RequestTypeSpecific::GetPeers(_) => &mut self.get_peers,
+                RequestTypeSpecific::GetValue(_) => &mut self.get_value,
+                RequestTypeSpecific::Put(_) => &mut self.put,
+            },
+            MessageType::Response(_) => &mut self.responses,
+            MessageType::Error(_) => &mut self.errors,
+        };
+
+        *count += bytes;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Snapshot of the bytes sent and received since this node started, see [super::Rpc::bandwidth].
+///
+/// Bytes are counted as the size of the UDP payloads, excluding IP and UDP headers.
+pub struct BandwidthStats {
+    /// Total bytes sent.
+    pub bytes_sent: u64,
+    /// Total bytes received, including invalid messages.
+    pub bytes_received: u64,
+    /// Bytes sent, by message type.
+    pub sent: MessageBytes,
+    /// Bytes received, by message type, excluding messages that failed to decode.
+    pub received: MessageBytes,
+    /// Bytes sent per second, averaged over the last [BANDWIDTH_RATE_WINDOW].
+    pub send_rate: f64,
+    /// Bytes received per second, averaged over the last [BANDWIDTH_RATE_WINDOW].
+    pub receive_rate: f64,
+}
+
+#[derive(Debug, Default)]
+/// Tallies of sent and received bytes, kept by the [super::socket::KrpcSocket].
+pub(crate) struct Bandwidth {
+    stats: BandwidthStats,
+    /// Bytes sent and received in each [BUCKET] of the last [BANDWIDTH_RATE_WINDOW],
+    /// from the oldest to the newest.
+    buckets: VecDeque<(Instant, u64, u64)>,
+}
+
+impl Bandwidth {
+    /// Record a sent datagram, and the type of its message.
+    pub fn sent(&mut self, now: Instant, message_type: &MessageType, bytes: usize) {
+        let bytes = bytes as u64;
+
+        self.stats.bytes_sent += bytes;
+        self.stats.sent.add(message_type, bytes);
+        self.bucket(now).1 += bytes;
+    }
+
+    /// Record a received datagram, before it is decoded.
+    pub fn received(&mut self, now: Instant, bytes: usize) {
+        let bytes = bytes as u64;
+
+        self.stats.bytes_received += bytes;
+        self.bucket(now).2 += bytes;
+    }
+
+    /// Record the type of a received message that was decoded successfully.
+    pub fn received_message(&mut self, message_type: &MessageType, bytes: usize) {
+        self.stats.received.add(message_type, bytes as u64);
+    }
+
+    /// Returns the tallies, with the rates over the last [BANDWIDTH_RATE_WINDOW] before `now`.
+    pub fn stats(&self, now: Instant) -> BandwidthStats {
+        let (sent, received) = self
+            .buckets
+            .iter()
+            .filter(|(start, _, _)| now.saturating_duration_since(*start) < BANDWIDTH_RATE_WINDOW)
+            .fold((0, 0), |(sent, received), (_, s, r)| {
+                (sent + s, received + r)
+            });
+
+        let window = BANDWIDTH_RATE_WINDOW.as_secs_f64();
+
+        BandwidthStats {
+            send_rate: sent as f64 / window,
+            receive_rate: received as f64 / window,
+            ..self.stats.clone()
+        }
+    }
+
+    /// Returns the bucket of `now`, dropping buckets older than the window.
+    fn bucket(&mut self, now: Instant) -> &mut (Instant, u64, u64) {
+        while self.buckets.front().is_some_and(|(start, _, _)| {
+            now.saturating_duration_since(*start) >= BANDWIDTH_RATE_WINDOW
+        }) {
+            self.buckets.pop_front();
+        }
+
+        if self.buckets.back().map_or(true, |(start, _, _)| {
+            now.saturating_duration_since(*start) >= BUCKET
+        }) {
+            self.buckets.push_back((now, 0, 0));
+        }
+
+        self.buckets.back_mut().expect("pushed above")
+    }
+}
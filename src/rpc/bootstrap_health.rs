@@ -0,0 +1,114 @@
+//! Tracking which bootstrapping nodes respond, to query the healthy ones first.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Responses and timeouts of a bootstrapping node.
+pub struct NodeHealth {
+    /// Number of requests this node responded to.
+    pub successes: u32,
+    /// Number of requests to this node that timed out.
+    pub failures: u32,
+    /// Number of requests that timed out since the last response.
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Health of bootstrapping nodes, used to query historically responsive nodes
+/// first, and nodes that repeatedly time out last.
+///
+/// Every bootstrapping node is still queried, so if all of them are failing,
+/// they are all tried anyway.
+///
+/// Can be read from the [info](crate::Dht::info) of a running node, and passed to
+/// [crate::DhtBuilder::bootstrap_health] to persist it across restarts.
+pub struct BootstrapHealth {
+    nodes: HashMap<SocketAddr, NodeHealth>,
+}
+
+impl BootstrapHealth {
+    /// Create an empty [BootstrapHealth].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the health of a node, if it was ever queried.
+    pub fn get(&self, address: &SocketAddr) -> Option<&NodeHealth> {
+        self.nodes.get(address)
+    }
+
+    /// Set the health of a node, for example from a persisted [Self::iter].
+    pub fn insert(&mut self, address: SocketAddr, health: NodeHealth) {
+        self.nodes.insert(address, health);
+    }
+
+    /// Iterate over the health of all tracked nodes.
+    pub fn iter(&self) -> impl Iterator<Item = (&SocketAddr, &NodeHealth)> {
+        self.nodes.iter()
+    }
+
+    /// Record a response from a node.
+    pub fn success(&mut self, address: SocketAddr) {
+        let health = self.nodes.entry(address).or_default();
+
+        health.successes += 1;
+        health.consecutive_failures = 0;
+    }
+
+    /// Record a request to a node that timed out.
+    pub fn failure(&mut self, address: SocketAddr) {
+        let health = self.nodes.entry(address).or_default();
+
+        health.failures += 1;
+        health.consecutive_failures += 1;
+    }
+
+    /// Sort nodes by how many times they failed in a row, keeping the
+    /// original order of equally healthy nodes.
+    pub(crate) fn sort(&self, nodes: &mut [SocketAddr]) {
+        nodes.sort_by_key(|address| {
+            self.nodes
+                .get(address)
+                .map(|health| health.consecutive_failures)
+                .unwrap_or_default()
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sort() {
+        let a: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let c: SocketAddr = "127.0.0.1:3".parse().unwrap();
+
+        let mut health = BootstrapHealth::new();
+
+        health.failure(a);
+        health.failure(a);
+        health.failure(b);
+        health.success(c);
+
+        let mut nodes = [a, b, c];
+        health.sort(&mut nodes);
+        assert_eq!(nodes, [c, b, a]);
+
+        health.success(a);
+
+        let mut nodes = [a, b, c];
+        health.sort(&mut nodes);
+        assert_eq!(nodes, [a, c, b]);
+        assert_eq!(
+            health.get(&a),
+            Some(&NodeHealth {
+                successes: 1,
+                failures: 2,
+                consecutive_failures: 0
+            })
+        );
+    }
+}
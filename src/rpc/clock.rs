@@ -0,0 +1,24 @@
+//! Source of time for the [super::Rpc], replaceable for deterministic tests.
+
+use std::fmt::Debug;
+use std::time::Instant;
+
+/// A source of the current time, used for request timeouts, query timings,
+/// and periodic routing table maintenance.
+///
+/// The default is [SystemClock], but a custom clock can be set with
+/// [crate::DhtBuilder::clock], for example to advance time instantly in tests.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// A [Clock] returning the real [Instant::now].
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
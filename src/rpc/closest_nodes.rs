@@ -1,6 +1,12 @@
-use std::{collections::HashSet, convert::TryInto};
+use std::{collections::HashSet, convert::TryInto, net::IpAddr};
 
-use crate::{common::MAX_BUCKET_SIZE_K, Id, Node};
+use crate::{
+    common::{
+        messages::{bytes_to_nodes4, nodes4_to_bytes},
+        DecodeMessageError, MAX_BUCKET_SIZE_K,
+    },
+    Id, Node,
+};
 
 #[derive(Debug, Clone)]
 /// Manage closest nodes found in a query.
@@ -72,7 +78,7 @@ impl ClosestNodes {
     /// 2. The last node should be at a distance `edk` which is the expected distance of the 20th
     ///    node given previous estimations of the DHT size.
     /// 3. The number of subnets with unique 6 bits prefix in nodes ipv4 addresses match or exceeds
-    ///     the average from previous queries.
+    ///    the average from previous queries.
     ///
     /// If one or more of these conditions are not met, then we just take all responding nodes
     /// and store data at them.
@@ -120,6 +126,26 @@ impl ClosestNodes {
         subnets.len() as u8
     }
 
+    /// Encode the Ipv4 nodes, closest to the target first, in the compact node format
+    /// of [BEP_0005](https://www.bittorrent.org/beps/bep_0005.html), to share them with
+    /// other nodes over any channel.
+    ///
+    /// Ipv6 nodes are skipped.
+    pub fn to_compact_bytes(&self) -> Box<[u8]> {
+        nodes4_to_bytes(&self.nodes)
+    }
+
+    /// Decode nodes encoded by [Self::to_compact_bytes], sorted by their distance to the `target`.
+    pub fn from_compact_bytes(target: Id, bytes: &[u8]) -> Result<Self, DecodeMessageError> {
+        let mut closest_nodes = Self::new(target);
+
+        for node in bytes_to_nodes4(bytes)? {
+            closest_nodes.add(node);
+        }
+
+        Ok(closest_nodes)
+    }
+
     /// An estimation of the Dht from the distribution of closest nodes
     /// responding to a query.
     ///
@@ -135,7 +161,10 @@ impl ClosestNodes {
 }
 
 fn subnet(node: &Node) -> u8 {
-    ((node.address().ip().to_bits() >> 26) & 0b0011_1111) as u8
+    match node.socket_address().ip() {
+        IpAddr::V4(ip) => ((ip.to_bits() >> 26) & 0b0011_1111) as u8,
+        IpAddr::V6(ip) => ((ip.segments()[0] >> 10) & 0b0011_1111) as u8,
+    }
 }
 
 fn distance(target: &Id, node: &Node) -> u128 {
@@ -202,12 +231,46 @@ mod tests {
         assert_eq!(sorted, distances);
     }
 
+    #[test]
+    fn compact_bytes_round_trip() {
+        let target = Id::random();
+        let mut closest_nodes = ClosestNodes::new(target);
+
+        for i in 0..20 {
+            closest_nodes.add(Node::unique(i));
+        }
+
+        let bytes = closest_nodes.to_compact_bytes();
+        assert_eq!(bytes.len(), 20 * 26);
+
+        let decoded = ClosestNodes::from_compact_bytes(target, &bytes).unwrap();
+
+        assert_eq!(decoded.target(), target);
+        assert_eq!(
+            decoded
+                .nodes()
+                .iter()
+                .map(|node| (*node.id(), node.socket_address()))
+                .collect::<Vec<_>>(),
+            closest_nodes
+                .nodes()
+                .iter()
+                .map(|node| (*node.id(), node.socket_address()))
+                .collect::<Vec<_>>()
+        );
+
+        assert!(matches!(
+            ClosestNodes::from_compact_bytes(target, &bytes[1..]),
+            Err(DecodeMessageError::InvalidNodes4)
+        ));
+    }
+
     #[test]
     fn order_by_secure_id() {
         let unsecure = Node::random();
         let secure = Node(Arc::new(NodeInner {
             id: Id::from_str("5a3ce9c14e7a08645677bbd1cfe7d8f956d53256").unwrap(),
-            address: SocketAddrV4::new([21, 75, 31, 124].into(), 0),
+            address: SocketAddrV4::new([21, 75, 31, 124].into(), 0).into(),
             token: None,
             last_seen: Instant::now(),
         }));
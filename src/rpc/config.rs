@@ -1,9 +1,20 @@
 use std::{
-    net::{Ipv4Addr, SocketAddrV4},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    num::NonZeroUsize,
+    sync::Arc,
     time::Duration,
 };
 
-use super::{ServerSettings, DEFAULT_REQUEST_TIMEOUT};
+use crate::common::{Id, RequestTypeSpecific};
+
+use super::{
+    BootstrapHealth, Clock, DefaultEvictionPolicy, EventSink, EvictionPolicy, MutableValidator,
+    RequestObserver, ServerSettings, SystemClock, Transport, DEFAULT_CLIENT_VERSION,
+    DEFAULT_MAX_INFLIGHT_REQUESTS, DEFAULT_MAX_VALUE_SIZE, DEFAULT_MIN_REQUEST_TIMEOUT,
+    DEFAULT_PING_INTERVAL, DEFAULT_PUT_RETRY_BACKOFF, DEFAULT_QUERY_CACHE_SIZE,
+    DEFAULT_QUERY_CONCURRENCY, DEFAULT_QUERY_TIMEOUT, DEFAULT_REFRESH_INTERVAL,
+    DEFAULT_REQUEST_TIMEOUT,
+};
 
 #[derive(Debug, Clone)]
 /// Dht Configurations
@@ -12,10 +23,38 @@ pub struct Config {
     ///
     /// Defaults to [super::DEFAULT_BOOTSTRAP_NODES]
     pub bootstrap: Option<Vec<SocketAddrV4>>,
+    /// Ipv6 bootstrap nodes, added to [Config::bootstrap], or to the
+    /// [super::DEFAULT_BOOTSTRAP_NODES] if it is None.
+    ///
+    /// Ignored unless [Config::ipv6] is enabled.
+    ///
+    /// Defaults to an empty list.
+    pub bootstrap6: Vec<SocketAddrV6>,
+    /// Bootstrap nodes added to [Config::bootstrap] as is.
+    ///
+    /// If set while [Config::bootstrap] is None, the [super::DEFAULT_BOOTSTRAP_NODES]
+    /// are not used, so no DNS resolution is needed at all.
+    ///
+    /// Defaults to an empty list.
+    pub bootstrap_addrs: Vec<SocketAddr>,
+    /// Health of the bootstrapping nodes from a previous run, see [super::BootstrapHealth].
+    ///
+    /// Defaults to None, where all bootstrapping nodes start equally healthy.
+    pub bootstrap_health: Option<BootstrapHealth>,
     /// Explicit port to listen on.
     ///
-    /// Defaults to None
+    /// If the port is unavailable, [super::Rpc::new] fails, unless [Config::port_fallback] is enabled.
+    ///
+    /// Defaults to None, where the default port `6881` is tried first,
+    /// falling back to a random port.
     pub port: Option<u16>,
+    /// Whether or not to listen on a random port if the explicit [Config::port] is unavailable,
+    /// instead of failing.
+    ///
+    /// The actual port can be read from [super::Rpc::local_addr].
+    ///
+    /// Defaults to false.
+    pub port_fallback: bool,
     /// UDP socket request timeout duration.
     ///
     /// The longer this duration is, the longer queries take until they are deemeed "done".
@@ -24,28 +63,369 @@ pub struct Config {
     ///
     /// Defaults to [DEFAULT_REQUEST_TIMEOUT]
     pub request_timeout: Duration,
+    /// Whether or not to adapt the request timeout to the observed round trip times
+    /// of responses, as `srtt + 4 * rttvar` like TCP's retransmission timeout,
+    /// bounded by [Config::min_request_timeout] and [Config::request_timeout].
+    ///
+    /// Queries finish faster on fast networks, where unresponsive nodes are
+    /// abandoned sooner. Like TCP, the timeout is doubled whenever requests
+    /// time out, up to [Config::request_timeout], until the next response.
+    ///
+    /// Defaults to false.
+    pub adaptive_timeout: bool,
+    /// Lower bound of the request timeout if [Config::adaptive_timeout] is enabled.
+    ///
+    /// Defaults to [DEFAULT_MIN_REQUEST_TIMEOUT]
+    pub min_request_timeout: Duration,
+    /// Timeouts of specific request types, overriding [Config::request_timeout]
+    /// and [Config::adaptive_timeout] for these types.
+    ///
+    /// Defaults to no overrides.
+    pub timeouts: RequestTimeouts,
     /// Server to respond to incoming Requests
     pub server_settings: ServerSettings,
     /// Whether or not to start in server mode from the get go.
     ///
     /// Defaults to false where it will run in [Adaptive mode](https://github.com/pubky/mainline?tab=readme-ov-file#adaptive-mode).
     pub server_mode: bool,
+    /// Whether or not to never run in server mode, even if this node is publicly reachable,
+    /// so it is never used as a routing or storage node by others.
+    ///
+    /// All outgoing requests are marked as read-only as in [BEP_0043](https://www.bittorrent.org/beps/bep_0043.html),
+    /// and [Config::server_mode] is ignored.
+    ///
+    /// Defaults to false, where it will run in [Adaptive mode](https://github.com/pubky/mainline?tab=readme-ov-file#adaptive-mode).
+    pub read_only: bool,
+    /// Whether or not to answer `find_node` requests in client mode,
+    /// for example from bootstrapping or trusted peers that rely on this node.
+    ///
+    /// Other requests, like `get_peers`, `get` and `put`, are still ignored in client mode.
+    ///
+    /// Defaults to false.
+    pub always_answer_find_node: bool,
     /// A known public IPv4 address for this node to generate
     /// a secure node Id from according to [BEP_0042](https://www.bittorrent.org/beps/bep_0042.html)
     ///
     /// Defaults to None, where we depend on suggestions from responding nodes.
     pub public_ip: Option<Ipv4Addr>,
+    /// A fixed node Id, taking precedence over [Config::public_ip], and never
+    /// replaced by a secure Id for the public address suggested by responding nodes.
+    ///
+    /// The caller is responsible for its validity according to [BEP_0042](https://www.bittorrent.org/beps/bep_0042.html).
+    ///
+    /// Defaults to None, where a random or secure Id is generated.
+    pub node_id: Option<Id>,
+    /// Whether or not to only add nodes to the routing table if their [crate::Id]
+    /// is valid for their IP address according to [BEP_0042](https://www.bittorrent.org/beps/bep_0042.html).
+    ///
+    /// Nodes with insecure Ids are still queried during lookups, but never stored.
+    ///
+    /// Defaults to false.
+    pub enforce_secure_ids: bool,
+    /// Number of nodes that need to acknowledge storing a value, before a PUT
+    /// query is reported as done successfully.
+    ///
+    /// The remaining store requests are still awaited in the background, and the final
+    /// count is reported in [crate::rpc::RpcTickReport::done_background_put_queries].
+    ///
+    /// Defaults to None, where PUT queries wait for all nodes to respond or time out.
+    pub put_ack_threshold: Option<NonZeroUsize>,
+    /// Minimum number of nodes that need to acknowledge storing a value, for a PUT
+    /// query to succeed, otherwise it fails with
+    /// [crate::errors::PutQueryError::InsufficientStorageNodes].
+    ///
+    /// Defaults to 1.
+    pub min_storage_nodes: usize,
+    /// Number of times to retry the lookup of a PUT query that found no nodes
+    /// to store at, before reporting [crate::errors::PutQueryError::NoClosestNodes].
+    ///
+    /// Defaults to 0, where PUT queries fail immediately.
+    pub put_max_retries: usize,
+    /// Delay before the first retry of a PUT query, doubled for every following retry.
+    ///
+    /// Defaults to [DEFAULT_PUT_RETRY_BACKOFF]
+    pub put_retry_backoff: Duration,
+    /// Whether or not to bind a second UDP socket for Ipv6 and maintain
+    /// a parallel Ipv6 routing table according to [BEP_0032](https://www.bittorrent.org/beps/bep_0032.html).
+    ///
+    /// Defaults to false, where only Ipv4 is used.
+    ///
+    /// Enabled by any [Config::bootstrap_address_family] other than [AddressFamily::V4Only],
+    /// and enabling it with [AddressFamily::V4Only] is the same as [AddressFamily::Both].
+    pub ipv6: bool,
+    /// Which address families of the bootstrapping nodes to use, and in which order,
+    /// for example to only bootstrap from Ipv6 nodes on Ipv6-only networks.
+    ///
+    /// This only affects bootstrapping, the Ipv4 socket and routing table are
+    /// always kept, and filled by nodes learned from responses.
+    ///
+    /// Defaults to [AddressFamily::V4Only]
+    pub bootstrap_address_family: AddressFamily,
+    /// Maximum number of nodes a query waits for responses from at once
+    /// (the Kademlia "alpha" parameter).
+    ///
+    /// Lower values reduce burst traffic, higher values converge faster.
+    /// Zero is treated as 1.
+    ///
+    /// Defaults to [DEFAULT_QUERY_CONCURRENCY]
+    pub query_concurrency: usize,
+    /// Maximum duration of a query, after which it is done with whatever
+    /// responses it has so far, even if some nodes didn't respond yet.
+    ///
+    /// Bounds the latency of queries in sparse regions of the Dht,
+    /// unlike [Self::request_timeout] which applies to each request.
+    ///
+    /// Defaults to [DEFAULT_QUERY_TIMEOUT]
+    pub query_timeout: Duration,
+    /// How long to keep the responses of done GET queries, so that [super::Rpc::get]
+    /// returns them immediately as [super::GetStatus::Cached], while refreshing them
+    /// with a new query.
+    ///
+    /// Defaults to None, where responses are not cached.
+    pub value_cache_ttl: Option<Duration>,
+    /// Maximum number of peers to collect in a single `get_peers` query, beyond which
+    /// peers in further responses are ignored, while the query still continues
+    /// to find the closest nodes to the info_hash.
+    ///
+    /// Defaults to None, where all peers are collected.
+    pub max_peers_per_query: Option<usize>,
+    /// Maximum number of requests awaiting a response at once, across all queries.
+    ///
+    /// Further requests are queued and sent as earlier requests get a response
+    /// or time out, smoothing outbound traffic on busy nodes. Zero is treated as 1.
+    ///
+    /// Defaults to [DEFAULT_MAX_INFLIGHT_REQUESTS]
+    pub max_inflight_requests: usize,
+    /// Whether or not to use cryptographically random transaction ids for outgoing
+    /// requests, instead of sequential ones, so remote nodes can't predict them.
+    ///
+    /// Defaults to false.
+    pub random_transaction_ids: bool,
+    /// Receive structured [super::DhtEvent]s, like invalid values or public address
+    /// changes, in addition to the tracing logs.
+    ///
+    /// Defaults to None
+    pub event_sink: Option<EventSink>,
+    /// Observe every incoming request, before rate limiting or handling it,
+    /// including requests received in client mode, which are otherwise ignored.
+    ///
+    /// Defaults to None
+    pub request_observer: Option<RequestObserver>,
+    /// Validate mutable items in responses after checking their signatures,
+    /// dropping the items it rejects, for example values that aren't valid for the application.
+    ///
+    /// Doesn't affect immutable values or peers.
+    ///
+    /// Defaults to None
+    pub mutable_validator: Option<MutableValidator>,
+    /// Keep the raw values of the last [super::MAX_REJECTED_SAMPLES] invalid responses,
+    /// see [super::Rpc::rejected_samples].
+    ///
+    /// Defaults to false.
+    pub capture_rejected: bool,
+    /// Maximum number of incoming requests per second to respond to from any single IP,
+    /// with bursts up to the same number. Requests beyond that are silently dropped.
+    ///
+    /// Defaults to None, where every request is responded to.
+    pub max_requests_per_ip_per_sec: Option<u32>,
+    /// Maximum size in bytes of immutable and mutable values in `get` responses.
+    ///
+    /// Larger responses are dropped while decoding, before validating values or signatures,
+    /// and putting larger values fails with [super::PutQueryError::ValueTooLarge] without sending anything.
+    ///
+    /// Defaults to [DEFAULT_MAX_VALUE_SIZE]
+    pub max_value_size: usize,
+    /// Client identifier and version set as the `v` field of every outgoing message,
+    /// as in [BEP_0005](https://www.bittorrent.org/beps/bep_0005.html), so other
+    /// nodes can identify this node's traffic.
+    ///
+    /// If None, the `v` field is omitted.
+    ///
+    /// Defaults to [DEFAULT_CLIENT_VERSION]
+    pub client_version: Option<[u8; 4]>,
+    /// Maximum estimated size in bytes of the closest nodes cached from done queries,
+    /// evicting the least recently used queries beyond it.
+    ///
+    /// Enforced in addition to [Config::query_cache_size], and ignored if the cache is disabled.
+    ///
+    /// Defaults to None, where up to [Config::query_cache_size] queries are cached regardless of their size.
+    pub max_query_cache_bytes: Option<usize>,
+    /// Maximum number of done queries to cache the closest nodes of, to speed up
+    /// later queries and puts to the same targets.
+    ///
+    /// If None, nothing is cached to minimize memory usage, so every `put` starts
+    /// with a fresh `get` query, and the Dht size estimate only relies on the latest query.
+    /// [super::Rpc::seed_query_cache] is then a noop, and [super::Rpc::import_state]
+    /// only restores the routing tables.
+    ///
+    /// Defaults to [DEFAULT_QUERY_CACHE_SIZE]
+    pub query_cache_size: Option<NonZeroUsize>,
+    /// How often to rotate the secret of write tokens given to requesters in server mode.
+    ///
+    /// Tokens from the previous interval are still accepted, so tokens are valid
+    /// for one to two intervals, and rejected with error `203` after that.
+    ///
+    /// Defaults to 5 minutes.
+    pub token_rotation_interval: Duration,
+    /// How often to refresh the routing table by querying the closest nodes to our own Id.
+    ///
+    /// Every cycle is randomly shortened or lengthened by up to 10%.
+    ///
+    /// Defaults to [DEFAULT_REFRESH_INTERVAL]
+    pub refresh_interval: Duration,
+    /// How often to ping the routing table nodes that weren't seen recently,
+    /// and remove the ones that are stale.
+    ///
+    /// Every cycle is randomly shortened or lengthened by up to 10%.
+    ///
+    /// Defaults to [DEFAULT_PING_INTERVAL]
+    pub ping_interval: Duration,
+    /// How often to ping a few routing table nodes, rotating through the table,
+    /// to keep the NAT mapping of this node alive between the pings of [Config::ping_interval],
+    /// so incoming requests keep reaching it.
+    ///
+    /// Defaults to None
+    pub nat_keepalive_interval: Option<Duration>,
+    /// Decides which routing table nodes the periodic maintenance removes or pings,
+    /// see [EvictionPolicy] for what it doesn't control.
+    ///
+    /// Defaults to [DefaultEvictionPolicy]
+    pub eviction_policy: Arc<dyn EvictionPolicy>,
+    /// Source of time for request timeouts and periodic routing table maintenance.
+    ///
+    /// Defaults to [SystemClock]
+    pub clock: Arc<dyn Clock>,
+    /// Send and receive messages over a custom [Transport] instead of binding
+    /// an Ipv4 [UdpSocket](std::net::UdpSocket), ignoring [Config::port].
+    ///
+    /// Defaults to None
+    pub transport: Option<Arc<dyn Transport>>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             bootstrap: None,
+            bootstrap6: Vec::new(),
+            bootstrap_addrs: Vec::new(),
+            bootstrap_health: None,
             port: None,
+            port_fallback: false,
             request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            adaptive_timeout: false,
+            min_request_timeout: DEFAULT_MIN_REQUEST_TIMEOUT,
+            timeouts: RequestTimeouts::default(),
             server_settings: Default::default(),
             server_mode: false,
+            read_only: false,
+            always_answer_find_node: false,
             public_ip: None,
+            node_id: None,
+            enforce_secure_ids: false,
+            min_storage_nodes: 1,
+            put_ack_threshold: None,
+            put_max_retries: 0,
+            put_retry_backoff: DEFAULT_PUT_RETRY_BACKOFF,
+            ipv6: false,
+            bootstrap_address_family: AddressFamily::V4Only,
+            query_concurrency: DEFAULT_QUERY_CONCURRENCY,
+            query_timeout: DEFAULT_QUERY_TIMEOUT,
+            value_cache_ttl: None,
+            max_peers_per_query: None,
+            max_inflight_requests: DEFAULT_MAX_INFLIGHT_REQUESTS,
+            random_transaction_ids: false,
+            event_sink: None,
+            request_observer: None,
+            mutable_validator: None,
+            capture_rejected: false,
+            max_requests_per_ip_per_sec: None,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            client_version: Some(DEFAULT_CLIENT_VERSION),
+            max_query_cache_bytes: None,
+            query_cache_size: NonZeroUsize::new(DEFAULT_QUERY_CACHE_SIZE),
+            token_rotation_interval: crate::common::TOKEN_ROTATE_INTERVAL,
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            ping_interval: DEFAULT_PING_INTERVAL,
+            nat_keepalive_interval: None,
+            eviction_policy: Arc::new(DefaultEvictionPolicy),
+            clock: Arc::new(SystemClock),
+            transport: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Preferred address families of the bootstrapping nodes, see [Config::bootstrap_address_family].
+///
+/// Anything other than [AddressFamily::V4Only] binds the Ipv6 socket, as if [Config::ipv6] was enabled,
+/// but the Ipv4 socket is bound regardless.
+pub enum AddressFamily {
+    #[default]
+    /// Only use Ipv4 bootstrapping nodes.
+    V4Only,
+    /// Only use Ipv6 bootstrapping nodes.
+    V6Only,
+    /// Use both, starting with the Ipv4 bootstrapping nodes.
+    PreferV4,
+    /// Use both, starting with the Ipv6 bootstrapping nodes.
+    PreferV6,
+    /// Use both, in the order they were resolved.
+    Both,
+}
+
+impl AddressFamily {
+    /// Whether or not Ipv6 is used at all.
+    pub(crate) fn ipv6(&self) -> bool {
+        !matches!(self, AddressFamily::V4Only)
+    }
+
+    /// Filter and order addresses by this preference, keeping
+    /// the order of addresses within the same family.
+    pub(crate) fn apply(&self, addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let (ipv4, ipv6): (Vec<_>, Vec<_>) =
+            addresses.iter().partition(|address| address.is_ipv4());
+
+        match self {
+            AddressFamily::V4Only => ipv4,
+            AddressFamily::V6Only => ipv6,
+            AddressFamily::PreferV4 => ipv4.into_iter().chain(ipv6).collect(),
+            AddressFamily::PreferV6 => ipv6.into_iter().chain(ipv4).collect(),
+            AddressFamily::Both => addresses,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Timeouts of each request type, see [Config::timeouts].
+///
+/// Request types without a timeout use the [Config::request_timeout].
+pub struct RequestTimeouts {
+    /// Timeout of `ping` requests.
+    pub ping: Option<Duration>,
+    /// Timeout of `find_node` requests.
+    pub find_node: Option<Duration>,
+    /// Timeout of `get_peers` requests.
+    pub get_peers: Option<Duration>,
+    /// Timeout of `get` requests for immutable or mutable values.
+    pub get_value: Option<Duration>,
+    /// Timeout of `announce_peer` and `put` requests.
+    pub put: Option<Duration>,
+}
+
+impl RequestTimeouts {
+    /// Returns true if no request type has a timeout.
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the timeout of this request type, if any.
+    pub(crate) fn get(&self, request: &RequestTypeSpecific) -> Option<Duration> {
+        match request {
+            RequestTypeSpecific::Ping => self.ping,
+            RequestTypeSpecific::FindNode(_) => self.find_node,
+            RequestTypeSpecific::GetPeers(_) => self.get_peers,
+            RequestTypeSpecific::GetValue(_) => self.get_value,
+            RequestTypeSpecific::Put(_) => self.put,
         }
     }
 }
@@ -0,0 +1,128 @@
+//! Connectivity diagnostics for a single node.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::common::{
+    ErrorSpecific, FindNodeRequestArguments, Id, Message, MessageType, RequestSpecific,
+    RequestTypeSpecific, ResponseSpecific,
+};
+
+use super::socket::KrpcSocket;
+
+/// Diagnostics report of a single node, see [super::Rpc::diagnose_node].
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeDiagnostics {
+    /// The address of the diagnosed node.
+    pub address: SocketAddr,
+    /// Whether or not the node responded to any of the requests.
+    pub reachable: bool,
+    /// Round trip time of the `ping` request.
+    pub rtt: Option<Duration>,
+    /// The [Id] this node claims in its responses.
+    pub id: Option<Id>,
+    /// Whether or not the claimed [Id] is valid for the node's IP according to
+    /// [BEP_0042](https://www.bittorrent.org/beps/bep_0042.html).
+    pub secure: Option<bool>,
+    /// The client version the node sent in its responses.
+    pub version: Option<[u8; 4]>,
+    /// Number of nodes returned in a well formed response to the `find_node` request.
+    ///
+    /// `None` if the node didn't respond to the `find_node` request.
+    pub closer_nodes: Option<usize>,
+    /// Error response the node returned, if any.
+    pub error: Option<ErrorSpecific>,
+}
+
+#[derive(Debug)]
+/// Ping and find_node requests sent to a node to diagnose it.
+pub(crate) struct DiagnosticsQuery {
+    ping_tid: u16,
+    find_node_tid: u16,
+    sent_at: Instant,
+    report: NodeDiagnostics,
+}
+
+impl DiagnosticsQuery {
+    pub fn start(socket: &mut KrpcSocket, requester_id: Id, address: SocketAddr) -> Self {
+        let sent_at = socket.now();
+
+        let ping_tid = socket.request(
+            address,
+            RequestSpecific {
+                requester_id,
+                request_type: RequestTypeSpecific::Ping,
+            },
+        );
+
+        let find_node_tid = socket.request(
+            address,
+            RequestSpecific {
+                requester_id,
+                request_type: RequestTypeSpecific::FindNode(FindNodeRequestArguments {
+                    target: requester_id,
+                    want: vec![],
+                }),
+            },
+        );
+
+        Self {
+            ping_tid,
+            find_node_tid,
+            sent_at,
+            report: NodeDiagnostics {
+                address,
+                reachable: false,
+                rtt: None,
+                id: None,
+                secure: None,
+                version: None,
+                closer_nodes: None,
+                error: None,
+            },
+        }
+    }
+
+    /// Return true if a response (by transaction_id) is expected by this query.
+    pub fn inflight(&self, tid: u16) -> bool {
+        self.ping_tid == tid || self.find_node_tid == tid
+    }
+
+    /// Returns true if both requests got responses or timed out.
+    pub fn is_done(&self, socket: &KrpcSocket) -> bool {
+        !socket.inflight(&self.ping_tid) && !socket.inflight(&self.find_node_tid)
+    }
+
+    pub fn response(&mut self, message: Message, now: Instant) {
+        let report = &mut self.report;
+
+        report.reachable = true;
+        report.version = message.version.or(report.version);
+
+        if let Some(id) = message.get_author_id() {
+            report.secure = Some(id.is_valid_for_ip_addr(report.address.ip()));
+            report.id = Some(id);
+        }
+
+        match message.message_type {
+            MessageType::Response(ResponseSpecific::Ping(_)) => {
+                report.rtt = Some(now.saturating_duration_since(self.sent_at));
+            }
+            MessageType::Response(ResponseSpecific::FindNode(args)) => {
+                report.closer_nodes = Some(args.nodes.len());
+            }
+            MessageType::Error(error) => {
+                debug!(address = ?report.address, ?error, "Diagnosed node returned an error");
+
+                report.error = Some(error);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn report(self) -> NodeDiagnostics {
+        self.report
+    }
+}
@@ -0,0 +1,112 @@
+//! Structured events emitted by the [super::Rpc].
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    net::{SocketAddr, SocketAddrV4},
+    sync::Arc,
+};
+
+use crate::common::{Id, MutableItem, RequestSpecific};
+
+/// Notable events in the life of a Dht node, see [super::config::Config::event_sink].
+#[derive(Debug, Clone, PartialEq)]
+pub enum DhtEvent {
+    /// A node responded with an immutable value that doesn't match the target hash.
+    InvalidImmutable {
+        /// Target of the query.
+        target: Id,
+        /// Address of the responding node.
+        from: SocketAddr,
+    },
+    /// A node responded with a mutable item with an invalid signature,
+    /// or rejected by the [super::config::Config::mutable_validator].
+    InvalidMutable {
+        /// Target of the query.
+        target: Id,
+        /// Address of the responding node.
+        from: SocketAddr,
+    },
+    /// Responding nodes suggest a new public address for this node.
+    PublicAddressChanged(SocketAddrV4),
+    /// This node switched from adaptive mode to server mode.
+    SwitchedToServerMode,
+    /// The bootstrapping query finished without populating the routing table.
+    BootstrapFailed,
+}
+
+#[derive(Clone)]
+/// A callback that receives every [DhtEvent].
+///
+/// It is called on the Dht's actor thread, so it should return quickly.
+pub struct EventSink(Arc<dyn Fn(DhtEvent) + Send + Sync>);
+
+impl EventSink {
+    /// Create a new [EventSink] from a callback.
+    pub fn new(sink: impl Fn(DhtEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(sink))
+    }
+
+    pub(crate) fn emit(&self, event: DhtEvent) {
+        (self.0)(event)
+    }
+}
+
+impl Debug for EventSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "EventSink")
+    }
+}
+
+#[derive(Clone)]
+/// A callback that observes every incoming request before it is handled,
+/// see [super::config::Config::request_observer].
+///
+/// It only gets shared references, so it can't change how requests are handled,
+/// but it is called on the Dht's actor thread, so it should return quickly.
+pub struct RequestObserver(Arc<ObserverFn>);
+
+type ObserverFn = dyn Fn(&SocketAddr, &RequestSpecific) + Send + Sync;
+
+impl RequestObserver {
+    /// Create a new [RequestObserver] from a callback.
+    pub fn new(observer: impl Fn(&SocketAddr, &RequestSpecific) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(observer))
+    }
+
+    pub(crate) fn observe(&self, from: &SocketAddr, request: &RequestSpecific) {
+        (self.0)(from, request)
+    }
+}
+
+impl Debug for RequestObserver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "RequestObserver")
+    }
+}
+
+#[derive(Clone)]
+/// A callback that validates mutable items with valid signatures beyond their signature,
+/// see [super::config::Config::mutable_validator].
+///
+/// Items it returns false for are dropped like items with invalid signatures,
+/// and it is called on the Dht's actor thread, so it should return quickly.
+pub struct MutableValidator(Arc<ValidatorFn>);
+
+type ValidatorFn = dyn Fn(&MutableItem) -> bool + Send + Sync;
+
+impl MutableValidator {
+    /// Create a new [MutableValidator] from a callback.
+    pub fn new(validator: impl Fn(&MutableItem) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(validator))
+    }
+
+    pub(crate) fn validate(&self, item: &MutableItem) -> bool {
+        (self.0)(item)
+    }
+}
+
+impl Debug for MutableValidator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "MutableValidator")
+    }
+}
@@ -0,0 +1,40 @@
+//! Policy of the periodic routing table maintenance, replaceable for experimentation.
+
+use std::{fmt::Debug, time::Instant};
+
+use crate::common::Node;
+
+/// Decides which routing table nodes the periodic maintenance of the [super::Rpc]
+/// removes, and which it pings to check they are still responsive.
+///
+/// The policy only controls the periodic maintenance. A full bucket still replaces
+/// its least recently seen node with an incoming one once that node wasn't seen for
+/// 15 minutes, and [super::Rpc::to_bootstrap] still skips such nodes, so a policy
+/// keeping nodes longer only does so while their buckets have room.
+///
+/// The default is [DefaultEvictionPolicy], but a custom policy can be set with
+/// [crate::DhtBuilder::eviction_policy], for example to keep low latency nodes longer.
+///
+/// `now` is the time of the [super::Clock] of the [super::Rpc].
+pub trait EvictionPolicy: Debug + Send + Sync {
+    /// Returns true if this node should be removed from the routing table.
+    fn should_evict(&self, node: &Node, now: Instant) -> bool;
+
+    /// Returns true if this node should be pinged, if it isn't evicted.
+    fn should_ping(&self, node: &Node, now: Instant) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// An [EvictionPolicy] removing nodes not seen for 15 minutes,
+/// and pinging nodes not seen for 10 seconds.
+pub struct DefaultEvictionPolicy;
+
+impl EvictionPolicy for DefaultEvictionPolicy {
+    fn should_evict(&self, node: &Node, now: Instant) -> bool {
+        node.is_stale_at(now)
+    }
+
+    fn should_ping(&self, node: &Node, now: Instant) -> bool {
+        node.should_ping(now)
+    }
+}
@@ -1,18 +1,40 @@
-use std::net::SocketAddrV4;
+use std::net::{SocketAddrV4, SocketAddrV6};
 
 use crate::Id;
 
-use super::Rpc;
+use super::{BootstrapHealth, Metrics, NatType, RejectedSample, Rpc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Whether this node populated its routing table, see [Info::bootstrap_status].
+pub enum BootstrapStatus {
+    /// The first find_node query for this node's Id didn't finish yet.
+    #[default]
+    InProgress,
+    /// The last find_node query for this node's Id populated the routing table.
+    Done {
+        /// Number of nodes in the (Ipv4) routing table when the query finished.
+        table_size: usize,
+    },
+    /// The last find_node query for this node's Id finished with an empty routing table.
+    Failed,
+}
 
 /// Information and statistics about this mainline node.
 #[derive(Debug, Clone)]
 pub struct Info {
     id: Id,
     local_addr: SocketAddrV4,
+    local_addr6: Option<SocketAddrV6>,
     public_address: Option<SocketAddrV4>,
     firewalled: bool,
+    nat_type: NatType,
+    bootstrap_status: BootstrapStatus,
     dht_size_estimate: (usize, f64),
     server_mode: bool,
+    bucket_sizes: Vec<usize>,
+    bootstrap_health: BootstrapHealth,
+    metrics: Metrics,
+    rejected_samples: Vec<RejectedSample>,
 }
 
 impl Info {
@@ -24,6 +46,11 @@ impl Info {
     pub fn local_addr(&self) -> SocketAddrV4 {
         self.local_addr
     }
+    /// Local UDP Ipv6 socket address that this node is listening on,
+    /// if [crate::DhtBuilder::ipv6] is enabled.
+    pub fn local_addr6(&self) -> Option<SocketAddrV6> {
+        self.local_addr6
+    }
     /// Returns the best guess for this node's Public address.
     ///
     /// If [crate::DhtBuilder::public_ip] was set, this is what will be returned
@@ -41,6 +68,16 @@ impl Info {
         self.firewalled
     }
 
+    /// Returns the type of NAT this node is behind, as observed by responding nodes.
+    pub fn nat_type(&self) -> NatType {
+        self.nat_type
+    }
+
+    /// Returns whether this node is still bootstrapping, or populated its routing table or failed to.
+    pub fn bootstrap_status(&self) -> BootstrapStatus {
+        self.bootstrap_status
+    }
+
     /// Returns whether or not this node is running in server mode.
     pub fn server_mode(&self) -> bool {
         self.server_mode
@@ -54,6 +91,37 @@ impl Info {
     pub fn dht_size_estimate(&self) -> (usize, f64) {
         self.dht_size_estimate
     }
+
+    /// Returns the number of nodes in each bucket of the (Ipv4) routing table,
+    /// see [crate::RoutingTable::bucket_sizes].
+    ///
+    /// A well connected node has its furthest buckets full, with fewer
+    /// nodes the closer the bucket is.
+    pub fn bucket_sizes(&self) -> &[usize] {
+        &self.bucket_sizes
+    }
+
+    /// Returns the total number of nodes in the (Ipv4) routing table.
+    pub fn routing_table_size(&self) -> usize {
+        self.bucket_sizes.iter().sum()
+    }
+
+    /// Returns the responses and timeouts of the bootstrapping nodes so far,
+    /// which can be passed to [crate::DhtBuilder::bootstrap_health] in a future run.
+    pub fn bootstrap_health(&self) -> &BootstrapHealth {
+        &self.bootstrap_health
+    }
+
+    /// Returns a snapshot of counters of this node's activity, for monitoring.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Returns the most recent invalid values received from other nodes,
+    /// if [crate::DhtBuilder::capture_rejected] is enabled.
+    pub fn rejected_samples(&self) -> &[RejectedSample] {
+        &self.rejected_samples
+    }
 }
 
 impl From<&Rpc> for Info {
@@ -61,10 +129,17 @@ impl From<&Rpc> for Info {
         Self {
             id: *rpc.id(),
             local_addr: rpc.local_addr(),
+            local_addr6: rpc.local_addr6(),
             dht_size_estimate: rpc.dht_size_estimate(),
             public_address: rpc.public_address(),
             firewalled: rpc.firewalled(),
+            nat_type: rpc.nat_type(),
+            bootstrap_status: rpc.bootstrap_status(),
             server_mode: rpc.server_mode(),
+            bucket_sizes: rpc.routing_table().bucket_sizes(),
+            bootstrap_health: rpc.bootstrap_health().clone(),
+            metrics: rpc.metrics(),
+            rejected_samples: rpc.rejected_samples(),
         }
     }
 }
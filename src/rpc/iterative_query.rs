@@ -2,17 +2,44 @@
 
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::net::SocketAddrV4;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
 
 use tracing::{debug, trace};
 
-use super::{socket::KrpcSocket, ClosestNodes};
+use super::{socket::KrpcSocket, ClosestNodes, QueryKind, QuerySummary};
 use crate::common::{FindNodeRequestArguments, GetPeersRequestArguments, GetValueRequestArguments};
 use crate::{
-    common::{Id, Node, RequestSpecific, RequestTypeSpecific, MAX_BUCKET_SIZE_K},
+    common::{
+        BloomFilter, Id, MutableItem, Node, RequestSpecific, RequestTypeSpecific, MAX_BUCKET_SIZE_K,
+    },
     rpc::Response,
 };
 
+/// Default maximum number of nodes an [IterativeQuery] waits for responses from at once.
+pub const DEFAULT_QUERY_CONCURRENCY: usize = 3;
+
+/// Default maximum duration of an [IterativeQuery] before it is done with whatever responses it has.
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Timing of a single node visited by an [IterativeQuery], see [super::Rpc::last_query_trace].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HopTiming {
+    /// The node's [Id], as claimed by whoever told us about it,
+    /// or by the node itself in its response.
+    ///
+    /// `None` for explicitly visited (bootstrapping) nodes that didn't respond.
+    pub id: Option<Id>,
+    /// The node's address.
+    pub address: SocketAddr,
+    /// When the request was sent to this node.
+    pub queried_at: Instant,
+    /// Round trip time of the request, or `None` if the node didn't respond (yet).
+    pub rtt: Option<Duration>,
+    /// Whether or not the node responded with closer nodes.
+    pub closer_nodes: bool,
+}
+
 /// An iterative process of concurrently sending a request to the closest known nodes to
 /// the target, updating the routing table with closer nodes discovered in the responses, and
 /// repeating this process until no closer nodes (that aren't already queried) are found.
@@ -22,19 +49,46 @@ pub(crate) struct IterativeQuery {
     closest: ClosestNodes,
     responders: ClosestNodes,
     inflight_requests: Vec<u16>,
-    visited: HashSet<SocketAddrV4>,
+    /// Transaction ids of the query requests (excluding pings) that are
+    /// still awaiting a response.
+    visiting: Vec<u16>,
+    /// Maximum number of visited nodes awaiting a response at once.
+    concurrency: usize,
+    /// When this query started, see [Self::timeout].
+    started_at: Instant,
+    /// Maximum duration of this query, after which it is done even if
+    /// some requests are still inflight.
+    timeout: Duration,
+    visited: HashSet<SocketAddr>,
+    /// Timing of every visited node, by the transaction_id of the request sent to it.
+    hops: Vec<(u16, HopTiming)>,
     responses: Vec<Response>,
+    /// Peers seen in get_peers responses so far.
+    peers: HashSet<SocketAddr>,
+    /// Union of the seeders and peers bloom filters in scrape responses.
+    scrape: Option<(BloomFilter, BloomFilter)>,
+    /// Highest seq of the mutable items seen so far.
+    most_recent_seq: Option<i64>,
     public_address_votes: HashMap<SocketAddrV4, u16>,
+    /// Whether this query was finished early by [Self::finish].
+    finished: bool,
+    /// Number of callers waiting for this query's responses, see [super::Rpc::subscribe].
+    subscribers: usize,
 }
 
 #[derive(Debug)]
+/// Arguments of a GET query.
 pub enum GetRequestSpecific {
+    /// Find the closest nodes to a target.
     FindNode(FindNodeRequestArguments),
+    /// Get peers announced on an info hash.
     GetPeers(GetPeersRequestArguments),
+    /// Get an immutable value or a mutable item.
     GetValue(GetValueRequestArguments),
 }
 
 impl GetRequestSpecific {
+    /// Returns the target of this query.
     pub fn target(&self) -> &Id {
         match self {
             GetRequestSpecific::FindNode(args) => &args.target,
@@ -45,7 +99,14 @@ impl GetRequestSpecific {
 }
 
 impl IterativeQuery {
-    pub fn new(requester_id: Id, target: Id, request: GetRequestSpecific) -> Self {
+    pub fn new(
+        requester_id: Id,
+        target: Id,
+        request: GetRequestSpecific,
+        concurrency: usize,
+        timeout: Duration,
+        started_at: Instant,
+    ) -> Self {
         let request_type = match request {
             GetRequestSpecific::FindNode(s) => RequestTypeSpecific::FindNode(s),
             GetRequestSpecific::GetPeers(s) => RequestTypeSpecific::GetPeers(s),
@@ -64,11 +125,21 @@ impl IterativeQuery {
             responders: ClosestNodes::new(target),
 
             inflight_requests: Vec::new(),
+            visiting: Vec::new(),
+            concurrency: concurrency.max(1),
+            started_at,
+            timeout,
             visited: HashSet::new(),
+            hops: Vec::new(),
 
             responses: Vec::new(),
+            peers: HashSet::new(),
+            scrape: None,
+            most_recent_seq: None,
 
             public_address_votes: HashMap::new(),
+            finished: false,
+            subscribers: 0,
         }
     }
 
@@ -92,6 +163,88 @@ impl IterativeQuery {
         &self.responses
     }
 
+    /// Takes all the responses, leaving none behind,
+    /// so it should only be called once the query is done.
+    pub fn take_responses(&mut self) -> Vec<Response> {
+        std::mem::take(&mut self.responses)
+    }
+
+    /// Transaction ids of all the requests sent by this query.
+    pub fn inflight_requests(&self) -> &[u16] {
+        &self.inflight_requests
+    }
+
+    /// Timing of every node visited so far, in the order they were visited.
+    pub fn trace(&self) -> Vec<HopTiming> {
+        self.hops.iter().map(|(_, hop)| hop.clone()).collect()
+    }
+
+    /// Returns the closest nodes that responded to this query so far,
+    /// unlike [Self::closest] which includes nodes that were only
+    /// seen in responses of other nodes.
+    pub fn live_nodes(&self) -> Box<[Node]> {
+        let mut live = ClosestNodes::new(self.target());
+
+        for (_, hop) in &self.hops {
+            if let (Some(id), Some(rtt)) = (hop.id, hop.rtt) {
+                live.add(Node::seen_at(id, hop.address, hop.queried_at + rtt));
+            }
+        }
+
+        live.nodes()
+            .iter()
+            .take(MAX_BUCKET_SIZE_K)
+            .cloned()
+            .collect()
+    }
+
+    /// Finish this query at the next [Self::tick], without waiting for inflight requests.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Returns true if this query was finished early by [Self::finish], so its
+    /// responders are likely fewer than the closest nodes to the target.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Count one more caller waiting for this query's responses.
+    pub fn subscribe(&mut self) {
+        self.subscribers += 1;
+    }
+
+    /// Number of callers waiting for this query's responses.
+    pub fn subscribers(&self) -> usize {
+        self.subscribers
+    }
+
+    pub fn summary(&self, socket: &KrpcSocket) -> QuerySummary {
+        let kind = match self.request.request_type {
+            RequestTypeSpecific::GetPeers(_) => QueryKind::GetPeers,
+            RequestTypeSpecific::GetValue(_) => QueryKind::GetValue,
+            _ => QueryKind::FindNode,
+        };
+
+        QuerySummary {
+            target: self.target(),
+            kind,
+            elapsed: socket.now().saturating_duration_since(self.started_at),
+            inflight: self
+                .inflight_requests
+                .iter()
+                .filter(|tid| socket.inflight(tid))
+                .count(),
+            candidates: self.closest.len(),
+            responders: self.responders.len(),
+        }
+    }
+
+    /// Votes of responders for this node's public address.
+    pub fn address_votes(&self) -> &HashMap<SocketAddrV4, u16> {
+        &self.public_address_votes
+    }
+
     pub fn best_address(&self) -> Option<SocketAddrV4> {
         let mut max = 0_u16;
         let mut best_addr = None;
@@ -115,7 +268,6 @@ impl IterativeQuery {
 
     /// Add a candidate node to query on next tick if it is among the closest nodes.
     pub fn add_candidate(&mut self, node: Node) {
-        // ready for a ipv6 routing table?
         self.closest.add(node);
     }
 
@@ -129,20 +281,10 @@ impl IterativeQuery {
 
     /// Visit explicitly given addresses, and add them to the visited set.
     /// only used from the Rpc when calling bootstrapping nodes.
-    pub fn visit(&mut self, socket: &mut KrpcSocket, address: SocketAddrV4) {
-        let tid = socket.request(address, self.request.clone());
-        self.inflight_requests.push(tid);
-
-        let tid = socket.request(
-            address,
-            RequestSpecific {
-                requester_id: Id::random(),
-                request_type: RequestTypeSpecific::Ping,
-            },
-        );
-        self.inflight_requests.push(tid);
-
-        self.visited.insert(address);
+    ///
+    /// Addresses that were already visited by this query are ignored.
+    pub fn visit(&mut self, socket: &mut KrpcSocket, address: SocketAddr) {
+        self.visit_node(socket, address, None)
     }
 
     /// Return true if a response (by transaction_id) is expected by this query.
@@ -150,13 +292,30 @@ impl IterativeQuery {
         self.inflight_requests.contains(&tid)
     }
 
+    /// Record the timing of a response to the request sent to a visited node.
+    ///
+    /// Responses to the accompanying pings are ignored.
+    pub fn hop_response(
+        &mut self,
+        now: Instant,
+        tid: u16,
+        responder_id: Option<Id>,
+        closer_nodes: bool,
+    ) {
+        if let Some((_, hop)) = self.hops.iter_mut().find(|(hop_tid, _)| *hop_tid == tid) {
+            hop.rtt = Some(now.saturating_duration_since(hop.queried_at));
+            hop.id = responder_id.or(hop.id);
+            hop.closer_nodes = closer_nodes;
+        }
+    }
+
     /// Add a node that responded with a token as a probable storage node.
     pub fn add_responding_node(&mut self, node: Node) {
         self.responders.add(node)
     }
 
     /// Store received response.
-    pub fn response(&mut self, from: SocketAddrV4, response: Response) {
+    pub fn response(&mut self, from: SocketAddr, response: Response) {
         let target = self.target();
 
         debug!(?target, ?response, ?from, "Query got response");
@@ -164,10 +323,69 @@ impl IterativeQuery {
         self.responses.push(response.to_owned());
     }
 
+    /// Returns true if the item has a strictly higher seq than
+    /// all the mutable items seen so far, and remember it.
+    pub fn newer_mutable(&mut self, item: &MutableItem) -> bool {
+        if self.most_recent_seq.is_some_and(|seq| seq >= item.seq()) {
+            return false;
+        }
+
+        self.most_recent_seq = Some(item.seq());
+
+        true
+    }
+
+    /// Takes all the peers seen in get_peers responses, leaving none behind,
+    /// so it should only be called once the query is done.
+    pub fn take_peers(&mut self) -> Vec<SocketAddr> {
+        std::mem::take(&mut self.peers).into_iter().collect()
+    }
+
+    /// Returns the peers that weren't seen in previous responses, in their original order,
+    /// up to a maximum number of peers seen in total.
+    pub fn new_peers(&mut self, peers: Vec<SocketAddr>, max: Option<usize>) -> Vec<SocketAddr> {
+        peers
+            .into_iter()
+            .filter(|peer| {
+                max.map_or(true, |max| self.peers.len() < max) && self.peers.insert(*peer)
+            })
+            .collect()
+    }
+
+    /// Merge the seeders and peers bloom filters of a scrape response,
+    /// and return the new estimates of seeders and peers.
+    pub fn add_scrape(&mut self, seeders: &BloomFilter, peers: &BloomFilter) -> (usize, usize) {
+        let (all_seeders, all_peers) = self.scrape.get_or_insert_with(Default::default);
+
+        all_seeders.union(seeders);
+        all_peers.union(peers);
+
+        (
+            all_seeders.estimate().round() as usize,
+            all_peers.estimate().round() as usize,
+        )
+    }
+
     /// Query closest nodes for this query's target and message.
     ///
     /// Returns true if it is done.
     pub fn tick(&mut self, socket: &mut KrpcSocket) -> bool {
+        if self.finished {
+            debug!(id=?self.target(), closest = ?self.closest.len(), visited = ?self.visited.len(), responders = ?self.responders.len(), "Query finished early");
+
+            socket.cancel(&self.inflight_requests);
+
+            return true;
+        }
+
+        if socket.now().saturating_duration_since(self.started_at) >= self.timeout {
+            debug!(id=?self.target(), closest = ?self.closest.len(), visited = ?self.visited.len(), responders = ?self.responders.len(), "Query timed out");
+
+            socket.cancel(&self.inflight_requests);
+
+            return true;
+        }
+
         // Visit closest nodes
         self.visit_closest(socket);
 
@@ -187,19 +405,106 @@ impl IterativeQuery {
 
     // === Private Methods ===
 
-    /// Visit the closest candidates and remove them as candidates
+    /// Visit the closest candidates and remove them as candidates,
+    /// without exceeding the query concurrency.
     fn visit_closest(&mut self, socket: &mut KrpcSocket) {
+        self.visiting.retain(|tid| socket.inflight(tid));
+
         let to_visit = self
             .closest
             .nodes()
             .iter()
             .take(MAX_BUCKET_SIZE_K)
-            .filter(|node| !self.visited.contains(&node.address()))
-            .map(|node| node.address())
+            .filter(|node| !self.visited.contains(&node.socket_address()))
+            .take(self.concurrency.saturating_sub(self.visiting.len()))
+            .map(|node| (*node.id(), node.socket_address()))
             .collect::<Vec<_>>();
 
-        for address in to_visit {
-            self.visit(socket, address);
+        for (id, address) in to_visit {
+            self.visit_node(socket, address, Some(id));
         }
     }
+
+    fn visit_node(&mut self, socket: &mut KrpcSocket, address: SocketAddr, id: Option<Id>) {
+        if self.visited.contains(&address) {
+            return;
+        }
+
+        let tid = socket.request(address, self.request.clone());
+        self.inflight_requests.push(tid);
+        self.visiting.push(tid);
+        self.hops.push((
+            tid,
+            HopTiming {
+                id,
+                address,
+                queried_at: socket.now(),
+                rtt: None,
+                closer_nodes: false,
+            },
+        ));
+
+        let tid = socket.request(
+            address,
+            RequestSpecific {
+                requester_id: Id::random(),
+                request_type: RequestTypeSpecific::Ping,
+            },
+        );
+        self.inflight_requests.push(tid);
+
+        self.visited.insert(address);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::UdpSocket;
+
+    use super::*;
+
+    #[test]
+    fn query_concurrency() {
+        let mut socket = KrpcSocket::client().unwrap();
+
+        // Bound but never respond.
+        let silent = (0..5)
+            .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect::<Vec<_>>();
+
+        let target = Id::random();
+        let mut query = IterativeQuery::new(
+            Id::random(),
+            target,
+            GetRequestSpecific::FindNode(FindNodeRequestArguments {
+                target,
+                want: vec![],
+            }),
+            1,
+            DEFAULT_QUERY_TIMEOUT,
+            socket.now(),
+        );
+
+        for socket in &silent {
+            query.add_candidate(Node::new(Id::random(), socket.local_addr().unwrap()));
+        }
+
+        for _ in 0..silent.len() {
+            query.tick(&mut socket);
+            query.tick(&mut socket);
+
+            let inflight = query
+                .visiting
+                .iter()
+                .filter(|tid| socket.inflight(tid))
+                .count();
+            assert_eq!(inflight, 1);
+
+            // Simulate a response or a timeout.
+            let visiting = query.visiting.clone();
+            socket.cancel(&visiting);
+        }
+
+        assert_eq!(query.visited.len(), silent.len());
+    }
 }
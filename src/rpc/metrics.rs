@@ -0,0 +1,81 @@
+//! Counters of the [super::Rpc] activity, for monitoring.
+
+use crate::common::RequestTypeSpecific;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Number of requests of each type.
+pub struct RequestCounts {
+    /// Number of `ping` requests.
+    pub ping: u64,
+    /// Number of `find_node` requests.
+    pub find_node: u64,
+    /// Number of `get_peers` requests.
+    pub get_peers: u64,
+    /// Number of `get` requests for immutable or mutable values.
+    pub get_value: u64,
+    /// Number of `announce_peer` and `put` requests.
+    pub put: u64,
+}
+
+impl RequestCounts {
+    /// Returns the total number of requests of all types.
+    pub fn total(&self) -> u64 {
+        self.ping + self.find_node + self.get_peers + self.get_value + self.put
+    }
+
+    pub(crate) fn increment(&mut self, request: &RequestTypeSpecific) {
+        let count = match request {
+            RequestTypeSpecific::Ping => &mut self.ping,
+            RequestTypeSpecific::FindNode(_) => &mut self.find_node,
+            RequestTypeSpecific::GetPeers(_) => &mut self.get_peers,
+            RequestTypeSpecific::GetValue(_) => &mut self.get_value,
+            RequestTypeSpecific::Put(_) => &mut self.put,
+        };
+
+        *count += 1;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Snapshot of counters since this node started, see [super::Rpc::metrics].
+///
+/// Counters only ever increase, so they can be exported as is
+/// to monitoring systems like Prometheus.
+pub struct Metrics {
+    /// Requests sent to other nodes, by type.
+    pub requests_sent: RequestCounts,
+    /// Requests received from other nodes, by type.
+    pub requests_received: RequestCounts,
+    /// Responses (including error responses) received to inflight requests.
+    pub responses_received: u64,
+    /// Requests that timed out without a response.
+    pub timeouts: u64,
+    /// Requests dropped because the OS send buffer was full, see [super::RpcTickReport::send_pressure].
+    pub dropped_sends: u64,
+    /// Iterative queries started by [super::Rpc::get] or internally.
+    pub queries_started: u64,
+    /// Iterative queries reported in [super::RpcTickReport::done_get_queries].
+    pub queries_completed: u64,
+    /// Queries seeded with the closest nodes of a previous query to the same target.
+    pub cache_hits: u64,
+    /// Queries started without a cached previous query to the same target.
+    pub cache_misses: u64,
+    /// Current number of nodes in the routing tables.
+    pub routing_table_size: usize,
+    /// Current Dht size estimate, see [super::Rpc::dht_size_estimate].
+    pub dht_size_estimate: usize,
+}
+
+impl Metrics {
+    /// Returns the ratio of queries that hit the cache of previous queries,
+    /// or zero if no queries were started yet.
+    pub fn cache_hit_rate(&self) -> f64 {
+        let total = self.cache_hits + self.cache_misses;
+
+        if total == 0 {
+            return 0.0;
+        }
+
+        self.cache_hits as f64 / total as f64
+    }
+}
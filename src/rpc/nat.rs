@@ -0,0 +1,96 @@
+//! Detecting the type of NAT this node is behind, from the public address votes of responders.
+
+use std::collections::HashMap;
+use std::net::SocketAddrV4;
+
+/// Minimum number of votes in a query to classify the NAT.
+const MIN_NAT_VOTES: u16 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// The type of NAT this node is behind, as observed by responding nodes, see [crate::Dht::info].
+pub enum NatType {
+    /// Not enough responses yet to tell.
+    #[default]
+    Unknown,
+    /// Responders agree on our public address, and it is reachable.
+    Open,
+    /// Responders agree on our public address, but it isn't confirmed reachable yet.
+    ConeNat,
+    /// Responders see our public IP with different ports, so the NAT maps every
+    /// destination to a different port, and this node can't be reached by others.
+    ///
+    /// Nodes behind a symmetric NAT never switch to server mode.
+    SymmetricNat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How strongly the responders of the last query agreed on this node's
+/// public address, see [super::Rpc::public_address_confidence].
+pub struct AddressConfidence {
+    /// The address with the most votes.
+    pub address: SocketAddrV4,
+    /// Number of responders that voted for [Self::address].
+    pub votes: u16,
+    /// Number of responders that voted for any other address.
+    pub dissenting: u16,
+}
+
+impl AddressConfidence {
+    pub(crate) fn new(address: SocketAddrV4, votes: &HashMap<SocketAddrV4, u16>) -> Self {
+        let total = votes.values().sum::<u16>();
+        let votes = votes.get(&address).copied().unwrap_or_default();
+
+        Self {
+            address,
+            votes,
+            dissenting: total - votes,
+        }
+    }
+}
+
+/// Returns whether the votes of a single query show a symmetric NAT, where no single
+/// port has the majority of the votes for the most voted IP, or `None` if there
+/// aren't enough votes.
+pub(crate) fn is_symmetric(votes: &HashMap<SocketAddrV4, u16>) -> Option<bool> {
+    let mut ips: HashMap<_, (u16, u16)> = HashMap::new();
+
+    for (address, count) in votes {
+        let (total, max) = ips.entry(*address.ip()).or_default();
+
+        *total += count;
+        *max = (*max).max(*count);
+    }
+
+    let (total, max) = ips.into_values().max_by_key(|(total, _)| *total)?;
+
+    if total < MIN_NAT_VOTES {
+        return None;
+    }
+
+    Some(max * 2 <= total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn symmetric() {
+        let votes = |ports: &[u16]| {
+            let mut votes = HashMap::new();
+
+            for port in ports {
+                *votes
+                    .entry(SocketAddrV4::new([1, 2, 3, 4].into(), *port))
+                    .or_default() += 1;
+            }
+
+            votes
+        };
+
+        assert_eq!(is_symmetric(&votes(&[])), None);
+        assert_eq!(is_symmetric(&votes(&[1, 2])), None);
+        assert_eq!(is_symmetric(&votes(&[1, 1, 1, 2])), Some(false));
+        assert_eq!(is_symmetric(&votes(&[1, 2, 3, 4])), Some(true));
+    }
+}
@@ -1,13 +1,20 @@
+use std::time::{Duration, Instant};
+
 use tracing::{debug, trace};
 
 use crate::{
     common::{
-        ErrorSpecific, Id, PutRequest, PutRequestSpecific, RequestSpecific, RequestTypeSpecific,
+        ErrorSpecific, Id, PutImmutableRequestArguments, PutMutableRequestArguments, PutRequest,
+        PutRequestSpecific, RequestSpecific, RequestTypeSpecific, MAX_SALT_SIZE,
     },
     Node,
 };
 
-use super::socket::KrpcSocket;
+use super::{socket::KrpcSocket, QueryKind, QuerySummary};
+
+/// Default delay before the first retry of a put query that found no nodes to store at,
+/// doubled for every following retry, see [super::config::Config::put_max_retries].
+pub const DEFAULT_PUT_RETRY_BACKOFF: Duration = Duration::from_secs(1);
 
 #[derive(Debug)]
 /// Once an [super::IterativeQuery] is done, or if a previous cached one was a vailable,
@@ -21,10 +28,24 @@ pub struct PutQuery {
     pub request: PutRequestSpecific,
     errors: Vec<(u8, ErrorSpecific)>,
     extra_nodes: Box<[Node]>,
+    /// Number of acks after which this query is considered successful,
+    /// even if some requests are still inflight.
+    ack_threshold: Option<usize>,
+    /// Number of acks below which this query fails, even if some nodes stored the value.
+    min_storage_nodes: usize,
+    /// When this query was created, before waiting for the closest nodes.
+    started_at: Instant,
 }
 
 impl PutQuery {
-    pub fn new(target: Id, request: PutRequestSpecific, extra_nodes: Option<Box<[Node]>>) -> Self {
+    pub fn new(
+        target: Id,
+        request: PutRequestSpecific,
+        extra_nodes: Option<Box<[Node]>>,
+        ack_threshold: Option<usize>,
+        min_storage_nodes: usize,
+        started_at: Instant,
+    ) -> Self {
         Self {
             target,
             stored_at: 0,
@@ -32,6 +53,9 @@ impl PutQuery {
             request,
             errors: Vec::new(),
             extra_nodes: extra_nodes.unwrap_or(Box::new([])),
+            ack_threshold,
+            min_storage_nodes,
+            started_at,
         }
     }
 
@@ -59,7 +83,7 @@ impl PutQuery {
             // Set correct values to the request placeholders
             if let Some(token) = node.token() {
                 let tid = socket.request(
-                    node.address(),
+                    node.socket_address(),
                     RequestSpecific {
                         requester_id: Id::random(),
                         request_type: RequestTypeSpecific::Put(PutRequest {
@@ -76,14 +100,39 @@ impl PutQuery {
         Ok(())
     }
 
+    /// Number of nodes that confirmed storing the value so far.
+    pub fn stored_at(&self) -> u8 {
+        self.stored_at
+    }
+
     pub fn started(&self) -> bool {
         !self.inflight_requests.is_empty()
     }
 
+    /// Transaction ids of all the requests sent by this query.
+    pub fn inflight_requests(&self) -> &[u16] {
+        &self.inflight_requests
+    }
+
     pub fn inflight(&self, tid: u16) -> bool {
         self.inflight_requests.contains(&tid)
     }
 
+    pub fn summary(&self, socket: &KrpcSocket) -> QuerySummary {
+        QuerySummary {
+            target: self.target,
+            kind: QueryKind::Put,
+            elapsed: socket.now().saturating_duration_since(self.started_at),
+            inflight: self
+                .inflight_requests
+                .iter()
+                .filter(|tid| socket.inflight(tid))
+                .count(),
+            candidates: 0,
+            responders: self.stored_at as usize,
+        }
+    }
+
     pub fn success(&mut self) {
         debug!(target = ?self.target, "PutQuery got success response");
         self.stored_at += 1
@@ -138,8 +187,28 @@ impl PutQuery {
                     .unwrap_or(PutQueryError::Timeout.into()));
             }
 
+            if (self.stored_at as usize) < self.min_storage_nodes {
+                debug!(
+                    ?target,
+                    stored_at = ?self.stored_at,
+                    min_storage_nodes = ?self.min_storage_nodes,
+                    "Put Query: stored at too few nodes"
+                );
+
+                return Err(PutQueryError::InsufficientStorageNodes {
+                    stored: self.stored_at as usize,
+                    required: self.min_storage_nodes,
+                })?;
+            }
+
             debug!(?target, stored_at = ?self.stored_at, "PutQuery Done successfully");
 
+            return Ok(true);
+        } else if self.ack_threshold.is_some_and(|threshold| {
+            self.stored_at as usize >= threshold.max(self.min_storage_nodes)
+        }) {
+            debug!(target = ?self.target, stored_at = ?self.stored_at, "PutQuery reached ack threshold");
+
             return Ok(true);
         } else if let Some(most_common_error) = self.majority_nodes_rejected_put_mutable() {
             let target = self.target;
@@ -157,24 +226,26 @@ impl PutQuery {
         Ok(false)
     }
 
-    fn is_done(&self, socket: &KrpcSocket) -> bool {
+    /// Returns true if all requests got responses or timed out.
+    pub fn is_done(&self, socket: &KrpcSocket) -> bool {
         !self
             .inflight_requests
             .iter()
             .any(|&tid| socket.inflight(&tid))
     }
 
-    fn majority_nodes_rejected_put_mutable(&self) -> Option<ConcurrencyError> {
+    fn majority_nodes_rejected_put_mutable(&self) -> Option<PutError> {
         let half = ((self.inflight_requests.len() / 2) + 1) as u8;
 
         if matches!(self.request, PutRequestSpecific::PutMutable(_)) {
             return self.most_common_error().and_then(|(count, error)| {
-                if count >= half {
-                    if let PutError::Concurrency(err) = error {
-                        Some(err)
-                    } else {
-                        None
-                    }
+                if count >= half
+                    && matches!(
+                        error,
+                        PutError::Concurrency(_) | PutError::CasConflict { .. }
+                    )
+                {
+                    Some(error)
                 } else {
                     None
                 }
@@ -188,7 +259,20 @@ impl PutQuery {
         self.errors
             .first()
             .and_then(|(count, error)| match error.code {
-                301 => Some((*count, PutError::from(ConcurrencyError::CasFailed))),
+                // BEP_0044: CAS mismatch
+                301 => Some((
+                    *count,
+                    match &self.request {
+                        PutRequestSpecific::PutMutable(PutMutableRequestArguments {
+                            cas: Some(cas),
+                            ..
+                        }) => PutError::CasConflict {
+                            target: self.target,
+                            expected: *cas,
+                        },
+                        _ => PutError::from(ConcurrencyError::CasFailed),
+                    },
+                )),
                 302 => Some((*count, PutError::from(ConcurrencyError::NotMostRecent))),
                 _ => None,
             })
@@ -205,6 +289,48 @@ pub enum PutError {
     #[error(transparent)]
     /// PutQuery for [crate::MutableItem] errors
     Concurrency(#[from] ConcurrencyError),
+
+    /// Most nodes rejected storing a [crate::MutableItem] because its `cas` didn't match
+    /// the `seq` of the item they have (error code `301`).
+    ///
+    /// Read the most recent item, and retry with its `seq` as the new `cas`.
+    #[error("CAS mismatch for {target}, expected seq {expected} is not the most recent")]
+    CasConflict {
+        /// Target of the PUT query.
+        target: Id,
+        /// The `cas` sent with the request.
+        expected: i64,
+    },
+}
+
+#[derive(thiserror::Error, Debug, Clone)]
+/// Errors of the requests that failed immediately in a [super::Rpc::put_batch], by target.
+#[error("{} of the batched PUT requests failed", .0.len())]
+pub struct PutBatchError(pub Vec<(Id, PutError)>);
+
+/// Check the value and salt sizes of a put request before sending it,
+/// since nodes reject values larger than BEP_0044 limits.
+pub(crate) fn validate_put_request(
+    request: &PutRequestSpecific,
+    max_value_size: usize,
+) -> Result<(), PutError> {
+    let (v, salt) = match request {
+        PutRequestSpecific::PutImmutable(PutImmutableRequestArguments { v, .. }) => (v, None),
+        PutRequestSpecific::PutMutable(PutMutableRequestArguments { v, salt, .. }) => {
+            (v, salt.as_ref())
+        }
+        PutRequestSpecific::AnnouncePeer(_) => return Ok(()),
+    };
+
+    if v.len() > max_value_size {
+        return Err(PutQueryError::ValueTooLarge(v.len()).into());
+    }
+
+    if let Some(salt) = salt.filter(|salt| salt.len() > MAX_SALT_SIZE) {
+        return Err(PutQueryError::SaltTooLarge(salt.len()).into());
+    }
+
+    Ok(())
 }
 
 #[derive(thiserror::Error, Debug, Clone)]
@@ -226,10 +352,38 @@ pub enum PutQueryError {
     /// PutQuery timed out with no responses neither success or errors
     #[error("PutQuery timed out with no responses neither success or errors")]
     Timeout,
+
+    /// The node is shutting down, see [super::Rpc::shutdown], and no longer accepts new queries.
+    #[error("Node is shutting down and no longer accepts new queries")]
+    ShuttingDown,
+
+    /// The value is larger than the maximum value size, see [crate::DhtBuilder::max_value_size],
+    /// so nodes would reject storing it.
+    #[error("Value is {0} bytes, more than the maximum value size")]
+    ValueTooLarge(usize),
+
+    /// The salt of a [crate::MutableItem] is longer than [MAX_SALT_SIZE] bytes,
+    /// so nodes would reject storing it.
+    #[error("Salt is {0} bytes, more than the maximum {MAX_SALT_SIZE} bytes")]
+    SaltTooLarge(usize),
+
+    /// Fewer nodes than [crate::DhtBuilder::min_storage_nodes] acknowledged storing the value.
+    #[error("Stored at {stored} nodes, fewer than the required {required}")]
+    InsufficientStorageNodes {
+        /// Number of nodes that acknowledged storing the value.
+        stored: usize,
+        /// Configured minimum number of nodes.
+        required: usize,
+    },
+
+    /// The lookup that a put from [super::Rpc::put_batch] was waiting for
+    /// was cancelled with [super::Rpc::cancel_get].
+    #[error("The lookup this batched put was waiting for was cancelled")]
+    LookupCancelled,
 }
 
 #[derive(thiserror::Error, Debug, Clone)]
-/// PutQuery for [crate::MutableItem] errors
+/// PutQuery concurrency errors, mostly for [crate::MutableItem]
 pub enum ConcurrencyError {
     /// Trying to PUT mutable items with the same `key`, and `salt` but different `seq`.
     ///
@@ -252,4 +406,10 @@ pub enum ConcurrencyError {
     /// The `CAS` condition does not match the `seq` of the most recent knonw signed item.
     #[error("CAS check failed, try reading most recent item before writing again.")]
     CasFailed,
+
+    /// Another PUT query for the same target is still inflight, and can't be replaced.
+    ///
+    /// Try again once it is reported in [super::RpcTickReport::done_put_queries].
+    #[error("Another PUT query for the same target is inflight, try again once it is done.")]
+    Inflight,
 }
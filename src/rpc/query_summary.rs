@@ -0,0 +1,38 @@
+//! Read-only summaries of the active queries of the [super::Rpc], for introspection.
+
+use std::time::Duration;
+
+use crate::common::Id;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The kind of an active query.
+pub enum QueryKind {
+    /// Iterative `find_node` query.
+    FindNode,
+    /// Iterative `get_peers` query.
+    GetPeers,
+    /// Iterative `get` query for an immutable or mutable value.
+    GetValue,
+    /// `announce_peer` or `put` query, either storing at the closest nodes
+    /// or waiting for the lookup of the closest nodes to finish.
+    Put,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Summary of an active query, see [super::Rpc::active_queries].
+pub struct QuerySummary {
+    /// Target of the query.
+    pub target: Id,
+    /// Kind of the query.
+    pub kind: QueryKind,
+    /// Time since the query started.
+    pub elapsed: Duration,
+    /// Number of requests still awaiting a response.
+    pub inflight: usize,
+    /// Number of candidate nodes closest to the target found so far,
+    /// always zero for [QueryKind::Put].
+    pub candidates: usize,
+    /// Number of nodes that responded so far, or for [QueryKind::Put],
+    /// the number of nodes that confirmed storing the value.
+    pub responders: usize,
+}
@@ -0,0 +1,86 @@
+//! Token bucket rate limiting of incoming requests per source IP.
+
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::time::Instant;
+
+use lru::LruCache;
+
+/// Maximum number of source IPs to keep buckets for.
+const MAX_RATE_LIMITED_IPS: usize = 10_000;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+/// Rate limiter of incoming requests, see [super::config::Config::max_requests_per_ip_per_sec].
+pub(crate) struct RateLimiter {
+    /// Requests allowed per second, also the maximum burst size.
+    rate: u32,
+    buckets: LruCache<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            buckets: LruCache::new(
+                NonZeroUsize::new(MAX_RATE_LIMITED_IPS)
+                    .expect("MAX_RATE_LIMITED_IPS is NonZeroUsize"),
+            ),
+        }
+    }
+
+    /// Returns true if a request from this IP at `now` is within its rate limit, and consumes a token.
+    pub fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+        let rate = self.rate as f64;
+
+        let bucket = self.buckets.get_or_insert_mut(ip, || Bucket {
+            tokens: rate,
+            last_refill: now,
+        });
+
+        let elapsed = now
+            .saturating_duration_since(bucket.last_refill)
+            .as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn refill_over_time() {
+        let mut rate_limiter = RateLimiter::new(2);
+
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let now = Instant::now();
+
+        assert!(rate_limiter.allow(ip, now));
+        assert!(rate_limiter.allow(ip, now));
+        assert!(!rate_limiter.allow(ip, now));
+
+        assert!(rate_limiter.allow(IpAddr::from([127, 0, 0, 2]), now));
+
+        let now = now + Duration::from_millis(500);
+
+        assert!(rate_limiter.allow(ip, now));
+        assert!(!rate_limiter.allow(ip, now));
+    }
+}
@@ -0,0 +1,76 @@
+//! Samples of values rejected by the [super::Rpc], for debugging misbehaving nodes.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use crate::common::{Id, MutableError};
+
+/// Maximum number of [RejectedSample]s kept, dropping the oldest beyond it.
+pub const MAX_REJECTED_SAMPLES: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Why a value was rejected.
+pub enum RejectionReason {
+    /// The immutable value doesn't match the target hash.
+    InvalidImmutable,
+    /// The mutable item failed validation, for example because of an invalid signature.
+    InvalidMutable(MutableError),
+    /// The mutable item has a valid signature, but was rejected by
+    /// the [super::config::Config::mutable_validator].
+    RejectedMutable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A value rejected by the [super::Rpc], see [super::config::Config::capture_rejected].
+pub struct RejectedSample {
+    /// Target of the query.
+    pub target: Id,
+    /// Address of the responding node.
+    pub from: SocketAddr,
+    /// The raw `v` field of the response.
+    pub v: Box<[u8]>,
+    /// Why the value was rejected.
+    pub reason: RejectionReason,
+}
+
+#[derive(Debug, Default)]
+/// Bounded buffer of the most recent [RejectedSample]s.
+pub(crate) struct RejectedSamples(VecDeque<RejectedSample>);
+
+impl RejectedSamples {
+    pub(crate) fn push(&mut self, sample: RejectedSample) {
+        if self.0.len() >= MAX_REJECTED_SAMPLES {
+            self.0.pop_front();
+        }
+
+        self.0.push_back(sample);
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<RejectedSample> {
+        self.0.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bounded() {
+        let mut samples = RejectedSamples::default();
+
+        for i in 0..(MAX_REJECTED_SAMPLES + 10) {
+            samples.push(RejectedSample {
+                target: Id::random(),
+                from: SocketAddr::from(([127, 0, 0, 1], i as u16)),
+                v: [].into(),
+                reason: RejectionReason::InvalidImmutable,
+            });
+        }
+
+        let samples = samples.to_vec();
+
+        assert_eq!(samples.len(), MAX_REJECTED_SAMPLES);
+        assert_eq!(samples[0].from.port(), 10);
+    }
+}
@@ -0,0 +1,132 @@
+//! Round trip times of responses, for the adaptive request timeout, see [super::config::Config::adaptive_timeout].
+
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+/// Smoothed round trip time and its variance, computing a retransmission timeout
+/// like TCP does in [RFC 6298](https://www.rfc-editor.org/rfc/rfc6298), as
+/// `srtt + 4 * rttvar`, bounded by a minimum and a maximum.
+///
+/// The timeout is doubled on every [Self::backoff], until the next sample.
+pub(crate) struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    /// Number of times the timeout was doubled since the last sample.
+    backoff: u32,
+    min: Duration,
+    max: Duration,
+}
+
+impl RttEstimator {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            backoff: 0,
+            min: min.min(max),
+            max,
+        }
+    }
+
+    /// Update the estimates with the round trip time of a response.
+    pub fn sample(&mut self, rtt: Duration) {
+        self.backoff = 0;
+
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let deviation = srtt.max(rtt) - srtt.min(rtt);
+
+                self.rttvar = (self.rttvar * 3 + deviation) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+    }
+
+    /// Double the timeout, up to the maximum, after requests timed out.
+    pub fn backoff(&mut self) {
+        if self.timeout() < self.max {
+            self.backoff = self.backoff.saturating_add(1);
+        }
+    }
+
+    /// Returns the smoothed round trip time, if there were any samples.
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// Returns the current timeout, or the maximum before any samples.
+    pub fn timeout(&self) -> Duration {
+        match self.srtt {
+            None => self.max,
+            Some(srtt) => (srtt + self.rttvar * 4)
+                .clamp(self.min, self.max)
+                .saturating_mul(2u32.saturating_pow(self.backoff))
+                .min(self.max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn converges() {
+        let mut estimator = RttEstimator::new(Duration::from_millis(10), Duration::from_secs(2));
+        assert_eq!(estimator.timeout(), Duration::from_secs(2));
+
+        estimator.sample(Duration::from_millis(100));
+        assert_eq!(estimator.timeout(), Duration::from_millis(300));
+
+        // Alternating between 80ms and 120ms, the mean deviation converges to 20ms.
+        for i in 0..200 {
+            estimator.sample(Duration::from_millis(if i % 2 == 0 { 80 } else { 120 }));
+        }
+
+        let timeout = estimator.timeout();
+        assert!(
+            timeout > Duration::from_millis(170) && timeout < Duration::from_millis(190),
+            "{timeout:?}"
+        );
+
+        // Stable round trips bring the timeout down to the minimum.
+        for _ in 0..200 {
+            estimator.sample(Duration::from_millis(1));
+        }
+        assert_eq!(estimator.timeout(), Duration::from_millis(10));
+
+        // Slow round trips are capped at the maximum.
+        for _ in 0..10 {
+            estimator.sample(Duration::from_secs(5));
+        }
+        assert_eq!(estimator.timeout(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn backoff() {
+        let mut estimator = RttEstimator::new(Duration::from_millis(10), Duration::from_secs(2));
+
+        estimator.sample(Duration::from_millis(100));
+        assert_eq!(estimator.timeout(), Duration::from_millis(300));
+
+        estimator.backoff();
+        assert_eq!(estimator.timeout(), Duration::from_millis(600));
+
+        estimator.backoff();
+        assert_eq!(estimator.timeout(), Duration::from_millis(1200));
+
+        // Capped at the maximum.
+        for _ in 0..100 {
+            estimator.backoff();
+        }
+        assert_eq!(estimator.timeout(), Duration::from_secs(2));
+
+        // The next sample resets the backoff.
+        estimator.sample(Duration::from_millis(100));
+        assert_eq!(estimator.timeout(), Duration::from_millis(250));
+    }
+}
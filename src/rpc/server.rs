@@ -3,7 +3,13 @@
 pub mod peers;
 pub mod tokens;
 
-use std::{fmt::Debug, net::SocketAddrV4, num::NonZeroUsize};
+use std::{
+    fmt::{self, Debug, Formatter},
+    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
+    num::NonZeroUsize,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use dyn_clone::DynClone;
 use lru::LruCache;
@@ -13,9 +19,9 @@ use crate::common::{
     validate_immutable, AnnouncePeerRequestArguments, ErrorSpecific, FindNodeRequestArguments,
     FindNodeResponseArguments, GetImmutableResponseArguments, GetMutableResponseArguments,
     GetPeersRequestArguments, GetPeersResponseArguments, GetValueRequestArguments, Id, MutableItem,
-    NoMoreRecentValueResponseArguments, NoValuesResponseArguments, PingResponseArguments,
+    NoMoreRecentValueResponseArguments, NoValuesResponseArguments, Node, PingResponseArguments,
     PutImmutableRequestArguments, PutMutableRequestArguments, PutRequest, PutRequestSpecific,
-    RequestTypeSpecific, ResponseSpecific, RoutingTable,
+    RequestTypeSpecific, ResponseSpecific, RoutingTable, Want,
 };
 
 use peers::PeersStore;
@@ -36,6 +42,13 @@ pub const MAX_VALUES: usize = 1000;
 pub trait RequestFilter: Send + Sync + Debug + DynClone {
     /// Returns true if the request from this source is allowed.
     fn allow_request(&self, request: &RequestSpecific, from: SocketAddrV4) -> bool;
+
+    /// Returns true if the request from this Ipv6 source is allowed.
+    ///
+    /// Defaults to allowing every request.
+    fn allow_request6(&self, _request: &RequestSpecific, _from: SocketAddrV6) -> bool {
+        true
+    }
 }
 
 dyn_clone::clone_trait_object!(RequestFilter);
@@ -49,6 +62,29 @@ impl RequestFilter for DefaultFilter {
     }
 }
 
+#[derive(Clone)]
+/// A predicate deciding which info_hashes to store announced peers for,
+/// see [ServerSettings::announce_filter].
+pub struct AnnounceFilter(Arc<dyn Fn(&Id) -> bool + Send + Sync>);
+
+impl AnnounceFilter {
+    /// Create a new [AnnounceFilter] from a predicate.
+    pub fn new(filter: impl Fn(&Id) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(filter))
+    }
+
+    /// Returns true if peers announced for this info_hash should be stored.
+    pub fn allow(&self, info_hash: &Id) -> bool {
+        (self.0)(info_hash)
+    }
+}
+
+impl Debug for AnnounceFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "AnnounceFilter")
+    }
+}
+
 #[derive(Debug)]
 /// A server that handles incoming requests.
 ///
@@ -66,6 +102,10 @@ pub struct Server {
     mutable_values: LruCache<Id, MutableItem>,
     /// Filter requests before handling them.
     filter: Box<dyn RequestFilter>,
+    /// Info hashes to store announced peers for.
+    announce_filter: Option<AnnounceFilter>,
+    /// Only answer ping and find_node requests.
+    routing_only: bool,
 }
 
 impl Default for Server {
@@ -97,6 +137,19 @@ pub struct ServerSettings {
     ///
     /// Defaults to a function that always returns true.
     pub filter: Box<dyn RequestFilter>,
+    /// Only store peers announced for info_hashes this filter allows,
+    /// rejecting other announces with error `203`.
+    ///
+    /// Doesn't affect any other requests, including `get_peers`.
+    ///
+    /// Defaults to None, where peers are stored for any info_hash.
+    pub announce_filter: Option<AnnounceFilter>,
+    /// Only answer `ping` and `find_node` requests, so this node is routable,
+    /// but never stores anything, rejecting `get_peers`, `get`, `announce_peer`
+    /// and `put` requests with error `201`.
+    ///
+    /// Defaults to false.
+    pub routing_only: bool,
 }
 
 impl Default for ServerSettings {
@@ -108,6 +161,8 @@ impl Default for ServerSettings {
             max_immutable_values: MAX_VALUES,
 
             filter: Box::new(DefaultFilter),
+            announce_filter: None,
+            routing_only: false,
         }
     }
 }
@@ -136,53 +191,143 @@ impl Server {
                     .unwrap_or(NonZeroUsize::new(MAX_VALUES).expect("MAX_VALUES is NonZeroUsize")),
             ),
             filter: settings.filter,
+            announce_filter: settings.announce_filter,
+            routing_only: settings.routing_only,
         }
     }
 
+    /// Rotate the secret of write tokens every `interval` instead of every 5 minutes.
+    ///
+    /// Tokens from the previous interval are still accepted, but older ones are
+    /// rejected with error `203`.
+    pub fn set_token_rotation_interval(&mut self, interval: Duration) {
+        self.tokens = Tokens::with_rotation_interval(interval);
+    }
+
     /// Returns an optional response or an error for a request.
     ///
     /// Passed to the Rpc to send back to the requester.
+    ///
+    /// Closest nodes are returned from the address families in the request's `want`
+    /// argument as in [BEP_0032](https://www.bittorrent.org/beps/bep_0032.html), or
+    /// otherwise from the address family of the requester, so that Ipv6 requesters
+    /// get `nodes6` and Ipv4 requesters get `nodes`.
+    ///
+    /// The tokens secret is rotated lazily, once its interval passed before `now`.
     pub fn handle_request(
         &mut self,
-        routing_table: &RoutingTable,
-        from: SocketAddrV4,
+        routing_table4: &RoutingTable,
+        routing_table6: Option<&RoutingTable>,
+        from: SocketAddr,
         request: RequestSpecific,
+        now: Instant,
     ) -> Option<MessageType> {
-        if !self.filter.allow_request(&request, from) {
+        let allowed = match from {
+            SocketAddr::V4(from) => self.filter.allow_request(&request, from),
+            SocketAddr::V6(from) => self.filter.allow_request6(&request, from),
+        };
+        if !allowed {
             return None;
         }
 
+        if self.routing_only
+            && !matches!(
+                request.request_type,
+                RequestTypeSpecific::Ping | RequestTypeSpecific::FindNode(_)
+            )
+        {
+            return Some(MessageType::Error(ErrorSpecific {
+                code: 201,
+                description: "Routing only node, not storing".to_string(),
+            }));
+        }
+
         // Lazily rotate secrets before handling a request
-        if self.tokens.should_update() {
-            self.tokens.rotate()
+        if self.tokens.should_update(now) {
+            self.tokens.rotate(now)
         }
 
         let requester_id = request.requester_id;
 
+        let routing_table = match (from, routing_table6) {
+            (SocketAddr::V6(_), Some(routing_table6)) => routing_table6,
+            _ => routing_table4,
+        };
+
         Some(match request.request_type {
             RequestTypeSpecific::Ping => {
                 MessageType::Response(ResponseSpecific::Ping(PingResponseArguments {
                     responder_id: *routing_table.id(),
                 }))
             }
-            RequestTypeSpecific::FindNode(FindNodeRequestArguments { target, .. }) => {
+            RequestTypeSpecific::FindNode(FindNodeRequestArguments { target, want }) => {
                 MessageType::Response(ResponseSpecific::FindNode(FindNodeResponseArguments {
                     responder_id: *routing_table.id(),
-                    nodes: routing_table.closest(target),
+                    nodes: closest_wanted(
+                        routing_table,
+                        routing_table4,
+                        routing_table6,
+                        target,
+                        &want,
+                    ),
                 }))
             }
-            RequestTypeSpecific::GetPeers(GetPeersRequestArguments { info_hash, .. }) => {
-                MessageType::Response(match self.peers.get_random_peers(&info_hash) {
+            RequestTypeSpecific::GetPeers(GetPeersRequestArguments {
+                info_hash,
+                scrape,
+                noseed,
+                want,
+            }) => {
+                let nodes = closest_wanted(
+                    routing_table,
+                    routing_table4,
+                    routing_table6,
+                    info_hash,
+                    &want,
+                );
+
+                // Only return peers of the same address family as the requester.
+                let peers = self
+                    .peers
+                    .get_random_peers(&info_hash, noseed)
+                    .and_then(|peers| {
+                        let peers = peers
+                            .into_iter()
+                            .filter(|peer| peer.is_ipv4() == from.is_ipv4())
+                            .collect::<Vec<_>>();
+
+                        (!peers.is_empty()).then_some(peers)
+                    });
+
+                // Scrape requests are always answered with the bloom filters, even if empty.
+                if scrape {
+                    let (seeders, scraped_peers) = self.peers.scrape(&info_hash);
+
+                    return Some(MessageType::Response(ResponseSpecific::GetPeers(
+                        GetPeersResponseArguments {
+                            responder_id: *routing_table.id(),
+                            token: self.tokens.generate_token(from).into(),
+                            nodes: Some(nodes),
+                            values: peers.unwrap_or_default(),
+                            seeders: Some(seeders),
+                            peers: Some(scraped_peers),
+                        },
+                    )));
+                }
+
+                MessageType::Response(match peers {
                     Some(peers) => ResponseSpecific::GetPeers(GetPeersResponseArguments {
                         responder_id: *routing_table.id(),
                         token: self.tokens.generate_token(from).into(),
-                        nodes: Some(routing_table.closest(info_hash)),
+                        nodes: Some(nodes),
                         values: peers,
+                        seeders: None,
+                        peers: None,
                     }),
                     None => ResponseSpecific::NoValues(NoValuesResponseArguments {
                         responder_id: *routing_table.id(),
                         token: self.tokens.generate_token(from).into(),
-                        nodes: Some(routing_table.closest(info_hash)),
+                        nodes: Some(nodes),
                     }),
                 })
             }
@@ -210,6 +355,7 @@ impl Server {
                     info_hash,
                     port,
                     implied_port,
+                    seed,
                     ..
                 }) => {
                     if !self.tokens.validate(from, &token) {
@@ -227,13 +373,29 @@ impl Server {
                         }));
                     }
 
+                    if let Some(announce_filter) = &self.announce_filter {
+                        if !announce_filter.allow(&info_hash) {
+                            debug!(
+                                ?info_hash,
+                                ?requester_id,
+                                ?from,
+                                "Rejected announce by the announce filter"
+                            );
+
+                            return Some(MessageType::Error(ErrorSpecific {
+                                code: 203,
+                                description: "Info hash not allowed".to_string(),
+                            }));
+                        }
+                    }
+
                     let peer = match implied_port {
                         Some(true) => from,
-                        _ => SocketAddrV4::new(*from.ip(), port),
+                        _ => SocketAddr::new(from.ip(), port),
                     };
 
                     self.peers
-                        .add_peer(info_hash, (&request.requester_id, peer));
+                        .add_peer(info_hash, (&request.requester_id, peer), seed);
 
                     return Some(MessageType::Response(ResponseSpecific::Ping(
                         PingResponseArguments {
@@ -383,7 +545,7 @@ impl Server {
     fn handle_get_mutable(
         &mut self,
         routing_table: &RoutingTable,
-        from: SocketAddrV4,
+        from: SocketAddr,
         target: Id,
         seq: Option<i64>,
     ) -> ResponseSpecific {
@@ -419,3 +581,232 @@ impl Server {
         }
     }
 }
+
+/// Closest nodes to the target from the routing tables of the `want`ed address families,
+/// or from the requester's `routing_table` if `want` is empty.
+fn closest_wanted(
+    routing_table: &RoutingTable,
+    routing_table4: &RoutingTable,
+    routing_table6: Option<&RoutingTable>,
+    target: Id,
+    want: &[Want],
+) -> Box<[Node]> {
+    if want.is_empty() {
+        return routing_table.closest(target);
+    }
+
+    let mut nodes = Vec::new();
+
+    if want.contains(&Want::N4) {
+        nodes.extend(routing_table4.closest(target).into_vec());
+    }
+    if let (true, Some(routing_table6)) = (want.contains(&Want::N6), routing_table6) {
+        nodes.extend(routing_table6.closest(target).into_vec());
+    }
+
+    nodes.into_boxed_slice()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn get_peers(
+        server: &mut Server,
+        from: SocketAddr,
+        info_hash: Id,
+        noseed: bool,
+    ) -> ResponseSpecific {
+        let routing_table = RoutingTable::new(Id::random());
+
+        match server.handle_request(
+            &routing_table,
+            None,
+            from,
+            RequestSpecific {
+                requester_id: Id::random(),
+                request_type: RequestTypeSpecific::GetPeers(GetPeersRequestArguments {
+                    info_hash,
+                    scrape: false,
+                    noseed,
+                    want: vec![],
+                }),
+            },
+            Instant::now(),
+        ) {
+            Some(MessageType::Response(response)) => response,
+            message => panic!("unexpected get_peers response {message:?}"),
+        }
+    }
+
+    fn announce(
+        server: &mut Server,
+        from: SocketAddr,
+        info_hash: Id,
+        seed: bool,
+    ) -> Option<MessageType> {
+        let token = match get_peers(server, from, info_hash, false) {
+            ResponseSpecific::NoValues(NoValuesResponseArguments { token, .. })
+            | ResponseSpecific::GetPeers(GetPeersResponseArguments { token, .. }) => token,
+            response => panic!("unexpected get_peers response {response:?}"),
+        };
+
+        announce_with_token(server, from, info_hash, seed, token, Instant::now())
+    }
+
+    fn announce_with_token(
+        server: &mut Server,
+        from: SocketAddr,
+        info_hash: Id,
+        seed: bool,
+        token: Box<[u8]>,
+        now: Instant,
+    ) -> Option<MessageType> {
+        server.handle_request(
+            &RoutingTable::new(Id::random()),
+            None,
+            from,
+            RequestSpecific {
+                requester_id: Id::random(),
+                request_type: RequestTypeSpecific::Put(PutRequest {
+                    token,
+                    put_request_type: PutRequestSpecific::AnnouncePeer(
+                        AnnouncePeerRequestArguments {
+                            info_hash,
+                            port: 6881,
+                            implied_port: None,
+                            seed,
+                        },
+                    ),
+                }),
+            },
+            now,
+        )
+    }
+
+    #[test]
+    fn announce_filter() {
+        let allowed = Id::random();
+        let rejected = Id::random();
+
+        let mut server = Server::new(ServerSettings {
+            announce_filter: Some(AnnounceFilter::new(move |info_hash| *info_hash == allowed)),
+            ..Default::default()
+        });
+
+        let from = SocketAddr::from(([127, 0, 0, 1], 6881));
+
+        assert!(matches!(
+            announce(&mut server, from, allowed, false),
+            Some(MessageType::Response(ResponseSpecific::Ping(_)))
+        ));
+        assert!(matches!(
+            announce(&mut server, from, rejected, false),
+            Some(MessageType::Error(ErrorSpecific { code: 203, .. }))
+        ));
+
+        assert!(matches!(
+            get_peers(&mut server, from, allowed, false),
+            ResponseSpecific::GetPeers(GetPeersResponseArguments { values, .. }) if values == vec![from]
+        ));
+        assert!(matches!(
+            get_peers(&mut server, from, rejected, false),
+            ResponseSpecific::NoValues(_)
+        ));
+    }
+
+    #[test]
+    fn idle_token_rotation() {
+        let mut server = Server::new(ServerSettings::default());
+
+        let from = SocketAddr::from(([127, 0, 0, 1], 6881));
+        let info_hash = Id::random();
+
+        let token = match get_peers(&mut server, from, info_hash, false) {
+            ResponseSpecific::NoValues(NoValuesResponseArguments { token, .. }) => token,
+            response => panic!("unexpected get_peers response {response:?}"),
+        };
+
+        // Idle for 3 intervals, so the token is older than the grace window.
+        let now = Instant::now() + crate::common::TOKEN_ROTATE_INTERVAL * 3;
+
+        assert!(matches!(
+            announce_with_token(&mut server, from, info_hash, false, token, now),
+            Some(MessageType::Error(ErrorSpecific { code: 203, .. }))
+        ));
+    }
+
+    #[test]
+    fn noseed() {
+        let mut server = Server::new(ServerSettings::default());
+
+        let info_hash = Id::random();
+        let seed = SocketAddr::from(([127, 0, 0, 1], 6881));
+        let leecher = SocketAddr::from(([127, 0, 0, 2], 6881));
+
+        announce(&mut server, seed, info_hash, true);
+        announce(&mut server, leecher, info_hash, false);
+
+        assert!(matches!(
+            get_peers(&mut server, leecher, info_hash, false),
+            ResponseSpecific::GetPeers(GetPeersResponseArguments { values, .. }) if values.len() == 2
+        ));
+        assert!(matches!(
+            get_peers(&mut server, leecher, info_hash, true),
+            ResponseSpecific::GetPeers(GetPeersResponseArguments { values, .. }) if values == vec![leecher]
+        ));
+    }
+
+    #[test]
+    fn routing_only() {
+        let mut server = Server::new(ServerSettings {
+            routing_only: true,
+            ..Default::default()
+        });
+
+        let from = SocketAddr::from(([127, 0, 0, 1], 6881));
+        let routing_table = RoutingTable::from_nodes(Id::random(), vec![Node::random()]);
+
+        assert!(matches!(
+            server.handle_request(
+                &routing_table,
+                None,
+                from,
+                RequestSpecific {
+                    requester_id: Id::random(),
+                    request_type: RequestTypeSpecific::FindNode(FindNodeRequestArguments {
+                        target: Id::random(),
+                        want: vec![],
+                    }),
+                },
+                Instant::now(),
+            ),
+            Some(MessageType::Response(ResponseSpecific::FindNode(FindNodeResponseArguments { nodes, .. }))) if nodes.len() == 1
+        ));
+
+        let announce = server.handle_request(
+            &routing_table,
+            None,
+            from,
+            RequestSpecific {
+                requester_id: Id::random(),
+                request_type: RequestTypeSpecific::Put(PutRequest {
+                    token: vec![0; 4].into(),
+                    put_request_type: PutRequestSpecific::AnnouncePeer(
+                        AnnouncePeerRequestArguments {
+                            info_hash: Id::random(),
+                            port: 6881,
+                            implied_port: None,
+                            seed: false,
+                        },
+                    ),
+                }),
+            },
+            Instant::now(),
+        );
+        assert!(matches!(
+            announce,
+            Some(MessageType::Error(ErrorSpecific { code: 201, .. }))
+        ));
+    }
+}
@@ -1,8 +1,8 @@
 //! Manage announced peers for info_hashes
 
-use std::{net::SocketAddrV4, num::NonZeroUsize};
+use std::{net::SocketAddr, num::NonZeroUsize};
 
-use crate::common::Id;
+use crate::common::{BloomFilter, Id};
 
 use getrandom::getrandom;
 use lru::LruCache;
@@ -14,7 +14,8 @@ const CHANCE_SCALE: f32 = 2.0 * (1u32 << 31) as f32;
 ///
 /// Read [BEP_0005](https://www.bittorrent.org/beps/bep_0005.html) for more information.
 pub struct PeersStore {
-    info_hashes: LruCache<Id, LruCache<Id, SocketAddrV4>>,
+    /// Announced peers, and whether or not they announced themselves as seeds.
+    info_hashes: LruCache<Id, LruCache<Id, (SocketAddr, bool)>>,
     max_peers: NonZeroUsize,
 }
 
@@ -27,44 +28,46 @@ impl PeersStore {
         }
     }
 
-    /// Add a peer for an info hash.
-    pub fn add_peer(&mut self, info_hash: Id, peer: (&Id, SocketAddrV4)) {
+    /// Add a peer for an info hash, and whether or not it is a seed.
+    pub fn add_peer(&mut self, info_hash: Id, peer: (&Id, SocketAddr), seed: bool) {
         if let Some(info_hash_lru) = self.info_hashes.get_mut(&info_hash) {
-            info_hash_lru.put(*peer.0, peer.1);
+            info_hash_lru.put(*peer.0, (peer.1, seed));
         } else {
             let mut info_hash_lru = LruCache::new(self.max_peers);
-            info_hash_lru.put(*peer.0, peer.1);
+            info_hash_lru.put(*peer.0, (peer.1, seed));
             self.info_hashes.put(info_hash, info_hash_lru);
         };
     }
 
-    /// Returns a random set of peers per an info hash.
-    pub fn get_random_peers(&mut self, info_hash: &Id) -> Option<Vec<SocketAddrV4>> {
+    /// Returns a random set of peers per an info hash,
+    /// excluding seeds if `noseed` is true.
+    pub fn get_random_peers(&mut self, info_hash: &Id, noseed: bool) -> Option<Vec<SocketAddr>> {
         if let Some(info_hash_lru) = self.info_hashes.get(info_hash) {
-            let size = info_hash_lru.len();
+            let peers = info_hash_lru
+                .iter()
+                .filter(|(_, (_, seed))| !(noseed && *seed))
+                .map(|(_, (addr, _))| *addr)
+                .collect::<Vec<_>>();
+
+            let size = peers.len();
             let target_size = 20;
 
             if size == 0 {
                 return None;
             }
             if size < target_size {
-                return Some(
-                    info_hash_lru
-                        .iter()
-                        .map(|n| n.1.to_owned())
-                        .collect::<Vec<_>>(),
-                );
+                return Some(peers);
             }
 
             let mut results = Vec::with_capacity(20);
 
-            let mut chunk = vec![0_u8; info_hash_lru.iter().len() * 4];
+            let mut chunk = vec![0_u8; size * 4];
             getrandom(chunk.as_mut_slice()).expect("getrandom");
 
-            for (index, (_, addr)) in info_hash_lru.iter().enumerate() {
+            for (index, addr) in peers.iter().enumerate() {
                 // Calculate the chance of adding the current item based on remaining items and slots
                 let remaining_slots = target_size - results.len();
-                let remaining_items = info_hash_lru.len() - index;
+                let remaining_items = size - index;
                 let current_chance =
                     ((remaining_slots as f32 / remaining_items as f32) * CHANCE_SCALE) as u32;
 
@@ -86,10 +89,31 @@ impl PeersStore {
 
         None
     }
+
+    /// Returns [BEP_0033](https://www.bittorrent.org/beps/bep_0033.html) bloom filters
+    /// of the IPs of the seeds and of the other peers announced for an info hash.
+    pub fn scrape(&self, info_hash: &Id) -> (BloomFilter, BloomFilter) {
+        let mut seeders = BloomFilter::new();
+        let mut peers = BloomFilter::new();
+
+        if let Some(info_hash_lru) = self.info_hashes.peek(info_hash) {
+            for (_, (address, seed)) in info_hash_lru.iter() {
+                if *seed {
+                    seeders.insert(&address.ip());
+                } else {
+                    peers.insert(&address.ip());
+                }
+            }
+        }
+
+        (seeders, peers)
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use std::net::SocketAddrV4;
+
     use super::*;
 
     #[test]
@@ -104,17 +128,25 @@ mod test {
 
         store.add_peer(
             info_hash_a,
-            (&info_hash_a, SocketAddrV4::new([127, 0, 1, 1].into(), 0)),
+            (
+                &info_hash_a,
+                SocketAddrV4::new([127, 0, 1, 1].into(), 0).into(),
+            ),
+            false,
         );
         store.add_peer(
             info_hash_b,
-            (&info_hash_b, SocketAddrV4::new([127, 0, 1, 1].into(), 0)),
+            (
+                &info_hash_b,
+                SocketAddrV4::new([127, 0, 1, 1].into(), 0).into(),
+            ),
+            false,
         );
 
         assert_eq!(store.info_hashes.len(), 1);
         assert_eq!(
-            store.get_random_peers(&info_hash_b),
-            Some([SocketAddrV4::new([127, 0, 1, 1].into(), 0)].into())
+            store.get_random_peers(&info_hash_b, false),
+            Some([SocketAddrV4::new([127, 0, 1, 1].into(), 0).into()].into())
         );
     }
 
@@ -129,23 +161,35 @@ mod test {
 
         store.add_peer(
             info_hash_a,
-            (&info_hash_a, SocketAddrV4::new([127, 0, 1, 1].into(), 0)),
+            (
+                &info_hash_a,
+                SocketAddrV4::new([127, 0, 1, 1].into(), 0).into(),
+            ),
+            false,
         );
         store.add_peer(
             info_hash_a,
-            (&info_hash_b, SocketAddrV4::new([127, 0, 1, 2].into(), 0)),
+            (
+                &info_hash_b,
+                SocketAddrV4::new([127, 0, 1, 2].into(), 0).into(),
+            ),
+            false,
         );
         store.add_peer(
             info_hash_a,
-            (&info_hash_c, SocketAddrV4::new([127, 0, 1, 3].into(), 0)),
+            (
+                &info_hash_c,
+                SocketAddrV4::new([127, 0, 1, 3].into(), 0).into(),
+            ),
+            false,
         );
 
         assert_eq!(
-            store.get_random_peers(&info_hash_a),
+            store.get_random_peers(&info_hash_a, false),
             Some(
                 [
-                    SocketAddrV4::new([127, 0, 1, 3].into(), 0),
-                    SocketAddrV4::new([127, 0, 1, 2].into(), 0),
+                    SocketAddrV4::new([127, 0, 1, 3].into(), 0).into(),
+                    SocketAddrV4::new([127, 0, 1, 2].into(), 0).into(),
                 ]
                 .into()
             )
@@ -164,13 +208,17 @@ mod test {
         for i in 0..200 {
             store.add_peer(
                 info_hash,
-                (&Id::random(), SocketAddrV4::new([127, 0, 1, i].into(), 0)),
+                (
+                    &Id::random(),
+                    SocketAddrV4::new([127, 0, 1, i].into(), 0).into(),
+                ),
+                false,
             )
         }
 
         assert_eq!(store.info_hashes.get(&info_hash).unwrap().len(), 200);
 
-        let sample = store.get_random_peers(&info_hash).unwrap();
+        let sample = store.get_random_peers(&info_hash, false).unwrap();
 
         assert_eq!(sample.len(), 20);
     }
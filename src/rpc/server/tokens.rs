@@ -4,8 +4,8 @@ use crc::{Crc, CRC_32_ISCSI};
 use getrandom::getrandom;
 use std::{
     fmt::{self, Debug, Formatter},
-    net::SocketAddrV4,
-    time::Instant,
+    net::SocketAddr,
+    time::{Duration, Instant},
 };
 
 use tracing::trace;
@@ -16,12 +16,16 @@ const CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
 
 /// Tokens generator.
 ///
+/// The secret is rotated every `rotation_interval`, and tokens generated with the
+/// previous secret are still accepted, so a token is valid for one to two intervals.
+///
 /// Read [BEP_0005](https://www.bittorrent.org/beps/bep_0005.html) for more information.
 #[derive(Clone)]
 pub struct Tokens {
     prev_secret: [u8; SECRET_SIZE],
     curr_secret: [u8; SECRET_SIZE],
     last_updated: Instant,
+    rotation_interval: Duration,
 }
 
 impl Debug for Tokens {
@@ -31,42 +35,55 @@ impl Debug for Tokens {
 }
 
 impl Tokens {
-    /// Create a Tokens generator.
+    /// Create a Tokens generator, rotating its secret every [crate::common::TOKEN_ROTATE_INTERVAL].
     pub fn new() -> Self {
+        Self::with_rotation_interval(crate::common::TOKEN_ROTATE_INTERVAL)
+    }
+
+    /// Create a Tokens generator, rotating its secret every `rotation_interval`.
+    pub fn with_rotation_interval(rotation_interval: Duration) -> Self {
         Tokens {
             prev_secret: random(),
             curr_secret: random(),
             last_updated: Instant::now(),
+            rotation_interval,
         }
     }
 
     // === Public Methods ===
 
-    /// Returns `true` if the current secret needs to be updated after an interval.
-    pub fn should_update(&self) -> bool {
-        self.last_updated.elapsed() > crate::common::TOKEN_ROTATE_INTERVAL
+    /// Returns `true` if the current secret needs to be updated an interval after the last rotation.
+    pub fn should_update(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_updated) > self.rotation_interval
     }
 
-    /// Validate that the token was generated within the past 10 minutes
-    pub fn validate(&mut self, address: SocketAddrV4, token: &[u8]) -> bool {
+    /// Validate that the token was generated with the current or the previous secret.
+    pub fn validate(&mut self, address: SocketAddr, token: &[u8]) -> bool {
         let prev = self.internal_generate_token(address, self.prev_secret);
         let curr = self.internal_generate_token(address, self.curr_secret);
 
         token == curr || token == prev
     }
 
-    /// Rotate the tokens secret.
-    pub fn rotate(&mut self) {
+    /// Rotate the tokens secret, starting the next interval at `now`.
+    ///
+    /// If two or more intervals passed since the last rotation, both secrets are
+    /// replaced, so tokens older than the grace window are never accepted.
+    pub fn rotate(&mut self, now: Instant) {
         trace!("Rotating secrets");
 
-        self.prev_secret = self.curr_secret;
+        if now.saturating_duration_since(self.last_updated) >= self.rotation_interval * 2 {
+            self.prev_secret = random();
+        } else {
+            self.prev_secret = self.curr_secret;
+        }
         self.curr_secret = random();
 
-        self.last_updated = Instant::now();
+        self.last_updated = now;
     }
 
     /// Generates a new token for a remote peer.
-    pub fn generate_token(&mut self, address: SocketAddrV4) -> [u8; 4] {
+    pub fn generate_token(&mut self, address: SocketAddr) -> [u8; 4] {
         self.internal_generate_token(address, self.curr_secret)
     }
 
@@ -74,12 +91,15 @@ impl Tokens {
 
     fn internal_generate_token(
         &mut self,
-        address: SocketAddrV4,
+        address: SocketAddr,
         secret: [u8; SECRET_SIZE],
     ) -> [u8; TOKEN_SIZE] {
         let mut digest = CASTAGNOLI.digest();
 
-        let octets: Box<[u8]> = address.ip().octets().into();
+        let octets: Box<[u8]> = match address {
+            SocketAddr::V4(address) => address.ip().octets().into(),
+            SocketAddr::V6(address) => address.ip().octets().into(),
+        };
 
         digest.update(&octets);
         digest.update(&secret);
@@ -112,9 +132,53 @@ mod test {
     fn valid_tokens() {
         let mut tokens = Tokens::new();
 
-        let address = SocketAddrV4::new([127, 0, 0, 1].into(), 6881);
+        let address = SocketAddr::from(([127, 0, 0, 1], 6881));
         let token = tokens.generate_token(address);
 
         assert!(tokens.validate(address, &token))
     }
+
+    #[test]
+    fn rotate_tokens() {
+        let mut tokens = Tokens::new();
+
+        let address = SocketAddr::from(([127, 0, 0, 1], 6881));
+        let token = tokens.generate_token(address);
+
+        tokens.rotate(Instant::now());
+        let previous = tokens.generate_token(address);
+
+        tokens.rotate(Instant::now());
+
+        assert!(tokens.validate(address, &previous));
+        assert!(!tokens.validate(address, &token));
+    }
+
+    #[test]
+    fn rotate_after_idle_intervals() {
+        let mut tokens = Tokens::new();
+
+        let address = SocketAddr::from(([127, 0, 0, 1], 6881));
+        let token = tokens.generate_token(address);
+
+        tokens.rotate(Instant::now() + crate::common::TOKEN_ROTATE_INTERVAL * 3);
+
+        assert!(!tokens.validate(address, &token));
+    }
+
+    #[test]
+    fn rotation_interval() {
+        let now = Instant::now();
+
+        assert!(!Tokens::new().should_update(now));
+        assert!(Tokens::with_rotation_interval(Duration::ZERO)
+            .should_update(now + Duration::from_millis(1)));
+
+        let mut tokens = Tokens::new();
+        let later = now + crate::common::TOKEN_ROTATE_INTERVAL * 2;
+        assert!(tokens.should_update(later));
+
+        tokens.rotate(later);
+        assert!(!tokens.should_update(later));
+    }
 }
@@ -1,41 +1,95 @@
 //! UDP socket layer managing incoming/outgoing requests and responses.
 
 use std::cmp::Ordering;
-use std::net::{SocketAddr, SocketAddrV4, UdpSocket};
+use std::collections::{HashSet, VecDeque};
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tracing::{debug, error, trace};
-
-use crate::common::{ErrorSpecific, Message, MessageType, RequestSpecific, ResponseSpecific};
-
-use super::config::Config;
-
-const VERSION: [u8; 4] = [82, 83, 0, 4]; // "RS" version 04
+use tracing::{debug, trace};
+
+use crate::common::{
+    DecodeMessageError, ErrorSpecific, FindNodeRequestArguments, GetPeersRequestArguments, Message,
+    MessageType, RequestSpecific, RequestTypeSpecific, ResponseSpecific, Want,
+};
+
+use super::{
+    bandwidth::{Bandwidth, BandwidthStats},
+    config::{Config, RequestTimeouts},
+    rtt::RttEstimator,
+    Clock, Metrics, Transport,
+};
+
+/// Default client identifier and version in outgoing messages, "RS" version 04.
+pub const DEFAULT_CLIENT_VERSION: [u8; 4] = [82, 83, 0, 4];
 const MTU: usize = 2048;
 
 pub const DEFAULT_PORT: u16 = 6881;
 /// Default request timeout before abandoning an inflight request to a non-responding node.
 pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_millis(2000); // 2 seconds
+/// Default lower bound of the adaptive request timeout, see [Config::adaptive_timeout].
+pub const DEFAULT_MIN_REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+/// Default maximum size of values in `get` responses, as specified in [BEP_0044](https://www.bittorrent.org/beps/bep_0044.html).
+pub const DEFAULT_MAX_VALUE_SIZE: usize = 1000;
 pub const READ_TIMEOUT: Duration = Duration::from_millis(10);
+/// Default maximum number of requests awaiting a response at once, across all queries.
+pub const DEFAULT_MAX_INFLIGHT_REQUESTS: usize = u16::MAX as usize;
+/// Maximum number of requests queued while [Config::max_inflight_requests] is reached,
+/// beyond which new requests are dropped.
+pub const MAX_QUEUED_REQUESTS: usize = 1024;
 
 /// A UdpSocket wrapper that formats and correlates DHT requests and responses.
 #[derive(Debug)]
 pub struct KrpcSocket {
     next_tid: u16,
-    socket: UdpSocket,
+    /// Draw random unused transaction ids instead of sequential ones.
+    random_tids: bool,
+    /// The Ipv4 socket, or a custom [Config::transport].
+    socket: Arc<dyn Transport>,
+    /// Optional Ipv6 socket, only bound if [Config::ipv6] is enabled.
+    socket6: Option<UdpSocket>,
     pub(crate) server_mode: bool,
     request_timeout: Duration,
+    /// Round trip times of responses.
+    rtt: RttEstimator,
+    /// See [Config::adaptive_timeout].
+    adaptive_timeout: bool,
+    /// See [Config::timeouts].
+    timeouts: RequestTimeouts,
+    clock: Arc<dyn Clock>,
+    /// Responses with larger values are dropped while decoding.
+    max_value_size: usize,
+    /// The `v` field of outgoing messages, see [Config::client_version].
+    client_version: Option<[u8; 4]>,
     /// We don't need a HashMap, since we know the capacity is `65536` requests.
-    /// Requests are also ordered by their transaction_id and thus sent_at, so lookup is fast.
+    /// Requests are ordered by their sent_at, and unless [Config::random_transaction_ids]
+    /// is enabled, also by their transaction_id, so lookup is fast.
     inflight_requests: Vec<InflightRequest>,
+    /// Transaction ids of the [Self::inflight_requests] and [Self::queued_requests],
+    /// to check whether a transaction id is in use without scanning them.
+    tids: HashSet<u16>,
+    /// See [Config::max_inflight_requests].
+    max_inflight_requests: usize,
+    /// Requests waiting for inflight requests to finish, in the order they were made.
+    queued_requests: VecDeque<(SocketAddr, Message)>,
+    /// Counters of sent and received messages, the rest is filled by the [super::Rpc].
+    metrics: Metrics,
+    /// Set when a request couldn't be sent because the send buffer was full,
+    /// until reset by [Self::take_send_pressure].
+    send_pressure: bool,
+    /// Bytes sent and received, see [Self::bandwidth].
+    bandwidth: Bandwidth,
 
     local_addr: SocketAddrV4,
+    local_addr6: Option<SocketAddrV6>,
 }
 
 #[derive(Debug)]
 pub struct InflightRequest {
     tid: u16,
-    to: SocketAddrV4,
+    to: SocketAddr,
     sent_at: Instant,
+    /// Timeout of this request type, overriding [KrpcSocket::request_timeout], see [Config::timeouts].
+    timeout: Option<Duration>,
 }
 
 impl KrpcSocket {
@@ -43,30 +97,93 @@ impl KrpcSocket {
         let request_timeout = config.request_timeout;
         let port = config.port;
 
-        let socket = if let Some(port) = port {
-            UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port)))?
-        } else {
-            match UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT))) {
-                Ok(socket) => Ok(socket),
-                Err(_) => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))),
-            }?
+        let socket: Arc<dyn Transport> = match &config.transport {
+            Some(transport) => transport.clone(),
+            None => {
+                let socket = if let Some(port) = port {
+                    match UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port))) {
+                        Ok(socket) => socket,
+                        Err(error) if config.port_fallback => {
+                            debug!(
+                                ?error,
+                                port, "Port is unavailable, falling back to a random port"
+                            );
+
+                            UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))?
+                        }
+                        Err(error) => {
+                            return Err(std::io::Error::new(
+                                error.kind(),
+                                format!("port {port}: {error}"),
+                            ))
+                        }
+                    }
+                } else {
+                    match UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT))) {
+                        Ok(socket) => Ok(socket),
+                        Err(_) => UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))),
+                    }?
+                };
+
+                socket.set_read_timeout(Some(READ_TIMEOUT))?;
+
+                Arc::new(socket)
+            }
         };
 
         let local_addr = match socket.local_addr()? {
             SocketAddr::V4(addr) => addr,
-            SocketAddr::V6(_) => unimplemented!("KrpcSocket does not support Ipv6"),
+            SocketAddr::V6(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "KrpcSocket transport must have an Ipv4 local address",
+                ))
+            }
         };
 
-        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+        let (socket6, local_addr6) = if config.ipv6 {
+            // Try to listen on the same port as the Ipv4 socket for consistency.
+            let socket6 = match UdpSocket::bind(SocketAddr::from(([0u16; 8], local_addr.port()))) {
+                Ok(socket) => Ok(socket),
+                Err(_) => UdpSocket::bind(SocketAddr::from(([0u16; 8], 0))),
+            }?;
+
+            let local_addr6 = match socket6.local_addr()? {
+                SocketAddr::V6(addr) => addr,
+                SocketAddr::V4(_) => unreachable!("Ipv6 socket bound to an Ipv4 address"),
+            };
+
+            // Polled before the Ipv4 socket, so it shouldn't block.
+            socket6.set_nonblocking(true)?;
+
+            (Some(socket6), Some(local_addr6))
+        } else {
+            (None, None)
+        };
 
         Ok(Self {
             socket,
+            socket6,
             next_tid: 0,
-            server_mode: config.server_mode,
+            random_tids: config.random_transaction_ids,
+            server_mode: config.server_mode && !config.read_only,
             request_timeout,
+            rtt: RttEstimator::new(config.min_request_timeout, config.request_timeout),
+            adaptive_timeout: config.adaptive_timeout,
+            timeouts: config.timeouts,
+            clock: config.clock.clone(),
+            max_value_size: config.max_value_size,
+            client_version: config.client_version,
             inflight_requests: Vec::with_capacity(u16::MAX as usize),
+            tids: HashSet::new(),
+            max_inflight_requests: config.max_inflight_requests.max(1),
+            queued_requests: VecDeque::new(),
+            metrics: Metrics::default(),
+            send_pressure: false,
+            bandwidth: Bandwidth::default(),
 
             local_addr,
+            local_addr6,
         })
     }
 
@@ -85,36 +202,119 @@ impl KrpcSocket {
 
     // === Getters ===
 
+    /// Returns the current time according to the [Config::clock].
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
     /// Returns the address the server is listening to.
     #[inline]
     pub fn local_addr(&self) -> SocketAddrV4 {
         self.local_addr
     }
 
+    /// Returns the address the Ipv6 socket is listening to, if [Config::ipv6] is enabled.
+    #[inline]
+    pub fn local_addr6(&self) -> Option<SocketAddrV6> {
+        self.local_addr6
+    }
+
+    /// Returns the counters of sent and received messages.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Returns the bytes sent and received so far, and their recent rates.
+    pub fn bandwidth(&self) -> BandwidthStats {
+        self.bandwidth.stats(self.clock.now())
+    }
+
+    /// Returns true if a request was dropped because the send buffer was full
+    /// since the last call, and resets it.
+    pub fn take_send_pressure(&mut self) -> bool {
+        std::mem::take(&mut self.send_pressure)
+    }
+
+    /// Returns true if this socket can send messages to the given address.
+    pub fn supports(&self, address: &SocketAddr) -> bool {
+        address.is_ipv4() || self.socket6.is_some()
+    }
+
+    /// Returns the timeout of inflight requests, which is adapted to the
+    /// round trip times of responses if [Config::adaptive_timeout] is enabled.
+    pub fn request_timeout(&self) -> Duration {
+        if self.adaptive_timeout {
+            self.rtt.timeout()
+        } else {
+            self.request_timeout
+        }
+    }
+
+    /// Returns the smoothed round trip time of responses so far, if any.
+    pub fn average_rtt(&self) -> Option<Duration> {
+        self.rtt.srtt()
+    }
+
+    #[cfg(test)]
+    /// Record the round trip time of a response.
+    pub(crate) fn sample_rtt(&mut self, rtt: Duration) {
+        self.rtt.sample(rtt)
+    }
+
     // === Public Methods ===
 
-    /// Returns true if this message's transaction_id is still inflight
+    /// Returns true if this message's transaction_id is still inflight,
+    /// or queued to be sent once there is room, see [Config::max_inflight_requests].
     pub fn inflight(&self, transaction_id: &u16) -> bool {
+        self.tids.contains(transaction_id)
+    }
+
+    #[cfg(test)]
+    /// Returns the number of requests sent and still awaiting a response.
+    pub(crate) fn inflight_count(&self) -> usize {
+        self.inflight_requests.len()
+    }
+
+    /// Stop waiting for responses to these transaction_ids, freeing their slots.
+    ///
+    /// Late responses to cancelled requests will be ignored.
+    pub fn cancel(&mut self, transaction_ids: &[u16]) {
+        for tid in transaction_ids {
+            self.tids.remove(tid);
+        }
+
         self.inflight_requests
-            .binary_search_by(|request| request.tid.cmp(transaction_id))
-            .is_ok()
+            .retain(|request| !transaction_ids.contains(&request.tid));
+        self.queued_requests
+            .retain(|(_, message)| !transaction_ids.contains(&message.transaction_id));
     }
 
     /// Send a request to the given address and return the transaction_id
-    pub fn request(&mut self, address: SocketAddrV4, request: RequestSpecific) -> u16 {
+    ///
+    /// If [Config::max_inflight_requests] is reached, the request is queued and sent
+    /// once earlier requests are done, or dropped if [MAX_QUEUED_REQUESTS] are already
+    /// queued, in which case the transaction_id is never [Self::inflight].
+    pub fn request(&mut self, address: SocketAddr, request: RequestSpecific) -> u16 {
+        self.metrics.requests_sent.increment(&request.request_type);
+
         let message = self.request_message(request);
-        trace!(context = "socket_message_sending", message = ?message);
+        let tid = message.transaction_id;
 
-        self.inflight_requests.push(InflightRequest {
-            tid: message.transaction_id,
-            to: address,
-            sent_at: Instant::now(),
-        });
+        if !self.queued_requests.is_empty()
+            || self.inflight_requests.len() >= self.max_inflight_requests
+        {
+            if self.queued_requests.len() < MAX_QUEUED_REQUESTS {
+                trace!(context = "socket_message_queueing", message = ?message);
+                self.tids.insert(tid);
+                self.queued_requests.push_back((address, message));
+            } else {
+                debug!(?address, tid, "Dropping request, too many queued requests");
+            }
 
-        let tid = message.transaction_id;
-        let _ = self.send(address, message).map_err(|e| {
-            debug!(?e, "Error sending request message");
-        });
+            return tid;
+        }
+
+        self.send_request(address, message);
 
         tid
     }
@@ -122,7 +322,7 @@ impl KrpcSocket {
     /// Send a response to the given address.
     pub fn response(
         &mut self,
-        address: SocketAddrV4,
+        address: SocketAddr,
         transaction_id: u16,
         response: ResponseSpecific,
     ) {
@@ -135,7 +335,7 @@ impl KrpcSocket {
     }
 
     /// Send an error to the given address.
-    pub fn error(&mut self, address: SocketAddrV4, transaction_id: u16, error: ErrorSpecific) {
+    pub fn error(&mut self, address: SocketAddr, transaction_id: u16, error: ErrorSpecific) {
         let message = self.response_message(MessageType::Error(error), address, transaction_id);
         let _ = self.send(address, message).map_err(|e| {
             debug!(?e, "Error sending error message");
@@ -144,28 +344,73 @@ impl KrpcSocket {
 
     /// Receives a single krpc message on the socket.
     /// On success, returns the dht message and the origin.
-    pub fn recv_from(&mut self) -> Option<(Message, SocketAddrV4)> {
+    ///
+    /// If [Config::ipv6] is enabled, the Ipv6 socket is polled first without blocking.
+    pub fn recv_from(&mut self) -> Option<(Message, SocketAddr)> {
         let mut buf = [0u8; MTU];
 
         // Cleanup timed-out transaction_ids.
         // Find the first timedout request, and delete all earlier requests.
-        match self.inflight_requests.binary_search_by(|request| {
-            if request.sent_at.elapsed() > self.request_timeout {
-                Ordering::Less
-            } else {
-                Ordering::Greater
-            }
-        }) {
-            Ok(index) => {
-                self.inflight_requests.drain(..index);
-            }
-            Err(index) => {
-                self.inflight_requests.drain(..index);
+        let now = self.clock.now();
+        let request_timeout = self.request_timeout();
+        if self.timeouts.is_empty() {
+            match self.inflight_requests.binary_search_by(|request| {
+                if now.saturating_duration_since(request.sent_at) > request_timeout {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }) {
+                Ok(index) | Err(index) => {
+                    self.metrics.timeouts += index as u64;
+
+                    if index > 0 {
+                        self.rtt.backoff();
+                    }
+
+                    for request in self.inflight_requests.drain(..index) {
+                        self.tids.remove(&request.tid);
+                    }
+                }
+            };
+        } else {
+            // Requests with different timeouts aren't ordered by their deadlines.
+            let before = self.inflight_requests.len();
+            let tids = &mut self.tids;
+            let mut backoff = false;
+            self.inflight_requests.retain(|request| {
+                let inflight = now.saturating_duration_since(request.sent_at)
+                    <= request.timeout.unwrap_or(request_timeout);
+
+                if !inflight {
+                    tids.remove(&request.tid);
+                    // Only requests using the adaptive timeout back it off.
+                    backoff |= request.timeout.is_none();
+                }
+
+                inflight
+            });
+            self.metrics.timeouts += (before - self.inflight_requests.len()) as u64;
+
+            if backoff {
+                self.rtt.backoff();
             }
+        }
+
+        self.send_queued_requests();
+
+        let received = match self
+            .socket6
+            .as_ref()
+            .and_then(|socket6| socket6.recv_from(&mut buf).ok())
+        {
+            Some(received) => Ok(received),
+            None => self.socket.recv_from(&mut buf),
         };
 
-        if let Ok((amt, SocketAddr::V4(from))) = self.socket.recv_from(&mut buf) {
+        if let Ok((amt, from)) = received {
             let bytes = &buf[..amt];
+            self.bandwidth.received(self.clock.now(), amt);
 
             if from.port() == 0 {
                 trace!(
@@ -175,11 +420,17 @@ impl KrpcSocket {
                 return None;
             }
 
-            match Message::from_bytes(bytes) {
+            match Message::from_bytes(bytes, self.max_value_size) {
                 Ok(message) => {
                     // Parsed correctly.
-                    let should_return = match message.message_type {
-                        MessageType::Request(_) => {
+                    self.bandwidth.received_message(&message.message_type, amt);
+
+                    let should_return = match &message.message_type {
+                        MessageType::Request(request) => {
+                            self.metrics
+                                .requests_received
+                                .increment(&request.request_type);
+
                             trace!(
                                 context = "socket_message_receiving",
                                 ?message,
@@ -212,9 +463,20 @@ impl KrpcSocket {
                     };
 
                     if should_return {
+                        if !matches!(message.message_type, MessageType::Request(_)) {
+                            self.metrics.responses_received += 1;
+                        }
+
                         return Some((message, from));
                     }
                 }
+                Err(error @ DecodeMessageError::ValueTooLarge(_)) => {
+                    debug!(
+                        ?error,
+                        ?from,
+                        "Dropping response with a value that is too large"
+                    );
+                }
                 Err(error) => {
                     trace!(context = "socket_error", ?error, ?from, message = ?String::from_utf8_lossy(bytes), "Received invalid Bencode message.");
                 }
@@ -226,21 +488,71 @@ impl KrpcSocket {
 
     // === Private Methods ===
 
-    fn is_expected_response(&mut self, message: &Message, from: &SocketAddrV4) -> bool {
+    /// Send a request message and track it as inflight.
+    fn send_request(&mut self, address: SocketAddr, message: Message) {
+        trace!(context = "socket_message_sending", message = ?message);
+
+        let timeout = match &message.message_type {
+            MessageType::Request(request) => self.timeouts.get(&request.request_type),
+            _ => None,
+        };
+
+        self.push_inflight(InflightRequest {
+            tid: message.transaction_id,
+            to: address,
+            sent_at: self.clock.now(),
+            timeout,
+        });
+
+        match self.send(address, message) {
+            Ok(()) => {}
+            Err(SendMessageError::IO(error)) if is_send_buffer_full(&error) => {
+                debug!(?address, "Dropping request, send buffer is full");
+
+                self.metrics.dropped_sends += 1;
+                self.send_pressure = true;
+            }
+            Err(e) => {
+                debug!(?e, "Error sending request message");
+            }
+        }
+    }
+
+    /// Track a sent request until it gets a response, times out, or is cancelled.
+    fn push_inflight(&mut self, request: InflightRequest) {
+        self.tids.insert(request.tid);
+        self.inflight_requests.push(request);
+    }
+
+    /// Send queued requests, in order, as long as there is room for more inflight requests.
+    fn send_queued_requests(&mut self) {
+        while self.inflight_requests.len() < self.max_inflight_requests {
+            match self.queued_requests.pop_front() {
+                Some((address, message)) => self.send_request(address, message),
+                None => break,
+            }
+        }
+    }
+
+    fn is_expected_response(&mut self, message: &Message, from: &SocketAddr) -> bool {
         // Positive or an error response or to an inflight request.
-        match self
-            .inflight_requests
-            .binary_search_by(|request| request.tid.cmp(&message.transaction_id))
-        {
-            Ok(index) => {
+        match self.inflight_index(message.transaction_id) {
+            Some(index) => {
                 let inflight_request = self
                     .inflight_requests
                     .get(index)
                     .expect("should be infallible");
 
                 if compare_socket_addr(&inflight_request.to, from) {
+                    self.rtt.sample(
+                        self.clock
+                            .now()
+                            .saturating_duration_since(inflight_request.sent_at),
+                    );
+
                     // Confirm that it is a response we actually sent.
                     self.inflight_requests.remove(index);
+                    self.tids.remove(&message.transaction_id);
 
                     return true;
                 } else {
@@ -250,7 +562,7 @@ impl KrpcSocket {
                     );
                 }
             }
-            Err(_) => {
+            None => {
                 trace!(
                     context = "socket_validation",
                     message = "Unexpected response id"
@@ -261,8 +573,66 @@ impl KrpcSocket {
         false
     }
 
-    /// Increments self.next_tid and returns the previous value.
+    /// Returns the index of the inflight request with this transaction_id.
+    fn inflight_index(&self, tid: u16) -> Option<usize> {
+        if !self.tids.contains(&tid) {
+            return None;
+        }
+
+        if self.random_tids {
+            self.inflight_requests
+                .iter()
+                .position(|request| request.tid == tid)
+        } else {
+            self.inflight_requests
+                .binary_search_by(|request| request.tid.cmp(&tid))
+                .ok()
+        }
+    }
+
+    /// Returns a random transaction_id that is neither inflight nor queued.
+    ///
+    /// If all transaction_ids are taken, the oldest inflight request,
+    /// or otherwise the oldest queued one, is abandoned to reuse its transaction_id.
+    fn random_tid(&mut self) -> u16 {
+        if self.tids.len() > u16::MAX as usize {
+            let tid = if self.inflight_requests.is_empty() {
+                self.queued_requests
+                    .pop_front()
+                    .map(|(_, message)| message.transaction_id)
+            } else {
+                Some(self.inflight_requests.remove(0).tid)
+            };
+
+            if let Some(tid) = tid {
+                debug!(
+                    tid,
+                    "Abandoning the oldest request to reuse its transaction_id"
+                );
+                self.tids.remove(&tid);
+
+                return tid;
+            }
+        }
+
+        loop {
+            let mut bytes = [0_u8; 2];
+            getrandom::getrandom(&mut bytes).expect("getrandom");
+            let tid = u16::from_be_bytes(bytes);
+
+            if !self.inflight(&tid) {
+                return tid;
+            }
+        }
+    }
+
+    /// Increments self.next_tid and returns the previous value,
+    /// or a random unused transaction_id if [Config::random_transaction_ids] is enabled.
     fn tid(&mut self) -> u16 {
+        if self.random_tids {
+            return self.random_tid();
+        }
+
         // We don't bother much with reusing freed transaction ids,
         // since the timeout is so short we are unlikely to run out
         // of 65535 ids in 2 seconds.
@@ -272,13 +642,27 @@ impl KrpcSocket {
     }
 
     /// Set transactin_id, version and read_only
-    fn request_message(&mut self, message: RequestSpecific) -> Message {
+    fn request_message(&mut self, mut message: RequestSpecific) -> Message {
         let transaction_id = self.tid();
 
+        // Unless explicitly set, only ask for nodes we can reach.
+        if let RequestTypeSpecific::FindNode(FindNodeRequestArguments { want, .. })
+        | RequestTypeSpecific::GetPeers(GetPeersRequestArguments { want, .. }) =
+            &mut message.request_type
+        {
+            if want.is_empty() {
+                want.push(Want::N4);
+
+                if self.socket6.is_some() {
+                    want.push(Want::N6);
+                }
+            }
+        }
+
         Message {
             transaction_id,
             message_type: MessageType::Request(message),
-            version: Some(VERSION),
+            version: self.client_version,
             read_only: !self.server_mode,
             requester_ip: None,
         }
@@ -288,13 +672,13 @@ impl KrpcSocket {
     fn response_message(
         &mut self,
         message: MessageType,
-        requester_ip: SocketAddrV4,
+        requester_ip: SocketAddr,
         request_tid: u16,
     ) -> Message {
         Message {
             transaction_id: request_tid,
             message_type: message,
-            version: Some(VERSION),
+            version: self.client_version,
             read_only: !self.server_mode,
             // BEP_0042 Only relevant in responses.
             requester_ip: Some(requester_ip),
@@ -302,8 +686,18 @@ impl KrpcSocket {
     }
 
     /// Send a raw dht message
-    fn send(&mut self, address: SocketAddrV4, message: Message) -> Result<(), SendMessageError> {
-        self.socket.send_to(&message.to_bytes()?, address)?;
+    fn send(&mut self, address: SocketAddr, message: Message) -> Result<(), SendMessageError> {
+        let bytes = message.to_bytes()?;
+
+        match (address, &self.socket6) {
+            (SocketAddr::V4(_), _) => self.socket.send_to(&bytes, address)?,
+            (SocketAddr::V6(_), Some(socket6)) => socket6.send_to(&bytes, address)?,
+            (SocketAddr::V6(_), None) => return Err(SendMessageError::Ipv6Disabled),
+        };
+
+        self.bandwidth
+            .sent(self.clock.now(), &message.message_type, bytes.len());
+
         trace!(context = "socket_message_sending", message = ?message);
         Ok(())
     }
@@ -319,28 +713,48 @@ pub enum SendMessageError {
     #[error(transparent)]
     /// Transparent [std::io::Error]
     IO(#[from] std::io::Error),
+
+    /// Tried to send a message to an Ipv6 address without an Ipv6 socket.
+    #[error("Can't send to an Ipv6 address, Ipv6 is disabled")]
+    Ipv6Disabled,
 }
 
-// Same as SocketAddr::eq but ignores the ip if it is unspecified for testing reasons.
-fn compare_socket_addr(a: &SocketAddrV4, b: &SocketAddrV4) -> bool {
-    if a.port() != b.port() {
+/// Returns true if the error means the OS send buffer is full,
+/// either as `WouldBlock` or as `ENOBUFS`, which has no [std::io::ErrorKind].
+fn is_send_buffer_full(error: &std::io::Error) -> bool {
+    const ENOBUFS: i32 = if cfg!(any(target_os = "linux", target_os = "android")) {
+        105
+    } else if cfg!(windows) {
+        10055
+    } else {
+        55
+    };
+
+    error.kind() == std::io::ErrorKind::WouldBlock || error.raw_os_error() == Some(ENOBUFS)
+}
+
+/// Same as SocketAddr::eq, except that requests sent to an unspecified ip (like `0.0.0.0`),
+/// which only reach the local host, are answered from a loopback ip.
+fn compare_socket_addr(to: &SocketAddr, from: &SocketAddr) -> bool {
+    if to.port() != from.port() {
         return false;
     }
 
-    if a.ip().is_unspecified() {
-        return true;
+    if to.ip().is_unspecified() {
+        return from.ip().is_loopback();
     }
 
-    a.ip() == b.ip()
+    to.ip() == from.ip()
 }
 
 #[cfg(test)]
 mod test {
-    use std::thread;
+    use std::{net::UdpSocket, thread};
 
-    use crate::common::{Id, PingResponseArguments, RequestTypeSpecific};
+    use crate::common::{GetValueRequestArguments, Id, PingResponseArguments, RequestTypeSpecific};
 
     use super::*;
+    use crate::rpc::{MessageBytes, BANDWIDTH_RATE_WINDOW};
 
     #[test]
     fn tid() {
@@ -377,13 +791,17 @@ mod test {
                 assert_eq!(from.port(), client_address.port());
                 assert_eq!(message.transaction_id, 120);
                 assert!(message.read_only, "Read-only should be true");
-                assert_eq!(message.version, Some(VERSION), "Version should be 'RS'");
+                assert_eq!(
+                    message.version,
+                    Some(DEFAULT_CLIENT_VERSION),
+                    "Version should be 'RS'"
+                );
                 assert_eq!(message.message_type, MessageType::Request(expected_request));
                 break;
             }
         });
 
-        client.request(server_address, request);
+        client.request(server_address.into(), request);
 
         server_thread.join().unwrap();
     }
@@ -404,17 +822,22 @@ mod test {
             tx.send(server_address).unwrap();
 
             loop {
-                server.inflight_requests.push(InflightRequest {
+                server.push_inflight(InflightRequest {
                     tid: 8,
-                    to: client_address,
+                    to: client_address.into(),
                     sent_at: Instant::now(),
+                    timeout: None,
                 });
 
                 if let Some((message, from)) = server.recv_from() {
                     assert_eq!(from.port(), client_address.port());
                     assert_eq!(message.transaction_id, 8);
                     assert!(message.read_only, "Read-only should be true");
-                    assert_eq!(message.version, Some(VERSION), "Version should be 'RS'");
+                    assert_eq!(
+                        message.version,
+                        Some(DEFAULT_CLIENT_VERSION),
+                        "Version should be 'RS'"
+                    );
                     assert_eq!(
                         message.message_type,
                         MessageType::Response(ResponseSpecific::Ping(PingResponseArguments {
@@ -428,7 +851,7 @@ mod test {
 
         let server_address = rx.recv().unwrap();
 
-        client.response(server_address, 8, response);
+        client.response(server_address.into(), 8, response);
 
         server_thread.join().unwrap();
     }
@@ -442,10 +865,11 @@ mod test {
 
         let client_address = client.local_addr();
 
-        server.inflight_requests.push(InflightRequest {
+        server.push_inflight(InflightRequest {
             tid: 8,
-            to: SocketAddrV4::new([127, 0, 0, 1].into(), client_address.port() + 1),
+            to: SocketAddrV4::new([127, 0, 0, 1].into(), client_address.port() + 1).into(),
             sent_at: Instant::now(),
+            timeout: None,
         });
 
         let response = ResponseSpecific::Ping(PingResponseArguments {
@@ -462,8 +886,403 @@ mod test {
             );
         });
 
-        client.response(server_address, 8, response);
+        client.response(server_address.into(), 8, response);
 
         server_thread.join().unwrap();
     }
+
+    #[test]
+    fn ignore_response_from_wrong_ip() {
+        let mut server = KrpcSocket::client().unwrap();
+        let server_address = server.local_addr();
+
+        let mut client = KrpcSocket::client().unwrap();
+
+        let client_address = client.local_addr();
+
+        // Same port, but a different ip than the one the response comes from.
+        server.push_inflight(InflightRequest {
+            tid: 8,
+            to: SocketAddrV4::new([127, 0, 0, 2].into(), client_address.port()).into(),
+            sent_at: Instant::now(),
+            timeout: None,
+        });
+
+        client.response(
+            server_address.into(),
+            8,
+            ResponseSpecific::Ping(PingResponseArguments {
+                responder_id: Id::random(),
+            }),
+        );
+
+        thread::sleep(Duration::from_millis(5));
+        assert!(server.recv_from().is_none());
+        assert!(server.inflight(&8));
+    }
+
+    #[test]
+    fn adaptive_timeout() {
+        let mut server = KrpcSocket::client().unwrap();
+        let server_address = server.local_addr();
+
+        let mut client = KrpcSocket::new(&Config {
+            adaptive_timeout: true,
+            min_request_timeout: Duration::from_millis(10),
+            ..Default::default()
+        })
+        .unwrap();
+        let client_address = client.local_addr();
+
+        assert_eq!(client.request_timeout(), DEFAULT_REQUEST_TIMEOUT);
+
+        client.push_inflight(InflightRequest {
+            tid: 8,
+            to: server_address.into(),
+            sent_at: Instant::now() - Duration::from_millis(100),
+            timeout: None,
+        });
+
+        server.response(
+            client_address.into(),
+            8,
+            ResponseSpecific::Ping(PingResponseArguments {
+                responder_id: Id::random(),
+            }),
+        );
+
+        thread::sleep(Duration::from_millis(5));
+        assert!(client.recv_from().is_some());
+
+        // srtt + 4 * rttvar of the first sample is 3 times the round trip time.
+        let timeout = client.request_timeout();
+        assert!(
+            timeout >= Duration::from_millis(300) && timeout < Duration::from_millis(400),
+            "{timeout:?}"
+        );
+
+        // A timed out request doubles the timeout.
+        client.push_inflight(InflightRequest {
+            tid: 9,
+            to: server_address.into(),
+            sent_at: Instant::now() - Duration::from_secs(1),
+            timeout: None,
+        });
+
+        assert!(client.recv_from().is_none());
+        assert!(!client.inflight(&9));
+        assert_eq!(client.request_timeout(), timeout * 2);
+    }
+
+    #[test]
+    fn request_type_timeouts() {
+        // Bound but never responds.
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_address = silent.local_addr().unwrap();
+
+        let mut client = KrpcSocket::new(&Config {
+            timeouts: RequestTimeouts {
+                ping: Some(Duration::from_millis(10)),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        let get = client.request(
+            silent_address,
+            RequestSpecific {
+                requester_id: Id::random(),
+                request_type: RequestTypeSpecific::GetValue(GetValueRequestArguments {
+                    target: Id::random(),
+                    seq: None,
+                    salt: None,
+                }),
+            },
+        );
+        let ping = client.request(
+            silent_address,
+            RequestSpecific {
+                requester_id: Id::random(),
+                request_type: RequestTypeSpecific::Ping,
+            },
+        );
+
+        thread::sleep(Duration::from_millis(20));
+        client.recv_from();
+
+        assert!(!client.inflight(&ping));
+        assert!(client.inflight(&get));
+        assert_eq!(client.metrics().timeouts, 1);
+    }
+
+    #[test]
+    fn inflight_tids() {
+        // Bound but never responds.
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_address = silent.local_addr().unwrap();
+
+        let mut client = KrpcSocket::new(&Config {
+            request_timeout: Duration::from_millis(10),
+            max_inflight_requests: 2,
+            random_transaction_ids: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Two inflight requests, and two queued ones.
+        let tids = (0..4)
+            .map(|_| {
+                client.request(
+                    silent_address,
+                    RequestSpecific {
+                        requester_id: Id::random(),
+                        request_type: RequestTypeSpecific::Ping,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert!(tids.iter().all(|tid| client.inflight(tid)));
+        assert_eq!(client.tids.len(), 4);
+
+        client.cancel(&[tids[0], tids[3]]);
+        assert_eq!(client.tids, HashSet::from([tids[1], tids[2]]));
+
+        // The inflight request times out, and the queued one is sent.
+        thread::sleep(Duration::from_millis(20));
+        client.recv_from();
+        assert_eq!(client.tids, HashSet::from([tids[2]]));
+
+        thread::sleep(Duration::from_millis(20));
+        client.recv_from();
+        assert!(client.tids.is_empty());
+    }
+
+    #[test]
+    fn random_tids_exhausted() {
+        // Bound but never responds.
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_address = silent.local_addr().unwrap();
+
+        let mut client = KrpcSocket::new(&Config {
+            max_inflight_requests: 2,
+            random_transaction_ids: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let ping = || RequestSpecific {
+            requester_id: Id::random(),
+            request_type: RequestTypeSpecific::Ping,
+        };
+
+        // Every transaction_id but one is inflight, and the last one is queued.
+        for tid in 1..=u16::MAX {
+            client.push_inflight(InflightRequest {
+                tid,
+                to: silent_address,
+                sent_at: Instant::now(),
+                timeout: None,
+            });
+        }
+        let queued = client.request(silent_address, ping());
+        assert_eq!(queued, 0);
+        assert_eq!(client.tids.len(), u16::MAX as usize + 1);
+
+        // The oldest inflight request is abandoned.
+        let tid = client.request(silent_address, ping());
+        assert_eq!(tid, 1);
+        assert_eq!(client.inflight_requests.len(), u16::MAX as usize - 1);
+        assert_eq!(client.tids.len(), u16::MAX as usize + 1);
+        assert!(client.inflight(&tid));
+    }
+
+    #[test]
+    fn compare_unspecified_ip() {
+        let to = SocketAddr::from(([0, 0, 0, 0], 6881));
+
+        assert!(compare_socket_addr(
+            &to,
+            &SocketAddr::from(([127, 0, 0, 1], 6881))
+        ));
+        assert!(!compare_socket_addr(
+            &to,
+            &SocketAddr::from(([1, 1, 1, 1], 6881))
+        ));
+        assert!(!compare_socket_addr(
+            &to,
+            &SocketAddr::from(([127, 0, 0, 1], 6882))
+        ));
+    }
+
+    #[test]
+    fn random_tids() {
+        let mut client = KrpcSocket::new(&Config {
+            random_transaction_ids: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Bound but never responds.
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_address = silent.local_addr().unwrap();
+
+        let tids = (0..10_000)
+            .map(|_| {
+                client.request(
+                    silent_address,
+                    RequestSpecific {
+                        requester_id: Id::random(),
+                        request_type: RequestTypeSpecific::Ping,
+                    },
+                )
+            })
+            .collect::<std::collections::HashSet<_>>();
+
+        assert_eq!(tids.len(), 10_000);
+        assert_eq!(client.inflight_requests.len(), 10_000);
+        assert!(tids.iter().all(|tid| client.inflight(tid)));
+
+        // Not sequential
+        assert!((0..100).any(|tid| !tids.contains(&tid)));
+    }
+
+    #[test]
+    fn random_tids_response() {
+        let mut server = KrpcSocket::server().unwrap();
+        let server_address = server.local_addr();
+
+        let mut client = KrpcSocket::new(&Config {
+            random_transaction_ids: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let tid = client.request(
+            server_address.into(),
+            RequestSpecific {
+                requester_id: Id::random(),
+                request_type: RequestTypeSpecific::Ping,
+            },
+        );
+
+        let (message, from) = loop {
+            if let Some(received) = server.recv_from() {
+                break received;
+            }
+        };
+        assert_eq!(message.transaction_id, tid);
+
+        let responder_id = Id::random();
+        server.response(
+            from,
+            message.transaction_id,
+            ResponseSpecific::Ping(PingResponseArguments { responder_id }),
+        );
+
+        let (message, _) = loop {
+            if let Some(received) = client.recv_from() {
+                break received;
+            }
+        };
+        assert_eq!(message.transaction_id, tid);
+        assert!(!client.inflight(&tid));
+    }
+
+    #[test]
+    fn client_version() {
+        let request = || RequestSpecific {
+            requester_id: Id::random(),
+            request_type: RequestTypeSpecific::Ping,
+        };
+
+        let mut socket = KrpcSocket::new(&Config {
+            client_version: Some(*b"MLxx"),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let bytes = socket.request_message(request()).to_bytes().unwrap();
+        assert!(bytes.windows(7).any(|window| window == b"1:v4:ML"));
+
+        let mut socket = KrpcSocket::new(&Config {
+            client_version: None,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let bytes = socket.request_message(request()).to_bytes().unwrap();
+        assert!(!bytes.windows(3).any(|window| window == b"1:v"));
+    }
+
+    #[test]
+    fn bandwidth() {
+        let mut server = KrpcSocket::server().unwrap();
+        let server_address = server.local_addr();
+        let mut client = KrpcSocket::client().unwrap();
+
+        client.request(
+            server_address.into(),
+            RequestSpecific {
+                requester_id: Id::random(),
+                request_type: RequestTypeSpecific::Ping,
+            },
+        );
+
+        let (request, from) = loop {
+            if let Some(received) = server.recv_from() {
+                break received;
+            }
+        };
+        let request_size = request.to_bytes().unwrap().len() as u64;
+
+        server.response(
+            from,
+            request.transaction_id,
+            ResponseSpecific::Ping(PingResponseArguments {
+                responder_id: Id::random(),
+            }),
+        );
+
+        let (response, _) = loop {
+            if let Some(received) = client.recv_from() {
+                break received;
+            }
+        };
+        let response_size = response.to_bytes().unwrap().len() as u64;
+
+        let client_stats = client.bandwidth();
+        assert_eq!(client_stats.bytes_sent, request_size);
+        assert_eq!(client_stats.sent.ping, request_size);
+        assert_eq!(client_stats.bytes_received, response_size);
+        assert_eq!(client_stats.received.responses, response_size);
+        assert_eq!(
+            client_stats.send_rate,
+            request_size as f64 / BANDWIDTH_RATE_WINDOW.as_secs_f64()
+        );
+
+        let server_stats = server.bandwidth();
+        assert_eq!(server_stats.bytes_received, request_size);
+        assert_eq!(server_stats.received.ping, request_size);
+        assert_eq!(server_stats.bytes_sent, response_size);
+        assert_eq!(server_stats.sent.responses, response_size);
+
+        // Invalid messages are counted in the total, but not by type.
+        let garbage = UdpSocket::bind("127.0.0.1:0").unwrap();
+        garbage.send_to(b"garbage", server_address).unwrap();
+        while server.bandwidth().bytes_received == request_size {
+            server.recv_from();
+        }
+
+        let server_stats = server.bandwidth();
+        assert_eq!(server_stats.bytes_received, request_size + 7);
+        assert_eq!(
+            server_stats.received,
+            MessageBytes {
+                ping: request_size,
+                ..Default::default()
+            }
+        );
+    }
 }
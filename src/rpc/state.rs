@@ -0,0 +1,185 @@
+//! Compact binary snapshot of the [super::Rpc] state, see [super::Rpc::export_state].
+//!
+//! The format is a version byte, followed by the node Id, the nodes of the routing
+//! tables, and the closest nodes of the cached queries. Lists are prefixed by their
+//! length as a big endian `u32`, and nodes are encoded as their Id followed by an
+//! address family byte (`4` or `6`), the IP and the big endian port.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::common::{Id, Node, ID_SIZE};
+
+/// Current version of the [super::Rpc::export_state] format.
+pub const STATE_VERSION: u8 = 1;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+/// Errors decoding a state exported by [super::Rpc::export_state].
+pub enum ImportStateError {
+    #[error("Unsupported state version: {0}, expected at most {STATE_VERSION}")]
+    /// The state was exported by a newer version of this crate.
+    UnsupportedVersion(u8),
+
+    #[error("State ended unexpectedly")]
+    /// The state is truncated.
+    Truncated,
+
+    #[error("Invalid address family: {0}")]
+    /// A node address is neither Ipv4 nor Ipv6.
+    InvalidAddressFamily(u8),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RpcState {
+    pub id: Id,
+    pub nodes: Vec<Node>,
+    /// Closest nodes of cached queries, from the least to the most recently used.
+    pub cached_queries: Vec<(Id, Vec<Node>)>,
+}
+
+impl RpcState {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![STATE_VERSION];
+
+        bytes.extend_from_slice(self.id.as_bytes());
+        encode_nodes(&mut bytes, &self.nodes);
+
+        bytes.extend_from_slice(&(self.cached_queries.len() as u32).to_be_bytes());
+        for (target, nodes) in &self.cached_queries {
+            bytes.extend_from_slice(target.as_bytes());
+            encode_nodes(&mut bytes, nodes);
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ImportStateError> {
+        let mut reader = Reader(bytes);
+
+        let version = reader.u8()?;
+        if version == 0 || version > STATE_VERSION {
+            return Err(ImportStateError::UnsupportedVersion(version));
+        }
+
+        let id = reader.id()?;
+        let nodes = reader.nodes()?;
+
+        let count = reader.u32()?;
+        let mut cached_queries = Vec::new();
+        for _ in 0..count {
+            cached_queries.push((reader.id()?, reader.nodes()?));
+        }
+
+        // Fields added in future versions are appended here.
+
+        Ok(Self {
+            id,
+            nodes,
+            cached_queries,
+        })
+    }
+}
+
+fn encode_nodes(bytes: &mut Vec<u8>, nodes: &[Node]) {
+    bytes.extend_from_slice(&(nodes.len() as u32).to_be_bytes());
+
+    for node in nodes {
+        bytes.extend_from_slice(node.id().as_bytes());
+
+        match node.socket_address() {
+            SocketAddr::V4(address) => {
+                bytes.push(4);
+                bytes.extend_from_slice(&address.ip().octets());
+                bytes.extend_from_slice(&address.port().to_be_bytes());
+            }
+            SocketAddr::V6(address) => {
+                bytes.push(6);
+                bytes.extend_from_slice(&address.ip().octets());
+                bytes.extend_from_slice(&address.port().to_be_bytes());
+            }
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ImportStateError> {
+        if self.0.len() < N {
+            return Err(ImportStateError::Truncated);
+        }
+
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+
+        Ok(head.try_into().expect("checked length"))
+    }
+
+    fn u8(&mut self) -> Result<u8, ImportStateError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, ImportStateError> {
+        Ok(u32::from_be_bytes(self.take()?))
+    }
+
+    fn id(&mut self) -> Result<Id, ImportStateError> {
+        Ok(Id::from(self.take::<ID_SIZE>()?))
+    }
+
+    fn nodes(&mut self) -> Result<Vec<Node>, ImportStateError> {
+        let count = self.u32()?;
+        let mut nodes = Vec::new();
+
+        for _ in 0..count {
+            let id = self.id()?;
+
+            let address: SocketAddr = match self.u8()? {
+                4 => (Ipv4Addr::from(self.take::<4>()?), self.port()?).into(),
+                6 => (Ipv6Addr::from(self.take::<16>()?), self.port()?).into(),
+                family => return Err(ImportStateError::InvalidAddressFamily(family)),
+            };
+
+            nodes.push(Node::new(id, address));
+        }
+
+        Ok(nodes)
+    }
+
+    fn port(&mut self) -> Result<u16, ImportStateError> {
+        Ok(u16::from_be_bytes(self.take()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn invalid() {
+        let state = RpcState {
+            id: Id::random(),
+            nodes: vec![Node::random()],
+            cached_queries: vec![(Id::random(), vec![Node::random()])],
+        };
+        let bytes = state.encode();
+
+        assert_eq!(
+            RpcState::decode(&bytes[..bytes.len() - 1]).unwrap_err(),
+            ImportStateError::Truncated
+        );
+
+        let mut newer = bytes.clone();
+        newer[0] = STATE_VERSION + 1;
+        assert_eq!(
+            RpcState::decode(&newer).unwrap_err(),
+            ImportStateError::UnsupportedVersion(STATE_VERSION + 1)
+        );
+
+        let mut family = bytes.clone();
+        family[1 + ID_SIZE + 4 + ID_SIZE] = 5;
+        assert_eq!(
+            RpcState::decode(&family).unwrap_err(),
+            ImportStateError::InvalidAddressFamily(5)
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Datagram transport of the [super::Rpc], replaceable for hermetic tests or custom tunnels.
+
+use std::fmt::Debug;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+/// A datagram transport that the [super::Rpc] sends and receives
+/// [KRPC](https://www.bittorrent.org/beps/bep_0005.html) messages over,
+/// instead of binding a [UdpSocket].
+///
+/// Can be set with [crate::DhtBuilder::transport], for example to connect
+/// nodes in memory in tests, or to tunnel the Dht over a relay.
+pub trait Transport: Debug + Send + Sync {
+    /// Send a datagram to the given address.
+    fn send_to(&self, buf: &[u8], address: SocketAddr) -> io::Result<usize>;
+
+    /// Receive a single datagram, returning its size and origin.
+    ///
+    /// Called on every [super::Rpc::tick], so it should wait for at most a
+    /// few milliseconds before returning an error if nothing was received,
+    /// like the 10 milliseconds read timeout of the default [UdpSocket].
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Returns the Ipv4 address other nodes can reach this transport at.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, buf: &[u8], address: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, address)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod memory {
+    //! In-memory [Transport]s connected to each other, without touching the network.
+
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::rpc::socket::READ_TIMEOUT;
+
+    type Inboxes = Arc<Mutex<HashMap<SocketAddr, flume::Sender<(Box<[u8]>, SocketAddr)>>>>;
+
+    #[derive(Debug, Clone, Default)]
+    /// A network of [MemoryTransport]s, delivering datagrams by their addresses.
+    pub(crate) struct MemoryNetwork {
+        inboxes: Inboxes,
+    }
+
+    impl MemoryNetwork {
+        /// Create a transport reachable at `address` within this network.
+        pub fn transport(&self, address: SocketAddr) -> MemoryTransport {
+            let (sender, receiver) = flume::unbounded();
+
+            self.inboxes.lock().unwrap().insert(address, sender);
+
+            MemoryTransport {
+                address,
+                inboxes: self.inboxes.clone(),
+                receiver,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct MemoryTransport {
+        address: SocketAddr,
+        inboxes: Inboxes,
+        receiver: flume::Receiver<(Box<[u8]>, SocketAddr)>,
+    }
+
+    impl Transport for MemoryTransport {
+        fn send_to(&self, buf: &[u8], address: SocketAddr) -> io::Result<usize> {
+            // Like UDP, datagrams to unknown addresses are silently dropped.
+            if let Some(inbox) = self.inboxes.lock().unwrap().get(&address) {
+                let _ = inbox.send((buf.into(), self.address));
+            }
+
+            Ok(buf.len())
+        }
+
+        fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            let (datagram, from) = self
+                .receiver
+                .recv_timeout(READ_TIMEOUT)
+                .map_err(|_| io::Error::from(io::ErrorKind::WouldBlock))?;
+
+            let size = datagram.len().min(buf.len());
+            buf[..size].copy_from_slice(&datagram[..size]);
+
+            Ok((size, from))
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(self.address)
+        }
+    }
+}